@@ -1,11 +1,13 @@
 // use assets::{Program, ProgramInner, ProgramSafetyPromise};
+use assets::irb::IRB;
+use ecstasy::scene::Scene;
 use libremexre::{catch, err, errors::Result};
-use log::{error, warn};
+use log::{error, info, warn};
 use serde_cbor::to_vec;
 use shaderc::{Compiler, ShaderKind};
 use std::{
     fs::{read, read_to_string, write},
-    path::PathBuf,
+    path::{Path, PathBuf},
     process::exit,
     sync::Arc,
 };
@@ -70,11 +72,63 @@ fn main() -> Result<()> {
                 warn!("{}", fs.get_warning_messages());
             }
         }
+        Subcommand::PackScenes { scenes, irb } => {
+            let mut bundle = if irb.exists() {
+                IRB::load_from_file(&irb)?
+            } else {
+                IRB::new()
+            };
+
+            for path in scenes {
+                let name = path
+                    .file_stem()
+                    .ok_or_else(|| err!("Invalid scene path: {}", path.display()))?
+                    .to_string_lossy()
+                    .into_owned();
+                let src = read_to_string(&path)?;
+                let scene = load_scene(&path, &src)?;
+                scene
+                    .check_assets(|asset| bundle.contains(asset))
+                    .map_err(|err| err!("In {}: {}", path.display(), err))?;
+                info!("Packing scene {}", name);
+                bundle.insert_scene(name, src);
+            }
+            bundle.save_to_file(&irb)?;
+        }
+        Subcommand::ValidateScenes { scenes } => {
+            let mut ok = true;
+            for path in scenes {
+                let src = read_to_string(&path)?;
+                match load_scene(&path, &src) {
+                    Ok(scene) => {
+                        let mut assets = scene.asset_names().collect::<Vec<_>>();
+                        assets.sort();
+                        assets.dedup();
+                        println!(
+                            "{}: {} entities, using assets {:?}",
+                            path.display(),
+                            scene.entities.len(),
+                            assets
+                        );
+                    }
+                    Err(err) => {
+                        error!("{}", err);
+                        ok = false;
+                    }
+                }
+            }
+            if !ok {
+                exit(1);
+            }
+        }
         Subcommand::ParseIQM { file } => {
             let data = read(file)?;
             match iqm::IQM::parse_from(&data) {
-                Some(iqm) => println!("{:#?}", iqm),
-                None => eprintln!("Failed to parse file"),
+                Ok(iqm) => println!("{:#?}", iqm),
+                Err(err) => {
+                    error!("Failed to parse file: {}", err);
+                    exit(1);
+                }
             }
         }
     }
@@ -82,6 +136,15 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Parses a scene, and checks that it doesn't refer to entities outside the scene.
+fn load_scene(path: &Path, src: &str) -> Result<Scene> {
+    let mut scene = Scene::parse(src).map_err(|err| err!("In {}: {}", path.display(), err))?;
+    scene
+        .check_entities()
+        .map_err(|err| err!("In {}: {}", path.display(), err))?;
+    Ok(scene)
+}
+
 #[derive(Debug, StructOpt)]
 struct Options {
     /// Silence all log output.
@@ -108,7 +171,20 @@ enum Subcommand {
         shader_bundle: PathBuf,
     },
 
+    /// Validates scenes and packs them into an IRB file, creating it if it does not exist. Every
+    /// asset the scenes refer to must already be in the IRB file.
+    #[structopt(name = "pack-scenes")]
+    PackScenes {
+        scenes: Vec<PathBuf>,
+        #[structopt(short = "o", long = "output")]
+        irb: PathBuf,
+    },
+
     /// Parses and prints an IQM file.
     #[structopt(name = "parse-iqm")]
     ParseIQM { file: PathBuf },
+
+    /// Checks that scene files are valid, printing a summary of each.
+    #[structopt(name = "validate-scenes")]
+    ValidateScenes { scenes: Vec<PathBuf> },
 }
@@ -1,4 +1,19 @@
-use std::{error::Error, fs::read, path::PathBuf};
+use assets::Assets;
+use ecstasy::{replay::Recording, Engine};
+use protocol::{
+    from_bytes, to_bytes,
+    udp::{Channel, Config, Endpoint, Event},
+    ClientMessage, ClientStats, ServerMessage,
+};
+use std::{
+    error::Error,
+    fs::{read, read_to_string},
+    io::{self, ErrorKind},
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -8,9 +23,20 @@ fn main() -> Result<(), Box<dyn Error>> {
     match options.subcommand {
         Subcommand::ParseIQM { file } => {
             let data = read(file)?;
-            match iqm::IQM::parse_from(&data) {
-                Some(iqm) => println!("{:#?}", iqm),
-                None => eprintln!("Failed to parse file"),
+            let iqm = iqm::IQM::parse_from(&data)?;
+            println!("{:#?}", iqm);
+        }
+        Subcommand::Replay { file } => {
+            let recording = Recording::parse(&read_to_string(file)?)?;
+            let ticks = recording.ticks.len();
+            Engine::new(Assets::new()).replay(recording)?;
+            println!("Replayed {} ticks without diverging", ticks);
+        }
+        Subcommand::ServerStats { addr } => {
+            let stats = server_stats(&addr)?;
+            println!("{} clients connected", stats.len());
+            for client in stats {
+                println!("{:#?}", client);
             }
         }
     }
@@ -18,6 +44,50 @@ fn main() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
+/// Asks a server for statistics about its clients, without joining the game.
+fn server_stats(addr: &str) -> Result<Vec<ClientStats>, Box<dyn Error>> {
+    let server = addr
+        .to_socket_addrs()?
+        .next()
+        .ok_or("no address to connect to")?;
+    let local: SocketAddr = if server.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(local)?;
+    socket.set_nonblocking(true)?;
+
+    let now = Instant::now();
+    let mut endpoint = Endpoint::client(socket, server, Config::default(), now);
+    endpoint.send(
+        server,
+        Channel::Reliable,
+        &to_bytes(&ClientMessage::GetStats)?,
+    )?;
+    let deadline = now + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        for event in endpoint.update(Instant::now())? {
+            match event {
+                Event::Message { msg, .. } => {
+                    let stats = match from_bytes(&msg)? {
+                        ServerMessage::Stats(stats) => stats,
+                        msg => return Err(format!("Unexpected message: {:?}", msg).into()),
+                    };
+                    endpoint.disconnect(server)?;
+                    return Ok(stats);
+                }
+                Event::Disconnected(_, reason) => {
+                    return Err(format!("Disconnected: {:?}", reason).into())
+                }
+                Event::Connected(_) => {}
+            }
+        }
+        sleep(Duration::from_millis(1));
+    }
+    Err(io::Error::from(ErrorKind::TimedOut).into())
+}
+
 #[derive(Debug, StructOpt)]
 struct Options {
     /// Silence all log output.
@@ -38,4 +108,13 @@ enum Subcommand {
     /// Parses and prints an IQM file.
     #[structopt(name = "parse-iqm")]
     ParseIQM { file: PathBuf },
+
+    /// Replays a recording made with `ia --record`, checking that it doesn't diverge. This engine
+    /// has no systems, so recordings made with any (such as the renderer) are refused.
+    #[structopt(name = "replay")]
+    Replay { file: PathBuf },
+
+    /// Prints statistics about the clients connected to an `ia-server`.
+    #[structopt(name = "server-stats")]
+    ServerStats { addr: String },
 }
@@ -0,0 +1,535 @@
+//! Logging players in.
+//!
+//! With an `Authenticator` set, a client must log in before it's given a character, by sending
+//! `Credentials` in its `Hello`: either the password of the player's account, or a session token
+//! from an earlier login. Accounts are looked up through an `AccountProvider`; `LocalAccounts` is
+//! one that keeps them in a file, so that a server can run offline and in tests. Passwords are
+//! only ever stored as Argon2 hashes.
+//!
+//! Hashing passwords is deliberately slow, so they're checked on a thread of their own rather than
+//! the one running the game: `Authenticator::start_login` starts a login, and the server picks up
+//! the result from `Authenticator::finished_logins` in a later tick. Accounts that don't exist are
+//! checked against a dummy hash, so that how long a login takes doesn't give away which accounts
+//! exist.
+//!
+//! Logging in gives the client a session token, which it can log in with again (e.g. after
+//! reconnecting) until the session expires. Failed logins are counted for each address, and an
+//! address with too many recent failures can't log in at all for a while, so that passwords can't
+//! be guessed quickly.
+//!
+//! The UDP transport isn't encrypted, so passwords are sent in the clear; servers on untrusted
+//! networks should be run behind something that encrypts the connection.
+
+use crate::persistence::is_valid_name;
+use protocol::Credentials;
+use rand::{thread_rng, Rng};
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    error::Error,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    fs::{read_to_string, rename, write},
+    io::{Error as IoError, ErrorKind},
+    net::IpAddr,
+    path::PathBuf,
+    sync::mpsc::{channel, Receiver, Sender},
+    thread::Builder,
+    time::{Duration, Instant},
+};
+
+/// A source of accounts, which checks players' passwords. Passwords are checked on a thread of
+/// their own, so providers must be `Send`.
+pub trait AccountProvider: Send {
+    /// Returns whether `password` is the password of the account with the given name. Returns
+    /// `false` if there is no such account.
+    fn check_password(&self, name: &str, password: &str) -> Result<bool, AuthError>;
+}
+
+/// Hashes a password with Argon2 and a random salt, returning the hash in its encoded form, which
+/// includes the salt and the parameters used.
+pub fn hash_password(password: &str) -> String {
+    let salt = thread_rng().gen::<[u8; 16]>();
+    argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::default())
+        .expect("the default Argon2 parameters were invalid")
+}
+
+/// Returns whether `password` matches a hash from `hash_password`. Invalid hashes match nothing.
+pub fn verify_password(hash: &str, password: &str) -> bool {
+    argon2::verify_encoded(hash, password.as_bytes()).unwrap_or(false)
+}
+
+/// Accounts kept in a local file, with one account per line: its name, a space, and the hash of
+/// its password. Changes are written to the file immediately.
+#[derive(Debug)]
+pub struct LocalAccounts {
+    path: PathBuf,
+    hashes: BTreeMap<String, String>,
+
+    /// The hash passwords for accounts that don't exist are checked against, so that they take
+    /// as long to check as those for accounts that do.
+    dummy_hash: String,
+}
+
+impl LocalAccounts {
+    /// Opens the accounts file at the given path. If it doesn't exist, there are no accounts, and
+    /// it will be created when one is added.
+    pub fn open<P: Into<PathBuf>>(path: P) -> Result<LocalAccounts, AuthError> {
+        let path = path.into();
+        let src = match read_to_string(&path) {
+            Ok(src) => src,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut hashes = BTreeMap::new();
+        for (i, line) in src.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(hash), None) if is_valid_name(name) => {
+                    let _ = hashes.insert(name.to_string(), hash.to_string());
+                }
+                _ => return Err(AuthError::InvalidAccountsFile { line: i + 1 }),
+            }
+        }
+        Ok(LocalAccounts {
+            path,
+            hashes,
+            dummy_hash: hash_password(""),
+        })
+    }
+
+    /// Returns the names of the accounts, in order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.hashes.keys().map(|name| name.as_str())
+    }
+
+    /// Adds an account.
+    pub fn create(&mut self, name: &str, password: &str) -> Result<(), AuthError> {
+        if !is_valid_name(name) {
+            return Err(AuthError::InvalidName(name.to_string()));
+        }
+        if self.hashes.contains_key(name) {
+            return Err(AuthError::AccountExists(name.to_string()));
+        }
+        let _ = self
+            .hashes
+            .insert(name.to_string(), hash_password(password));
+        self.save()
+    }
+
+    /// Changes the password of an account.
+    pub fn set_password(&mut self, name: &str, password: &str) -> Result<(), AuthError> {
+        match self.hashes.get_mut(name) {
+            Some(hash) => *hash = hash_password(password),
+            None => return Err(AuthError::NoSuchAccount(name.to_string())),
+        }
+        self.save()
+    }
+
+    /// Removes an account, returning whether it existed.
+    pub fn remove(&mut self, name: &str) -> Result<bool, AuthError> {
+        if self.hashes.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Writes the accounts to the file, replacing it atomically.
+    fn save(&self) -> Result<(), AuthError> {
+        let src = self
+            .hashes
+            .iter()
+            .map(|(name, hash)| format!("{} {}\n", name, hash))
+            .collect::<String>();
+        let tmp = self.path.with_extension("tmp");
+        write(&tmp, src)?;
+        rename(tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl AccountProvider for LocalAccounts {
+    fn check_password(&self, name: &str, password: &str) -> Result<bool, AuthError> {
+        match self.hashes.get(name) {
+            Some(hash) => Ok(verify_password(hash, password)),
+            None => {
+                let _ = verify_password(&self.dummy_hash, password);
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// The sessions of players who have logged in.
+pub struct Sessions {
+    lifetime: Duration,
+    tokens: HashMap<String, (String, Instant)>,
+}
+
+impl Sessions {
+    /// Creates an empty set of sessions, which will each last for `lifetime`.
+    pub fn new(lifetime: Duration) -> Sessions {
+        Sessions {
+            lifetime,
+            tokens: HashMap::new(),
+        }
+    }
+
+    /// Starts a session for the player with the given name, returning its token.
+    pub fn issue(&mut self, name: &str, now: Instant) -> String {
+        self.tokens.retain(|_, &mut (_, expires)| expires > now);
+        let token = thread_rng()
+            .gen::<[u8; 16]>()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        let _ = self
+            .tokens
+            .insert(token.clone(), (name.to_string(), now + self.lifetime));
+        token
+    }
+
+    /// Returns the name of the player whose session has the given token, if it hasn't expired.
+    pub fn check(&self, token: &str, now: Instant) -> Option<&str> {
+        match self.tokens.get(token) {
+            Some((name, expires)) if *expires > now => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Ends the session with the given token.
+    pub fn revoke(&mut self, token: &str) {
+        let _ = self.tokens.remove(token);
+    }
+
+    /// Ends every session of the player with the given name, e.g. after their password changes.
+    pub fn revoke_all(&mut self, name: &str) {
+        self.tokens.retain(|_, (player, _)| player != name);
+    }
+}
+
+impl Debug for Sessions {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        // The tokens are left out, since anyone who has one can use it to log in.
+        fmt.debug_struct("Sessions")
+            .field("lifetime", &self.lifetime)
+            .field("count", &self.tokens.len())
+            .finish()
+    }
+}
+
+/// A limit on how many failed logins each address can make in a window of time.
+///
+/// Only the most recent `max_failures` failures of each address are kept, and addresses whose
+/// failures are all older than the window are forgotten once per window, so that the limiter
+/// doesn't grow without bound as new addresses fail to log in.
+#[derive(Debug)]
+pub struct RateLimiter {
+    max_failures: usize,
+    window: Duration,
+    failures: HashMap<IpAddr, VecDeque<Instant>>,
+    last_pruned: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter that allows `max_failures` failed logins from each address in any period
+    /// of length `window`.
+    pub fn new(max_failures: usize, window: Duration) -> RateLimiter {
+        RateLimiter {
+            max_failures,
+            window,
+            failures: HashMap::new(),
+            last_pruned: Instant::now(),
+        }
+    }
+
+    /// Returns the number of addresses whose failures are being kept.
+    pub fn num_addresses(&self) -> usize {
+        self.failures.len()
+    }
+
+    /// Returns whether the address has failed to log in too many times recently to try again.
+    pub fn is_limited(&mut self, addr: IpAddr, now: Instant) -> bool {
+        self.recent_failures(addr, now) >= self.max_failures
+    }
+
+    /// Returns the number of times the address has failed to log in within the window, forgetting
+    /// older failures.
+    fn recent_failures(&mut self, addr: IpAddr, now: Instant) -> usize {
+        let window = self.window;
+        match self.failures.get_mut(&addr) {
+            Some(failures) => {
+                while let Some(&failure) = failures.front() {
+                    if now - failure < window {
+                        break;
+                    }
+                    let _ = failures.pop_front();
+                }
+                let len = failures.len();
+                if len == 0 {
+                    let _ = self.failures.remove(&addr);
+                }
+                len
+            }
+            None => 0,
+        }
+    }
+
+    /// Records a failed login from the address.
+    pub fn record_failure(&mut self, addr: IpAddr, now: Instant) {
+        let failures = self.failures.entry(addr).or_default();
+        failures.push_back(now);
+        if failures.len() > self.max_failures {
+            let _ = failures.pop_front();
+        }
+
+        if now > self.last_pruned && now - self.last_pruned >= self.window {
+            self.prune(now);
+        }
+    }
+
+    /// Forgets every address whose newest failure is older than the window.
+    pub fn prune(&mut self, now: Instant) {
+        let window = self.window;
+        self.failures.retain(|_, failures| match failures.back() {
+            Some(&newest) => now - newest < window,
+            None => false,
+        });
+        self.last_pruned = now;
+    }
+}
+
+/// A password being checked, with its login's number.
+type PasswordCheck = (u64, String, String);
+
+/// A login whose password is being checked.
+#[derive(Debug)]
+struct PendingLogin {
+    addr: IpAddr,
+    name: String,
+}
+
+/// Checks the credentials clients log in with.
+pub struct Authenticator {
+    checks: Sender<PasswordCheck>,
+    results: Receiver<(u64, Result<bool, AuthError>)>,
+    pending: HashMap<u64, PendingLogin>,
+    finished: Vec<(u64, Result<String, AuthError>)>,
+    next_login: u64,
+    sessions: Sessions,
+    limiter: RateLimiter,
+}
+
+impl Authenticator {
+    /// Creates an authenticator for the given accounts, starting the thread passwords are checked
+    /// on. By default, sessions last a day, and each address can fail to log in 5 times a minute.
+    pub fn new<A: 'static + AccountProvider>(accounts: A) -> Authenticator {
+        let (checks, requests) = channel::<PasswordCheck>();
+        let (done, results) = channel();
+        // The thread stops once the authenticator (and so the sending half of `checks`) is
+        // dropped.
+        let _ = Builder::new()
+            .name("password checker".to_string())
+            .spawn(move || {
+                for (login, name, password) in requests {
+                    let result = accounts.check_password(&name, &password);
+                    if done.send((login, result)).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("couldn't start the password checking thread");
+
+        Authenticator {
+            checks,
+            results,
+            pending: HashMap::new(),
+            finished: Vec::new(),
+            next_login: 0,
+            sessions: Sessions::new(Duration::from_secs(24 * 60 * 60)),
+            limiter: RateLimiter::new(5, Duration::from_secs(60)),
+        }
+    }
+
+    /// Sets how long sessions last. Sessions that have already started are ended.
+    pub fn with_session_lifetime(mut self, lifetime: Duration) -> Authenticator {
+        self.sessions = Sessions::new(lifetime);
+        self
+    }
+
+    /// Sets how many times each address can fail to log in within `window`.
+    pub fn with_rate_limit(mut self, max_failures: usize, window: Duration) -> Authenticator {
+        self.limiter = RateLimiter::new(max_failures, window);
+        self
+    }
+
+    /// Returns the sessions of players who have logged in.
+    pub fn sessions_mut(&mut self) -> &mut Sessions {
+        &mut self.sessions
+    }
+
+    /// Starts logging in as the player with the given name, from the given address, returning the
+    /// number of the login. Its result (the token of the new session) is returned by
+    /// `finished_logins` once it's ready, which for a password is after it has been checked on the
+    /// password checking thread.
+    ///
+    /// Logins whose passwords are still being checked count as failures towards the rate limit
+    /// until they finish, so that an address can't try many passwords at once.
+    pub fn start_login(&mut self, addr: IpAddr, name: &str, credentials: Credentials) -> u64 {
+        let login = self.next_login;
+        self.next_login += 1;
+
+        let now = Instant::now();
+        let pending = self.pending.values().filter(|p| p.addr == addr).count();
+        if self.limiter.recent_failures(addr, now) + pending >= self.limiter.max_failures {
+            self.finished.push((login, Err(AuthError::RateLimited)));
+            return login;
+        }
+
+        match credentials {
+            Credentials::Password(password) => {
+                let _ = self.pending.insert(
+                    login,
+                    PendingLogin {
+                        addr,
+                        name: name.to_string(),
+                    },
+                );
+                // The thread only stops once `checks` is dropped, so this can't fail.
+                let _ = self.checks.send((login, name.to_string(), password));
+            }
+            Credentials::Token(token) => {
+                let result = if self.sessions.check(&token, now) == Some(name) {
+                    self.sessions.revoke(&token);
+                    Ok(self.sessions.issue(name, now))
+                } else {
+                    self.limiter.record_failure(addr, now);
+                    Err(AuthError::WrongCredentials)
+                };
+                self.finished.push((login, result));
+            }
+        }
+        login
+    }
+
+    /// Returns the logins that have finished since this was last called, each with its number and
+    /// either the token of its new session or why it failed.
+    pub fn finished_logins(&mut self) -> Vec<(u64, Result<String, AuthError>)> {
+        while let Ok((login, result)) = self.results.try_recv() {
+            self.finish(login, result);
+        }
+        self.finished.drain(..).collect()
+    }
+
+    /// Logs in as the player with the given name, from the given address, waiting for the
+    /// password (if any) to be checked. Returns the token of the new session.
+    pub fn login(
+        &mut self,
+        addr: IpAddr,
+        name: &str,
+        credentials: &Credentials,
+    ) -> Result<String, AuthError> {
+        let login = self.start_login(addr, name, credentials.clone());
+        loop {
+            if let Some(i) = self.finished.iter().position(|&(l, _)| l == login) {
+                return self.finished.remove(i).1;
+            }
+            // The thread only stops early if the account provider panicked.
+            let (login, result) = self
+                .results
+                .recv()
+                .expect("the password checking thread panicked");
+            self.finish(login, result);
+        }
+    }
+
+    /// Finishes a login whose password has been checked.
+    fn finish(&mut self, login: u64, result: Result<bool, AuthError>) {
+        let PendingLogin { addr, name } = match self.pending.remove(&login) {
+            Some(pending) => pending,
+            None => return,
+        };
+        let now = Instant::now();
+        let result = match result {
+            Ok(true) => Ok(self.sessions.issue(&name, now)),
+            Ok(false) => {
+                self.limiter.record_failure(addr, now);
+                Err(AuthError::WrongCredentials)
+            }
+            Err(err) => Err(err),
+        };
+        self.finished.push((login, result));
+    }
+}
+
+impl Debug for Authenticator {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("Authenticator")
+            .field("pending", &self.pending)
+            .field("sessions", &self.sessions)
+            .field("limiter", &self.limiter)
+            .finish()
+    }
+}
+
+/// An error logging in, or managing accounts.
+#[derive(Debug)]
+pub enum AuthError {
+    /// An account with the given name already exists.
+    AccountExists(String),
+
+    /// The accounts file is malformed at the given line.
+    InvalidAccountsFile {
+        /// The (1-based) number of the malformed line.
+        line: usize,
+    },
+
+    /// The given name can't be used for an account.
+    InvalidName(String),
+
+    /// The accounts couldn't be read or written.
+    Io(IoError),
+
+    /// There is no account with the given name.
+    NoSuchAccount(String),
+
+    /// The address has failed to log in too many times recently.
+    RateLimited,
+
+    /// The password or session token was wrong, or the account doesn't exist.
+    WrongCredentials,
+}
+
+impl Display for AuthError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            AuthError::AccountExists(name) => write!(fmt, "The account {} already exists", name),
+            AuthError::InvalidAccountsFile { line } => {
+                write!(fmt, "Invalid accounts file: malformed line {}", line)
+            }
+            AuthError::InvalidName(name) => write!(fmt, "Invalid account name: {}", name),
+            AuthError::Io(err) => write!(fmt, "{}", err),
+            AuthError::NoSuchAccount(name) => write!(fmt, "No such account: {}", name),
+            AuthError::RateLimited => write!(fmt, "Too many failed logins; try again later"),
+            AuthError::WrongCredentials => write!(fmt, "Wrong name or password"),
+        }
+    }
+}
+
+impl Error for AuthError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AuthError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for AuthError {
+    fn from(err: IoError) -> AuthError {
+        AuthError::Io(err)
+    }
+}
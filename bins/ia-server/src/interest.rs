@@ -0,0 +1,51 @@
+//! Deciding which entities each client is sent.
+
+use ecstasy::{
+    components::{AlwaysRelevant, Position},
+    ComponentStore, Entity,
+};
+use std::collections::HashSet;
+
+/// Returns the entities relevant to a player: the player itself, entities within `radius` of the
+/// player's position, entities marked `AlwaysRelevant`, and entities without a position at all. If
+/// `radius` is `None`, or the player has no position, every entity is relevant.
+pub(crate) fn relevant_entities(
+    store: &ComponentStore,
+    player: Entity,
+    radius: Option<f32>,
+) -> HashSet<Entity> {
+    let alive = store
+        .iter_entities()
+        .filter(|&entity| store.is_alive(entity));
+    let (center, radius) = match (store.get_component::<Position>(player), radius) {
+        (Some(position), Some(radius)) => (position.0, radius),
+        _ => return alive.collect(),
+    };
+
+    let mut relevant = alive
+        .clone()
+        .filter(|&entity| {
+            entity == player
+                || store.get_component::<AlwaysRelevant>(entity).is_some()
+                || store.get_component::<Position>(entity).is_none()
+        })
+        .collect::<HashSet<_>>();
+    match store.spatial_index() {
+        Some(index) => relevant.extend(
+            index
+                .within_radius(center, radius)
+                .into_iter()
+                .filter(|&entity| store.is_alive(entity)),
+        ),
+        None => relevant.extend(alive.filter(|&entity| {
+            store
+                .get_component::<Position>(entity)
+                .map(|position| {
+                    let d = position.0 - center;
+                    d.x * d.x + d.y * d.y + d.z * d.z <= radius * radius
+                })
+                .unwrap_or(false)
+        })),
+    }
+    relevant
+}
@@ -0,0 +1,878 @@
+//! The authoritative, headless Ia game server.
+//!
+//! The server runs an `ecstasy::Engine` at a fixed tick rate, with no window or GPU. Clients
+//! connect over UDP (see the `protocol` crate), are each given a player entity, and are sent the
+//! changes to the world after every tick. Updates may be lost, so each is the changes since the
+//! last update the client acknowledged.
+//!
+//! Each client's inputs are applied one per tick, in order, and each update tells the client which
+//! of its inputs was applied last, so that it can reconcile its own predictions. Inputs can only
+//! set the types of components the server allows (see `Server::allow_input`); an input that sets
+//! any other type is ignored as a whole.
+//!
+//! If an interest radius is set, each client is only sent the entities near its player (along with
+//! those marked `AlwaysRelevant`, and those with no position). Entities are spawned on the client
+//! when they come into range, and despawned when they leave it.
+//!
+//! The `movement` module checks the moves players make, and lets hit tests be done against the
+//! world as a client saw it.
+//!
+//! With an `Authenticator` set (see the `auth` module), clients must log in before they join.
+//!
+//! With a `CharacterStore` set (see the `persistence` module), each player's character is loaded
+//! by name when they join, and saved when they leave.
+#![deny(
+    bad_style,
+    bare_trait_objects,
+    const_err,
+    dead_code,
+    improper_ctypes,
+    legacy_directory_ownership,
+    missing_debug_implementations,
+    missing_docs,
+    no_mangle_generic_items,
+    non_shorthand_field_patterns,
+    overflowing_literals,
+    path_statements,
+    patterns_in_fns_without_body,
+    plugin_as_library,
+    private_in_public,
+    safe_extern_statics,
+    trivial_casts,
+    trivial_numeric_casts,
+    unconditional_recursion,
+    unions_with_drop_fields,
+    unused,
+    unused_allocation,
+    unused_comparisons,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_parens,
+    unused_qualifications,
+    unused_results,
+    while_true
+)]
+
+pub mod auth;
+mod interest;
+pub mod movement;
+pub mod persistence;
+
+use crate::{
+    auth::Authenticator,
+    interest::relevant_entities,
+    movement::{validate_move, MovementRules, PositionHistory},
+    persistence::{character_entities, CharacterStore, PersistenceError},
+};
+use cgmath::{Point3, Vector3};
+use ecstasy::{
+    components::{AlwaysRelevant, Collider, Name, Owner, Position},
+    delta::Delta,
+    quantize::{Codec, WorldBounds},
+    replay::Input,
+    Component, Engine, Entity, SystemMut,
+};
+use log::{error, info, warn};
+use protocol::{
+    from_bytes, to_bytes,
+    udp::{Channel, Config, Endpoint, Event, Socket, MAX_UNRELIABLE_LEN},
+    ClientMessage, ClientStats, EncodedDelta, ServerMessage,
+};
+use std::{
+    any::{type_name, TypeId},
+    collections::{HashSet, VecDeque},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Result,
+    mem::take,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    sync::atomic::{AtomicBool, Ordering},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// An input waiting to be applied: its number, the tick the client was viewing, and the components
+/// to set.
+type WaitingInput = (u64, f32, Vec<Box<dyn Component>>);
+
+/// The most inputs that are kept waiting for each client. If a client sends inputs faster than
+/// the server runs ticks, the oldest ones are dropped.
+const MAX_WAITING_INPUTS: usize = 8;
+
+/// The most updates that are remembered for each client while waiting for it to acknowledge one.
+/// Past this, the oldest two are merged, and neither can be acknowledged any more.
+const MAX_UNACKED_UPDATES: usize = 64;
+
+/// The game server. It's usually run over a `UdpSocket`, but tests can run it over a
+/// `protocol::fake_net::FakeSocket`.
+pub struct Server<S: Socket = UdpSocket> {
+    engine: Engine<Box<dyn SystemMut>>,
+    endpoint: Endpoint<S>,
+
+    /// The events from the last time the endpoint was updated, waiting to be handled.
+    events: Vec<Event>,
+
+    clients: Vec<Client>,
+    tick: u64,
+    tick_rate: u32,
+    interest_radius: Option<f32>,
+    movement_rules: Option<MovementRules>,
+    history: PositionHistory,
+    characters: Option<CharacterStore>,
+    auth: Option<Authenticator>,
+    codec: Codec,
+
+    /// The types of components clients can set as inputs.
+    input_types: HashSet<TypeId>,
+}
+
+impl Server {
+    /// Starts listening for clients on the given address. The engine will be run `tick_rate`
+    /// times per second.
+    pub fn bind<A: ToSocketAddrs, P: 'static + SystemMut>(
+        addr: A,
+        engine: Engine<P>,
+        tick_rate: u32,
+    ) -> Result<Server> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Server::new(socket, engine, tick_rate))
+    }
+
+    /// Returns the address the server is listening on.
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        self.endpoint.socket().local_addr()
+    }
+}
+
+impl<S: Socket> Server<S> {
+    /// Creates a server that listens for clients on the given socket. The engine will be run
+    /// `tick_rate` times per second.
+    pub fn new<P: 'static + SystemMut>(socket: S, engine: Engine<P>, tick_rate: u32) -> Server<S> {
+        assert!(tick_rate > 0, "tick rate must be positive");
+        Server {
+            engine: engine.boxed(),
+            endpoint: Endpoint::server(socket, Config::default()),
+            events: Vec::new(),
+            clients: Vec::new(),
+            tick: 0,
+            tick_rate,
+            interest_radius: None,
+            movement_rules: None,
+            history: PositionHistory::new(tick_rate as usize),
+            characters: None,
+            auth: None,
+            codec: Codec::new(WorldBounds::default()),
+            input_types: vec![TypeId::of::<Position>()].into_iter().collect(),
+        }
+    }
+
+    /// Returns the engine being run.
+    pub fn engine(&self) -> &Engine<Box<dyn SystemMut>> {
+        &self.engine
+    }
+
+    /// Returns the engine being run, mutably.
+    pub fn engine_mut(&mut self) -> &mut Engine<Box<dyn SystemMut>> {
+        &mut self.engine
+    }
+
+    /// Returns the number of ticks that have been run.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// Returns the number of clients that have joined the game.
+    pub fn player_count(&self) -> usize {
+        self.clients
+            .iter()
+            .filter(|client| client.player.is_some())
+            .count()
+    }
+
+    /// Sets how far from its player an entity can be and still be sent to a client. If this is
+    /// `None` (the default), every client is sent every entity.
+    pub fn set_interest_radius(&mut self, radius: Option<f32>) {
+        self.interest_radius = radius;
+    }
+
+    /// Lets clients set `T` components on their players as inputs. Only `Position`s are allowed by
+    /// default.
+    ///
+    /// Panics if `T` is one of the components the server relies on being its own: `Name` and
+    /// `Owner`, which say whose entities are whose, `AlwaysRelevant`, and `Collider`, which moves
+    /// are checked against.
+    pub fn allow_input<T: Component>(&mut self) {
+        let server_owned = [
+            TypeId::of::<Name>(),
+            TypeId::of::<Owner>(),
+            TypeId::of::<AlwaysRelevant>(),
+            TypeId::of::<Collider>(),
+        ];
+        assert!(
+            !server_owned.contains(&TypeId::of::<T>()),
+            "{} components can't be inputs",
+            type_name::<T>()
+        );
+        let _ = self.input_types.insert(TypeId::of::<T>());
+    }
+
+    /// Sets the rules players' moves are checked against. If this is `None` (the default), clients
+    /// can set their players' positions to anything.
+    pub fn set_movement_rules(&mut self, rules: Option<MovementRules>) {
+        self.movement_rules = rules;
+    }
+
+    /// Sets the store players' characters are loaded from when they join, and saved to when they
+    /// leave. If this is `None` (the default), every player starts afresh.
+    pub fn set_character_store(&mut self, characters: Option<CharacterStore>) {
+        self.characters = characters;
+    }
+
+    /// Sets the authenticator clients must log in with before joining. If this is `None` (the
+    /// default), clients can join as any player.
+    pub fn set_authenticator(&mut self, auth: Option<Authenticator>) {
+        self.auth = auth;
+    }
+
+    /// Returns the authenticator clients log in with, if one is set.
+    pub fn authenticator_mut(&mut self) -> Option<&mut Authenticator> {
+        self.auth.as_mut()
+    }
+
+    /// Returns the codec updates are encoded with. Components of the game's own that are derived
+    /// with `#[component(quantize)]` should be registered with it, and clients must register the
+    /// same types in the same order.
+    pub fn codec_mut(&mut self) -> &mut Codec {
+        &mut self.codec
+    }
+
+    /// Saves the characters of every player in the game.
+    pub fn save_characters(&self) {
+        if let Some(characters) = &self.characters {
+            for client in &self.clients {
+                save_character(characters, &self.engine, client);
+            }
+        }
+    }
+
+    /// Returns the positions of entities over the last second.
+    pub fn history(&self) -> &PositionHistory {
+        &self.history
+    }
+
+    /// Returns the tick the player's client was showing other entities at, as of its last
+    /// applied input.
+    pub fn view_tick(&self, player: Entity) -> Option<f32> {
+        self.clients
+            .iter()
+            .find(|client| client.player == Some(player))
+            .and_then(|client| client.view_tick)
+    }
+
+    /// Casts a ray on behalf of a player, against the entities as the player's client was showing
+    /// them (see `PositionHistory::raycast_at`). Players whose clients haven't sent any inputs are
+    /// taken to see the present.
+    pub fn raycast_from(
+        &self,
+        player: Entity,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Vec<(Entity, f32)> {
+        let tick = self.view_tick(player).unwrap_or(self.tick as f32);
+        self.history.raycast_at(
+            &self.engine.store,
+            tick,
+            origin,
+            direction,
+            max_distance,
+            player,
+        )
+    }
+
+    /// Returns statistics about each connected client.
+    pub fn client_stats(&self) -> Vec<ClientStats> {
+        self.clients
+            .iter()
+            .filter(|client| !client.closed)
+            .map(|client| client.stats(&self.endpoint))
+            .collect()
+    }
+
+    /// Runs the server at its tick rate until `stop` is set.
+    pub fn run(&mut self, stop: &AtomicBool) {
+        let tick_length = Duration::from_secs(1) / self.tick_rate;
+        let mut next_tick = Instant::now();
+        while !stop.load(Ordering::SeqCst) {
+            self.step();
+
+            next_tick += tick_length;
+            let now = Instant::now();
+            if next_tick > now {
+                sleep(next_tick - now);
+            } else {
+                // Don't try to catch up if we've fallen behind.
+                next_tick = now;
+            }
+        }
+        self.save_characters();
+    }
+
+    /// Runs a single tick: accepts new clients, applies their inputs, runs the engine, and sends
+    /// them the changes.
+    pub fn step(&mut self) {
+        self.step_at(Instant::now());
+    }
+
+    /// Runs a single tick, taking the current time to be `now`. This is for running the server
+    /// over a `FakeNetwork`, whose time is separate from the real time.
+    pub fn step_at(&mut self, now: Instant) {
+        self.receive(now);
+        self.apply_inputs();
+
+        self.engine.run_tick(1.0 / self.tick_rate as f32);
+        self.tick += 1;
+        self.history.record(self.tick, &self.engine.store);
+        self.send_updates();
+
+        // Send the updates now, rather than waiting for the next tick.
+        match self.endpoint.update(now) {
+            Ok(events) => self.events.extend(events),
+            Err(err) => warn!("Error sending to clients: {}", err),
+        }
+    }
+
+    /// Handles the connections, disconnections and messages from clients since the last tick.
+    fn receive(&mut self, now: Instant) {
+        let mut events = take(&mut self.events);
+        match self.endpoint.update(now) {
+            Ok(more) => events.extend(more),
+            Err(err) => warn!("Error receiving from clients: {}", err),
+        }
+
+        let stats = self.client_stats();
+        let mut playing = self
+            .clients
+            .iter()
+            .filter_map(|client| match &client.logging_in {
+                Some((_, name)) => Some(name.clone()),
+                None => client.name.clone(),
+            })
+            .collect::<HashSet<_>>();
+        for event in events {
+            let (addr, msg) = match event {
+                Event::Connected(addr) => {
+                    info!("{} connected", addr);
+                    self.clients.push(Client::new(addr));
+                    continue;
+                }
+                Event::Disconnected(addr, reason) => {
+                    if let Some(client) = self.clients.iter_mut().find(|c| c.addr == addr) {
+                        info!("{} disconnected: {:?}", addr, reason);
+                        client.closed = true;
+                        client.gone = true;
+                    }
+                    continue;
+                }
+                Event::Message { from, msg, .. } => (from, msg),
+            };
+            let client = match self.clients.iter_mut().find(|c| c.addr == addr) {
+                Some(client) if !client.closed => client,
+                _ => continue,
+            };
+            let msg = match from_bytes(&msg) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    warn!("Error receiving from {}: {}", addr, err);
+                    client.closed = true;
+                    continue;
+                }
+            };
+
+            let endpoint = &mut self.endpoint;
+            match (msg, client.player) {
+                (ClientMessage::Hello { name, credentials }, None)
+                    if client.logging_in.is_none() =>
+                {
+                    if playing.contains(&name) {
+                        client.reject(endpoint, format!("{} is already playing", name));
+                        continue;
+                    }
+
+                    match (&mut self.auth, credentials) {
+                        (None, _) => {
+                            let _ = playing.insert(name.clone());
+                            let engine = &mut self.engine;
+                            let characters = self.characters.as_ref();
+                            client.join(endpoint, engine, characters, self.tick_rate, name, None);
+                        }
+                        (Some(auth), Some(credentials)) => {
+                            // The player joins once they've logged in, in `finish_logins`.
+                            let login = auth.start_login(addr.ip(), &name, credentials);
+                            let _ = playing.insert(name.clone());
+                            client.logging_in = Some((login, name));
+                        }
+                        (Some(_), None) => {
+                            let reason = "The server requires logging in".to_string();
+                            client.reject(endpoint, reason);
+                        }
+                    }
+                }
+                (
+                    ClientMessage::Input {
+                        tick,
+                        view_tick,
+                        components,
+                    },
+                    Some(_),
+                ) => {
+                    if client.inputs.len() == MAX_WAITING_INPUTS {
+                        let _ = client.inputs.pop_front();
+                    }
+                    client.inputs.push_back((tick, view_tick, components));
+                }
+                (ClientMessage::Ack { tick }, Some(_)) => client.ack(tick),
+                (ClientMessage::GetStats, None) => {
+                    client.send(
+                        endpoint,
+                        Channel::Reliable,
+                        &ServerMessage::Stats(stats.clone()),
+                    );
+                }
+                (msg, _) => {
+                    warn!("Unexpected message from {}: {:?}", addr, msg);
+                    client.closed = true;
+                }
+            }
+        }
+
+        self.finish_logins();
+
+        // The characters of clients that leave are saved and removed from the world straight
+        // away, but a client that was rejected is kept until it has been told why.
+        let engine = &mut self.engine;
+        let characters = self.characters.as_ref();
+        for client in self.clients.iter_mut().filter(|client| client.closed) {
+            if let Some(player) = client.player {
+                if let Some(characters) = characters {
+                    save_character(characters, engine, client);
+                }
+                for entity in character_entities(&engine.store, player) {
+                    let _ = engine.apply_input(Input::DeleteEntity(entity));
+                }
+            }
+            client.name = None;
+            client.player = None;
+            client.logging_in = None;
+        }
+        let endpoint = &mut self.endpoint;
+        self.clients.retain(|client| {
+            if !client.closed || (!client.gone && endpoint.unacked_fragments(client.addr) > 0) {
+                return true;
+            }
+            if !client.gone {
+                info!("Disconnecting {}", client.addr);
+                if let Err(err) = endpoint.disconnect(client.addr) {
+                    warn!("Error disconnecting {}: {}", client.addr, err);
+                }
+            }
+            false
+        });
+    }
+
+    /// Lets the clients whose logins have finished join the game, or rejects them if their logins
+    /// failed.
+    fn finish_logins(&mut self) {
+        let auth = match &mut self.auth {
+            Some(auth) => auth,
+            None => return,
+        };
+        for (login, result) in auth.finished_logins() {
+            let client = self
+                .clients
+                .iter_mut()
+                .find(|client| client.logging_in.as_ref().map(|&(l, _)| l) == Some(login));
+            let client = match client {
+                Some(client) if !client.closed => client,
+                _ => {
+                    // The client left while logging in, so its session won't be used.
+                    if let Ok(token) = result {
+                        auth.sessions_mut().revoke(&token);
+                    }
+                    continue;
+                }
+            };
+            let (_, name) = client.logging_in.take().unwrap();
+            let endpoint = &mut self.endpoint;
+            match result {
+                Ok(token) => {
+                    let engine = &mut self.engine;
+                    let characters = self.characters.as_ref();
+                    let tick_rate = self.tick_rate;
+                    client.join(endpoint, engine, characters, tick_rate, name, Some(token));
+                }
+                Err(err) => client.reject(endpoint, err.to_string()),
+            }
+        }
+    }
+
+    /// Applies the next waiting input from each client to its player's entity, checking any
+    /// moves against the movement rules. Inputs that set types of components the server doesn't
+    /// allow are ignored, but still count as applied.
+    fn apply_inputs(&mut self) {
+        let dt = 1.0 / self.tick_rate as f32;
+        for client in &mut self.clients {
+            let player = match client.player {
+                Some(player) => player,
+                None => continue,
+            };
+            if let Some((tick, view_tick, components)) = client.inputs.pop_front() {
+                let input_types = &self.input_types;
+                let disallowed = components
+                    .iter()
+                    .find(|component| !input_types.contains(&component.as_any().type_id()));
+                if let Some(component) = disallowed {
+                    warn!(
+                        "Ignoring an input from {} setting {:?}",
+                        client.addr, component
+                    );
+                    client.last_input = Some(tick);
+                    continue;
+                }
+
+                // Moves are all checked against the world as it was before the input, so nothing
+                // else the input sets can affect them.
+                let (store, movement_rules) = (&self.engine.store, &self.movement_rules);
+                let components = components
+                    .into_iter()
+                    .filter_map(|component| {
+                        let moved_to = component.as_any().downcast_ref::<Position>().cloned();
+                        match (moved_to, movement_rules) {
+                            (Some(to), Some(rules)) => validate_move(store, player, to, dt, rules)
+                                .map(|position| -> Box<dyn Component> { Box::new(position) }),
+                            _ => Some(component),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                for component in components {
+                    let _ = self
+                        .engine
+                        .apply_input(Input::SetComponent(player, component));
+                }
+                client.last_input = Some(tick);
+
+                // Clients can't claim to be seeing the future, or a past the server has forgotten.
+                let oldest = self.history.oldest_tick().unwrap_or(self.tick);
+                client.view_tick = Some(view_tick.max(oldest as f32).min(self.tick as f32));
+            }
+        }
+    }
+
+    /// Sends each client the changes to the entities in its scope since the last update it
+    /// acknowledged.
+    ///
+    /// The client may also have applied any of the updates sent since then, so entities that
+    /// weren't in all of them are sent afresh if they're in scope, and destroyed if they're not.
+    fn send_updates(&mut self) {
+        let store = &self.engine.store;
+        for client in &mut self.clients {
+            let player = match client.player {
+                Some(player) => player,
+                None => continue,
+            };
+
+            let scope = relevant_entities(store, player, self.interest_radius);
+            let (since, surely, maybe) = client.unacked_scope();
+            let mut delta = Delta::since_in_scope(
+                store,
+                since,
+                |entity| surely.contains(&entity),
+                |entity| scope.contains(&entity),
+            );
+            delta.destroyed.extend(
+                maybe
+                    .into_iter()
+                    .filter(|entity| !surely.contains(entity) && !scope.contains(entity)),
+            );
+            client.last_update_len = delta.added.len() + delta.changed.len() + delta.removed.len();
+            client.scope = scope;
+
+            let update = ServerMessage::Update {
+                tick: self.tick,
+                input: client.last_input,
+                delta: EncodedDelta::encode(&self.codec, &delta),
+            };
+            client.send(&mut self.endpoint, Channel::Unreliable, &update);
+        }
+
+        // Later changes will be at the next change tick, so that's where the next update starts.
+        self.engine.store.maintain();
+        let next_tick = self.engine.store.change_tick();
+        for client in &mut self.clients {
+            if client.player.is_some() {
+                client.sent_update(self.tick, next_tick);
+            }
+        }
+    }
+}
+
+impl<S: Socket + Debug> Debug for Server<S> {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("Server")
+            .field("endpoint", &self.endpoint)
+            .field("events", &self.events)
+            .field("clients", &self.clients)
+            .field("tick", &self.tick)
+            .field("tick_rate", &self.tick_rate)
+            .field("interest_radius", &self.interest_radius)
+            .field("movement_rules", &self.movement_rules)
+            .field("history", &self.history)
+            .field("characters", &self.characters)
+            .field("auth", &self.auth)
+            .field("codec", &self.codec)
+            .field("input_types", &self.input_types)
+            .finish()
+    }
+}
+
+/// Creates the entity for a player who just joined, loading their saved character if there is
+/// one. The character is spawned as inputs to the engine, so that joining is recorded.
+fn spawn_player(
+    engine: &mut Engine<Box<dyn SystemMut>>,
+    characters: Option<&CharacterStore>,
+    name: String,
+) -> std::result::Result<Entity, PersistenceError> {
+    let saved = match characters {
+        Some(characters) => match characters.load(&name)? {
+            Some(scene) => Some(engine.spawn_scene(scene)?[0]),
+            None => None,
+        },
+        None => None,
+    };
+    let player = match saved {
+        Some(player) => player,
+        None => engine
+            .apply_input(Input::NewEntity)
+            .expect("creating an entity didn't return it"),
+    };
+    let _ = engine.apply_input(Input::SetComponent(player, Box::new(Name(name))));
+    Ok(player)
+}
+
+/// Saves a client's character, if it has joined the game.
+fn save_character(
+    characters: &CharacterStore,
+    engine: &Engine<Box<dyn SystemMut>>,
+    client: &Client,
+) {
+    if let (Some(name), Some(player)) = (&client.name, client.player) {
+        if let Err(err) = characters.save(name, &engine.store, player) {
+            error!("Couldn't save the character of {}: {}", client.addr, err);
+        }
+    }
+}
+
+/// A connected client.
+#[derive(Debug)]
+struct Client {
+    addr: SocketAddr,
+
+    /// The name the client joined with.
+    name: Option<String>,
+
+    /// The client's entity, once it has joined.
+    player: Option<Entity>,
+
+    /// The number of the client's login and the name it's logging in as, while its credentials
+    /// are being checked.
+    logging_in: Option<(u64, String)>,
+
+    /// The inputs waiting to be applied, with their numbers and view ticks.
+    inputs: VecDeque<WaitingInput>,
+
+    /// The number of the last input that was applied.
+    last_input: Option<u64>,
+
+    /// The tick the client was showing other entities at when it made the last applied input.
+    view_tick: Option<f32>,
+
+    /// The last update the client acknowledged.
+    acked: Option<SentUpdate>,
+
+    /// The updates sent since the acknowledged one, oldest first.
+    unacked: VecDeque<SentUpdate>,
+
+    /// The entities the client was sent in the last update.
+    scope: HashSet<Entity>,
+
+    /// The number of components in the last update.
+    last_update_len: usize,
+
+    /// Whether the connection should be closed.
+    closed: bool,
+
+    /// Whether the connection has already been closed, by the client or by timing out.
+    gone: bool,
+}
+
+/// An update that was sent to a client, which it may or may not have received.
+#[derive(Debug)]
+struct SentUpdate {
+    /// The tick of the update, or `None` if it stands for several updates that were merged.
+    tick: Option<u64>,
+
+    /// The change tick the update went up to (but not including).
+    next_tick: u64,
+
+    /// The entities that were in scope in the update, or in every one of the merged updates.
+    in_every: HashSet<Entity>,
+
+    /// The entities that were in scope in the update, or in any of the merged updates.
+    in_any: HashSet<Entity>,
+}
+
+impl Client {
+    /// Creates the state for a client that just connected.
+    fn new(addr: SocketAddr) -> Client {
+        Client {
+            addr,
+            name: None,
+            player: None,
+            logging_in: None,
+            inputs: VecDeque::new(),
+            last_input: None,
+            view_tick: None,
+            acked: None,
+            unacked: VecDeque::new(),
+            scope: HashSet::new(),
+            last_update_len: 0,
+            closed: false,
+            gone: false,
+        }
+    }
+
+    /// Returns what the next update should be computed from: the change tick of the last update
+    /// the client acknowledged, the entities it surely has (those in every update since that one,
+    /// inclusive), and those it may have (those in any of them).
+    fn unacked_scope(&self) -> (u64, HashSet<Entity>, HashSet<Entity>) {
+        let mut maybe = HashSet::new();
+        for update in self.acked.iter().chain(&self.unacked) {
+            maybe.extend(update.in_any.iter().cloned());
+        }
+        let acked = match &self.acked {
+            Some(acked) => acked,
+            None => return (0, HashSet::new(), maybe),
+        };
+        let surely = acked
+            .in_every
+            .iter()
+            .cloned()
+            .filter(|entity| self.unacked.iter().all(|u| u.in_every.contains(entity)))
+            .collect();
+        (acked.next_tick, surely, maybe)
+    }
+
+    /// Remembers that the client was sent an update of the entities in its scope.
+    fn sent_update(&mut self, tick: u64, next_tick: u64) {
+        if self.unacked.len() == MAX_UNACKED_UPDATES {
+            let oldest = self.unacked.pop_front().unwrap();
+            let merged = &mut self.unacked[0];
+            merged.tick = None;
+            merged
+                .in_every
+                .retain(|entity| oldest.in_every.contains(entity));
+            merged.in_any.extend(oldest.in_any);
+        }
+        self.unacked.push_back(SentUpdate {
+            tick: Some(tick),
+            next_tick,
+            in_every: self.scope.clone(),
+            in_any: self.scope.clone(),
+        });
+    }
+
+    /// Handles the client acknowledging the update for the given tick. Acknowledgements of
+    /// updates older than the last acknowledged one are ignored.
+    fn ack(&mut self, tick: u64) {
+        if let Some(i) = self.unacked.iter().position(|u| u.tick == Some(tick)) {
+            let _ = self.unacked.drain(..i);
+            self.acked = self.unacked.pop_front();
+        }
+    }
+
+    /// Sends the client a message, on the unreliable channel if asked and it fits, or else on the
+    /// reliable channel. Closes the connection if it can't be sent.
+    fn send<S: Socket>(
+        &mut self,
+        endpoint: &mut Endpoint<S>,
+        channel: Channel,
+        msg: &ServerMessage,
+    ) {
+        let sent = to_bytes(msg).and_then(|bytes| {
+            let channel = match channel {
+                Channel::Unreliable if bytes.len() > MAX_UNRELIABLE_LEN => Channel::Reliable,
+                channel => channel,
+            };
+            endpoint.send(self.addr, channel, &bytes)
+        });
+        if let Err(err) = sent {
+            warn!("Error sending to {}: {}", self.addr, err);
+            self.closed = true;
+        }
+    }
+
+    /// Spawns the client's player and tells the client it has joined the game, along with the
+    /// token of its session if it logged in.
+    fn join<S: Socket>(
+        &mut self,
+        endpoint: &mut Endpoint<S>,
+        engine: &mut Engine<Box<dyn SystemMut>>,
+        characters: Option<&CharacterStore>,
+        tick_rate: u32,
+        name: String,
+        session: Option<String>,
+    ) {
+        let player = match spawn_player(engine, characters, name.clone()) {
+            Ok(player) => player,
+            Err(err) => {
+                // Don't start the player afresh, or their saved character would be overwritten
+                // when they leave.
+                self.reject(endpoint, format!("Couldn't load your character: {}", err));
+                return;
+            }
+        };
+        info!("{} joined as {:?}", self.addr, player);
+        self.name = Some(name);
+        self.player = Some(player);
+        let welcome = ServerMessage::Welcome {
+            player,
+            tick_rate,
+            session,
+        };
+        self.send(endpoint, Channel::Reliable, &welcome);
+    }
+
+    /// Tells the client it can't join the game, and closes the connection once it has been told.
+    fn reject<S: Socket>(&mut self, endpoint: &mut Endpoint<S>, reason: String) {
+        info!("Rejecting {}: {}", self.addr, reason);
+        self.send(
+            endpoint,
+            Channel::Reliable,
+            &ServerMessage::Rejected(reason),
+        );
+        self.closed = true;
+    }
+
+    /// Returns statistics about the client.
+    fn stats<S: Socket>(&self, endpoint: &Endpoint<S>) -> ClientStats {
+        ClientStats {
+            addr: self.addr.to_string(),
+            name: self.name.clone(),
+            player: self.player,
+            entities_in_scope: self.scope.len(),
+            bytes_sent: endpoint.bytes_sent(self.addr),
+            bytes_received: endpoint.bytes_received(self.addr),
+            last_update_len: self.last_update_len,
+        }
+    }
+}
@@ -0,0 +1,127 @@
+use assets::{irb::IRB, Assets};
+use ecstasy::{scene::Scene, spatial::SpatialIndex, Engine};
+use ia_server::{
+    auth::{Authenticator, LocalAccounts},
+    movement::MovementRules,
+    persistence::CharacterStore,
+    Server,
+};
+use libremexre::errors::Result;
+use log::info;
+use std::{
+    io::{stdin, BufRead},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use structopt::StructOpt;
+
+/// The version of the schema characters are saved with.
+const CHARACTER_VERSION: u32 = 1;
+
+fn main() -> Result<()> {
+    let options = Options::from_args();
+    libremexre::init_logger(options.verbose + 1, options.quiet);
+
+    let accounts = match options.accounts {
+        Some(path) => Some(LocalAccounts::open(path)?),
+        None => None,
+    };
+    if let Some(name) = options.add_account {
+        let mut accounts =
+            accounts.ok_or_else(|| libremexre::err!("--add-account requires --accounts"))?;
+        let mut password = String::new();
+        let _ = stdin().lock().read_line(&mut password)?;
+        accounts.create(&name, password.trim_end_matches(&['\r', '\n'][..]))?;
+        info!("Added the account {}", name);
+        return Ok(());
+    }
+
+    // Load the assets that don't need a GPU, and assemble the engine.
+    let assets = match options.irb {
+        Some(path) => Assets::from_irb_headless(IRB::load_from_file(path)?),
+        None => Assets::new(),
+    };
+    let mut engine = Engine::new(assets);
+    if let Some(scene) = options.scene {
+        let _ = Scene::load(&engine.assets, &scene)?.spawn(&mut engine.store)?;
+    }
+
+    if let Some(radius) = options.interest_radius {
+        engine.store.set_spatial_index(SpatialIndex::new(radius));
+    }
+
+    let mut server = Server::bind(options.addr.as_str(), engine, options.tick_rate)?;
+    server.set_interest_radius(options.interest_radius);
+    server.set_movement_rules(options.max_speed.map(|max_speed| MovementRules {
+        max_speed,
+        ..MovementRules::default()
+    }));
+    server.set_authenticator(accounts.map(Authenticator::new));
+    if let Some(dir) = options.characters {
+        server.set_character_store(Some(CharacterStore::open(dir, CHARACTER_VERSION)?));
+    }
+    // Stop on SIGINT or SIGTERM, so that the characters get saved on the way out.
+    let stop = Arc::new(AtomicBool::new(false));
+    let handler_stop = stop.clone();
+    ctrlc::set_handler(move || {
+        info!("Shutting down...");
+        handler_stop.store(true, Ordering::SeqCst);
+    })?;
+
+    info!("Listening on {}", server.local_addr()?);
+    server.run(&stop);
+    Ok(())
+}
+
+#[derive(Debug, StructOpt)]
+struct Options {
+    /// Silence all log output.
+    #[structopt(short = "q", long = "quiet")]
+    quiet: bool,
+
+    /// Increase log verbosity (-v, -vv, -vvv, etc. supported).
+    #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
+    verbose: usize,
+
+    /// The address to listen for clients on.
+    #[structopt(long = "addr", default_value = "0.0.0.0:7878")]
+    addr: String,
+
+    /// The asset bundle to load models and scenes from.
+    #[structopt(long = "irb", parse(from_os_str))]
+    irb: Option<PathBuf>,
+
+    /// The name of a scene in the asset bundle to spawn at startup.
+    #[structopt(long = "scene")]
+    scene: Option<String>,
+
+    /// The number of ticks to run per second.
+    #[structopt(long = "tick-rate", default_value = "30")]
+    tick_rate: u32,
+
+    /// How far from their player entities are sent to each client. If not given, every client is
+    /// sent every entity.
+    #[structopt(long = "interest-radius")]
+    interest_radius: Option<f32>,
+
+    /// The fastest players can move, in units per second. If not given, players' moves aren't
+    /// checked.
+    #[structopt(long = "max-speed")]
+    max_speed: Option<f32>,
+
+    /// The directory to save players' characters in. If not given, characters aren't saved.
+    #[structopt(long = "characters", parse(from_os_str))]
+    characters: Option<PathBuf>,
+
+    /// The file of accounts players must log in with. If not given, players can join as anyone.
+    #[structopt(long = "accounts", parse(from_os_str))]
+    accounts: Option<PathBuf>,
+
+    /// Adds an account to the accounts file and exits, rather than running the server. The
+    /// password is read from a line of standard input.
+    #[structopt(long = "add-account")]
+    add_account: Option<String>,
+}
@@ -0,0 +1,232 @@
+//! Checking players' movement, and rewinding it for hit tests.
+//!
+//! Clients move their players by sending `Position`s as inputs. With `MovementRules` set, the
+//! server checks each new position against the player's old one: it can be at most `max_speed`
+//! times the length of a tick away, and the straight path to it can't pass through another
+//! entity's `Collider`. Moves that break the rules are cut short rather than ignored, so a client
+//! whose prediction is slightly off still moves, and is corrected by the next update. Moves are
+//! only checked against components the server keeps for itself, which clients can't set as inputs.
+//!
+//! Clients show other entities a little in the past, so a shot aimed at where an entity appears
+//! to be would miss where it is on the server. The server therefore keeps the `Position`s from the
+//! last few ticks in a `PositionHistory`, and hit tests are done against the positions at the
+//! tick the client was showing when it sent its input.
+
+use cgmath::{InnerSpace, Point3, Vector3};
+use ecstasy::{
+    components::{Collider, Position},
+    ComponentStore, Entity,
+};
+use std::{
+    cmp::Ordering,
+    collections::{HashMap, VecDeque},
+};
+
+/// The limits on how players can move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovementRules {
+    /// The fastest a player can move, in units per second.
+    pub max_speed: f32,
+
+    /// The fraction by which a move can exceed the maximum speed, to allow for rounding.
+    pub tolerance: f32,
+}
+
+impl Default for MovementRules {
+    fn default() -> MovementRules {
+        MovementRules {
+            max_speed: 10.0,
+            tolerance: 0.05,
+        }
+    }
+}
+
+/// Checks a player's move to `to`, which is made over `dt` seconds. Returns the position the
+/// player actually ends up at, or `None` if the move should be ignored entirely (because `to`
+/// isn't finite).
+///
+/// A player without a position can be placed anywhere, since the server doesn't choose where
+/// players appear. Colliders the player already overlaps are ignored, so that players can't get
+/// stuck in each other.
+pub fn validate_move(
+    store: &ComponentStore,
+    player: Entity,
+    to: Position,
+    dt: f32,
+    rules: &MovementRules,
+) -> Option<Position> {
+    let to = to.0;
+    if !(to.x.is_finite() && to.y.is_finite() && to.z.is_finite()) {
+        return None;
+    }
+    let from = match store.get_component::<Position>(player) {
+        Some(position) => position.0,
+        None => return Some(Position(to)),
+    };
+
+    let offset = to - from;
+    let max_distance = rules.max_speed * dt * (1.0 + rules.tolerance);
+    let mut distance = offset.magnitude().min(max_distance);
+    if distance == 0.0 {
+        return Some(Position(from));
+    }
+    let direction = offset.normalize();
+
+    let own_radius = store.get_component::<Collider>(player).map_or(0.0, |c| c.0);
+    for (entity, center, radius) in colliders(store) {
+        if entity == player || (center - from).magnitude() < radius + own_radius {
+            continue;
+        }
+        if let Some(hit) = ray_sphere(from, direction, center, radius + own_radius) {
+            distance = distance.min(hit);
+        }
+    }
+    Some(Position(from + direction * distance))
+}
+
+/// The `Position`s of entities over the last few ticks.
+#[derive(Debug)]
+pub struct PositionHistory {
+    capacity: usize,
+    records: VecDeque<(u64, HashMap<Entity, Position>)>,
+}
+
+impl PositionHistory {
+    /// Creates an empty history that holds the positions from up to `capacity` ticks.
+    pub fn new(capacity: usize) -> PositionHistory {
+        assert!(capacity > 0, "capacity must be positive");
+        PositionHistory {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Records the positions of every entity as of the given tick, forgetting the oldest tick if
+    /// the history is full.
+    pub fn record(&mut self, tick: u64, store: &ComponentStore) {
+        let positions = store
+            .iter_entities()
+            .filter(|&entity| store.is_alive(entity))
+            .filter_map(|entity| {
+                store
+                    .get_component::<Position>(entity)
+                    .map(|&position| (entity, position))
+            })
+            .collect();
+        if self.records.len() == self.capacity {
+            let _ = self.records.pop_front();
+        }
+        self.records.push_back((tick, positions));
+    }
+
+    /// Returns the oldest tick in the history.
+    pub fn oldest_tick(&self) -> Option<u64> {
+        self.records.front().map(|&(tick, _)| tick)
+    }
+
+    /// Returns the newest tick in the history.
+    pub fn newest_tick(&self) -> Option<u64> {
+        self.records.back().map(|&(tick, _)| tick)
+    }
+
+    /// Returns the positions of entities at the given tick, interpolating between the recorded
+    /// ticks around it. Ticks outside the history are moved to the nearest end of it.
+    pub fn positions_at(&self, tick: f32) -> HashMap<Entity, Position> {
+        let after = match self.records.iter().position(|&(t, _)| t as f32 >= tick) {
+            Some(0) => return self.records[0].1.clone(),
+            Some(after) => after,
+            None => {
+                return self
+                    .records
+                    .back()
+                    .map(|(_, p)| p.clone())
+                    .unwrap_or_default()
+            }
+        };
+        let (from_tick, from) = &self.records[after - 1];
+        let (to_tick, to) = &self.records[after];
+        let t = (tick - *from_tick as f32) / (to_tick - from_tick) as f32;
+
+        to.iter()
+            .map(|(&entity, to_position)| {
+                let position = match from.get(&entity) {
+                    Some(from_position) => {
+                        Position(from_position.0 + (to_position.0 - from_position.0) * t)
+                    }
+                    None => *to_position,
+                };
+                (entity, position)
+            })
+            .collect()
+    }
+
+    /// Returns the entities with a `Collider` that the ray starting at `origin` going in
+    /// `direction` hits within `max_distance`, as they were at the given tick. Each is returned
+    /// with its distance along the ray, and they are sorted by that distance. `ignore` is never
+    /// hit, so that players don't shoot themselves.
+    pub fn raycast_at(
+        &self,
+        store: &ComponentStore,
+        tick: f32,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+        ignore: Entity,
+    ) -> Vec<(Entity, f32)> {
+        if direction.magnitude2() == 0.0 {
+            return Vec::new();
+        }
+        let direction = direction.normalize();
+        let positions = self.positions_at(tick);
+        let mut hits = positions
+            .iter()
+            .filter(|&(&entity, _)| entity != ignore && store.is_alive(entity))
+            .filter_map(|(&entity, position)| {
+                let radius = store.get_component::<Collider>(entity)?.0;
+                ray_sphere(origin, direction, position.0, radius)
+                    .filter(|&distance| distance <= max_distance)
+                    .map(|distance| (entity, distance))
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|l, r| l.1.partial_cmp(&r.1).unwrap_or(Ordering::Equal));
+        hits
+    }
+}
+
+/// Returns every living entity with both a `Position` and a `Collider`, with the center and radius
+/// of its collider.
+fn colliders(store: &ComponentStore) -> impl '_ + Iterator<Item = (Entity, Point3<f32>, f32)> {
+    store
+        .iter_entities()
+        .filter(move |&entity| store.is_alive(entity))
+        .filter_map(move |entity| {
+            let position = store.get_component::<Position>(entity)?;
+            let collider = store.get_component::<Collider>(entity)?;
+            Some((entity, position.0, collider.0))
+        })
+}
+
+/// Returns the distance along the ray from `origin` in the (normalized) `direction` at which it
+/// enters the sphere, or `None` if it misses. If the origin is inside the sphere, the distance is
+/// zero.
+fn ray_sphere(
+    origin: Point3<f32>,
+    direction: Vector3<f32>,
+    center: Point3<f32>,
+    radius: f32,
+) -> Option<f32> {
+    let offset = center - origin;
+    let along = offset.dot(direction);
+    let closest2 = offset.magnitude2() - along * along;
+    let radius2 = radius * radius;
+    if closest2 > radius2 {
+        return None;
+    }
+    let entry = along - (radius2 - closest2).sqrt();
+    let exit = along + (radius2 - closest2).sqrt();
+    if exit < 0.0 {
+        None
+    } else {
+        Some(entry.max(0.0))
+    }
+}
@@ -0,0 +1,297 @@
+//! Saving characters between sessions.
+//!
+//! A character is a player's entity along with every entity it owns (see `Owner`), such as the
+//! items in its inventory and its progress through quests. A `CharacterStore` saves each character
+//! to its own file in a directory, as a `Scene` whose first entity is the player's, so components
+//! are saved with their `typetag` names just as in scenes.
+//!
+//! Each file starts with a line giving the version of the schema it was saved with. The schema is
+//! up to the game: when the components a character is made of change, the game bumps the version
+//! and adds a migration from the old one. Characters saved with older versions are brought up to
+//! date by running each migration in turn when they are loaded. Since components are deserialized
+//! before the migrations run, types of component that migrations replace must still be registered
+//! with `typetag` (e.g. by keeping the old type around under a new name, with an explicit
+//! `#[typetag::serde(name = "...")]`).
+
+use ecstasy::{
+    components::Owner,
+    scene::{Scene, SceneError},
+    ComponentStore, Entity,
+};
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    fs::{create_dir_all, read_to_string, remove_file, rename, write},
+    io::{Error as IoError, ErrorKind},
+    path::PathBuf,
+};
+
+/// The start of the first line of every character file, which is followed by the schema version.
+const HEADER: &str = "ia-character";
+
+/// A migration, which updates a character saved with one version of the schema to the next.
+pub type Migration = fn(&mut Scene) -> Result<(), String>;
+
+/// A directory of saved characters.
+pub struct CharacterStore {
+    dir: PathBuf,
+    version: u32,
+    migrations: BTreeMap<u32, Migration>,
+}
+
+impl CharacterStore {
+    /// Opens the store in the given directory, creating it if it doesn't exist. Characters will be
+    /// saved with the given schema version.
+    pub fn open<P: Into<PathBuf>>(
+        dir: P,
+        version: u32,
+    ) -> Result<CharacterStore, PersistenceError> {
+        let dir = dir.into();
+        create_dir_all(&dir)?;
+        Ok(CharacterStore {
+            dir,
+            version,
+            migrations: BTreeMap::new(),
+        })
+    }
+
+    /// Adds the migration from version `from` of the schema to version `from + 1`.
+    pub fn with_migration(mut self, from: u32, migration: Migration) -> CharacterStore {
+        let _ = self.migrations.insert(from, migration);
+        self
+    }
+
+    /// Returns the version of the schema characters are saved with.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns whether a character with the given name has been saved.
+    pub fn exists(&self, name: &str) -> Result<bool, PersistenceError> {
+        Ok(self.path(name)?.exists())
+    }
+
+    /// Saves the character whose entity is `character`, replacing any character saved with the
+    /// same name. The file is replaced atomically, so a crash while saving leaves the old save
+    /// intact.
+    ///
+    /// Components that refer to entities outside the character (such as a quest given by another
+    /// player) can't be spawned again later, so they aren't saved, rather than the whole character
+    /// failing to save.
+    pub fn save(
+        &self,
+        name: &str,
+        store: &ComponentStore,
+        character: Entity,
+    ) -> Result<(), PersistenceError> {
+        let path = self.path(name)?;
+        let entities = character_entities(store, character);
+        let (scene, dropped) = Scene::from_entities_dropping_dangling(store, &entities)?;
+        for component in dropped {
+            warn!(
+                "Not saving a {} component of {}, since it refers to an entity outside the character",
+                component, name
+            );
+        }
+        let src = format!("{} {}\n{}", HEADER, self.version, scene.to_sexpr()?);
+
+        let tmp = path.with_extension("tmp");
+        write(&tmp, src)?;
+        rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Loads the character with the given name, migrating it to the current version of the
+    /// schema. Returns `None` if no such character has been saved.
+    pub fn load(&self, name: &str) -> Result<Option<Scene>, PersistenceError> {
+        let src = match read_to_string(self.path(name)?) {
+            Ok(src) => src,
+            Err(ref err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut lines = src.splitn(2, '\n');
+        let version = lines
+            .next()
+            .and_then(|line| line.strip_prefix(HEADER))
+            .and_then(|version| version.trim().parse::<u32>().ok())
+            .ok_or(PersistenceError::NotACharacter)?;
+        if version > self.version {
+            return Err(PersistenceError::TooNew {
+                version,
+                supported: self.version,
+            });
+        }
+
+        let mut scene = Scene::parse(lines.next().unwrap_or(""))?;
+        for from in version..self.version {
+            let migration = self
+                .migrations
+                .get(&from)
+                .ok_or(PersistenceError::MissingMigration(from))?;
+            migration(&mut scene).map_err(|msg| PersistenceError::Migration { from, msg })?;
+        }
+        if scene.entities.is_empty() {
+            return Err(PersistenceError::NotACharacter);
+        }
+        scene.check_entities()?;
+        Ok(Some(scene))
+    }
+
+    /// Loads the character with the given name and spawns it into the store, returning the
+    /// character's entity. Returns `None` if no such character has been saved.
+    pub fn spawn(
+        &self,
+        name: &str,
+        store: &mut ComponentStore,
+    ) -> Result<Option<Entity>, PersistenceError> {
+        match self.load(name)? {
+            Some(scene) => Ok(Some(scene.spawn(store)?[0])),
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes the character with the given name, if it has been saved.
+    pub fn delete(&self, name: &str) -> Result<(), PersistenceError> {
+        match remove_file(self.path(name)?) {
+            Err(ref err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => Ok(result?),
+        }
+    }
+
+    /// Returns the path of the file the character with the given name is saved in. Names are
+    /// limited to letters, digits, `-`, and `_`, so that they can't escape the directory.
+    fn path(&self, name: &str) -> Result<PathBuf, PersistenceError> {
+        if is_valid_name(name) {
+            Ok(self.dir.join(name).with_extension("character"))
+        } else {
+            Err(PersistenceError::InvalidName(name.to_string()))
+        }
+    }
+}
+
+impl Debug for CharacterStore {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("CharacterStore")
+            .field("dir", &self.dir)
+            .field("version", &self.version)
+            .field("migrations", &self.migrations.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Returns whether a name can be used for a character (or an account): it must be non-empty, and
+/// only contain ASCII letters and digits, `-`, and `_`.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Returns the entities that make up a character: the character's own entity first, followed by
+/// the living entities it owns, directly or indirectly.
+pub fn character_entities(store: &ComponentStore, character: Entity) -> Vec<Entity> {
+    let mut entities = vec![character];
+    let mut seen = entities.iter().cloned().collect::<HashSet<_>>();
+    let mut i = 0;
+    while i < entities.len() {
+        let owner = entities[i];
+        for entity in store.iter_entities() {
+            let owned = store.is_alive(entity)
+                && store.get_component::<Owner>(entity).map(|o| o.0) == Some(owner);
+            if owned && seen.insert(entity) {
+                entities.push(entity);
+            }
+        }
+        i += 1;
+    }
+    entities
+}
+
+/// An error saving or loading a character.
+#[derive(Debug)]
+pub enum PersistenceError {
+    /// The character's name can't be used as a file name.
+    InvalidName(String),
+
+    /// The file couldn't be read or written.
+    Io(IoError),
+
+    /// The migration from the given version of the schema failed.
+    Migration {
+        /// The version being migrated from.
+        from: u32,
+
+        /// What went wrong.
+        msg: String,
+    },
+
+    /// There is no migration from the given version of the schema.
+    MissingMigration(u32),
+
+    /// The file isn't a saved character.
+    NotACharacter,
+
+    /// The character couldn't be converted to or from a scene.
+    Scene(SceneError),
+
+    /// The character was saved with a newer version of the schema than the store supports.
+    TooNew {
+        /// The version the character was saved with.
+        version: u32,
+
+        /// The version the store supports.
+        supported: u32,
+    },
+}
+
+impl Display for PersistenceError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            PersistenceError::InvalidName(name) => write!(fmt, "Invalid character name: {}", name),
+            PersistenceError::Io(err) => write!(fmt, "{}", err),
+            PersistenceError::Migration { from, msg } => write!(
+                fmt,
+                "Migrating from version {} to {} failed: {}",
+                from,
+                from + 1,
+                msg
+            ),
+            PersistenceError::MissingMigration(from) => {
+                write!(fmt, "No migration from version {}", from)
+            }
+            PersistenceError::NotACharacter => write!(fmt, "Not a saved character"),
+            PersistenceError::Scene(err) => write!(fmt, "{}", err),
+            PersistenceError::TooNew { version, supported } => write!(
+                fmt,
+                "The character was saved with version {}, but only versions up to {} are supported",
+                version, supported
+            ),
+        }
+    }
+}
+
+impl Error for PersistenceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PersistenceError::Io(err) => Some(err),
+            PersistenceError::Scene(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<IoError> for PersistenceError {
+    fn from(err: IoError) -> PersistenceError {
+        PersistenceError::Io(err)
+    }
+}
+
+impl From<SceneError> for PersistenceError {
+    fn from(err: SceneError) -> PersistenceError {
+        PersistenceError::Scene(err)
+    }
+}
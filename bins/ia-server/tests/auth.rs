@@ -0,0 +1,176 @@
+use ia_server::auth::{
+    AccountProvider, AuthError, Authenticator, LocalAccounts, RateLimiter, Sessions,
+};
+use protocol::Credentials;
+use std::{
+    collections::HashMap,
+    fs::{read_to_string, write},
+    net::{IpAddr, Ipv4Addr},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use crate::common::TempPath;
+
+const ALICE: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+const MALLORY: IpAddr = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+#[test]
+fn local_accounts() {
+    let file = TempPath::new("local-accounts");
+    let mut accounts = LocalAccounts::open(&file.0).unwrap();
+    accounts.create("alice", "hunter2").unwrap();
+    accounts.create("bob", "correct horse").unwrap();
+    match accounts.create("alice", "again") {
+        Err(AuthError::AccountExists(_)) => {}
+        result => panic!("expected an existing account, got {:?}", result),
+    }
+    match accounts.create("../alice", "hunter2") {
+        Err(AuthError::InvalidName(_)) => {}
+        result => panic!("expected an invalid name, got {:?}", result),
+    }
+
+    // Accounts are kept in the file, without their passwords.
+    let accounts = LocalAccounts::open(&file.0).unwrap();
+    assert_eq!(accounts.names().collect::<Vec<_>>(), vec!["alice", "bob"]);
+    assert!(accounts.check_password("alice", "hunter2").unwrap());
+    assert!(!accounts.check_password("alice", "hunter3").unwrap());
+    assert!(!accounts.check_password("carol", "hunter2").unwrap());
+    assert!(!read_to_string(&file.0).unwrap().contains("hunter2"));
+
+    let mut accounts = accounts;
+    accounts.set_password("alice", "hunter3").unwrap();
+    assert!(accounts.check_password("alice", "hunter3").unwrap());
+    assert!(accounts.remove("bob").unwrap());
+    assert!(!accounts.remove("bob").unwrap());
+    let accounts = LocalAccounts::open(&file.0).unwrap();
+    assert_eq!(accounts.names().collect::<Vec<_>>(), vec!["alice"]);
+
+    write(&file.0, "alice\n").unwrap();
+    match LocalAccounts::open(&file.0) {
+        Err(AuthError::InvalidAccountsFile { line: 1 }) => {}
+        result => panic!("expected an invalid file, got {:?}", result),
+    }
+}
+
+#[test]
+fn sessions_expire() {
+    let mut sessions = Sessions::new(Duration::from_secs(60));
+    let now = Instant::now();
+    let token = sessions.issue("alice", now);
+    let other = sessions.issue("alice", now);
+    assert_ne!(token, other);
+    assert_eq!(sessions.check(&token, now), Some("alice"));
+    assert_eq!(sessions.check(&token, now + Duration::from_secs(60)), None);
+    assert_eq!(sessions.check("nonsense", now), None);
+
+    sessions.revoke(&token);
+    assert_eq!(sessions.check(&token, now), None);
+    sessions.revoke_all("alice");
+    assert_eq!(sessions.check(&other, now), None);
+}
+
+#[test]
+fn rate_limits_failures() {
+    let mut limiter = RateLimiter::new(2, Duration::from_secs(10));
+    let now = Instant::now();
+    limiter.record_failure(MALLORY, now);
+    assert!(!limiter.is_limited(MALLORY, now));
+    limiter.record_failure(MALLORY, now + Duration::from_secs(5));
+    assert!(limiter.is_limited(MALLORY, now + Duration::from_secs(5)));
+    assert!(!limiter.is_limited(ALICE, now + Duration::from_secs(5)));
+
+    // Failures are forgotten once they're older than the window.
+    assert!(!limiter.is_limited(MALLORY, now + Duration::from_secs(10)));
+    assert!(!limiter.is_limited(MALLORY, now + Duration::from_secs(15)));
+}
+
+#[test]
+fn rate_limiter_forgets_addresses() {
+    let mut limiter = RateLimiter::new(2, Duration::from_secs(10));
+    let now = Instant::now();
+    for i in 0..=255 {
+        limiter.record_failure(IpAddr::V4(Ipv4Addr::new(10, 0, 1, i)), now);
+    }
+    assert_eq!(limiter.num_addresses(), 256);
+
+    // Addresses that haven't failed within the window are forgotten without being checked again.
+    limiter.record_failure(MALLORY, now + Duration::from_secs(5));
+    assert_eq!(limiter.num_addresses(), 257);
+    limiter.record_failure(ALICE, now + Duration::from_secs(12));
+    assert_eq!(limiter.num_addresses(), 2);
+    assert!(!limiter.is_limited(MALLORY, now + Duration::from_secs(12)));
+    limiter.record_failure(MALLORY, now + Duration::from_secs(13));
+    assert!(limiter.is_limited(MALLORY, now + Duration::from_secs(13)));
+}
+
+#[test]
+fn login() {
+    let file = TempPath::new("login");
+    let mut accounts = LocalAccounts::open(&file.0).unwrap();
+    accounts.create("alice", "hunter2").unwrap();
+    let mut auth = Authenticator::new(accounts).with_rate_limit(3, Duration::from_secs(60));
+
+    let password = Credentials::Password("hunter2".to_string());
+    let token = auth.login(ALICE, "alice", &password).unwrap();
+    match auth.login(ALICE, "bob", &password) {
+        Err(AuthError::WrongCredentials) => {}
+        result => panic!("expected wrong credentials, got {:?}", result),
+    }
+
+    // Tokens only work for the player they were issued to, and are replaced when used.
+    let wrong = auth.login(ALICE, "bob", &Credentials::Token(token.clone()));
+    assert!(wrong.is_err());
+    let new_token = auth
+        .login(ALICE, "alice", &Credentials::Token(token.clone()))
+        .unwrap();
+    assert!(auth
+        .login(ALICE, "alice", &Credentials::Token(token))
+        .is_err());
+    assert_eq!(
+        auth.sessions_mut().check(&new_token, Instant::now()),
+        Some("alice")
+    );
+
+    // After too many failures, even the right password doesn't work.
+    match auth.login(ALICE, "alice", &password) {
+        Err(AuthError::RateLimited) => {}
+        result => panic!("expected to be rate-limited, got {:?}", result),
+    }
+    assert!(auth.login(MALLORY, "alice", &password).is_ok());
+}
+
+#[test]
+fn logins_finish_later() {
+    let file = TempPath::new("logins-finish-later");
+    let mut accounts = LocalAccounts::open(&file.0).unwrap();
+    accounts.create("alice", "hunter2").unwrap();
+    let mut auth = Authenticator::new(accounts).with_rate_limit(2, Duration::from_secs(60));
+
+    let password = |password: &str| Credentials::Password(password.to_string());
+    let ok = auth.start_login(ALICE, "alice", password("hunter2"));
+    let unknown = auth.start_login(MALLORY, "carol", password("hunter2"));
+    let wrong = auth.start_login(MALLORY, "alice", password("hunter3"));
+    // Logins that are still being checked count against the rate limit.
+    let limited = auth.start_login(MALLORY, "alice", password("hunter2"));
+
+    let mut results = HashMap::new();
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while results.len() < 4 && Instant::now() < deadline {
+        results.extend(auth.finished_logins());
+        sleep(Duration::from_millis(10));
+    }
+    assert!(results[&ok].is_ok());
+    for login in &[unknown, wrong] {
+        match results[login] {
+            Err(AuthError::WrongCredentials) => {}
+            ref result => panic!("expected wrong credentials, got {:?}", result),
+        }
+    }
+    match results[&limited] {
+        Err(AuthError::RateLimited) => {}
+        ref result => panic!("expected to be rate-limited, got {:?}", result),
+    }
+}
@@ -0,0 +1,183 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use ia_server::Server;
+use protocol::{
+    from_bytes, to_bytes,
+    udp::{Channel, Config, DisconnectReason, Endpoint, Event},
+    ClientMessage, EncodedDelta, ServerMessage,
+};
+use std::{
+    collections::VecDeque,
+    env::temp_dir,
+    fs::{remove_dir_all, remove_file},
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::channel,
+        Arc,
+    },
+    thread::{sleep, spawn, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the server before failing a test.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A path in the temporary directory, which is removed (whether it's a file or a directory) when
+/// this is dropped.
+pub struct TempPath(pub PathBuf);
+
+impl TempPath {
+    /// Returns a path that's unique to the test process, removing anything already there.
+    pub fn new(name: &str) -> TempPath {
+        let path = temp_dir().join(format!("ia-server-{}-{}", name, process::id()));
+        remove(&path);
+        TempPath(path)
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
+
+/// Removes a file or directory, if it exists.
+fn remove(path: &Path) {
+    if path.is_dir() {
+        let _ = remove_dir_all(path);
+    } else {
+        let _ = remove_file(path);
+    }
+}
+
+/// A server running on another thread, which is stopped when this is dropped.
+pub struct TestServer {
+    pub addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl TestServer {
+    /// Runs the server `make` creates, on another thread.
+    pub fn start<F: 'static + Send + FnOnce() -> Server>(make: F) -> TestServer {
+        let stop = Arc::new(AtomicBool::new(false));
+        let (send, recv) = channel();
+        let thread = spawn({
+            let stop = stop.clone();
+            move || {
+                let mut server = make();
+                send.send(server.local_addr().unwrap()).unwrap();
+                server.run(&stop);
+            }
+        });
+        TestServer {
+            addr: recv.recv().unwrap(),
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// A connection to the server over UDP, which sends and receives messages as they are, with no
+/// engine of its own.
+#[derive(Debug)]
+pub struct TestClient {
+    endpoint: Endpoint<UdpSocket>,
+    server: SocketAddr,
+
+    /// The messages that have arrived but haven't been received yet, and the reason the
+    /// connection was closed, if it was.
+    received: VecDeque<Result<ServerMessage, DisconnectReason>>,
+
+    /// The tick of the newest update that has been received.
+    last_update: u64,
+}
+
+impl TestClient {
+    pub fn connect(server: SocketAddr) -> TestClient {
+        TestClient::connect_with(server, Config::default())
+    }
+
+    pub fn connect_with(server: SocketAddr, config: Config) -> TestClient {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_nonblocking(true).unwrap();
+        TestClient {
+            endpoint: Endpoint::client(socket, server, config, Instant::now()),
+            server,
+            received: VecDeque::new(),
+            last_update: 0,
+        }
+    }
+
+    /// Sends a message on the reliable channel.
+    pub fn send(&mut self, msg: &ClientMessage) {
+        let msg = to_bytes(msg).unwrap();
+        self.endpoint
+            .send(self.server, Channel::Reliable, &msg)
+            .unwrap();
+        self.update();
+    }
+
+    /// Receives the next message, waiting for one to arrive, or the reason the connection was
+    /// closed.
+    pub fn recv(&mut self) -> Result<ServerMessage, DisconnectReason> {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if let Some(msg) = self.received.pop_front() {
+                return msg;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for a message");
+            sleep(Duration::from_millis(1));
+            self.update();
+        }
+    }
+
+    /// Receives the next update that's newer than the ones before it, and acknowledges it.
+    pub fn recv_update(&mut self) -> EncodedDelta {
+        loop {
+            match self.recv() {
+                Ok(ServerMessage::Update { tick, delta, .. }) if tick > self.last_update => {
+                    self.last_update = tick;
+                    let ack = to_bytes(&ClientMessage::Ack { tick }).unwrap();
+                    self.endpoint
+                        .send(self.server, Channel::Unreliable, &ack)
+                        .unwrap();
+                    return delta;
+                }
+                Ok(ServerMessage::Update { .. }) => {}
+                msg => panic!("expected an update, got {:?}", msg),
+            }
+        }
+    }
+
+    /// Closes the connection.
+    pub fn leave(mut self) {
+        self.endpoint.disconnect(self.server).unwrap();
+    }
+
+    /// Sends and receives packets.
+    fn update(&mut self) {
+        for event in self.endpoint.update(Instant::now()).unwrap() {
+            match event {
+                Event::Message { msg, .. } => {
+                    self.received.push_back(Ok(from_bytes(&msg).unwrap()))
+                }
+                Event::Disconnected(_, reason) => self.received.push_back(Err(reason)),
+                Event::Connected(_) => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,398 @@
+use assets::Assets;
+use ecstasy::{
+    components::{AlwaysRelevant, Name, Position},
+    quantize::{Codec, DecodeError, WorldBounds},
+    system_mut, Component, ComponentStore, Engine, Entity,
+};
+use ia_server::{
+    auth::{Authenticator, LocalAccounts},
+    movement::MovementRules,
+    persistence::CharacterStore,
+    Server,
+};
+use protocol::{
+    udp::{Config, DisconnectReason},
+    ClientMessage, Credentials, ServerMessage, PROTOCOL_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    net::SocketAddr,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+mod common;
+
+use crate::common::{TempPath, TestClient, TestServer, TIMEOUT};
+
+#[derive(Component, Debug, Deserialize, PartialEq, Serialize)]
+#[component(quantize)]
+struct Counter(u32);
+
+#[system_mut]
+fn CountUp(_entity: Entity, _dt: f32, counter: &mut Counter) {
+    counter.0 += 1;
+}
+
+/// Starts a server with a rock whose counter counts up, calling `setup` on it before it starts
+/// running.
+fn start_with<F: 'static + Send + FnOnce(&mut Server)>(setup: F) -> TestServer {
+    TestServer::start(move || {
+        let mut engine = Engine::new(Assets::new()).add_mut_pass(CountUp);
+        let rock = engine.store.new_entity();
+        engine.store.set_component(rock, Name("rock".to_string()));
+        engine.store.set_component(rock, Counter(0));
+
+        let mut server = Server::bind("127.0.0.1:0", engine, 60).unwrap();
+        server.allow_input::<Counter>();
+        server.codec_mut().register::<Counter>();
+        setup(&mut server);
+        server
+    })
+}
+
+fn start() -> TestServer {
+    start_with(|_| {})
+}
+
+/// Connects to the server and joins the game, returning the connection and the player's entity.
+fn join(addr: SocketAddr, name: &str) -> (TestClient, Entity) {
+    let (client, player, _) = login(addr, name, None).unwrap();
+    (client, player)
+}
+
+/// Connects to the server and joins the game with the given credentials, returning the
+/// connection, the player's entity, and the session token, or the reason the server rejected the
+/// client.
+fn login(
+    addr: SocketAddr,
+    name: &str,
+    credentials: Option<Credentials>,
+) -> Result<(TestClient, Entity, Option<String>), String> {
+    let mut client = TestClient::connect(addr);
+    client.send(&ClientMessage::Hello {
+        name: name.to_string(),
+        credentials,
+    });
+    loop {
+        match client.recv() {
+            Ok(ServerMessage::Welcome {
+                player,
+                tick_rate,
+                session,
+            }) => {
+                assert_eq!(tick_rate, 60);
+                return Ok((client, player, session));
+            }
+            Ok(ServerMessage::Rejected(reason)) => {
+                client.leave();
+                return Err(reason);
+            }
+            // Updates are sent on the unreliable channel, so they can overtake the welcome. None
+            // of them have been acknowledged, so the next one has the whole world anyway.
+            Ok(ServerMessage::Update { .. }) => {}
+            msg => panic!("expected a welcome, got {:?}", msg),
+        }
+    }
+}
+
+/// Returns a codec with the same types registered as the test server's.
+fn codec() -> Codec {
+    let mut codec = Codec::new(WorldBounds::default());
+    codec.register::<Counter>();
+    codec
+}
+
+/// Applies updates from the server to `store` until `done` returns true.
+fn sync_until<F: FnMut(&ComponentStore) -> bool>(
+    client: &mut TestClient,
+    store: &mut ComponentStore,
+    mut done: F,
+) {
+    let codec = codec();
+    let deadline = Instant::now() + TIMEOUT;
+    while !done(store) {
+        assert!(Instant::now() < deadline, "timed out waiting for updates");
+        let delta = client.recv_update();
+        delta.decode(&codec).unwrap().apply(store).unwrap();
+    }
+}
+
+#[test]
+fn join_and_play() {
+    let server = start();
+    let (mut client, player) = join(server.addr, "alice");
+
+    // The first update contains the whole world, and later ones keep it up to date.
+    let mut store = ComponentStore::new();
+    sync_until(&mut client, &mut store, |store| {
+        store.find_by_name("alice") == Some(player)
+    });
+    let rock = store.find_by_name("rock").unwrap();
+    let count = store.get_component::<Counter>(rock).unwrap().0;
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Counter>(rock).unwrap().0 > count + 5
+    });
+
+    // Inputs are set on the player's entity, and simulated by the server.
+    client.send(&ClientMessage::Input {
+        tick: 0,
+        view_tick: 0.0,
+        components: vec![Box::new(Counter(1000))],
+    });
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Counter>(player).map(|c| c.0 > 1000) == Some(true)
+    });
+
+    client.leave();
+}
+
+#[test]
+fn inputs_are_restricted() {
+    let server = start();
+    let (mut client, player) = join(server.addr, "alice");
+
+    // Clients can't set types of components the server doesn't allow, even alongside ones it
+    // does.
+    client.send(&ClientMessage::Input {
+        tick: 0,
+        view_tick: 0.0,
+        components: vec![Box::new(Counter(1000)), Box::new(Name("bob".to_string()))],
+    });
+    client.send(&ClientMessage::Input {
+        tick: 1,
+        view_tick: 0.0,
+        components: vec![Box::new(Counter(2000))],
+    });
+
+    let mut store = ComponentStore::new();
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Counter>(player).is_some()
+    });
+    assert!(store.get_component::<Counter>(player).unwrap().0 >= 2000);
+    assert_eq!(store.find_by_name("alice"), Some(player));
+    assert_eq!(store.find_by_name("bob"), None);
+    client.leave();
+}
+
+#[test]
+fn updates_are_quantized() {
+    let server = start();
+    let (mut client, _) = join(server.addr, "alice");
+
+    // The rock's counter is quantized, so a codec that doesn't know the type can't read it.
+    let delta = client.recv_update();
+    match delta.decode(&Codec::new(WorldBounds::default())) {
+        Err(DecodeError::UnknownType(_)) => {}
+        result => panic!("expected an unknown type, got {:?}", result),
+    }
+
+    let mut store = ComponentStore::new();
+    delta.decode(&codec()).unwrap().apply(&mut store).unwrap();
+    let rock = store.find_by_name("rock").unwrap();
+    assert!(store.get_component::<Counter>(rock).is_some());
+    client.leave();
+}
+
+#[test]
+fn players_see_each_other_leave() {
+    let server = start();
+    let (alice, alice_player) = join(server.addr, "alice");
+    let (mut bob, _) = join(server.addr, "bob");
+
+    let mut store = ComponentStore::new();
+    sync_until(&mut bob, &mut store, |store| {
+        store.find_by_name("alice") == Some(alice_player)
+    });
+
+    alice.leave();
+    sync_until(&mut bob, &mut store, |store| !store.is_alive(alice_player));
+    assert_eq!(store.find_by_name("alice"), None);
+}
+
+#[test]
+fn interest_management() {
+    let server = start_with(|server| {
+        server.set_interest_radius(Some(10.0));
+        let store = &mut server.engine_mut().store;
+        for &(name, x) in &[("near", 1.0), ("far", 100.0), ("beacon", 200.0)] {
+            let entity = store.new_entity();
+            store.set_component(entity, Name(name.to_string()));
+            store.set_component(entity, Position::new(x, 0.0, 0.0));
+        }
+        let beacon = store.find_by_name("beacon").unwrap();
+        store.set_component(beacon, AlwaysRelevant);
+    });
+    let (mut client, player) = join(server.addr, "alice");
+    let mut store = ComponentStore::new();
+
+    // Only nearby entities, always-relevant ones, and ones with no position are sent.
+    client.send(&ClientMessage::Input {
+        tick: 0,
+        view_tick: 0.0,
+        components: vec![Box::new(Position::new(0.0, 0.0, 0.0))],
+    });
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Position>(player).is_some() && store.find_by_name("far").is_none()
+    });
+    assert!(store.find_by_name("near").is_some());
+    assert!(store.find_by_name("beacon").is_some());
+    assert!(store.find_by_name("rock").is_some());
+
+    // Moving brings entities into scope, and takes others out of it.
+    client.send(&ClientMessage::Input {
+        tick: 1,
+        view_tick: 0.0,
+        components: vec![Box::new(Position::new(95.0, 0.0, 0.0))],
+    });
+    sync_until(&mut client, &mut store, |store| {
+        store.find_by_name("far").is_some() && store.find_by_name("near").is_none()
+    });
+    // Positions are quantized, so they're only sent to within the codec's precision.
+    let far = store.find_by_name("far").unwrap();
+    let x = store.get_component::<Position>(far).unwrap().0.x;
+    assert!((x - 100.0).abs() <= WorldBounds::default().precision);
+
+    // Bandwidth statistics can be fetched without joining.
+    let mut debug = TestClient::connect(server.addr);
+    debug.send(&ClientMessage::GetStats);
+    match debug.recv() {
+        Ok(ServerMessage::Stats(stats)) => {
+            let alice = stats
+                .iter()
+                .find(|client| client.player == Some(player))
+                .unwrap();
+            assert_eq!(alice.name, Some("alice".to_string()));
+            assert_eq!(alice.entities_in_scope, 4);
+            assert!(alice.bytes_sent > 0 && alice.bytes_received > 0);
+            assert!(stats.iter().any(|client| client.player.is_none()));
+        }
+        msg => panic!("expected stats, got {:?}", msg),
+    }
+    debug.leave();
+    client.leave();
+}
+
+#[test]
+fn teleports_are_cut_short() {
+    let server = start_with(|server| {
+        server.set_movement_rules(Some(MovementRules {
+            max_speed: 60.0,
+            tolerance: 0.0,
+        }))
+    });
+    let (mut client, player) = join(server.addr, "mallory");
+    let mut store = ComponentStore::new();
+
+    for &(tick, x) in &[(0, 0.0), (1, 100.0)] {
+        client.send(&ClientMessage::Input {
+            tick,
+            view_tick: 0.0,
+            components: vec![Box::new(Position::new(x, 0.0, 0.0))],
+        });
+    }
+
+    // At 60 ticks per second, the player can only move a unit per tick.
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Position>(player).map(|p| p.0.x) > Some(0.5)
+    });
+    let x = store.get_component::<Position>(player).unwrap().0.x;
+    assert!(
+        (x - 1.0).abs() <= WorldBounds::default().precision,
+        "moved to {}",
+        x
+    );
+    client.leave();
+}
+
+#[test]
+fn characters_are_saved() {
+    let dir = TempPath::new("localhost");
+    let server = start_with({
+        let dir = dir.0.clone();
+        move |server| server.set_character_store(Some(CharacterStore::open(dir, 1).unwrap()))
+    });
+
+    let (mut client, player) = join(server.addr, "alice");
+    let mut store = ComponentStore::new();
+    client.send(&ClientMessage::Input {
+        tick: 0,
+        view_tick: 0.0,
+        components: vec![Box::new(Counter(1000))],
+    });
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Counter>(player).is_some()
+    });
+    client.leave();
+
+    // The character is saved when the player leaves, and loaded when they come back.
+    let characters = CharacterStore::open(&dir.0, 1).unwrap();
+    let deadline = Instant::now() + TIMEOUT;
+    while !characters.exists("alice").unwrap() {
+        assert!(Instant::now() < deadline, "timed out waiting for a save");
+        sleep(Duration::from_millis(10));
+    }
+    let (mut client, player) = join(server.addr, "alice");
+    let mut store = ComponentStore::new();
+    sync_until(&mut client, &mut store, |store| {
+        store.get_component::<Counter>(player).map(|c| c.0 > 1000) == Some(true)
+    });
+    client.leave();
+    drop(server);
+}
+
+#[test]
+fn logging_in() {
+    let file = TempPath::new("accounts");
+    let mut accounts = LocalAccounts::open(&file.0).unwrap();
+    accounts.create("alice", "hunter2").unwrap();
+    accounts.create("bob", "correct horse").unwrap();
+    let server = start_with(|server| {
+        let auth = Authenticator::new(accounts).with_rate_limit(2, Duration::from_secs(60));
+        server.set_authenticator(Some(auth));
+    });
+
+    assert!(login(server.addr, "alice", None).is_err());
+    let password = Credentials::Password("hunter2".to_string());
+    let (client, _, session) = login(server.addr, "alice", Some(password)).unwrap();
+
+    // Each player can only be in the game once.
+    let token = Credentials::Token(session.unwrap());
+    assert!(login(server.addr, "alice", Some(token.clone())).is_err());
+    client.leave();
+
+    // Once alice has left, her session can be used to come back.
+    let deadline = Instant::now() + TIMEOUT;
+    let (client, _, _) = loop {
+        match login(server.addr, "alice", Some(token.clone())) {
+            Ok(joined) => break joined,
+            Err(_) => assert!(Instant::now() < deadline, "timed out waiting to rejoin"),
+        }
+        sleep(Duration::from_millis(10));
+    };
+    client.leave();
+
+    // Guessing passwords gets an address locked out.
+    let wrong = Credentials::Password("hunter3".to_string());
+    for _ in 0..2 {
+        assert!(login(server.addr, "bob", Some(wrong.clone())).is_err());
+    }
+    let password = Credentials::Password("correct horse".to_string());
+    let reason = login(server.addr, "bob", Some(password)).unwrap_err();
+    assert!(reason.contains("Too many"), "rejected with {:?}", reason);
+    drop(server);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let server = start();
+    let config = Config {
+        version: PROTOCOL_VERSION + 1,
+        ..Config::default()
+    };
+    let mut client = TestClient::connect_with(server.addr, config);
+    match client.recv() {
+        Err(DisconnectReason::Rejected(_)) => {}
+        msg => panic!("expected a rejection, got {:?}", msg),
+    }
+}
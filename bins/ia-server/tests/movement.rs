@@ -0,0 +1,110 @@
+use assets::Assets;
+use cgmath::{Point3, Vector3};
+use ecstasy::{
+    components::{Collider, Position},
+    ComponentStore, Engine,
+};
+use ia_server::{
+    movement::{validate_move, MovementRules, PositionHistory},
+    Server,
+};
+
+const RULES: MovementRules = MovementRules {
+    max_speed: 10.0,
+    tolerance: 0.0,
+};
+
+fn x_of(position: Option<Position>) -> f32 {
+    position.unwrap().0.x
+}
+
+#[test]
+fn limits_speed() {
+    let mut store = ComponentStore::new();
+    let player = store.new_entity();
+
+    // Players without a position can be placed anywhere.
+    let to = Position::new(100.0, 0.0, 0.0);
+    assert_eq!(validate_move(&store, player, to, 0.1, &RULES), Some(to));
+
+    store.set_component(player, to);
+    let to = Position::new(100.5, 0.0, 0.0);
+    assert_eq!(validate_move(&store, player, to, 0.1, &RULES), Some(to));
+    let moved = validate_move(&store, player, Position::new(0.0, 0.0, 0.0), 0.1, &RULES);
+    assert!((x_of(moved) - 99.0).abs() < 1e-4);
+
+    let to = Position::new(f32::NAN, 0.0, 0.0);
+    assert_eq!(validate_move(&store, player, to, 0.1, &RULES), None);
+}
+
+#[test]
+fn stops_at_colliders() {
+    let mut store = ComponentStore::new();
+    let player = store.new_entity();
+    store.set_component(player, Position::new(0.0, 0.0, 0.0));
+    store.set_component(player, Collider(0.5));
+    let wall = store.new_entity();
+    store.set_component(wall, Position::new(5.0, 0.0, 0.0));
+    store.set_component(wall, Collider(1.0));
+
+    let to = Position::new(8.0, 0.0, 0.0);
+    let moved = validate_move(&store, player, to, 1.0, &RULES);
+    assert!((x_of(moved) - 3.5).abs() < 1e-4);
+
+    // Moving past the wall is fine.
+    let to = Position::new(0.0, 8.0, 0.0);
+    assert_eq!(validate_move(&store, player, to, 1.0, &RULES), Some(to));
+
+    // Players already overlapping something can move out of it.
+    store.set_component(player, Position::new(4.0, 0.0, 0.0));
+    let to = Position::new(3.0, 0.0, 0.0);
+    assert_eq!(validate_move(&store, player, to, 1.0, &RULES), Some(to));
+}
+
+#[test]
+fn rewinds_positions() {
+    let mut store = ComponentStore::new();
+    let shooter = store.new_entity();
+    store.set_component(shooter, Position::new(0.0, 0.0, 0.0));
+    store.set_component(shooter, Collider(0.5));
+    let target = store.new_entity();
+    store.set_component(target, Collider(0.5));
+
+    // The target moves along the y axis, one unit per tick.
+    let mut history = PositionHistory::new(4);
+    for tick in 1..=6 {
+        store.set_component(target, Position::new(10.0, tick as f32, 0.0));
+        history.record(tick, &store);
+    }
+    assert_eq!(history.oldest_tick(), Some(3));
+    assert_eq!(history.newest_tick(), Some(6));
+
+    let y_at = |tick: f32| history.positions_at(tick)[&target].0.y;
+    assert_eq!(y_at(4.0), 4.0);
+    assert_eq!(y_at(4.25), 4.25);
+    assert_eq!(y_at(0.0), 3.0);
+    assert_eq!(y_at(10.0), 6.0);
+
+    // A shot at where the target was on tick 4 hits it then, but not now.
+    let origin = Point3::new(0.0, 0.0, 0.0);
+    let direction = Vector3::new(10.0, 4.0, 0.0);
+    let hits = history.raycast_at(&store, 4.0, origin, direction, 100.0, shooter);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].0, target);
+    assert!(history
+        .raycast_at(&store, 6.0, origin, direction, 100.0, shooter)
+        .is_empty());
+
+    // Nor does it hit if it's too short.
+    assert!(history
+        .raycast_at(&store, 4.0, origin, direction, 5.0, shooter)
+        .is_empty());
+}
+
+#[test]
+#[should_panic(expected = "can't be inputs")]
+fn colliders_cant_be_inputs() {
+    // Otherwise players could shrink their colliders to squeeze past others.
+    let mut server = Server::bind("127.0.0.1:0", Engine::new(Assets::new()), 60).unwrap();
+    server.allow_input::<Collider>();
+}
@@ -0,0 +1,223 @@
+use ecstasy::{
+    components::{Name, Owner},
+    scene::Scene,
+    Component, ComponentStore, Entity,
+};
+use ia_server::persistence::{character_entities, CharacterStore, PersistenceError};
+use serde::{Deserialize, Serialize};
+use std::fs::write;
+
+mod common;
+
+use crate::common::TempPath;
+
+#[derive(Component, Debug, Deserialize, PartialEq, Serialize)]
+struct Coins(u32);
+
+#[derive(Component, Debug, Deserialize, PartialEq, Serialize)]
+struct Gold(u32);
+
+#[derive(Component, Debug, Deserialize, PartialEq, Serialize)]
+struct Quest {
+    stage: u32,
+    #[component(entity)]
+    giver: Entity,
+}
+
+/// Creates a character with a sword, a bag with a potion in it, and a quest given by the bag.
+fn make_character(store: &mut ComponentStore) -> Entity {
+    let character = store.new_entity();
+    store.set_component(character, Name("alice".to_string()));
+    store.set_component(character, Coins(3));
+
+    let sword = store.new_entity();
+    store.set_component(sword, Name("sword".to_string()));
+    store.set_component(sword, Owner(character));
+    let bag = store.new_entity();
+    store.set_component(bag, Name("bag".to_string()));
+    store.set_component(bag, Owner(character));
+    let potion = store.new_entity();
+    store.set_component(potion, Name("potion".to_string()));
+    store.set_component(potion, Owner(bag));
+
+    let quest = store.new_entity();
+    store.set_component(quest, Owner(character));
+    store.set_component(
+        quest,
+        Quest {
+            stage: 2,
+            giver: bag,
+        },
+    );
+    character
+}
+
+/// The migration from version 1, in which money was counted in coins, to version 2, in which it's
+/// counted in gold.
+fn coins_to_gold(scene: &mut Scene) -> Result<(), String> {
+    for entity in &mut scene.entities {
+        for component in &mut entity.components {
+            if let Some(&Coins(coins)) = component.as_any().downcast_ref() {
+                *component = Box::new(Gold(coins * 100));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn round_trip() {
+    let dir = TempPath::new("round-trip");
+    let characters = CharacterStore::open(&dir.0, 1).unwrap();
+    let mut store = ComponentStore::new();
+    let bystander = store.new_entity();
+    store.set_component(bystander, Name("bob".to_string()));
+    let character = make_character(&mut store);
+    assert_eq!(character_entities(&store, character).len(), 5);
+
+    assert!(!characters.exists("alice").unwrap());
+    assert!(characters.load("alice").unwrap().is_none());
+    characters.save("alice", &store, character).unwrap();
+    assert!(characters.exists("alice").unwrap());
+
+    // Only the character and what it owns are spawned, with their references intact.
+    let mut copy = ComponentStore::new();
+    let spawned = characters.spawn("alice", &mut copy).unwrap().unwrap();
+    assert_eq!(copy.find_by_name("alice"), Some(spawned));
+    assert_eq!(copy.find_by_name("bob"), None);
+    assert_eq!(copy.get_component::<Coins>(spawned), Some(&Coins(3)));
+    let bag = copy.find_by_name("bag").unwrap();
+    assert_eq!(copy.get_component::<Owner>(bag), Some(&Owner(spawned)));
+    let potion = copy.find_by_name("potion").unwrap();
+    assert_eq!(copy.get_component::<Owner>(potion), Some(&Owner(bag)));
+    let quests = character_entities(&copy, spawned)
+        .into_iter()
+        .filter_map(|entity| copy.get_component::<Quest>(entity))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        quests,
+        vec![&Quest {
+            stage: 2,
+            giver: bag
+        }]
+    );
+
+    characters.delete("alice").unwrap();
+    assert!(!characters.exists("alice").unwrap());
+}
+
+#[test]
+fn outside_references_are_dropped() {
+    let dir = TempPath::new("outside-references");
+    let characters = CharacterStore::open(&dir.0, 1).unwrap();
+    let mut store = ComponentStore::new();
+    let bystander = store.new_entity();
+    store.set_component(bystander, Name("bob".to_string()));
+    let character = make_character(&mut store);
+    let quest = store.new_entity();
+    store.set_component(quest, Owner(character));
+    store.set_component(
+        quest,
+        Quest {
+            stage: 1,
+            giver: bystander,
+        },
+    );
+
+    // The quest given by someone outside the character is lost, but the rest is saved.
+    characters.save("alice", &store, character).unwrap();
+    let mut copy = ComponentStore::new();
+    let spawned = characters.spawn("alice", &mut copy).unwrap().unwrap();
+    assert_eq!(copy.get_component::<Coins>(spawned), Some(&Coins(3)));
+    let bag = copy.find_by_name("bag").unwrap();
+    let quests = character_entities(&copy, spawned)
+        .into_iter()
+        .filter_map(|entity| copy.get_component::<Quest>(entity))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        quests,
+        vec![&Quest {
+            stage: 2,
+            giver: bag
+        }]
+    );
+}
+
+#[test]
+fn migrations() {
+    let dir = TempPath::new("migrations");
+    let mut store = ComponentStore::new();
+    let character = make_character(&mut store);
+    CharacterStore::open(&dir.0, 1)
+        .unwrap()
+        .save("alice", &store, character)
+        .unwrap();
+
+    // Without a migration, the character can't be loaded...
+    let characters = CharacterStore::open(&dir.0, 2).unwrap();
+    match characters.load("alice") {
+        Err(PersistenceError::MissingMigration(1)) => {}
+        result => panic!("expected a missing migration, got {:?}", result),
+    }
+
+    // ...but with one, it's brought up to date.
+    let characters = characters.with_migration(1, coins_to_gold);
+    let mut copy = ComponentStore::new();
+    let spawned = characters.spawn("alice", &mut copy).unwrap().unwrap();
+    assert_eq!(copy.get_component::<Gold>(spawned), Some(&Gold(300)));
+    assert_eq!(copy.get_component::<Coins>(spawned), None);
+
+    // Once saved again, the character no longer needs migrating, and older servers can't load it.
+    characters.save("alice", &copy, spawned).unwrap();
+    let characters = CharacterStore::open(&dir.0, 2).unwrap();
+    assert!(characters.load("alice").unwrap().is_some());
+    match CharacterStore::open(&dir.0, 1).unwrap().load("alice") {
+        Err(PersistenceError::TooNew {
+            version: 2,
+            supported: 1,
+        }) => {}
+        result => panic!("expected a newer version, got {:?}", result),
+    }
+}
+
+#[test]
+fn migrations_that_remove_the_character() {
+    let dir = TempPath::new("removed-character");
+    let mut store = ComponentStore::new();
+    let character = make_character(&mut store);
+    CharacterStore::open(&dir.0, 1)
+        .unwrap()
+        .save("alice", &store, character)
+        .unwrap();
+
+    // A migration that leaves no entities leaves nothing to spawn as the character.
+    let characters = CharacterStore::open(&dir.0, 2)
+        .unwrap()
+        .with_migration(1, |scene| {
+            scene.entities.clear();
+            Ok(())
+        });
+    let mut copy = ComponentStore::new();
+    match characters.spawn("alice", &mut copy) {
+        Err(PersistenceError::NotACharacter) => {}
+        result => panic!("expected not a character, got {:?}", result),
+    }
+}
+
+#[test]
+fn invalid_files() {
+    let dir = TempPath::new("invalid-files");
+    let characters = CharacterStore::open(&dir.0, 1).unwrap();
+    for name in &["", "../alice", "alice.character", "a b"] {
+        match characters.load(name) {
+            Err(PersistenceError::InvalidName(_)) => {}
+            result => panic!("expected an invalid name, got {:?}", result),
+        }
+    }
+
+    write(dir.0.join("mallory.character"), "(entities ())").unwrap();
+    match characters.load("mallory") {
+        Err(PersistenceError::NotACharacter) => {}
+        result => panic!("expected not a character, got {:?}", result),
+    }
+}
@@ -1,17 +1,25 @@
 use assets::{irb::IRB, Assets};
-use ecstasy::Engine;
+use ecstasy::{scene::Scene, Engine, System};
+use ia::net::Client;
 use libremexre::errors::Result;
 use log::info;
+use protocol::Credentials;
 use renderer::init_renderer;
+use std::{
+    fs::write,
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
-use winit::{Event, WindowEvent};
+use winit::{Event, EventsLoop, WindowEvent};
 
 fn main() -> Result<()> {
     let options = Options::from_args();
     libremexre::init_logger(options.verbose + 1, options.quiet);
 
     // Start the renderer, load the assets, and assemble the parts into the engine.
-    let (renderer, mut event_loop) = init_renderer!()?;
+    let (mut renderer, mut event_loop) = init_renderer!()?;
     let (assets, errs) = Assets::from_irb(IRB::load_from_file("")?, renderer.device());
     if !errs.is_empty() {
         let mut s = "Errors loading assets:".to_string();
@@ -21,26 +29,71 @@ fn main() -> Result<()> {
         }
         return Err(libremexre::err!("{}", s));
     }
-    let mut engine = Engine::new(assets).build_par_pass().add(renderer).finish();
 
-    let mut keep_running = true;
-    while keep_running {
-        event_loop.poll_events(|ev| {
-            if let Event::WindowEvent { event: ev, .. } = ev {
-                match ev {
-                    WindowEvent::CloseRequested => keep_running = false,
-                    ev => {
-                        info!("TODO: Handle event {:?}", ev);
-                    }
-                }
+    // When playing on a server, the world comes from the server, and is rendered after each tick.
+    if let Some(addr) = options.connect {
+        let engine = Engine::new(assets);
+        let mut client = match options.password {
+            Some(password) => Client::login(
+                addr.as_str(),
+                &options.name,
+                Credentials::Password(password),
+                engine,
+            )?,
+            None => Client::connect(addr.as_str(), &options.name, engine)?,
+        };
+        info!("Joined {} as {:?}", addr, client.player());
+        let tick_length = Duration::from_secs(1) / client.tick_rate();
+        let dt = (tick_length.as_nanos() as f32) / 1_000_000_000.0;
+        while poll_events(&mut event_loop) {
+            let start = Instant::now();
+            client.step(Vec::new())?;
+            renderer.run(&client.engine().store, dt);
+            if let Some(rest) = tick_length.checked_sub(start.elapsed()) {
+                sleep(rest);
             }
-        });
+        }
+        client.leave()?;
+        return Ok(());
+    }
+
+    let mut engine = Engine::new(assets).build_par_pass().add(renderer).finish();
+    if let Some(scene) = options.scene {
+        let _ = Scene::load(&engine.assets, &scene)?.spawn(&mut engine.store)?;
+    }
+    if options.record.is_some() {
+        engine.start_recording(options.checkpoint_interval)?;
+    }
+
+    while poll_events(&mut event_loop) {
         engine.run_once();
     }
 
+    if let Some(path) = options.record {
+        if let Some(recording) = engine.stop_recording() {
+            write(path, recording?.to_sexpr()?)?;
+        }
+    }
+
     Ok(())
 }
 
+/// Handles the events that have arrived, returning whether the game should keep running.
+fn poll_events(event_loop: &mut EventsLoop) -> bool {
+    let mut keep_running = true;
+    event_loop.poll_events(|ev| {
+        if let Event::WindowEvent { event: ev, .. } = ev {
+            match ev {
+                WindowEvent::CloseRequested => keep_running = false,
+                ev => {
+                    info!("TODO: Handle event {:?}", ev);
+                }
+            }
+        }
+    });
+    keep_running
+}
+
 #[derive(Debug, StructOpt)]
 struct Options {
     /// Silence all log output.
@@ -50,4 +103,30 @@ struct Options {
     /// Increase log verbosity (-v, -vv, -vvv, etc. supported).
     #[structopt(short = "v", long = "verbose", parse(from_occurrences))]
     verbose: usize,
+
+    /// The name of a scene in the asset bundle to spawn at startup.
+    #[structopt(long = "scene")]
+    scene: Option<String>,
+
+    /// Plays on the server at the given address, rather than locally.
+    #[structopt(long = "connect", raw(conflicts_with_all = r#"&["record", "scene"]"#))]
+    connect: Option<String>,
+
+    /// The name to play as, when playing on a server.
+    #[structopt(long = "name", default_value = "player")]
+    name: String,
+
+    /// The password to log in with, when playing on a server that requires logging in. This is
+    /// best given in the environment, where other users can't see it.
+    #[structopt(long = "password", env = "IA_PASSWORD")]
+    password: Option<String>,
+
+    /// Records the game to the given file, so it can be replayed with `ia-internal-debug-tool
+    /// replay`.
+    #[structopt(long = "record", parse(from_os_str))]
+    record: Option<PathBuf>,
+
+    /// When recording, how many ticks to run between saving hashes of the world.
+    #[structopt(long = "checkpoint-interval", default_value = "60")]
+    checkpoint_interval: usize,
 }
@@ -0,0 +1,456 @@
+//! Playing on a server.
+//!
+//! A `Client` joins a game run by `ia-server`, and keeps a copy of the world in an `Engine` of its
+//! own, which should contain the same systems as the server's (minus any that must only run on
+//! the server). Every tick, the player's inputs are sent to the server, tagged with an increasing
+//! number, and are applied to the local world straight away, so the player sees their own actions
+//! without waiting for the server. This is client-side prediction.
+//!
+//! Each update from the server says which of the inputs it has applied. When one arrives, the
+//! world is rolled back to the last state the server sent, the update is applied, and the inputs
+//! the server hasn't applied yet are applied again, each followed by a tick. If the prediction was
+//! wrong (e.g. because an input was lost, or another player got in the way), the world is thereby
+//! corrected to what the server says. Rolling back uses snapshots, so only components that opt into
+//! cloning are rolled back; systems that run on the client shouldn't change any others.
+//!
+//! Updates are sent over UDP, and may be lost or arrive out of order. The client acknowledges the
+//! updates it applies, and each update is the changes since the last one the client acknowledged,
+//! so a lost update is made up for by the next. Updates older than the last one applied are
+//! dropped.
+//!
+//! Other entities' `Position`s are not predicted. Instead, they are interpolated between the last
+//! few updates, a couple of ticks behind the server, so they move smoothly even when updates arrive
+//! unevenly.
+
+use ecstasy::{
+    components::Position,
+    quantize::{Codec, WorldBounds},
+    replay::{self, Input},
+    snapshot::Snapshot,
+    Component, Engine, Entity, SystemMut,
+};
+use protocol::{
+    from_bytes, to_bytes,
+    udp::{Channel, Config, DisconnectReason, Endpoint, Event, Socket},
+    ClientMessage, Credentials, ServerMessage,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Error, ErrorKind, Result},
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// How long to wait for the server to let the client join.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The default number of ticks remote entities are shown behind the server.
+const DEFAULT_INTERPOLATION_DELAY: f32 = 2.0;
+
+/// A connection to a game server, along with the client's predicted copy of the world. It's
+/// usually run over a `UdpSocket`, but tests can run it over a `protocol::fake_net::FakeSocket`.
+#[derive(Debug)]
+pub struct Client<P: SystemMut, S: Socket = UdpSocket> {
+    engine: Engine<P>,
+    endpoint: Endpoint<S>,
+    server: SocketAddr,
+    codec: Codec,
+    player: Entity,
+    tick_rate: u32,
+
+    /// The token of the session the server gave, if the client logged in.
+    session: Option<String>,
+
+    /// The world as of the last update from the server.
+    confirmed: Snapshot,
+
+    /// The server tick of the last update.
+    server_tick: u64,
+
+    /// The number the next input will be sent with.
+    next_input: u64,
+
+    /// The inputs the server hasn't applied yet, with their numbers.
+    pending: VecDeque<(u64, Vec<Box<dyn Component>>)>,
+
+    /// The positions of other entities in the last few updates, with the server ticks of the
+    /// updates.
+    history: VecDeque<(u64, HashMap<Entity, Position>)>,
+
+    /// The server tick remote entities are currently shown at.
+    render_tick: f32,
+
+    /// The number of ticks remote entities are shown behind the server.
+    interpolation_delay: f32,
+}
+
+impl<P: SystemMut> Client<P> {
+    /// Connects to a server and joins the game, waiting for the first update from the server. The
+    /// world from the server is replicated into the engine's store, which should be empty.
+    pub fn connect<A: ToSocketAddrs>(addr: A, name: &str, engine: Engine<P>) -> Result<Client<P>> {
+        Client::join(addr, name, None, engine, Codec::new(WorldBounds::default()))
+    }
+
+    /// Connects to a server that requires logging in, and joins the game as `connect` does. If
+    /// the server rejects the login (e.g. because the credentials are wrong), an error of kind
+    /// `PermissionDenied` is returned, with the server's reason.
+    pub fn login<A: ToSocketAddrs>(
+        addr: A,
+        name: &str,
+        credentials: Credentials,
+        engine: Engine<P>,
+    ) -> Result<Client<P>> {
+        Client::join(
+            addr,
+            name,
+            Some(credentials),
+            engine,
+            Codec::new(WorldBounds::default()),
+        )
+    }
+
+    /// Connects to a server and joins the game, with the given credentials if any. Updates are
+    /// decoded with `codec`, which must have the same types registered as the server's; `connect`
+    /// and `login` use one with only the built-in components registered.
+    pub fn join<A: ToSocketAddrs>(
+        addr: A,
+        name: &str,
+        credentials: Option<Credentials>,
+        engine: Engine<P>,
+        codec: Codec,
+    ) -> Result<Client<P>> {
+        let server = addr
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "no address to connect to"))?;
+        let local: SocketAddr = if server.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.set_nonblocking(true)?;
+        let wait = || {
+            sleep(Duration::from_millis(1));
+            Instant::now()
+        };
+        Client::join_with(socket, server, name, credentials, engine, codec, wait)
+    }
+}
+
+impl<P: SystemMut, S: Socket> Client<P, S> {
+    /// Joins the game as `join` does, over the given socket. `wait` is called whenever there's
+    /// nothing to do but wait for the server, and returns the current time; this is for running
+    /// the client over a `FakeNetwork`, whose time is separate from the real time.
+    pub fn join_with<W: FnMut() -> Instant>(
+        socket: S,
+        server: SocketAddr,
+        name: &str,
+        credentials: Option<Credentials>,
+        engine: Engine<P>,
+        codec: Codec,
+        mut wait: W,
+    ) -> Result<Client<P, S>> {
+        let logging_in = credentials.is_some();
+        let mut now = wait();
+        let deadline = now + CONNECT_TIMEOUT;
+        let mut endpoint = Endpoint::client(socket, server, Config::default(), now);
+        let hello = ClientMessage::Hello {
+            name: name.to_string(),
+            credentials,
+        };
+        endpoint.send(server, Channel::Reliable, &to_bytes(&hello)?)?;
+
+        // Updates are sent on the unreliable channel, so they can overtake the welcome. They're
+        // kept to be applied once the client is set up.
+        let mut events = Vec::new();
+        let mut welcome = None;
+        let (player, tick_rate, session) = loop {
+            for event in endpoint.update(now)? {
+                let msg = match event {
+                    Event::Message { ref msg, .. } => from_bytes(msg)?,
+                    Event::Disconnected(_, reason) => return Err(disconnected(reason)),
+                    Event::Connected(_) => continue,
+                };
+                match msg {
+                    ServerMessage::Welcome {
+                        player,
+                        tick_rate,
+                        session,
+                    } if welcome.is_none() => welcome = Some((player, tick_rate, session)),
+                    ServerMessage::Update { .. } => events.push(event),
+                    ServerMessage::Rejected(reason) if logging_in => {
+                        return Err(Error::new(ErrorKind::PermissionDenied, reason))
+                    }
+                    ServerMessage::Rejected(reason) => {
+                        return Err(Error::new(ErrorKind::ConnectionRefused, reason))
+                    }
+                    msg => return Err(unexpected(&msg)),
+                }
+            }
+            if let Some(welcome) = welcome {
+                break welcome;
+            } else if now > deadline {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            now = wait();
+        };
+
+        let confirmed = engine.store.snapshot();
+        let mut client = Client {
+            engine,
+            endpoint,
+            server,
+            codec,
+            player,
+            tick_rate,
+            session,
+            confirmed,
+            server_tick: 0,
+            next_input: 0,
+            pending: VecDeque::new(),
+            history: VecDeque::new(),
+            render_tick: 0.0,
+            interpolation_delay: DEFAULT_INTERPOLATION_DELAY,
+        };
+
+        client.handle(events)?;
+        while client.history.is_empty() {
+            if now > deadline {
+                return Err(ErrorKind::TimedOut.into());
+            }
+            now = wait();
+            client.receive(now)?;
+        }
+        client.render_tick = client.server_tick as f32 - client.interpolation_delay;
+        Ok(client)
+    }
+
+    /// Returns the engine, whose store holds the predicted world.
+    pub fn engine(&self) -> &Engine<P> {
+        &self.engine
+    }
+
+    /// Returns the player's entity.
+    pub fn player(&self) -> Entity {
+        self.player
+    }
+
+    /// Returns the number of ticks the server runs per second. `step` should be called this often.
+    pub fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    /// Returns the token of the session the server gave when the client logged in, which can be
+    /// used to log in again with `Credentials::Token`.
+    pub fn session(&self) -> Option<&str> {
+        self.session.as_deref()
+    }
+
+    /// Returns the server tick of the last update from the server.
+    pub fn server_tick(&self) -> u64 {
+        self.server_tick
+    }
+
+    /// Returns the number of inputs that have been sent, but that the server hasn't applied yet.
+    pub fn pending_inputs(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Sets the number of ticks remote entities are shown behind the server. Larger delays hide
+    /// more jitter in the arrival of updates, at the cost of showing a less recent world.
+    pub fn set_interpolation_delay(&mut self, ticks: f32) {
+        self.interpolation_delay = ticks.max(0.0);
+    }
+
+    /// Runs a single tick: sends the inputs (which are set as components on the player's entity),
+    /// predicts the result of applying them, and applies any updates from the server.
+    pub fn step(&mut self, inputs: Vec<Box<dyn Component>>) -> Result<()> {
+        self.step_at(inputs, Instant::now())
+    }
+
+    /// Runs a single tick as `step` does, taking the current time to be `now`.
+    pub fn step_at(&mut self, inputs: Vec<Box<dyn Component>>, now: Instant) -> Result<()> {
+        let tick = self.next_input;
+        self.next_input += 1;
+        let pending = copy_inputs(&inputs)?;
+        let input = ClientMessage::Input {
+            tick,
+            view_tick: self.render_tick,
+            components: inputs,
+        };
+        self.endpoint
+            .send(self.server, Channel::Reliable, &to_bytes(&input)?)?;
+        self.predict(copy_inputs(&pending)?);
+        self.pending.push_back((tick, pending));
+
+        // Receiving also sends the input, so it's queued first.
+        self.receive(now)?;
+        self.interpolate();
+        Ok(())
+    }
+
+    /// Leaves the game, closing the connection.
+    pub fn leave(mut self) -> Result<()> {
+        self.endpoint.disconnect(self.server)
+    }
+
+    /// Sends and receives packets, then handles what arrived.
+    fn receive(&mut self, now: Instant) -> Result<()> {
+        let events = self.endpoint.update(now)?;
+        self.handle(events)
+    }
+
+    /// Applies the updates that have arrived from the server and acknowledges the newest one,
+    /// then predicts the inputs the server hasn't applied yet.
+    fn handle(&mut self, events: Vec<Event>) -> Result<()> {
+        let mut rolled_back = false;
+        for event in events {
+            let msg = match event {
+                Event::Message { msg, .. } => from_bytes(&msg)?,
+                Event::Disconnected(_, reason) => return Err(disconnected(reason)),
+                Event::Connected(_) => continue,
+            };
+            let (tick, input, delta) = match msg {
+                ServerMessage::Update { tick, input, delta } => (tick, input, delta),
+                msg => return Err(unexpected(&msg)),
+            };
+            if tick <= self.server_tick {
+                continue;
+            }
+
+            if !rolled_back {
+                self.engine.store.restore(&self.confirmed);
+                rolled_back = true;
+            }
+            delta
+                .decode(&self.codec)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?
+                .apply(&mut self.engine.store)
+                .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+            self.engine.store.maintain();
+            self.server_tick = tick;
+            if let Some(input) = input {
+                while self.pending.front().map(|&(n, _)| n <= input) == Some(true) {
+                    let _ = self.pending.pop_front();
+                }
+            }
+            self.record_positions();
+        }
+
+        if rolled_back {
+            let ack = ClientMessage::Ack {
+                tick: self.server_tick,
+            };
+            self.endpoint
+                .send(self.server, Channel::Unreliable, &to_bytes(&ack)?)?;
+            self.confirmed = self.engine.store.snapshot();
+            let pending = self
+                .pending
+                .iter()
+                .map(|(_, inputs)| copy_inputs(inputs))
+                .collect::<Result<Vec<_>>>()?;
+            for inputs in pending {
+                self.predict(inputs);
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies inputs to the player's entity, and runs a tick.
+    fn predict(&mut self, inputs: Vec<Box<dyn Component>>) {
+        for component in inputs {
+            let _ = self
+                .engine
+                .apply_input(Input::SetComponent(self.player, component));
+        }
+        self.engine.run_tick(1.0 / self.tick_rate as f32);
+    }
+
+    /// Saves the positions of other entities, as of the update that was just applied.
+    fn record_positions(&mut self) {
+        let store = &self.engine.store;
+        let player = self.player;
+        let positions = store
+            .iter_entities()
+            .filter(|&entity| entity != player && store.is_alive(entity))
+            .filter_map(|entity| {
+                store
+                    .get_component::<Position>(entity)
+                    .map(|&position| (entity, position))
+            })
+            .collect();
+        self.history.push_back((self.server_tick, positions));
+    }
+
+    /// Advances the time remote entities are shown at by a tick, and moves them to where they
+    /// were at that time.
+    fn interpolate(&mut self) {
+        let latest = match self.history.back() {
+            Some(&(tick, _)) => tick as f32,
+            None => return,
+        };
+
+        // The render tick never goes backwards, but it doesn't overtake the latest update, and it
+        // skips ahead if it falls too far behind.
+        self.render_tick = (self.render_tick + 1.0)
+            .min(latest)
+            .max(latest - 2.0 * self.interpolation_delay);
+        while self.history.len() > 2 && self.history[1].0 as f32 <= self.render_tick {
+            let _ = self.history.pop_front();
+        }
+
+        let (from_tick, from) = &self.history[0];
+        let (to_tick, to) = self.history.get(1).unwrap_or(&self.history[0]);
+        let t = if to_tick > from_tick {
+            let t = (self.render_tick - *from_tick as f32) / (to_tick - from_tick) as f32;
+            t.clamp(0.0, 1.0)
+        } else {
+            1.0
+        };
+
+        let store = &mut self.engine.store;
+        for (&entity, to_position) in to {
+            if !store.is_alive(entity) {
+                continue;
+            }
+            let position = match from.get(&entity) {
+                Some(from_position) => {
+                    Position(from_position.0 + (to_position.0 - from_position.0) * t)
+                }
+                None => *to_position,
+            };
+            *store.get_mut_component(entity) = Some(position);
+        }
+    }
+}
+
+/// Copies inputs, so they can be both sent and applied.
+fn copy_inputs(inputs: &[Box<dyn Component>]) -> Result<Vec<Box<dyn Component>>> {
+    inputs
+        .iter()
+        .map(replay::copy)
+        .collect::<std::result::Result<_, _>>()
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))
+}
+
+/// Returns the error for the server closing the connection.
+fn disconnected(reason: DisconnectReason) -> Error {
+    match reason {
+        DisconnectReason::Closed => Error::new(
+            ErrorKind::ConnectionAborted,
+            "the server closed the connection",
+        ),
+        DisconnectReason::TimedOut => ErrorKind::TimedOut.into(),
+        DisconnectReason::Rejected(reason) => Error::new(ErrorKind::ConnectionRefused, reason),
+    }
+}
+
+/// Returns the error for a message from the server that wasn't expected.
+fn unexpected(msg: &ServerMessage) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("unexpected message from the server: {:?}", msg),
+    )
+}
@@ -0,0 +1,253 @@
+use assets::Assets;
+use ecstasy::{
+    components::{Name, Position},
+    quantize::{Codec, WorldBounds},
+    system_mut, Component, ComponentStore, Engine, Entity, SystemMut,
+};
+use ia::net::Client;
+use ia_server::Server;
+use protocol::fake_net::{Conditions, FakeNetwork, FakeSocket};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeSet, net::SocketAddr, time::Duration};
+
+const TICK_RATE: u32 = 60;
+
+/// The farthest along the x axis the server lets players walk.
+const WALL: f32 = 0.25;
+
+#[derive(Clone, Component, Debug, Deserialize, PartialEq, Serialize)]
+#[component(clone)]
+struct Walk(f32);
+
+#[derive(Clone, Component, Debug, Deserialize, PartialEq, Serialize)]
+struct Tag(u32);
+
+#[system_mut]
+fn Walking(_entity: Entity, dt: f32, walk: &mut Walk, position: &mut Position) {
+    position.0.x += walk.0 * dt;
+}
+
+/// Stops walkers at the wall. This only runs on the server, so clients mispredict walking into
+/// it.
+#[system_mut]
+fn StopAtWall(_entity: Entity, _dt: f32, _walk: &mut Walk, position: &mut Position) {
+    position.0.x = position.0.x.min(WALL);
+}
+
+/// Puts named entities that don't have a position at the origin, so players have a position as
+/// soon as they join.
+struct PlaceAtOrigin;
+
+impl SystemMut for PlaceAtOrigin {
+    fn run(&mut self, cs: &mut ComponentStore, _dt: f32) {
+        for entity in cs.iter_entities() {
+            if cs.get_component::<Name>(entity).is_some()
+                && cs.get_component::<Position>(entity).is_none()
+            {
+                cs.set_component(entity, Position::new(0.0, 0.0, 0.0));
+            }
+        }
+    }
+}
+
+/// A server and a client playing over a `FakeNetwork`.
+struct Game<P: SystemMut> {
+    net: FakeNetwork,
+    server: Server<FakeSocket>,
+    client: Client<P, FakeSocket>,
+}
+
+impl<P: SystemMut> Game<P> {
+    /// Starts a server over a network with the given conditions, and joins a client to it.
+    fn start(conditions: Conditions, name: &str, engine: Engine<P>) -> Game<P> {
+        let net = FakeNetwork::new(12345);
+        net.set_conditions(conditions);
+
+        let mut server_engine = Engine::new(Assets::new())
+            .add_mut_pass(PlaceAtOrigin)
+            .add_mut_pass(Walking)
+            .add_mut_pass(StopAtWall);
+        let store = &mut server_engine.store;
+        let rock = store.new_entity();
+        store.set_component(rock, Name("rock".to_string()));
+        store.set_component(rock, Position::new(0.0, 10.0, 0.0));
+        store.set_component(rock, Walk(-3.0));
+        let mut server = Server::new(net.socket(server_addr()), server_engine, TICK_RATE);
+        server.allow_input::<Walk>();
+
+        let client = Client::join_with(
+            net.socket(client_addr()),
+            server_addr(),
+            name,
+            None,
+            engine,
+            Codec::new(WorldBounds::default()),
+            || {
+                net.advance(tick_length());
+                server.step_at(net.now());
+                net.now()
+            },
+        )
+        .unwrap();
+        Game {
+            net,
+            server,
+            client,
+        }
+    }
+
+    /// Runs a tick on the server, then on the client.
+    fn step(&mut self, inputs: Vec<Box<dyn Component>>) {
+        self.net.advance(tick_length());
+        self.server.step_at(self.net.now());
+        self.client.step_at(inputs, self.net.now()).unwrap();
+    }
+}
+
+fn server_addr() -> SocketAddr {
+    "10.0.0.1:1234".parse().unwrap()
+}
+
+fn client_addr() -> SocketAddr {
+    "10.0.0.2:5678".parse().unwrap()
+}
+
+fn tick_length() -> Duration {
+    Duration::from_secs(1) / TICK_RATE
+}
+
+/// A network that delays, reorders, duplicates, and drops packets in both directions.
+fn lossy() -> Conditions {
+    Conditions {
+        latency: Duration::from_millis(50),
+        jitter: Duration::from_millis(20),
+        loss: 0.2,
+        duplication: 0.05,
+    }
+}
+
+fn x_of<P: SystemMut>(client: &Client<P, FakeSocket>, entity: Entity) -> f32 {
+    let store = &client.engine().store;
+    store.get_component::<Position>(entity).unwrap().0.x
+}
+
+/// Asserts that two coordinates are the same, up to the precision positions are sent with.
+fn assert_close(x: f32, expected: f32) {
+    let precision = WorldBounds::default().precision;
+    assert!((x - expected).abs() <= precision, "{} != {}", x, expected);
+}
+
+#[test]
+fn predicts_and_reconciles() {
+    let engine = Engine::new(Assets::new()).add_mut_pass(Walking);
+    let mut game = Game::start(lossy(), "alice", engine);
+    let player = game.client.player();
+    assert_close(x_of(&game.client, player), 0.0);
+
+    // The player moves straight away, long before the server could have seen the input.
+    game.step(vec![Box::new(Walk(1.0))]);
+    assert_close(x_of(&game.client, player), 1.0 / TICK_RATE as f32);
+    for _ in 1..30 {
+        game.step(vec![Box::new(Walk(1.0))]);
+    }
+
+    // The client walks through the wall, but once the server has caught up, it's corrected.
+    assert!(x_of(&game.client, player) > WALL);
+    game.step(vec![Box::new(Walk(0.0))]);
+    for _ in 0..60 {
+        game.step(Vec::new());
+    }
+    assert_close(x_of(&game.client, player), WALL);
+
+    game.client.leave().unwrap();
+}
+
+#[test]
+fn interpolates_remote_entities() {
+    let mut game = Game::start(lossy(), "bob", Engine::new(Assets::new()));
+    let rock = game.client.engine().store.find_by_name("rock").unwrap();
+
+    // The rock only ever moves forwards, even though updates arrive unevenly.
+    let start = x_of(&game.client, rock);
+    let mut last = start;
+    for _ in 0..60 {
+        game.step(Vec::new());
+        let x = x_of(&game.client, rock);
+        assert!(x <= last, "the rock moved backwards from {} to {}", last, x);
+        last = x;
+    }
+    assert!(
+        start - last > 0.5,
+        "the rock only moved from {} to {}",
+        start,
+        last
+    );
+
+    game.client.leave().unwrap();
+}
+
+#[test]
+fn recovers_from_lost_updates() {
+    // Without jitter, at most one update arrives per tick, so every tick the client never reached
+    // is an update that was lost.
+    let conditions = Conditions {
+        latency: tick_length() * 3,
+        loss: 0.2,
+        ..Conditions::default()
+    };
+    let mut game = Game::start(conditions, "carol", Engine::new(Assets::new()));
+
+    // Entities are created, changed, and destroyed, and components come and go, while packets
+    // are lost both ways.
+    let mut things = Vec::new();
+    let mut reached = BTreeSet::new();
+    for i in 0..180 {
+        let store = &mut game.server.engine_mut().store;
+        if i % 3 == 0 {
+            let thing = store.new_entity();
+            store.set_component(thing, Name(format!("thing {}", i)));
+            store.set_component(thing, Tag(i));
+            things.push(thing);
+        }
+        if i % 5 == 0 {
+            store.set_component(things[i as usize % things.len()], Tag(i));
+        }
+        if i % 7 == 0 && things.len() > 3 {
+            store.delete_entity(things.remove(0));
+        }
+        if i % 11 == 0 {
+            store.remove_component::<Tag>(things[things.len() / 2]);
+        }
+        game.step(Vec::new());
+        let _ = reached.insert(game.client.server_tick());
+    }
+    let first = *reached.iter().next().unwrap();
+    let missed = (first..game.client.server_tick())
+        .filter(|tick| !reached.contains(tick))
+        .count();
+    assert!(missed > 10, "only {} updates were lost", missed);
+
+    // Once things settle down, the client has exactly what the server has.
+    for _ in 0..60 {
+        game.step(Vec::new());
+    }
+    assert_eq!(
+        tags(&game.client.engine().store),
+        tags(&game.server.engine().store)
+    );
+    game.client.leave().unwrap();
+}
+
+/// Returns the living entities in a store, with their names and tags.
+fn tags(store: &ComponentStore) -> Vec<(Entity, Option<String>, Option<Tag>)> {
+    store
+        .iter_entities()
+        .filter(|&entity| store.is_alive(entity))
+        .map(|entity| {
+            let name = store
+                .get_component::<Name>(entity)
+                .map(|name| name.0.clone());
+            (entity, name, store.get_component::<Tag>(entity).cloned())
+        })
+        .collect()
+}
@@ -29,6 +29,17 @@ impl IRB {
         bincode::deserialize_from(zstd::Decoder::new(File::open(path)?)?).map_err(From::from)
     }
 
+    /// Returns whether an asset with the given name is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.assets.contains_key(name)
+    }
+
+    /// Adds a scene, replacing any existing asset with the same name. The scene should already
+    /// have been validated.
+    pub fn insert_scene(&mut self, name: String, src: String) {
+        let _ = self.assets.insert(name, IRBAsset::Scene(src));
+    }
+
     /// Encodes an IRB file into bytes. Prefer `save_to_file` if saving to a file.
     pub fn save_to_bytes(&self) -> Result<Vec<u8>> {
         zstd::encode_all(Cursor::new(bincode::serialize(self)?), 0).map_err(From::from)
@@ -58,6 +69,8 @@ pub(crate) enum IRBAsset {
     FragmentShader(Vec<u8>),
     Model(Model),
     VertexShader(Vec<u8>),
+    // New variants go at the end, since bincode identifies variants by their indices.
+    Scene(String),
 }
 
 impl From<Asset> for IRBAsset {
@@ -65,6 +78,7 @@ impl From<Asset> for IRBAsset {
         match asset {
             Asset::FragmentShader(spirv, _) => IRBAsset::FragmentShader(spirv),
             Asset::Model(model) => IRBAsset::Model(model),
+            Asset::Scene(src) => IRBAsset::Scene(src),
             Asset::VertexShader(spirv, _) => IRBAsset::VertexShader(spirv),
         }
     }
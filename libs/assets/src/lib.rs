@@ -74,6 +74,34 @@ impl Assets {
         }
         (Assets { assets }, errs)
     }
+
+    /// Loads the assets from an `IRB` that don't need a GPU, for headless use (such as by the
+    /// server). Shaders are skipped, so the returned `Assets` only contains models and scenes.
+    pub fn from_irb_headless(irb: IRB) -> Assets {
+        let assets = irb
+            .assets
+            .into_iter()
+            .filter_map(|(name, asset)| {
+                let asset = match asset {
+                    IRBAsset::Model(model) => Asset::Model(model),
+                    IRBAsset::Scene(src) => Asset::Scene(src),
+                    IRBAsset::FragmentShader(_) | IRBAsset::VertexShader(_) => return None,
+                };
+                Some((name, Arc::new(asset)))
+            })
+            .collect();
+        Assets { assets }
+    }
+
+    /// Adds an asset, replacing any existing asset with the same name.
+    pub fn insert(&mut self, name: String, asset: Asset) {
+        let _ = self.assets.insert(name, Arc::new(asset));
+    }
+
+    /// Returns the asset with the given name, if it exists.
+    pub fn get(&self, name: &str) -> Option<&Arc<Asset>> {
+        self.assets.get(name)
+    }
 }
 
 /// A single asset.
@@ -85,6 +113,9 @@ pub enum Asset {
     /// A model.
     Model(Model),
 
+    /// A scene, as the source text of an S-expression. See the `ecstasy::scene` module.
+    Scene(String),
+
     /// A vertex shader.
     VertexShader(Vec<u8>, Arc<ShaderModule>),
 }
@@ -97,6 +128,7 @@ impl Asset {
                 Asset::FragmentShader(spirv, sm)
             }
             IRBAsset::Model(model) => Asset::Model(model),
+            IRBAsset::Scene(src) => Asset::Scene(src),
             IRBAsset::VertexShader(spirv) => {
                 let sm = unsafe { ShaderModule::new(device.clone(), &spirv)? };
                 Asset::VertexShader(spirv, sm)
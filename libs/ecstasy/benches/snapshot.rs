@@ -0,0 +1,50 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use ecstasy::{
+    components::{Name, Position},
+    snapshot::SnapshotRing,
+    ComponentStore,
+};
+use rand::random;
+
+fn set_up_component_store(entities: usize) -> ComponentStore {
+    let mut cs = ComponentStore::new();
+    for i in 0..entities {
+        let e = cs.new_entity();
+        cs.set_component(e, Position::new(random(), random(), random()));
+        if random() {
+            cs.set_component(e, Name(format!("entity {}", i)));
+        }
+    }
+    cs
+}
+
+fn snapshot(c: &mut Criterion) {
+    c.bench_function("snapshot 10k entities", |b| {
+        let cs = set_up_component_store(10000);
+        b.iter(|| cs.snapshot())
+    });
+
+    c.bench_function("restore 10k entities", |b| {
+        let mut cs = set_up_component_store(10000);
+        let snapshot = cs.snapshot();
+        b.iter(|| cs.restore(&snapshot))
+    });
+
+    c.bench_function("snapshot 10k entities into a ring of 8", |b| {
+        let cs = set_up_component_store(10000);
+        let mut ring = SnapshotRing::new(8);
+        let mut tick = 0;
+        b.iter(|| {
+            ring.push(tick, cs.snapshot());
+            tick += 1;
+        })
+    });
+
+    c.bench_function("serialize 10k entities (for comparison)", |b| {
+        let cs = set_up_component_store(10000);
+        b.iter(|| serde_sexpr::to_string(&cs).unwrap())
+    });
+}
+
+criterion_group!(benches, snapshot);
+criterion_main!(benches);
@@ -0,0 +1,93 @@
+use crate::{unsafe_option_vec::UnsafeOptionVec, Component, ComponentStore, Entity};
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+
+/// The functions needed to clone a component whose type is not statically known.
+///
+/// Components opt into cloning by returning one of these from `Component::clone_component`,
+/// which the derive does when given `#[component(clone)]`.
+#[derive(Clone, Copy)]
+pub struct CloneComponent {
+    clone_to: fn(&mut ComponentStore, Entity, Entity, &mut dyn FnMut(Entity) -> Entity),
+    clone_vec: unsafe fn(&UnsafeOptionVec) -> UnsafeOptionVec,
+}
+
+impl CloneComponent {
+    /// Creates the clone functions for the given component type.
+    pub fn of<T: Clone + Component>() -> CloneComponent {
+        fn clone_to<T: Clone + Component>(
+            cs: &mut ComponentStore,
+            from: Entity,
+            to: Entity,
+            map: &mut dyn FnMut(Entity) -> Entity,
+        ) {
+            if let Some(mut component) = cs.get_component::<T>(from).cloned() {
+                component.remap_entities(map);
+                cs.set_component(to, component);
+            }
+        }
+
+        CloneComponent {
+            clone_to: clone_to::<T>,
+            clone_vec: UnsafeOptionVec::clone_as::<T>,
+        }
+    }
+
+    /// Copies the component from `from` to `to` (if `from` has one), remapping any entities it
+    /// refers to with `map`.
+    pub(crate) fn clone_to(
+        self,
+        cs: &mut ComponentStore,
+        from: Entity,
+        to: Entity,
+        map: &mut dyn FnMut(Entity) -> Entity,
+    ) {
+        (self.clone_to)(cs, from, to, map)
+    }
+
+    /// Copies every component in the storage for the component type. This must only be called
+    /// with the storage for the type the `CloneComponent` was created for.
+    pub(crate) unsafe fn clone_vec(self, vec: &UnsafeOptionVec) -> UnsafeOptionVec {
+        (self.clone_vec)(vec)
+    }
+}
+
+impl Debug for CloneComponent {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("CloneComponent").finish()
+    }
+}
+
+/// A value that contains references to entities, which may need to be rewritten when the value
+/// is copied to a different entity (or a different `ComponentStore`).
+///
+/// This is used by the derive for fields marked with `#[component(entity)]`.
+pub trait MapEntities {
+    /// Replaces each entity referred to by the value with the result of calling `map` on it.
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity);
+}
+
+impl MapEntities for Entity {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        *self = map(*self);
+    }
+}
+
+impl<T: MapEntities> MapEntities for Option<T> {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        if let Some(x) = self {
+            x.map_entities(map);
+        }
+    }
+}
+
+impl<T: ?Sized + MapEntities> MapEntities for Box<T> {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        (**self).map_entities(map)
+    }
+}
+
+impl<T: MapEntities> MapEntities for Vec<T> {
+    fn map_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.iter_mut().for_each(|x| x.map_entities(map))
+    }
+}
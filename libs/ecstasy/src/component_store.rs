@@ -1,13 +1,41 @@
-use crate::{unsafe_option_vec::UnsafeOptionVec, Component, Entity};
+use crate::{
+    components::Name, name_index::NameIndex, snapshot::Snapshot, spatial::SpatialIndex,
+    unsafe_option_vec::UnsafeOptionVec, CloneComponent, Component, Entity,
+};
 use hashbrown::HashMap;
 use safety_guard::safety;
-use std::{any::TypeId, cell::UnsafeCell, num::NonZeroUsize};
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    any::{type_name, TypeId},
+    cell::UnsafeCell,
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    ops::{Deref, DerefMut},
+};
 
 /// A container for components.
+///
+/// A store can be serialized and deserialized as a whole, which saves every entity along with all
+/// its components. Indices are not saved; deserialized stores start with only the name index.
+///
+/// The store keeps a change tick, which is recorded every time a component is written to, added,
+/// or removed. This lets indices (such as the spatial index) and other consumers find the
+/// components that may have changed since they last looked. The tick is advanced by `maintain`,
+/// which the `Engine` calls after every pass.
 #[derive(Debug)]
 pub struct ComponentStore {
-    components: UnsafeCell<HashMap<TypeId, UnsafeOptionVec>>,
+    components: UnsafeCell<HashMap<TypeId, Storage>>,
     next_entity: usize,
+    change_tick: u64,
+
+    /// The change tick at which each entity was created, indexed by entity.
+    created: Vec<u64>,
+
+    /// The change tick at which each deleted entity was deleted.
+    deleted: HashMap<Entity, u64>,
+
+    name_index: NameIndex,
+    spatial_index: Option<SpatialIndex>,
 }
 
 impl ComponentStore {
@@ -16,7 +44,7 @@ impl ComponentStore {
         ComponentStore::default()
     }
 
-    /// Returns an iterator over all entities.
+    /// Returns an iterator over all entities, including deleted ones.
     pub fn iter_entities(&self) -> impl Clone + Iterator<Item = Entity> {
         (1..self.next_entity).map(|n| {
             NonZeroUsize::new(n)
@@ -32,34 +60,169 @@ impl ComponentStore {
             .next_entity
             .checked_add(1)
             .expect("too many entities allocated");
+        self.created.push(self.change_tick);
         NonZeroUsize::new(n)
             .map(Entity)
             .expect("impossible case? entity 0")
     }
 
+    /// Deletes an entity, removing all its components. Since entities are never reused, the
+    /// entity is still returned by `iter_entities`, but it has no components, and `is_alive`
+    /// returns false for it. Components should not be added to deleted entities.
+    pub fn delete_entity(&mut self, entity: Entity) {
+        let n = entity.0.get();
+        let tick = self.change_tick;
+        for storage in unsafe { &mut *self.components.get() }.values_mut() {
+            if unsafe { (storage.get_dyn)(&storage.vec, n) }.is_some() {
+                unsafe { (storage.remove)(&mut storage.vec, n) };
+                storage.mark_changed(n, tick);
+            }
+        }
+        let _ = self.deleted.insert(entity, tick);
+        self.name_index.set(entity, None);
+    }
+
+    /// Returns whether the entity has been created and not deleted.
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        entity.0.get() < self.next_entity && !self.deleted.contains_key(&entity)
+    }
+
+    /// Brings a deleted entity back to life, as if it had just been created. This is only for
+    /// replicas of another store, in which an entity is deleted when it goes out of view and can
+    /// come back into view later.
+    pub(crate) fn revive(&mut self, entity: Entity) {
+        if self.deleted.remove(&entity).is_some() {
+            self.created[entity.0.get()] = self.change_tick;
+        }
+    }
+
+    /// Creates entities until the next entity to be created is `next_entity`.
+    pub(crate) fn allocate_up_to(&mut self, next_entity: usize) {
+        while self.next_entity < next_entity {
+            let _ = self.new_entity();
+        }
+    }
+
+    /// Returns the id the next entity will be created with.
+    pub(crate) fn next_entity(&self) -> usize {
+        self.next_entity
+    }
+
+    /// Returns the living entities created at or after the given change tick.
+    pub(crate) fn created_since(&self, tick: u64) -> Vec<Entity> {
+        self.iter_entities()
+            .filter(|entity| self.created[entity.0.get()] >= tick && self.is_alive(*entity))
+            .collect()
+    }
+
+    /// Returns the entities deleted at or after the given change tick, in order.
+    pub(crate) fn deleted_since(&self, tick: u64) -> Vec<Entity> {
+        let mut deleted = self
+            .deleted
+            .iter()
+            .filter(|&(_, &deleted)| deleted >= tick)
+            .map(|(&entity, _)| entity)
+            .collect::<Vec<_>>();
+        deleted.sort_by_key(|entity| entity.0);
+        deleted
+    }
+
+    /// Creates a new entity with copies of all the cloneable components of the given entity.
+    /// Components that have not opted into cloning are not copied.
+    pub fn clone_entity(&mut self, entity: Entity) -> Entity {
+        self.instantiate(&[entity])[0]
+    }
+
+    /// Stamps out a copy of a prefab, which is a group of template entities. Each entity in the
+    /// prefab gets a new entity with copies of its cloneable components, and the new entities are
+    /// returned in the same order.
+    ///
+    /// References from one entity in the prefab to another are remapped to point to the
+    /// corresponding copies; references to entities outside the prefab are left as-is. An entity
+    /// listed more than once is only copied once, and its copy is returned in each of its places.
+    pub fn instantiate(&mut self, prefab: &[Entity]) -> Vec<Entity> {
+        let mut copies = HashMap::with_capacity(prefab.len());
+        let mut templates = Vec::with_capacity(prefab.len());
+        for &template in prefab {
+            if !copies.contains_key(&template) {
+                let _ = copies.insert(template, self.new_entity());
+                templates.push(template);
+            }
+        }
+        let clones = unsafe { &*self.components.get() }
+            .values()
+            .filter_map(|storage| storage.clone)
+            .collect::<Vec<_>>();
+
+        let mut map = |entity| copies.get(&entity).cloned().unwrap_or(entity);
+        for clone in clones {
+            for template in &templates {
+                clone.clone_to(self, *template, copies[template], &mut map);
+            }
+        }
+
+        prefab.iter().map(|template| copies[template]).collect()
+    }
+
     /// Gets a component for a given entity.
     pub fn get_component<T: Component>(&self, entity: Entity) -> Option<&T> {
-        unsafe { self.unsafe_get_mut_component(entity) }.as_ref()
+        let storage = unsafe { &*self.components.get() }.get(&TypeId::of::<T>())?;
+        unsafe { storage.vec.get::<T>(entity.0.get()) }?.as_ref()
     }
 
-    /// Gets a component for a given entity.
-    pub fn get_mut_component<T: Component>(&mut self, entity: Entity) -> &mut Option<T> {
+    /// Gets a component for a given entity. The component is only counted as changed if it is
+    /// written to through the returned `ComponentMut`.
+    pub fn get_mut_component<T: Component>(&mut self, entity: Entity) -> ComponentMut<'_, T> {
         unsafe { self.unsafe_get_mut_component(entity) }
     }
 
     /// Removes a component from a given entity.
     pub fn remove_component<T: Component>(&mut self, entity: Entity) {
         *self.get_mut_component::<T>(entity) = None;
+        self.update_indices::<T>(entity);
     }
 
     /// Sets a component for a given entity.
     pub fn set_component<T: Component>(&mut self, entity: Entity, component: T) {
         *self.get_mut_component(entity) = Some(component);
+        self.update_indices::<T>(entity);
     }
 
     /// Tries to remove a component from an entity.
     pub fn take_component<T: Component>(&mut self, entity: Entity) -> Option<T> {
-        self.get_mut_component(entity).take()
+        let component = self.get_mut_component(entity).take();
+        self.update_indices::<T>(entity);
+        component
+    }
+
+    /// Finds an entity by its `Name` component. If several entities have the same name, the one
+    /// that was given the name first is returned; see `duplicate_names`.
+    ///
+    /// The name index is updated immediately by `set_component`, `remove_component`, and
+    /// `take_component`; changes made through mutable references are picked up by `maintain`.
+    pub fn find_by_name(&self, name: &str) -> Option<Entity> {
+        self.name_index.find(name).first().cloned()
+    }
+
+    /// Finds all the entities with the given name, in the order they were given it.
+    pub fn find_all_by_name(&self, name: &str) -> &[Entity] {
+        self.name_index.find(name)
+    }
+
+    /// Returns each name that is shared by more than one entity, along with the entities that
+    /// share it.
+    pub fn duplicate_names(&self) -> impl Iterator<Item = (&str, &[Entity])> {
+        self.name_index.duplicates()
+    }
+
+    /// Updates the indices that can be updated eagerly after a component was changed.
+    fn update_indices<T: Component>(&mut self, entity: Entity) {
+        if TypeId::of::<T>() == TypeId::of::<Name>() {
+            let name = self
+                .get_component::<Name>(entity)
+                .map(|name| name.0.clone());
+            self.name_index.set(entity, name);
+        }
     }
 
     /// Gets a component for a given entity. This is unsafe since it makes it possible to have two
@@ -68,14 +231,210 @@ impl ComponentStore {
     #[safety(
         "The references returned by calling this function with the same T must not exist at once."
     )]
-    pub unsafe fn unsafe_get_mut_component<T: Component>(&self, entity: Entity) -> &mut Option<T> {
-        self.components
+    pub unsafe fn unsafe_get_mut_component<T: Component>(
+        &self,
+        entity: Entity,
+    ) -> ComponentMut<'_, T> {
+        let n = entity.0.get();
+        let storage = self
+            .components
             .get()
             .as_mut()
             .unwrap()
             .entry(TypeId::of::<T>())
-            .or_insert_with(UnsafeOptionVec::new::<T>)
-            .get_mut::<T>(entity.0.get())
+            .or_insert_with(Storage::new::<T>);
+        let slot = storage.vec.get_mut::<T>(n);
+        ComponentMut {
+            was_some: slot.is_some(),
+            slot,
+            tag: &mut storage.tag,
+            changed: &mut storage.changed,
+            n,
+            tick: self.change_tick,
+            written: false,
+        }
+    }
+
+    /// Returns the current change tick. This is always at least 1.
+    pub fn change_tick(&self) -> u64 {
+        self.change_tick
+    }
+
+    /// Returns the entities whose `T` component was written to, added, or removed at or after the
+    /// given change tick.
+    pub fn changed_since<T: Component>(&self, tick: u64) -> Vec<Entity> {
+        unsafe { &*self.components.get() }
+            .get(&TypeId::of::<T>())
+            .map(|storage| {
+                storage
+                    .changed
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &changed)| changed != 0 && changed >= tick)
+                    .filter_map(|(n, _)| NonZeroUsize::new(n).map(Entity))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns every component in the store, grouped by type and then by entity. The types are
+    /// sorted by name, so the order does not depend on the layout of the `HashMap`.
+    pub(crate) fn all_components(&self) -> Vec<ComponentsOfType<'_>> {
+        let mut all = unsafe { &*self.components.get() }
+            .values()
+            .map(|storage| {
+                let components = self
+                    .iter_entities()
+                    .filter_map(|entity| {
+                        unsafe { (storage.get_dyn)(&storage.vec, entity.0.get()) }
+                            .map(|component| (entity, component))
+                    })
+                    .collect::<Vec<_>>();
+                (storage.tag(), components)
+            })
+            .filter(|(_, components)| !components.is_empty())
+            .collect::<Vec<_>>();
+        all.sort_by_key(|&(name, _)| name);
+        all
+    }
+
+    /// Returns the components that were written to, added, or removed at or after the given tick,
+    /// grouped by type like `all_components`. Components that have since been removed are `None`.
+    pub(crate) fn changed_components_since(&self, tick: u64) -> Vec<ChangedOfType<'_>> {
+        let mut all = unsafe { &*self.components.get() }
+            .values()
+            .map(|storage| {
+                let components = storage
+                    .changed
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &changed)| changed != 0 && changed >= tick)
+                    .filter_map(|(n, _)| {
+                        let entity = NonZeroUsize::new(n).map(Entity)?;
+                        Some((entity, unsafe { (storage.get_dyn)(&storage.vec, n) }))
+                    })
+                    .collect::<Vec<_>>();
+                (storage.tag(), components)
+            })
+            .filter(|(_, components)| !components.is_empty())
+            .collect::<Vec<_>>();
+        all.sort_by_key(|&(name, _)| name);
+        all
+    }
+
+    /// Removes the component whose type has the given `typetag` name from the entity. Does nothing
+    /// if there is no such component.
+    pub(crate) fn remove_by_tag(&mut self, entity: Entity, tag: &str) {
+        let n = entity.0.get();
+        let tick = self.change_tick;
+        let mut removed_name = false;
+        for storage in unsafe { &mut *self.components.get() }.values_mut() {
+            if storage.tag == Some(tag) && unsafe { (storage.get_dyn)(&storage.vec, n) }.is_some() {
+                unsafe { (storage.remove)(&mut storage.vec, n) };
+                storage.mark_changed(n, tick);
+                removed_name |= storage.name == type_name::<Name>();
+            }
+        }
+        if removed_name {
+            self.name_index.set(entity, None);
+        }
+    }
+
+    /// Brings the indices kept by the store up to date, then advances the change tick.
+    pub fn maintain(&mut self) {
+        self.update_name_index();
+        if let Some(mut spatial_index) = self.spatial_index.take() {
+            spatial_index.update(self);
+            self.spatial_index = Some(spatial_index);
+        }
+
+        self.change_tick += 1;
+    }
+
+    /// Updates the name index from the `Name`s that have changed since it was last updated.
+    fn update_name_index(&mut self) {
+        for entity in self.changed_since::<Name>(self.name_index.last_tick) {
+            let name = self
+                .get_component::<Name>(entity)
+                .map(|name| name.0.clone());
+            self.name_index.set(entity, name);
+        }
+        self.name_index.last_tick = self.change_tick;
+    }
+
+    /// Takes an in-memory snapshot of the store, which can later be restored with `restore`.
+    /// Only components that opt into cloning are saved; see the `snapshot` module.
+    pub fn snapshot(&self) -> Snapshot {
+        let storages = unsafe { &*self.components.get() }
+            .iter()
+            .filter_map(|(&type_id, storage)| {
+                let clone = storage.clone?;
+                Some((type_id, unsafe { clone.clone_vec(&storage.vec) }))
+            })
+            .collect();
+        Snapshot {
+            storages,
+            next_entity: self.next_entity,
+            deleted: self.deleted.clone(),
+        }
+    }
+
+    /// Restores a snapshot taken by `snapshot`, so every cloneable component is as it was when the
+    /// snapshot was taken. Components that don't opt into cloning are left as they are, except on
+    /// entities created after the snapshot was taken.
+    ///
+    /// Those entities are forgotten, along with all their components, and their ids will be handed
+    /// out again by `new_entity`. Every restored or removed component counts as changed, so the
+    /// indices are brought up to date by the next `maintain`.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        let tick = self.change_tick;
+        for (type_id, storage) in unsafe { &mut *self.components.get() }.iter_mut() {
+            let old_len = storage.vec.allocated_len();
+            let was_some = (0..old_len)
+                .map(|n| unsafe { (storage.get_dyn)(&storage.vec, n) }.is_some())
+                .collect::<Vec<_>>();
+            let clone = match storage.clone {
+                Some(clone) => clone,
+                None => {
+                    let forgotten = was_some.iter().enumerate().skip(snapshot.next_entity);
+                    for (n, &was_some) in forgotten {
+                        if was_some {
+                            unsafe { (storage.remove)(&mut storage.vec, n) };
+                            storage.mark_changed(n, tick);
+                        }
+                    }
+                    continue;
+                }
+            };
+            storage.vec = match snapshot.storages.get(type_id) {
+                Some(vec) => unsafe { clone.clone_vec(vec) },
+                None => (storage.empty)(),
+            };
+
+            for n in 0..old_len.max(storage.vec.allocated_len()) {
+                let is_some = unsafe { (storage.get_dyn)(&storage.vec, n) }.is_some();
+                if is_some || was_some.get(n) == Some(&true) {
+                    storage.mark_changed(n, tick);
+                }
+            }
+        }
+        self.next_entity = snapshot.next_entity;
+        self.created.resize(snapshot.next_entity, tick);
+        self.deleted = snapshot.deleted.clone();
+        self.update_name_index();
+    }
+
+    /// Returns the spatial index over `Position` components, if one has been set. It is up to date
+    /// as of the last call to `maintain`.
+    pub fn spatial_index(&self) -> Option<&SpatialIndex> {
+        self.spatial_index.as_ref()
+    }
+
+    /// Sets the spatial index to be kept up to date by `maintain`, bringing it up to date
+    /// immediately.
+    pub fn set_spatial_index(&mut self, mut spatial_index: SpatialIndex) {
+        spatial_index.update(self);
+        self.spatial_index = Some(spatial_index);
     }
 }
 
@@ -84,9 +443,198 @@ impl Default for ComponentStore {
         ComponentStore {
             components: UnsafeCell::new(HashMap::new()),
             next_entity: 1,
+            change_tick: 1,
+            created: vec![0],
+            deleted: HashMap::new(),
+            name_index: NameIndex::default(),
+            spatial_index: None,
+        }
+    }
+}
+
+impl Serialize for ComponentStore {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut entities = BTreeMap::new();
+        for (_, components) in self.all_components() {
+            for (entity, component) in components {
+                entities
+                    .entry(entity.0.get())
+                    .or_insert_with(|| (entity, Vec::new()))
+                    .1
+                    .push(component);
+            }
+        }
+
+        SerializedStore {
+            next_entity: self.next_entity,
+            entities: entities.into_iter().map(|(_, entity)| entity).collect(),
+            deleted: self.deleted_since(0),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for ComponentStore {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<ComponentStore, D::Error> {
+        let serialized = SerializedStore::<Box<dyn Component>>::deserialize(deserializer)?;
+        let mut cs = ComponentStore::new();
+        cs.next_entity = serialized.next_entity.max(1);
+        cs.created.resize(cs.next_entity, 0);
+        for entity in serialized.deleted {
+            let _ = cs.deleted.insert(entity, 0);
         }
+        for (entity, components) in serialized.entities {
+            if !cs.is_alive(entity) {
+                return Err(D::Error::custom(format!(
+                    "{:?} has components, but is not alive",
+                    entity
+                )));
+            }
+            for component in components {
+                component.set_boxed(&mut cs, entity);
+            }
+        }
+        Ok(cs)
     }
 }
 
+/// The serialized form of a `ComponentStore`. `C` is a reference to a component when serializing,
+/// and a `Box` when deserializing.
+#[derive(Deserialize, Serialize)]
+struct SerializedStore<C> {
+    next_entity: usize,
+    entities: Vec<(Entity, Vec<C>)>,
+    #[serde(default)]
+    deleted: Vec<Entity>,
+}
+
 unsafe impl Send for ComponentStore {}
 unsafe impl Sync for ComponentStore {}
+
+/// The `typetag` name of a type of component, along with every component of that type and its
+/// entity.
+pub(crate) type ComponentsOfType<'a> = (&'static str, Vec<(Entity, &'a dyn Component)>);
+
+/// The `typetag` name of a type of component, along with the entities whose component of that type
+/// changed and the component (if it is still present).
+pub(crate) type ChangedOfType<'a> = (&'static str, Vec<(Entity, Option<&'a dyn Component>)>);
+
+/// Guesses the name that `typetag` gives the type of component with the given type name, as the
+/// last segment of the path. This is wrong for generic types and for types whose `Component` impl
+/// gives another name, so it's only a hint; the store learns the real name from the components
+/// themselves.
+pub(crate) fn component_tag(type_name: &str) -> &str {
+    type_name.rsplit("::").next().unwrap_or(type_name)
+}
+
+/// A mutable reference to a slot for a component, as returned by `get_mut_component`. It records
+/// the change tick when it is dropped if it was written through, unless the slot was empty before
+/// and after.
+#[derive(Debug)]
+pub struct ComponentMut<'a, T: Component> {
+    slot: &'a mut Option<T>,
+    tag: &'a mut Option<&'static str>,
+    changed: &'a mut Vec<u64>,
+    n: usize,
+    tick: u64,
+    was_some: bool,
+    written: bool,
+}
+
+impl<T: Component> Deref for ComponentMut<'_, T> {
+    type Target = Option<T>;
+
+    fn deref(&self) -> &Option<T> {
+        self.slot
+    }
+}
+
+impl<T: Component> DerefMut for ComponentMut<'_, T> {
+    fn deref_mut(&mut self) -> &mut Option<T> {
+        self.written = true;
+        self.slot
+    }
+}
+
+impl<T: Component> Drop for ComponentMut<'_, T> {
+    fn drop(&mut self) {
+        if let (None, Some(component)) = (&self.tag, &self.slot) {
+            *self.tag = Some(component.typetag_name());
+        }
+        if self.written && (self.was_some || self.slot.is_some()) {
+            mark_changed(self.changed, self.n, self.tick);
+        }
+    }
+}
+
+/// Records that the `n`th component was changed at the given change tick.
+fn mark_changed(changed: &mut Vec<u64>, n: usize, tick: u64) {
+    if changed.len() <= n {
+        changed.resize(n + 1, 0);
+    }
+    changed[n] = tick;
+}
+
+/// The storage for a single type of component.
+#[derive(Debug)]
+struct Storage {
+    vec: UnsafeOptionVec,
+    clone: Option<CloneComponent>,
+
+    /// The name of the type of component stored.
+    name: &'static str,
+
+    /// The `typetag` name of the type of component stored, which is recorded when the first
+    /// component is stored.
+    tag: Option<&'static str>,
+
+    /// Creates an empty `UnsafeOptionVec` for the type of component.
+    empty: fn() -> UnsafeOptionVec,
+
+    /// Gets the `n`th component as a trait object. This must only be called with `vec`.
+    get_dyn: unsafe fn(&UnsafeOptionVec, usize) -> Option<&dyn Component>,
+
+    /// Removes the `n`th component, if it is present. This must only be called with `vec`.
+    remove: unsafe fn(&mut UnsafeOptionVec, usize),
+
+    /// The change tick at which each component was last changed, or 0 if it never was.
+    changed: Vec<u64>,
+}
+
+impl Storage {
+    fn new<T: Component>() -> Storage {
+        unsafe fn get_dyn<T: Component>(vec: &UnsafeOptionVec, n: usize) -> Option<&dyn Component> {
+            vec.get::<T>(n)?
+                .as_ref()
+                .map(|component| -> &dyn Component { component })
+        }
+
+        unsafe fn remove<T: Component>(vec: &mut UnsafeOptionVec, n: usize) {
+            if n < vec.allocated_len() {
+                *vec.get_mut::<T>(n) = None;
+            }
+        }
+
+        Storage {
+            vec: UnsafeOptionVec::new::<T>(),
+            clone: T::clone_component(),
+            name: type_name::<T>(),
+            tag: None,
+            empty: UnsafeOptionVec::new::<T>,
+            get_dyn: get_dyn::<T>,
+            remove: remove::<T>,
+            changed: Vec::new(),
+        }
+    }
+
+    /// Returns the `typetag` name of the type of component stored. This falls back to the name of
+    /// the type if no component has been stored, in which case the name isn't sent anywhere.
+    fn tag(&self) -> &'static str {
+        self.tag.unwrap_or(self.name)
+    }
+
+    /// Records that the `n`th component was changed at the given change tick.
+    fn mark_changed(&mut self, n: usize, tick: u64) {
+        mark_changed(&mut self.changed, n, tick);
+    }
+}
@@ -1,16 +1,55 @@
 //! Some common components.
 
-use crate::Component;
+use crate::{CloneComponent, Component, Entity};
 use cgmath::Point3;
 use derive_more::{Display, From, Into};
 use serde::{Deserialize, Serialize};
 
+/// A flag marking an entity as relevant to every client of a server, however far away from the
+/// client's player it is.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct AlwaysRelevant;
+
+#[typetag::serde]
+impl Component for AlwaysRelevant {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<AlwaysRelevant>())
+    }
+}
+
+/// The names of the assets used by the entity.
+#[derive(Clone, Debug, Default, Deserialize, Eq, From, Into, PartialEq, Serialize)]
+pub struct AssetRefs(pub Vec<String>);
+
+#[typetag::serde]
+impl Component for AssetRefs {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<AssetRefs>())
+    }
+}
+
+/// A sphere around the entity's `Position`, with the given radius, that other entities can't move
+/// through.
+#[derive(Clone, Copy, Debug, Deserialize, From, Into, PartialEq, Serialize)]
+pub struct Collider(pub f32);
+
+#[typetag::serde]
+impl Component for Collider {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<Collider>())
+    }
+}
+
 /// A dataless debug flag.
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub struct DebugFlag;
 
 #[typetag::serde]
-impl Component for DebugFlag {}
+impl Component for DebugFlag {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<DebugFlag>())
+    }
+}
 
 /// The name of the entity.
 #[derive(
@@ -19,10 +58,30 @@ impl Component for DebugFlag {}
 pub struct Name(pub String);
 
 #[typetag::serde]
-impl Component for Name {}
+impl Component for Name {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<Name>())
+    }
+}
+
+/// The entity that owns the entity, such as the character carrying an item or pursuing a quest.
+/// An entity and everything it owns (directly or indirectly) are saved and loaded together.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, From, Hash, Into, PartialEq, Serialize)]
+pub struct Owner(pub Entity);
+
+#[typetag::serde]
+impl Component for Owner {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<Owner>())
+    }
+
+    fn remap_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.0 = map(self.0);
+    }
+}
 
 /// The position of the entity.
-#[derive(Debug, Deserialize, From, Into, Serialize)]
+#[derive(Clone, Copy, Debug, Deserialize, From, Into, PartialEq, Serialize)]
 pub struct Position(pub Point3<f32>);
 
 impl Position {
@@ -33,4 +92,8 @@ impl Position {
 }
 
 #[typetag::serde]
-impl Component for Position {}
+impl Component for Position {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<Position>())
+    }
+}
@@ -0,0 +1,302 @@
+//! Deltas between states of a `ComponentStore`, for replicating a world to clients.
+//!
+//! A server computes a `Delta` either from the change ticks of its store (`Delta::since`) or by
+//! comparing two stores (`Delta::between`), serializes it, and sends it to a client, which applies
+//! it to its own copy of the world. Components are serialized with their `typetag` names, just as
+//! in scenes; removed components are identified by the same names. `quantize::Codec` gives a more
+//! compact encoding for sending over the network.
+//!
+//! A server with many clients can send each one only the entities near it with
+//! `Delta::since_in_scope`; entities then appear and disappear from the client's copy as they come
+//! into and go out of its scope.
+
+use crate::{Component, ComponentStore, Entity};
+use hashbrown::{HashMap, HashSet};
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// The most entities a store can be made to allocate by applying a `Delta` to it. Deltas usually
+/// come from the network, and each entity takes up memory even if it has no components.
+pub const MAX_ENTITIES: usize = 1 << 20;
+
+/// The changes between two states of a `ComponentStore`.
+///
+/// `C` is a reference to a component when the delta is computed (and serialized), and a `Box`
+/// when it is deserialized (and applied).
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(bound(deserialize = "C: Deserialize<'de>"))]
+pub struct Delta<C = Box<dyn Component>> {
+    /// The id the next entity will be created with.
+    pub next_entity: usize,
+
+    /// The entities that were created.
+    #[serde(default)]
+    pub created: Vec<Entity>,
+
+    /// The entities that were deleted.
+    #[serde(default)]
+    pub destroyed: Vec<Entity>,
+
+    /// The components that were added to entities.
+    #[serde(default)]
+    pub added: Vec<(Entity, C)>,
+
+    /// The components that were changed.
+    #[serde(default)]
+    pub changed: Vec<(Entity, C)>,
+
+    /// The components that were removed from entities, by the `typetag` name of their type.
+    #[serde(default)]
+    pub removed: Vec<(Entity, String)>,
+}
+
+impl<C> Delta<C> {
+    /// Returns whether the delta makes no changes other than possibly allocating entities.
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.destroyed.is_empty()
+            && self.added.is_empty()
+            && self.changed.is_empty()
+            && self.removed.is_empty()
+    }
+}
+
+impl<'a> Delta<&'a dyn Component> {
+    /// Computes the changes made to the store at or after the given change tick. A client that
+    /// has applied every delta up to (but not including) `tick` can apply this one to catch up.
+    ///
+    /// This relies on change ticks, so components that were written to but not actually changed
+    /// are sent anyway. Components on entities created since `tick` are counted as added, and
+    /// components are only counted as removed if they were present at some point since `tick`.
+    pub fn since(cs: &'a ComponentStore, tick: u64) -> Delta<&'a dyn Component> {
+        let created = cs.created_since(tick);
+        let is_new = created.iter().cloned().collect::<HashSet<_>>();
+        let mut delta = Delta {
+            next_entity: cs.next_entity(),
+            created,
+            destroyed: cs.deleted_since(tick),
+            added: Vec::new(),
+            changed: Vec::new(),
+            removed: Vec::new(),
+        };
+
+        for (name, components) in cs.changed_components_since(tick) {
+            for (entity, component) in components {
+                if !cs.is_alive(entity) {
+                    continue;
+                }
+                match component {
+                    Some(component) if is_new.contains(&entity) => {
+                        delta.added.push((entity, component))
+                    }
+                    Some(component) => delta.changed.push((entity, component)),
+                    None if is_new.contains(&entity) => {}
+                    None => delta.removed.push((entity, name.to_string())),
+                }
+            }
+        }
+        delta
+    }
+
+    /// Computes the changes made at or after the given change tick to the entities in a client's
+    /// scope (its area of interest), for a client that was last sent the entities for which
+    /// `was_in_scope` returns true.
+    ///
+    /// Entities that have come into scope are sent as if they had just been created, with all
+    /// their components, and entities that have gone out of scope are sent as if they had been
+    /// destroyed. Other entities are sent as by `since`.
+    pub fn since_in_scope<F, G>(
+        cs: &'a ComponentStore,
+        tick: u64,
+        was_in_scope: F,
+        in_scope: G,
+    ) -> Delta<&'a dyn Component>
+    where
+        F: Fn(Entity) -> bool,
+        G: Fn(Entity) -> bool,
+    {
+        let mut delta = Delta {
+            next_entity: cs.next_entity(),
+            created: Vec::new(),
+            destroyed: Vec::new(),
+            added: Vec::new(),
+            changed: Vec::new(),
+            removed: Vec::new(),
+        };
+        let mut stayed = HashSet::new();
+        for entity in cs.iter_entities() {
+            match (
+                was_in_scope(entity),
+                cs.is_alive(entity) && in_scope(entity),
+            ) {
+                (false, true) => delta.created.push(entity),
+                (true, false) => delta.destroyed.push(entity),
+                (true, true) => {
+                    let _ = stayed.insert(entity);
+                }
+                (false, false) => {}
+            }
+        }
+
+        if !delta.created.is_empty() {
+            let entered = delta.created.iter().cloned().collect::<HashSet<_>>();
+            for (_, components) in cs.all_components() {
+                delta.added.extend(
+                    components
+                        .into_iter()
+                        .filter(|(entity, _)| entered.contains(entity)),
+                );
+            }
+        }
+        for (name, components) in cs.changed_components_since(tick) {
+            for (entity, component) in components {
+                if !stayed.contains(&entity) {
+                    continue;
+                }
+                match component {
+                    Some(component) => delta.changed.push((entity, component)),
+                    None => delta.removed.push((entity, name.to_string())),
+                }
+            }
+        }
+        delta
+    }
+
+    /// Computes the changes that turn `old` into `new`. Components are compared by their `Debug`
+    /// output, so they must print all their state.
+    pub fn between(old: &ComponentStore, new: &'a ComponentStore) -> Delta<&'a dyn Component> {
+        let mut delta = Delta {
+            next_entity: new.next_entity(),
+            created: new
+                .iter_entities()
+                .filter(|&entity| new.is_alive(entity) && !old.is_alive(entity))
+                .collect(),
+            destroyed: old
+                .iter_entities()
+                .filter(|&entity| old.is_alive(entity) && !new.is_alive(entity))
+                .collect(),
+            added: Vec::new(),
+            changed: Vec::new(),
+            removed: Vec::new(),
+        };
+
+        let mut old_components = old
+            .all_components()
+            .into_iter()
+            .flat_map(|(name, components)| {
+                components
+                    .into_iter()
+                    .map(move |(entity, component)| ((name, entity), format!("{:?}", component)))
+            })
+            .collect::<HashMap<_, _>>();
+        for (name, components) in new.all_components() {
+            for (entity, component) in components {
+                match old_components.remove(&(name, entity)) {
+                    Some(old) => {
+                        if old != format!("{:?}", component) {
+                            delta.changed.push((entity, component));
+                        }
+                    }
+                    None => delta.added.push((entity, component)),
+                }
+            }
+        }
+
+        let mut removed = old_components
+            .into_iter()
+            .map(|((name, entity), _)| (entity, name))
+            .filter(|&(entity, _)| new.is_alive(entity))
+            .collect::<Vec<_>>();
+        removed.sort_by_key(|&(entity, name)| (name, entity.0));
+        delta.removed = removed
+            .into_iter()
+            .map(|(entity, name)| (entity, name.to_string()))
+            .collect();
+        delta
+    }
+}
+
+impl Delta {
+    /// Applies the delta to a store, which should be in the state the delta was computed from.
+    ///
+    /// Created entities that are already alive in the store have all their components removed
+    /// first, so that they end up exactly as sent. This lets a client that isn't sure which of
+    /// the entities it has are up to date be sent them afresh.
+    ///
+    /// Fails without changing the store if the delta refers to entities past its `next_entity`,
+    /// or would allocate more than `MAX_ENTITIES` entities.
+    pub fn apply(self, cs: &mut ComponentStore) -> Result<(), DeltaError> {
+        if self.next_entity > MAX_ENTITIES {
+            return Err(DeltaError::TooManyEntities(self.next_entity));
+        }
+        let entities = self
+            .created
+            .iter()
+            .chain(&self.destroyed)
+            .chain(
+                self.added
+                    .iter()
+                    .chain(&self.changed)
+                    .map(|(entity, _)| entity),
+            )
+            .chain(self.removed.iter().map(|(entity, _)| entity));
+        for &entity in entities {
+            if entity.0.get() >= self.next_entity {
+                return Err(DeltaError::EntityOutOfRange(entity));
+            }
+        }
+
+        cs.allocate_up_to(self.next_entity);
+        for &entity in &self.created {
+            if cs.is_alive(entity) {
+                cs.delete_entity(entity);
+            }
+            cs.revive(entity);
+        }
+
+        for entity in self.destroyed {
+            cs.delete_entity(entity);
+        }
+        for (entity, component) in self.added.into_iter().chain(self.changed) {
+            component.set_boxed(cs, entity);
+        }
+        for (entity, tag) in self.removed {
+            cs.remove_by_tag(entity, &tag);
+        }
+        Ok(())
+    }
+}
+
+/// An error applying a `Delta`.
+#[derive(Debug)]
+pub enum DeltaError {
+    /// The delta would allocate the given number of entities, which is more than `MAX_ENTITIES`.
+    TooManyEntities(usize),
+
+    /// The delta referred to an entity at or past its `next_entity`.
+    EntityOutOfRange(Entity),
+}
+
+impl Display for DeltaError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            DeltaError::TooManyEntities(n) => write!(
+                fmt,
+                "The delta would allocate {} entities, but at most {} are allowed",
+                n, MAX_ENTITIES
+            ),
+            DeltaError::EntityOutOfRange(entity) => {
+                write!(
+                    fmt,
+                    "The delta refers to {:?}, which it doesn't allocate",
+                    entity
+                )
+            }
+        }
+    }
+}
+
+impl Error for DeltaError {}
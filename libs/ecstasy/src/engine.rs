@@ -1,7 +1,17 @@
-use crate::{ComponentStore, System, SystemMut};
+use crate::{
+    replay::{self, Input, Recorder, Recording, ReplayError, WorldHash},
+    scene::{Scene, SceneError},
+    spatial::SpatialIndex,
+    ComponentStore, Entity, System, SystemMut,
+};
 use assets::Assets;
 use frunk::{hlist, Hlist};
-use std::time::Instant;
+use std::{cell::Cell, time::Instant};
+
+thread_local! {
+    /// Whether the systems in parallel passes should be run one at a time, in a fixed order.
+    static SEQUENTIAL: Cell<bool> = Cell::new(false);
+}
 
 /// An `Engine` that wraps a trait object.
 type BoxedEngine = Engine<Box<dyn SystemMut>>;
@@ -17,6 +27,8 @@ pub struct Engine<P: SystemMut> {
 
     last_frame: Instant,
     passes: P,
+    systems: Vec<String>,
+    recorder: Option<Recorder>,
 }
 
 impl Engine<Hlist![]> {
@@ -27,13 +39,16 @@ impl Engine<Hlist![]> {
             store: ComponentStore::new(),
             last_frame: Instant::now(),
             passes: hlist![],
+            systems: Vec::new(),
+            recorder: None,
         }
     }
 }
 
 impl<P: SystemMut> Engine<P> {
     /// Adds a `SystemMut` as a pass.
-    pub fn add_mut_pass<T: SystemMut>(self, system: T) -> Engine<Hlist![Mut<T>, ...P]> {
+    pub fn add_mut_pass<T: SystemMut>(mut self, system: T) -> Engine<Hlist![Mut<T>, ...P]> {
+        self.systems.push(system.name().to_string());
         self.map_passes(|p| hlist![Mut(system), ...p])
     }
 
@@ -52,6 +67,8 @@ impl<P: SystemMut> Engine<P> {
             store: self.store,
             last_frame: self.last_frame,
             passes: func(self.passes),
+            systems: self.systems,
+            recorder: self.recorder,
         }
     }
 
@@ -62,10 +79,103 @@ impl<P: SystemMut> Engine<P> {
         self.last_frame = now;
 
         let dt = (dt.as_nanos() as f32) / 1_000_000_000.0;
-        self.passes.run(&mut self.store, dt)
+        self.run_tick(dt)
+    }
+
+    /// Runs the engine for one turn with the given `dt` (in seconds), rather than the time since
+    /// the last turn. If the engine is recording, the turn is recorded.
+    pub fn run_tick(&mut self, dt: f32) {
+        let sequential = self.recorder.is_some();
+        run_passes(&mut self.passes, &mut self.store, dt, sequential);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_tick(dt, &self.store);
+        }
+    }
+
+    /// Applies an input from outside the engine to the store, returning the entity it created (if
+    /// any). If the engine is recording, the input is recorded.
+    pub fn apply_input(&mut self, input: Input) -> Option<Entity> {
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record_input(&input);
+        }
+        input.apply(&mut self.store)
+    }
+
+    /// Spawns a scene into the store as a series of inputs, returning its entities in the same
+    /// order as they were in the scene. If the engine is recording, the inputs are recorded, so
+    /// the scene doesn't have to be available to replay the recording.
+    pub fn spawn_scene(&mut self, scene: Scene) -> Result<Vec<Entity>, SceneError> {
+        scene.spawn_with(|input| self.apply_input(input))
+    }
+
+    /// Starts recording ticks and inputs, saving a hash of the world every `checkpoint_interval`
+    /// ticks. See the `replay` module for details.
+    ///
+    /// The store is replaced with a copy of itself, which has its change ticks reset, so that
+    /// replays start from exactly the same state.
+    pub fn start_recording(&mut self, checkpoint_interval: usize) -> Result<(), ReplayError> {
+        let world = replay::copy(&self.store)?;
+        let store = replay::copy(&self.store)?;
+        self.replace_store(store);
+        let systems = self.systems.clone();
+        self.recorder = Some(Recorder::new(world, systems, checkpoint_interval));
+        Ok(())
+    }
+
+    /// Stops recording, returning the recording. Returns `None` if the engine was not recording.
+    pub fn stop_recording(&mut self) -> Option<Result<Recording, ReplayError>> {
+        self.recorder.take().map(Recorder::finish)
+    }
+
+    /// Replays a recording, replacing the store with the world it starts from. The world is
+    /// checked against each hash saved in the recording, stopping at the first one that doesn't
+    /// match.
+    ///
+    /// The engine must have the same systems as the one that made the recording, added in the
+    /// same order; otherwise, the recording isn't replayed at all.
+    pub fn replay(&mut self, recording: Recording) -> Result<(), ReplayError> {
+        if recording.systems != self.systems {
+            return Err(ReplayError::WrongSystems {
+                expected: recording.systems,
+                found: self.systems.clone(),
+            });
+        }
+        self.replace_store(recording.world);
+        for (i, tick) in recording.ticks.into_iter().enumerate() {
+            for input in tick.inputs {
+                let _ = input.apply(&mut self.store);
+            }
+            run_passes(&mut self.passes, &mut self.store, tick.dt, true);
+
+            if let Some(expected) = tick.hash {
+                let components = WorldHash::of(&self.store).diff(&expected);
+                if !components.is_empty() {
+                    return Err(ReplayError::Diverged {
+                        tick: i,
+                        components,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the store, keeping a spatial index if the old store had one.
+    fn replace_store(&mut self, mut store: ComponentStore) {
+        if let Some(spatial_index) = self.store.spatial_index() {
+            store.set_spatial_index(SpatialIndex::new(spatial_index.cell_size()));
+        }
+        self.store = store;
     }
 }
 
+/// Runs the passes, optionally running the systems in parallel passes one at a time.
+fn run_passes<P: SystemMut>(passes: &mut P, cs: &mut ComponentStore, dt: f32, sequential: bool) {
+    let old = SEQUENTIAL.with(|s| s.replace(sequential));
+    passes.run(cs, dt);
+    SEQUENTIAL.with(|s| s.set(old));
+}
+
 impl<P: 'static + SystemMut> Engine<P> {
     /// Converts the engine to use a trait object as its bound.
     pub fn boxed(self) -> BoxedEngine {
@@ -85,7 +195,8 @@ pub struct EnginePassBuilder<P: SystemMut, B: System> {
 impl<P: SystemMut, B: System> EnginePassBuilder<P, B> {
     /// Adds a `System` to be run in parallel with the rest of the pass.
     #[allow(clippy::should_implement_trait)]
-    pub fn add<T: System>(self, system: T) -> EnginePassBuilder<P, Hlist![T, ...B]> {
+    pub fn add<T: System>(mut self, system: T) -> EnginePassBuilder<P, Hlist![T, ...B]> {
+        self.engine.systems.push(system.name().to_string());
         EnginePassBuilder {
             engine: self.engine,
             pass: hlist![system, ...self.pass],
@@ -109,6 +220,7 @@ impl<H: System, T: SystemMut> SystemMut for Hlist![Par<H>, ...T] {
     fn run(&mut self, cs: &mut ComponentStore, dt: f32) {
         self.tail.run(cs, dt);
         self.head.0.run(cs, dt);
+        cs.maintain();
     }
 }
 
@@ -116,6 +228,7 @@ impl<H: SystemMut, T: SystemMut> SystemMut for Hlist![Mut<H>, ...T] {
     fn run(&mut self, cs: &mut ComponentStore, dt: f32) {
         self.tail.run(cs, dt);
         self.head.0.run(cs, dt);
+        cs.maintain();
     }
 }
 
@@ -127,7 +240,12 @@ impl<H: System, T: System> System for Hlist![H, ...T] {
     fn run(&mut self, cs: &ComponentStore, dt: f32) {
         let h = &mut self.head;
         let t = &mut self.tail;
-        let ((), ()) = rayon::join(|| h.run(cs, dt), || t.run(cs, dt));
+        if SEQUENTIAL.with(Cell::get) {
+            h.run(cs, dt);
+            t.run(cs, dt);
+        } else {
+            let ((), ()) = rayon::join(|| h.run(cs, dt), || t.run(cs, dt));
+        }
     }
 }
 
@@ -109,22 +109,36 @@
 #[macro_use]
 extern crate pretty_assertions;
 
+// Lets the tests use the derive and system macros, which refer to this crate as `ecstasy`.
+#[cfg(test)]
+extern crate self as ecstasy;
+
+mod cloning;
 mod component_store;
 pub mod components;
+pub mod delta;
 mod engine;
+mod name_index;
+pub mod quantize;
+pub mod replay;
+pub mod scene;
+pub mod snapshot;
+pub mod spatial;
 mod unsafe_option_vec;
 
 pub use crate::{
-    component_store::ComponentStore,
+    cloning::{CloneComponent, MapEntities},
+    component_store::{ComponentMut, ComponentStore},
     engine::{Engine, EnginePassBuilder},
 };
 pub use ecstasy_proc_macros::{system, system_mut, Component};
-use std::{fmt::Debug, num::NonZeroUsize};
+use serde::{Deserialize, Serialize};
+use std::{any::Any, fmt::Debug, num::NonZeroUsize};
 
 /// An entity.
 ///
 /// This is an integer, wrapped up so as to preserve type safety.
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct Entity(NonZeroUsize);
 
 /// Components are data which can be attached to entities via a `ComponentStore`.
@@ -138,8 +152,56 @@ pub struct Entity(NonZeroUsize);
 /// #[derive(Component, Debug, Deserialize, Serialize)]
 /// struct Foo(u32, isize);
 /// ```
+///
+/// Components are not cloneable by default. Deriving with `#[component(clone)]` allows them to be
+/// copied by `ComponentStore::clone_entity` and `ComponentStore::instantiate`; fields marked with
+/// `#[component(entity)]` are remapped when they are copied.
+///
+/// ```
+/// # use serde::{Deserialize, Serialize};
+/// use ecstasy::{Component, Entity};
+///
+/// #[derive(Clone, Component, Debug, Deserialize, Serialize)]
+/// #[component(clone)]
+/// struct Parent(#[component(entity)] Entity);
+/// ```
+///
+/// Deriving with `#[component(quantize)]` also derives `quantize::Quantize`, so that the
+/// component can be sent compactly by a `quantize::Codec`.
 #[typetag::serde(tag = "t")]
-pub trait Component: 'static + Debug + Send + Sync {}
+pub trait Component: 'static + AnyComponent + Debug + Send + Sync {
+    /// Returns the functions used to clone the component, or `None` if it cannot be cloned.
+    fn clone_component() -> Option<CloneComponent>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Replaces each entity referred to by the component with the result of calling `map` on it.
+    fn remap_entities(&mut self, _map: &mut dyn FnMut(Entity) -> Entity) {}
+}
+
+/// Operations on components whose types are not statically known. This is automatically
+/// implemented for every `Component`.
+#[doc(hidden)]
+pub trait AnyComponent {
+    /// Sets the component on the given entity.
+    fn set_boxed(self: Box<Self>, cs: &mut ComponentStore, entity: Entity);
+
+    /// Returns the component as an `Any`, so that it can be downcast.
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Component> AnyComponent for T {
+    fn set_boxed(self: Box<T>, cs: &mut ComponentStore, entity: Entity) {
+        cs.set_component(entity, *self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
 
 /// A system that does not modify the `ComponentStore`. These systems can be run in parallel with
 /// *each other*, but should generally not use parallelism internally.
@@ -148,12 +210,22 @@ pub trait System: Send {
     ///
     /// `dt` is in seconds.
     fn run(&mut self, cs: &ComponentStore, dt: f32);
+
+    /// Returns the name of the system, which recordings use to check that they're replayed with
+    /// the same systems. Defaults to the name of the type.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
 }
 
 impl<T: ?Sized + System> System for Box<T> {
     fn run(&mut self, cs: &ComponentStore, dt: f32) {
         (**self).run(cs, dt)
     }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
 }
 
 /// A system that modifies the `ComponentStore`.
@@ -162,12 +234,22 @@ pub trait SystemMut {
     ///
     /// `dt` is in seconds.
     fn run(&mut self, cs: &mut ComponentStore, dt: f32);
+
+    /// Returns the name of the system, which recordings use to check that they're replayed with
+    /// the same systems. Defaults to the name of the type.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
 }
 
 impl<T: ?Sized + SystemMut> SystemMut for Box<T> {
     fn run(&mut self, cs: &mut ComponentStore, dt: f32) {
         (**self).run(cs, dt)
     }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,58 @@
+use crate::Entity;
+use hashbrown::HashMap;
+
+/// An index from the `Name` components of entities to the entities themselves.
+#[derive(Debug, Default)]
+pub(crate) struct NameIndex {
+    entities: HashMap<String, Vec<Entity>>,
+    names: HashMap<Entity, String>,
+
+    /// The change tick as of which the index is up to date.
+    pub last_tick: u64,
+}
+
+impl NameIndex {
+    /// Returns the entities with the given name, in the order they were given it.
+    pub fn find(&self, name: &str) -> &[Entity] {
+        self.entities
+            .get(name)
+            .map(|entities| &entities[..])
+            .unwrap_or(&[])
+    }
+
+    /// Returns the names that more than one entity has, along with the entities that have them.
+    pub fn duplicates(&self) -> impl Iterator<Item = (&str, &[Entity])> {
+        self.entities
+            .iter()
+            .filter(|(_, entities)| entities.len() > 1)
+            .map(|(name, entities)| (name.as_str(), &entities[..]))
+    }
+
+    /// Records that the entity has the given name (or no name).
+    pub fn set(&mut self, entity: Entity, name: Option<String>) {
+        if self.names.get(&entity) == name.as_ref() {
+            return;
+        }
+
+        if let Some(old) = self.names.remove(&entity) {
+            let now_empty = match self.entities.get_mut(&old) {
+                Some(entities) => {
+                    entities.retain(|&e| e != entity);
+                    entities.is_empty()
+                }
+                None => false,
+            };
+            if now_empty {
+                let _ = self.entities.remove(&old);
+            }
+        }
+
+        if let Some(name) = name {
+            self.entities
+                .entry(name.clone())
+                .or_insert_with(Vec::new)
+                .push(entity);
+            let _ = self.names.insert(entity, name);
+        }
+    }
+}
@@ -0,0 +1,790 @@
+//! Compact, lossy serialization of components, for sending them over the network.
+//!
+//! Components serialized with serde are written out in full: every `f32` of a `Position` is sent,
+//! as text in the S-expression format. Components that implement `Quantize` can instead be packed
+//! into a bit buffer with only the precision they need. `Point3<f32>`s are taken to be positions
+//! in the world, and are stored as fixed-point numbers within the `WorldBounds`; `Quaternion`s are
+//! stored as their smallest three components; and no value is padded out to a byte boundary.
+//!
+//! `Quantize` is derived along with `Component` by adding `#[component(quantize)]`, which
+//! quantizes each field of the struct in turn:
+//!
+//! ```
+//! # use cgmath::{Point3, Quaternion};
+//! # use serde::{Deserialize, Serialize};
+//! use ecstasy::{
+//!     quantize::{BitReader, BitWriter, Quantize, WorldBounds},
+//!     Component,
+//! };
+//!
+//! #[derive(Component, Debug, Deserialize, Serialize)]
+//! #[component(quantize)]
+//! struct Pose {
+//!     position: Point3<f32>,
+//!     rotation: Quaternion<f32>,
+//!     grounded: bool,
+//! }
+//!
+//! let pose = Pose {
+//!     position: Point3::new(12.5, -3.0, 100.0),
+//!     rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+//!     grounded: true,
+//! };
+//! let mut writer = BitWriter::new(WorldBounds::default());
+//! pose.quantize(&mut writer);
+//! // Each coordinate takes 22 bits within the default bounds, and the rotation takes 32.
+//! assert_eq!(writer.len_bits(), 3 * 22 + 32 + 1);
+//!
+//! let buf = writer.into_bytes();
+//! let pose = Pose::dequantize(&mut BitReader::new(&buf, WorldBounds::default())).unwrap();
+//! assert!((pose.position.x - 12.5).abs() < 0.01);
+//! assert!(pose.grounded);
+//! ```
+//!
+//! A `Codec` encodes whole `Delta`s, quantizing the types of component registered with it and
+//! serializing the rest with serde as usual. Since registered types are identified by the order
+//! they were registered in, the server and its clients must register the same types in the same
+//! order.
+
+use crate::{
+    component_store::component_tag,
+    components::{AlwaysRelevant, AssetRefs, Collider, DebugFlag, Name, Owner, Position},
+    delta::Delta,
+    Component, Entity,
+};
+use cgmath::{InnerSpace, Point3, Quaternion, Vector3};
+use hashbrown::HashMap;
+use std::{
+    any::{type_name, TypeId},
+    borrow::Borrow,
+    error::Error,
+    f32::consts::FRAC_1_SQRT_2,
+    fmt::{Debug, Display, Formatter, Result as FmtResult},
+    num::NonZeroUsize,
+};
+
+/// The number of bits each of the smallest three components of a quaternion is stored in.
+pub const ROTATION_BITS: u32 = 10;
+
+/// The region of the world positions are expected to be in, and how precisely they are stored.
+/// Positions outside the bounds are moved to the nearest point inside them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WorldBounds {
+    /// The corner of the region with the smallest coordinates.
+    pub min: Point3<f32>,
+
+    /// The corner of the region with the largest coordinates.
+    pub max: Point3<f32>,
+
+    /// The largest step between two storable values on each axis. Positions are stored to within
+    /// half of this.
+    pub precision: f32,
+}
+
+impl WorldBounds {
+    /// Returns the number of bits each coordinate of a position is stored in.
+    pub fn position_bits(&self) -> [u32; 3] {
+        let size = self.max - self.min;
+        let bits = |size: f32| {
+            let steps = (f64::from(size) / f64::from(self.precision)).ceil() + 1.0;
+            (steps.log2().ceil() as u32).clamp(1, 32)
+        };
+        [bits(size.x), bits(size.y), bits(size.z)]
+    }
+}
+
+impl Default for WorldBounds {
+    /// A cube 8km on a side, centered on the origin, with a precision of 1/256th of a meter.
+    fn default() -> WorldBounds {
+        WorldBounds {
+            min: Point3::new(-4096.0, -4096.0, -4096.0),
+            max: Point3::new(4096.0, 4096.0, 4096.0),
+            precision: 1.0 / 256.0,
+        }
+    }
+}
+
+/// A buffer that values are packed into bit by bit.
+#[derive(Clone, Debug)]
+pub struct BitWriter {
+    bounds: WorldBounds,
+    buf: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    /// Creates an empty buffer, which positions will be written to within the given bounds.
+    pub fn new(bounds: WorldBounds) -> BitWriter {
+        BitWriter {
+            bounds,
+            buf: Vec::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the bounds positions are written within.
+    pub fn bounds(&self) -> &WorldBounds {
+        &self.bounds
+    }
+
+    /// Returns the number of bits that have been written.
+    pub fn len_bits(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the bytes that have been written. The last byte is padded with zeroes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+
+    /// Writes the low `n` bits of `value`, most significant first. `n` must be at most 64.
+    pub fn write_bits(&mut self, value: u64, n: u32) {
+        assert!(n <= 64, "can't write more than 64 bits at once");
+        for i in (0..n).rev() {
+            if self.len % 8 == 0 {
+                self.buf.push(0);
+            }
+            let bit = (value >> i) as u8 & 1;
+            *self.buf.last_mut().unwrap() |= bit << (7 - self.len % 8);
+            self.len += 1;
+        }
+    }
+
+    /// Writes a single bit.
+    pub fn write_bool(&mut self, value: bool) {
+        self.write_bits(value as u64, 1);
+    }
+
+    /// Writes an integer in groups of 7 bits, each preceded by a bit saying whether another group
+    /// follows, so that small numbers take up little space.
+    pub fn write_varint(&mut self, mut value: u64) {
+        loop {
+            let more = value >= 0x80;
+            self.write_bool(more);
+            self.write_bits(value & 0x7f, 7);
+            value >>= 7;
+            if !more {
+                break;
+            }
+        }
+    }
+
+    /// Writes a length-prefixed sequence of bytes.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_varint(bytes.len() as u64);
+        for &byte in bytes {
+            self.write_bits(u64::from(byte), 8);
+        }
+    }
+
+    /// Writes `value` as a fixed-point number in `bits` bits, with `min` as zero and `max` as the
+    /// largest number that fits. Values outside `[min, max]` are clamped to it.
+    pub fn write_fixed(&mut self, value: f32, min: f32, max: f32, bits: u32) {
+        let t = (f64::from(value) - f64::from(min)) / (f64::from(max) - f64::from(min));
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        self.write_bits((t * max_fixed(bits)).round() as u64, bits);
+    }
+
+    /// Writes a position as fixed-point numbers within the bounds.
+    pub fn write_position(&mut self, position: Point3<f32>) {
+        let bounds = self.bounds;
+        let [x, y, z] = bounds.position_bits();
+        self.write_fixed(position.x, bounds.min.x, bounds.max.x, x);
+        self.write_fixed(position.y, bounds.min.y, bounds.max.y, y);
+        self.write_fixed(position.z, bounds.min.z, bounds.max.z, z);
+    }
+}
+
+/// A buffer that values are unpacked from bit by bit, as written by a `BitWriter`. The read
+/// methods return `None` if the buffer ends before the value does.
+#[derive(Clone, Debug)]
+pub struct BitReader<'a> {
+    bounds: WorldBounds,
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Creates a reader over the given bytes, from which positions will be read within the given
+    /// bounds.
+    pub fn new(buf: &'a [u8], bounds: WorldBounds) -> BitReader<'a> {
+        BitReader {
+            bounds,
+            buf,
+            pos: 0,
+        }
+    }
+
+    /// Returns the bounds positions are read within.
+    pub fn bounds(&self) -> &WorldBounds {
+        &self.bounds
+    }
+
+    /// Returns the number of bits that haven't been read yet. This includes any padding at the
+    /// end of the buffer.
+    pub fn remaining_bits(&self) -> usize {
+        self.buf.len() * 8 - self.pos
+    }
+
+    /// Reads `n` bits, most significant first. `n` must be at most 64.
+    pub fn read_bits(&mut self, n: u32) -> Option<u64> {
+        assert!(n <= 64, "can't read more than 64 bits at once");
+        if self.remaining_bits() < n as usize {
+            return None;
+        }
+        let mut value = 0;
+        for _ in 0..n {
+            let bit = self.buf[self.pos / 8] >> (7 - self.pos % 8) & 1;
+            value = value << 1 | u64::from(bit);
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    /// Reads a single bit.
+    pub fn read_bool(&mut self) -> Option<bool> {
+        self.read_bits(1).map(|bit| bit == 1)
+    }
+
+    /// Reads an integer written by `BitWriter::write_varint`.
+    pub fn read_varint(&mut self) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let more = self.read_bool()?;
+            let group = self.read_bits(7)?;
+            if shift >= 64 {
+                return None;
+            }
+            value |= group << shift;
+            shift += 7;
+            if !more {
+                return Some(value);
+            }
+        }
+    }
+
+    /// Reads a length-prefixed sequence of bytes.
+    pub fn read_bytes(&mut self) -> Option<Vec<u8>> {
+        let len = self.read_varint()? as usize;
+        if self.remaining_bits() / 8 < len {
+            return None;
+        }
+        (0..len)
+            .map(|_| self.read_bits(8).map(|b| b as u8))
+            .collect()
+    }
+
+    /// Reads a number written by `BitWriter::write_fixed` with the same `min`, `max`, and `bits`.
+    pub fn read_fixed(&mut self, min: f32, max: f32, bits: u32) -> Option<f32> {
+        let t = self.read_bits(bits)? as f64 / max_fixed(bits);
+        Some((f64::from(min) + t * (f64::from(max) - f64::from(min))) as f32)
+    }
+
+    /// Reads a position written by `BitWriter::write_position`.
+    pub fn read_position(&mut self) -> Option<Point3<f32>> {
+        let bounds = self.bounds;
+        let [x, y, z] = bounds.position_bits();
+        Some(Point3::new(
+            self.read_fixed(bounds.min.x, bounds.max.x, x)?,
+            self.read_fixed(bounds.min.y, bounds.max.y, y)?,
+            self.read_fixed(bounds.min.z, bounds.max.z, z)?,
+        ))
+    }
+}
+
+/// Returns the largest number that fits in the given number of bits, as a float.
+fn max_fixed(bits: u32) -> f64 {
+    ((1u128 << bits) - 1) as f64
+}
+
+/// A value that can be packed into a `BitWriter`, possibly losing some precision.
+///
+/// This is implemented for the built-in components, and can be derived for others with
+/// `#[component(quantize)]`.
+pub trait Quantize: Sized {
+    /// Writes the value.
+    fn quantize(&self, w: &mut BitWriter);
+
+    /// Reads a value written by `quantize`, returning `None` if it is truncated or invalid.
+    fn dequantize(r: &mut BitReader) -> Option<Self>;
+}
+
+impl Quantize for bool {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_bool(*self)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<bool> {
+        r.read_bool()
+    }
+}
+
+macro_rules! quantize_fixed_width {
+    ($($ty:ty),*) => {
+        $(impl Quantize for $ty {
+            fn quantize(&self, w: &mut BitWriter) {
+                w.write_bits(u64::from(*self), 8 * std::mem::size_of::<$ty>() as u32)
+            }
+
+            fn dequantize(r: &mut BitReader) -> Option<$ty> {
+                r.read_bits(8 * std::mem::size_of::<$ty>() as u32).map(|n| n as $ty)
+            }
+        })*
+    };
+}
+
+quantize_fixed_width!(u8, u16, u32);
+
+impl Quantize for u64 {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_varint(*self)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<u64> {
+        r.read_varint()
+    }
+}
+
+impl Quantize for usize {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_varint(*self as u64)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<usize> {
+        r.read_varint().map(|n| n as usize)
+    }
+}
+
+impl Quantize for i32 {
+    fn quantize(&self, w: &mut BitWriter) {
+        i64::from(*self).quantize(w)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<i32> {
+        i64::dequantize(r).map(|n| n as i32)
+    }
+}
+
+impl Quantize for i64 {
+    /// Written as a varint, with the sign in the lowest bit so that small negative numbers are
+    /// small too.
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_varint(((*self << 1) ^ (*self >> 63)) as u64)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<i64> {
+        let n = r.read_varint()?;
+        Some((n >> 1) as i64 ^ -((n & 1) as i64))
+    }
+}
+
+impl Quantize for f32 {
+    /// Written in full, since nothing is known about its range.
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_bits(u64::from(self.to_bits()), 32)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<f32> {
+        r.read_bits(32).map(|bits| f32::from_bits(bits as u32))
+    }
+}
+
+impl Quantize for String {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_bytes(self.as_bytes())
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<String> {
+        String::from_utf8(r.read_bytes()?).ok()
+    }
+}
+
+impl<T: Quantize> Quantize for Option<T> {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_bool(self.is_some());
+        if let Some(x) = self {
+            x.quantize(w);
+        }
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Option<T>> {
+        if r.read_bool()? {
+            T::dequantize(r).map(Some)
+        } else {
+            Some(None)
+        }
+    }
+}
+
+impl<T: Quantize> Quantize for Vec<T> {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_varint(self.len() as u64);
+        self.iter().for_each(|x| x.quantize(w));
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Vec<T>> {
+        let len = r.read_varint()?;
+        // Capping the length at the bits left keeps bogus lengths from allocating more than the
+        // input's size. Elements of zero-bit types take no bits, so longer vectors of those are
+        // rejected too.
+        if len > r.remaining_bits() as u64 {
+            return None;
+        }
+        (0..len).map(|_| T::dequantize(r)).collect()
+    }
+}
+
+impl Quantize for Entity {
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_varint(self.0.get() as u64)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Entity> {
+        NonZeroUsize::new(r.read_varint()? as usize).map(Entity)
+    }
+}
+
+impl Quantize for Point3<f32> {
+    /// Written as a position in the world, as by `BitWriter::write_position`.
+    fn quantize(&self, w: &mut BitWriter) {
+        w.write_position(*self)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Point3<f32>> {
+        r.read_position()
+    }
+}
+
+impl Quantize for Quaternion<f32> {
+    /// Written as a rotation, normalized, with the "smallest three" method: the index of the
+    /// component with the largest magnitude is written in two bits, followed by the other three in
+    /// `ROTATION_BITS` bits each. The largest component is recomputed from the others when it is
+    /// read, and is made positive by negating the whole quaternion (which doesn't change the
+    /// rotation it represents), so the others must each be between ±1/√2.
+    fn quantize(&self, w: &mut BitWriter) {
+        let q = self.normalize();
+        let parts = [q.v.x, q.v.y, q.v.z, q.s];
+        let mut largest = 0;
+        for i in 1..4 {
+            if parts[i].abs() > parts[largest].abs() {
+                largest = i;
+            }
+        }
+        let sign = if parts[largest] < 0.0 { -1.0 } else { 1.0 };
+
+        w.write_bits(largest as u64, 2);
+        for (i, &part) in parts.iter().enumerate() {
+            if i != largest {
+                w.write_fixed(part * sign, -FRAC_1_SQRT_2, FRAC_1_SQRT_2, ROTATION_BITS);
+            }
+        }
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Quaternion<f32>> {
+        let largest = r.read_bits(2)? as usize;
+        let mut parts = [0.0; 4];
+        let mut sum = 0.0;
+        for (i, part) in parts.iter_mut().enumerate() {
+            if i != largest {
+                *part = r.read_fixed(-FRAC_1_SQRT_2, FRAC_1_SQRT_2, ROTATION_BITS)?;
+                sum += *part * *part;
+            }
+        }
+        parts[largest] = (1.0 - sum).max(0.0).sqrt();
+        Some(Quaternion::from_sv(
+            parts[3],
+            Vector3::new(parts[0], parts[1], parts[2]),
+        ))
+    }
+}
+
+impl Quantize for AlwaysRelevant {
+    fn quantize(&self, _w: &mut BitWriter) {}
+
+    fn dequantize(_r: &mut BitReader) -> Option<AlwaysRelevant> {
+        Some(AlwaysRelevant)
+    }
+}
+
+impl Quantize for AssetRefs {
+    fn quantize(&self, w: &mut BitWriter) {
+        self.0.quantize(w)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<AssetRefs> {
+        Vec::dequantize(r).map(AssetRefs)
+    }
+}
+
+impl Quantize for Collider {
+    fn quantize(&self, w: &mut BitWriter) {
+        self.0.quantize(w)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Collider> {
+        f32::dequantize(r).map(Collider)
+    }
+}
+
+impl Quantize for DebugFlag {
+    fn quantize(&self, _w: &mut BitWriter) {}
+
+    fn dequantize(_r: &mut BitReader) -> Option<DebugFlag> {
+        Some(DebugFlag)
+    }
+}
+
+impl Quantize for Name {
+    fn quantize(&self, w: &mut BitWriter) {
+        self.0.quantize(w)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Name> {
+        String::dequantize(r).map(Name)
+    }
+}
+
+impl Quantize for Owner {
+    fn quantize(&self, w: &mut BitWriter) {
+        self.0.quantize(w)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Owner> {
+        Entity::dequantize(r).map(Owner)
+    }
+}
+
+impl Quantize for Position {
+    fn quantize(&self, w: &mut BitWriter) {
+        self.0.quantize(w)
+    }
+
+    fn dequantize(r: &mut BitReader) -> Option<Position> {
+        Point3::dequantize(r).map(Position)
+    }
+}
+
+/// Encodes and decodes `Delta`s in the compact form described in the module documentation.
+///
+/// The encoding starts with `next_entity`, followed by the lengths and elements of each list in
+/// the delta. Each component is preceded by a bit saying whether it is quantized: if so, it is
+/// identified by the index of its type in the codec; if not, it is serialized as an S-expression,
+/// with its `typetag` name. The types of removed components are identified the same way.
+pub struct Codec {
+    bounds: WorldBounds,
+    types: Vec<QuantizedType>,
+    indices: HashMap<TypeId, usize>,
+}
+
+/// A list of components and the entities they're on, as in a `Delta`.
+type EntityComponents = Vec<(Entity, Box<dyn Component>)>;
+
+/// A type of component registered with a `Codec`.
+struct QuantizedType {
+    /// A guess at the `typetag` name of the type, used to encode removals compactly. Removals
+    /// whose names don't match any registered type's are written out in full.
+    tag: &'static str,
+    quantize: fn(&dyn Component, &mut BitWriter),
+    dequantize: fn(&mut BitReader) -> Option<Box<dyn Component>>,
+}
+
+impl Codec {
+    /// Creates a codec that quantizes the built-in components, and writes positions within the
+    /// given bounds.
+    pub fn new(bounds: WorldBounds) -> Codec {
+        let mut codec = Codec {
+            bounds,
+            types: Vec::new(),
+            indices: HashMap::new(),
+        };
+        codec.register::<AlwaysRelevant>();
+        codec.register::<AssetRefs>();
+        codec.register::<Collider>();
+        codec.register::<DebugFlag>();
+        codec.register::<Name>();
+        codec.register::<Owner>();
+        codec.register::<Position>();
+        codec
+    }
+
+    /// Returns the bounds positions are written within.
+    pub fn bounds(&self) -> &WorldBounds {
+        &self.bounds
+    }
+
+    /// Registers a type of component to be quantized. Registering a type again does nothing.
+    pub fn register<T: Component + Quantize>(&mut self) {
+        fn quantize<T: Component + Quantize>(component: &dyn Component, w: &mut BitWriter) {
+            component
+                .as_any()
+                .downcast_ref::<T>()
+                .expect("component quantized as the wrong type")
+                .quantize(w)
+        }
+
+        fn dequantize<T: Component + Quantize>(r: &mut BitReader) -> Option<Box<dyn Component>> {
+            let component: Box<dyn Component> = Box::new(T::dequantize(r)?);
+            Some(component)
+        }
+
+        if self.indices.contains_key(&TypeId::of::<T>()) {
+            return;
+        }
+        let _ = self.indices.insert(TypeId::of::<T>(), self.types.len());
+        self.types.push(QuantizedType {
+            tag: component_tag(type_name::<T>()),
+            quantize: quantize::<T>,
+            dequantize: dequantize::<T>,
+        });
+    }
+
+    /// Encodes a delta.
+    pub fn encode_delta<C: Borrow<dyn Component>>(&self, delta: &Delta<C>) -> Vec<u8> {
+        let mut w = BitWriter::new(self.bounds);
+        w.write_varint(delta.next_entity as u64);
+        delta.created.quantize(&mut w);
+        delta.destroyed.quantize(&mut w);
+        for components in &[&delta.added, &delta.changed] {
+            w.write_varint(components.len() as u64);
+            for (entity, component) in components.iter() {
+                entity.quantize(&mut w);
+                self.encode_component(component.borrow(), &mut w);
+            }
+        }
+        w.write_varint(delta.removed.len() as u64);
+        for (entity, tag) in &delta.removed {
+            entity.quantize(&mut w);
+            match self.types.iter().position(|ty| ty.tag == tag) {
+                Some(index) => {
+                    w.write_bool(true);
+                    w.write_varint(index as u64);
+                }
+                None => {
+                    w.write_bool(false);
+                    w.write_bytes(tag.as_bytes());
+                }
+            }
+        }
+        w.into_bytes()
+    }
+
+    /// Decodes a delta encoded by `encode_delta`.
+    pub fn decode_delta(&self, buf: &[u8]) -> Result<Delta, DecodeError> {
+        let mut r = BitReader::new(buf, self.bounds);
+        let r = &mut r;
+        let next_entity = r.read_varint().ok_or(DecodeError::Invalid)? as usize;
+        let created = Vec::dequantize(r).ok_or(DecodeError::Invalid)?;
+        let destroyed = Vec::dequantize(r).ok_or(DecodeError::Invalid)?;
+        let mut components = || -> Result<EntityComponents, DecodeError> {
+            let len = r.read_varint().ok_or(DecodeError::Invalid)?;
+            let mut components = Vec::new();
+            for _ in 0..len {
+                let entity = Entity::dequantize(r).ok_or(DecodeError::Invalid)?;
+                components.push((entity, self.decode_component(r)?));
+            }
+            Ok(components)
+        };
+        let added = components()?;
+        let changed = components()?;
+
+        let len = r.read_varint().ok_or(DecodeError::Invalid)?;
+        let mut removed = Vec::new();
+        for _ in 0..len {
+            let entity = Entity::dequantize(r).ok_or(DecodeError::Invalid)?;
+            let tag = if r.read_bool().ok_or(DecodeError::Invalid)? {
+                let index = r.read_varint().ok_or(DecodeError::Invalid)?;
+                self.types
+                    .get(index as usize)
+                    .ok_or(DecodeError::UnknownType(index))?
+                    .tag
+                    .to_string()
+            } else {
+                String::dequantize(r).ok_or(DecodeError::Invalid)?
+            };
+            removed.push((entity, tag));
+        }
+
+        Ok(Delta {
+            next_entity,
+            created,
+            destroyed,
+            added,
+            changed,
+            removed,
+        })
+    }
+
+    /// Writes a single component, quantized if its type is registered.
+    fn encode_component(&self, component: &dyn Component, w: &mut BitWriter) {
+        match self.indices.get(&component.as_any().type_id()) {
+            Some(&index) => {
+                w.write_bool(true);
+                w.write_varint(index as u64);
+                (self.types[index].quantize)(component, w);
+            }
+            None => {
+                // Serializing a component only fails if its Serialize impl does, and then there's
+                // no way to send it at all.
+                let src =
+                    serde_sexpr::to_string(&component).expect("component can't be serialized");
+                w.write_bool(false);
+                w.write_bytes(src.as_bytes());
+            }
+        }
+    }
+
+    /// Reads a single component written by `encode_component`.
+    fn decode_component(&self, r: &mut BitReader) -> Result<Box<dyn Component>, DecodeError> {
+        if r.read_bool().ok_or(DecodeError::Invalid)? {
+            let index = r.read_varint().ok_or(DecodeError::Invalid)?;
+            let ty = self
+                .types
+                .get(index as usize)
+                .ok_or(DecodeError::UnknownType(index))?;
+            (ty.dequantize)(r).ok_or(DecodeError::Invalid)
+        } else {
+            let src = String::dequantize(r).ok_or(DecodeError::Invalid)?;
+            serde_sexpr::from_str(&src).map_err(|err| DecodeError::Serde(err.to_string()))
+        }
+    }
+}
+
+impl Debug for Codec {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_struct("Codec")
+            .field("bounds", &self.bounds)
+            .field(
+                "types",
+                &self.types.iter().map(|ty| ty.tag).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+/// An error decoding a delta.
+#[derive(Debug)]
+pub enum DecodeError {
+    /// The data ended early, or contained an invalid value.
+    Invalid,
+
+    /// A component's type was identified by an index that no type was registered with.
+    UnknownType(u64),
+
+    /// A component that wasn't quantized couldn't be deserialized.
+    Serde(String),
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            DecodeError::Invalid => write!(fmt, "Truncated or invalid delta"),
+            DecodeError::UnknownType(index) => {
+                write!(fmt, "No type of component registered as {}", index)
+            }
+            DecodeError::Serde(msg) => write!(fmt, "Invalid component: {}", msg),
+        }
+    }
+}
+
+impl Error for DecodeError {}
@@ -0,0 +1,275 @@
+//! Deterministic recording and replay of engine ticks.
+//!
+//! While an `Engine` is recording, it saves a copy of the world as it was when recording started,
+//! the `dt` of every tick, and every `Input` applied to it. Every few ticks, it also saves a hash
+//! of each type of component in the world. Replaying the `Recording` (which doesn't need a
+//! renderer) runs the same ticks with the same inputs, and reports the first tick at which the
+//! hashes differ, along with the types of component that differ.
+//!
+//! A recording also lists the names of the engine's systems, and is only replayed by an engine
+//! with the same systems, since any other engine couldn't reproduce its ticks.
+//!
+//! Recordings are stored as S-expressions, like scenes. To keep ticks reproducible, the systems in
+//! parallel passes are run one at a time (in a fixed order) while recording or replaying.
+
+use crate::{Component, ComponentStore, Entity};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult, Write},
+};
+
+/// A recording of the ticks run by an `Engine`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Recording {
+    /// The world as it was when recording started.
+    pub world: ComponentStore,
+
+    /// The names of the engine's systems, in the order they were added.
+    #[serde(default)]
+    pub systems: Vec<String>,
+
+    /// The ticks that were run, in order.
+    pub ticks: Vec<RecordedTick>,
+}
+
+impl Recording {
+    /// Parses a recording from an S-expression.
+    pub fn parse(src: &str) -> Result<Recording, ReplayError> {
+        serde_sexpr::from_str(src).map_err(|err| ReplayError::Parse(err.to_string()))
+    }
+
+    /// Serializes the recording to an S-expression.
+    pub fn to_sexpr(&self) -> Result<String, ReplayError> {
+        serde_sexpr::to_string(self).map_err(|err| ReplayError::Parse(err.to_string()))
+    }
+}
+
+/// A single tick in a `Recording`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RecordedTick {
+    /// The time step of the tick, in seconds.
+    pub dt: f32,
+
+    /// The inputs applied before the tick was run.
+    #[serde(default)]
+    pub inputs: Vec<Input>,
+
+    /// The hash of the world after the tick was run, if this tick was a checkpoint.
+    #[serde(default)]
+    pub hash: Option<WorldHash>,
+}
+
+/// An input from outside the engine, such as from the player or the network.
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Input {
+    /// Creates a new entity.
+    NewEntity,
+
+    /// Deletes an entity.
+    DeleteEntity(Entity),
+
+    /// Sets a component on an entity.
+    SetComponent(Entity, Box<dyn Component>),
+}
+
+impl Input {
+    /// Applies the input to the store, returning the entity that was created (if any).
+    pub fn apply(self, cs: &mut ComponentStore) -> Option<Entity> {
+        match self {
+            Input::NewEntity => Some(cs.new_entity()),
+            Input::DeleteEntity(entity) => {
+                cs.delete_entity(entity);
+                None
+            }
+            Input::SetComponent(entity, component) => {
+                component.set_boxed(cs, entity);
+                None
+            }
+        }
+    }
+}
+
+/// A hash of every component in a world, for each type of component (by its `typetag` name).
+///
+/// Components are hashed by their `Debug` output, so they must print all the state that affects
+/// the simulation.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WorldHash(pub BTreeMap<String, u64>);
+
+impl WorldHash {
+    /// Hashes the world.
+    pub fn of(cs: &ComponentStore) -> WorldHash {
+        WorldHash(
+            cs.all_components()
+                .into_iter()
+                .map(|(name, components)| {
+                    let mut hasher = Fnv1a::default();
+                    for (entity, component) in components {
+                        let _ = write!(hasher, "{:?}={:?};", entity, component);
+                    }
+                    (name.to_string(), hasher.0)
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the names of the types of component whose hashes differ between the two worlds.
+    pub fn diff(&self, other: &WorldHash) -> Vec<String> {
+        let mut names = self
+            .0
+            .iter()
+            .filter(|&(name, hash)| other.0.get(name) != Some(hash))
+            .map(|(name, _)| name.clone())
+            .collect::<Vec<_>>();
+        names.extend(
+            other
+                .0
+                .keys()
+                .filter(|name| !self.0.contains_key(*name))
+                .cloned(),
+        );
+        names.sort();
+        names
+    }
+}
+
+/// The FNV-1a hash function. This is used instead of `DefaultHasher` so that hashes are stable
+/// across Rust versions.
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Fnv1a {
+        Fnv1a(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Write for Fnv1a {
+    fn write_str(&mut self, s: &str) -> FmtResult {
+        for &b in s.as_bytes() {
+            self.0 ^= u64::from(b);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+        Ok(())
+    }
+}
+
+/// The state of an `Engine` that is recording.
+#[derive(Debug)]
+pub(crate) struct Recorder {
+    recording: Recording,
+    checkpoint_interval: usize,
+    inputs: Vec<Input>,
+    error: Option<ReplayError>,
+}
+
+impl Recorder {
+    /// Starts a recording, given a copy of the world and the names of the engine's systems. A hash
+    /// is saved every `checkpoint_interval` ticks.
+    pub fn new(
+        world: ComponentStore,
+        systems: Vec<String>,
+        checkpoint_interval: usize,
+    ) -> Recorder {
+        Recorder {
+            recording: Recording {
+                world,
+                systems,
+                ticks: Vec::new(),
+            },
+            checkpoint_interval: checkpoint_interval.max(1),
+            inputs: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Records an input, which is about to be applied.
+    pub fn record_input(&mut self, input: &Input) {
+        match copy(input) {
+            Ok(input) => self.inputs.push(input),
+            Err(err) => {
+                if self.error.is_none() {
+                    self.error = Some(err);
+                }
+            }
+        }
+    }
+
+    /// Records a tick, which has just been run.
+    pub fn record_tick(&mut self, dt: f32, cs: &ComponentStore) {
+        let hash = if (self.recording.ticks.len() + 1) % self.checkpoint_interval == 0 {
+            Some(WorldHash::of(cs))
+        } else {
+            None
+        };
+        self.recording.ticks.push(RecordedTick {
+            dt,
+            inputs: self.inputs.drain(..).collect(),
+            hash,
+        });
+    }
+
+    /// Finishes the recording.
+    pub fn finish(self) -> Result<Recording, ReplayError> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.recording),
+        }
+    }
+}
+
+/// Copies a value by serializing and deserializing it, for values (like worlds and components)
+/// that can't be cloned.
+pub fn copy<T: DeserializeOwned + Serialize>(value: &T) -> Result<T, ReplayError> {
+    let src = serde_sexpr::to_string(value).map_err(|err| ReplayError::Parse(err.to_string()))?;
+    serde_sexpr::from_str(&src).map_err(|err| ReplayError::Parse(err.to_string()))
+}
+
+/// An error recording or replaying ticks.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The world diverged from the recording. `tick` is the (0-based) index of the first tick
+    /// whose hash differed, and `components` are the types of component that differed.
+    Diverged {
+        /// The index of the tick.
+        tick: usize,
+
+        /// The names of the types of component that differed.
+        components: Vec<String>,
+    },
+
+    /// The recording was made by an engine with different systems than the one replaying it.
+    WrongSystems {
+        /// The names of the systems the recording was made with.
+        expected: Vec<String>,
+
+        /// The names of the systems of the engine replaying it.
+        found: Vec<String>,
+    },
+
+    /// The recording (or something in it) could not be parsed or serialized.
+    Parse(String),
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            ReplayError::Diverged { tick, components } => write!(
+                fmt,
+                "Replay diverged at tick {} in {}",
+                tick,
+                components.join(", ")
+            ),
+            ReplayError::WrongSystems { expected, found } => write!(
+                fmt,
+                "The recording was made with the systems [{}], but is being replayed with [{}]",
+                expected.join(", "),
+                found.join(", ")
+            ),
+            ReplayError::Parse(msg) => write!(fmt, "Invalid recording: {}", msg),
+        }
+    }
+}
+
+impl Error for ReplayError {}
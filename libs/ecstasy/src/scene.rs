@@ -0,0 +1,255 @@
+//! Data-driven scenes.
+//!
+//! A scene is a list of entities, each with some components and the names of the assets it uses.
+//! Scenes are stored as S-expressions (using `serde_sexpr`), so they can be written by hand and
+//! packed into IRB files by `ia-asset-tool`.
+//!
+//! Since the entities in a scene don't exist until it is spawned, any `Entity` inside a scene's
+//! components refers to another entity in the same scene, by its 1-based index in the scene. These
+//! are remapped to the spawned entities by `Component::remap_entities`.
+//!
+//! Scenes can also be made from entities in a store, with `Scene::from_entities`, so that they can
+//! be saved and spawned again later.
+
+use crate::{components::AssetRefs, replay::Input, Component, ComponentStore, Entity};
+use assets::{Asset, Assets};
+use hashbrown::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+    num::NonZeroUsize,
+};
+
+/// A scene, which is a list of entities to be spawned together.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Scene {
+    /// The entities in the scene.
+    pub entities: Vec<SceneEntity>,
+}
+
+impl Scene {
+    /// Copies the given entities out of the store into a scene, in the same order. Their
+    /// components are copied by serializing and deserializing them, so they don't need to be
+    /// cloneable. References to entities in the list become references to the corresponding
+    /// entities in the scene; references to any other entity are an error.
+    pub fn from_entities(cs: &ComponentStore, entities: &[Entity]) -> Result<Scene, SceneError> {
+        Scene::copy_entities(cs, entities, |_, entity| {
+            Err(SceneError::DanglingEntity(entity.0.get()))
+        })
+    }
+
+    /// Copies the given entities out of the store into a scene, as `from_entities` does, except
+    /// that components referring to any entity not in the list are left out of the scene rather
+    /// than being an error. The `typetag` names of the components that were left out are returned
+    /// along with the scene.
+    pub fn from_entities_dropping_dangling(
+        cs: &ComponentStore,
+        entities: &[Entity],
+    ) -> Result<(Scene, Vec<&'static str>), SceneError> {
+        let mut dropped = Vec::new();
+        let scene = Scene::copy_entities(cs, entities, |name, _| {
+            dropped.push(name);
+            Ok(())
+        })?;
+        Ok((scene, dropped))
+    }
+
+    /// Copies entities into a scene, calling `dangling` with the name of each type of component
+    /// that refers to an entity not in the list (and the entity it refers to). If it doesn't
+    /// return an error, the component is left out of the scene.
+    fn copy_entities<F>(
+        cs: &ComponentStore,
+        entities: &[Entity],
+        mut dangling: F,
+    ) -> Result<Scene, SceneError>
+    where
+        F: FnMut(&'static str, Entity) -> Result<(), SceneError>,
+    {
+        let indices = entities
+            .iter()
+            .enumerate()
+            .map(|(i, &entity)| (entity, scene_entity(i + 1).unwrap()))
+            .collect::<HashMap<_, _>>();
+        let mut scene = Scene {
+            entities: entities.iter().map(|_| SceneEntity::default()).collect(),
+        };
+
+        for (name, components) in cs.all_components() {
+            for (entity, component) in components {
+                let scene_entity = match indices.get(&entity) {
+                    Some(index) => &mut scene.entities[index.0.get() - 1],
+                    None => continue,
+                };
+                if let Some(AssetRefs(assets)) = component.as_any().downcast_ref() {
+                    scene_entity.assets = assets.clone();
+                    continue;
+                }
+
+                let src = serde_sexpr::to_string(&component)
+                    .map_err(|err| SceneError::Parse(err.to_string()))?;
+                let mut component: Box<dyn Component> = serde_sexpr::from_str(&src)
+                    .map_err(|err| SceneError::Parse(err.to_string()))?;
+                let mut outside = None;
+                component.remap_entities(&mut |entity| match indices.get(&entity) {
+                    Some(&index) => index,
+                    None => {
+                        outside = Some(entity);
+                        entity
+                    }
+                });
+                match outside {
+                    Some(entity) => dangling(name, entity)?,
+                    None => scene_entity.components.push(component),
+                }
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Parses a scene from an S-expression.
+    pub fn parse(src: &str) -> Result<Scene, SceneError> {
+        serde_sexpr::from_str(src).map_err(|err| SceneError::Parse(err.to_string()))
+    }
+
+    /// Loads the scene with the given name from `assets`, checking that all the assets and
+    /// entities it refers to are present.
+    pub fn load(assets: &Assets, name: &str) -> Result<Scene, SceneError> {
+        let mut scene = match assets.get(name).map(|asset| &**asset) {
+            Some(Asset::Scene(src)) => Scene::parse(src)?,
+            Some(_) => return Err(SceneError::NotAScene(name.to_string())),
+            None => return Err(SceneError::MissingAsset(name.to_string())),
+        };
+        scene.check_assets(|name| assets.get(name).is_some())?;
+        scene.check_entities()?;
+        Ok(scene)
+    }
+
+    /// Serializes the scene to an S-expression.
+    pub fn to_sexpr(&self) -> Result<String, SceneError> {
+        serde_sexpr::to_string(self).map_err(|err| SceneError::Parse(err.to_string()))
+    }
+
+    /// Returns the names of all the assets referred to by the scene.
+    pub fn asset_names(&self) -> impl Iterator<Item = &str> {
+        self.entities
+            .iter()
+            .flat_map(|entity| entity.assets.iter().map(String::as_str))
+    }
+
+    /// Checks that every asset referred to by the scene exists, according to `exists`.
+    pub fn check_assets<F: Fn(&str) -> bool>(&self, exists: F) -> Result<(), SceneError> {
+        match self.asset_names().find(|name| !exists(name)) {
+            Some(name) => Err(SceneError::MissingAsset(name.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that every entity referred to by a component in the scene is in the scene.
+    pub fn check_entities(&mut self) -> Result<(), SceneError> {
+        let len = self.entities.len();
+        let mut dangling = None;
+        for entity in &mut self.entities {
+            for component in &mut entity.components {
+                component.remap_entities(&mut |entity| {
+                    if entity.0.get() > len {
+                        dangling = Some(entity.0.get());
+                    }
+                    entity
+                });
+            }
+        }
+        match dangling {
+            Some(n) => Err(SceneError::DanglingEntity(n)),
+            None => Ok(()),
+        }
+    }
+
+    /// Spawns the entities of the scene into the `ComponentStore`, returning them in the same
+    /// order as they were in the scene.
+    ///
+    /// Fails without spawning anything if a component refers to an entity that isn't in the
+    /// scene, as checked by `check_entities`.
+    pub fn spawn(self, cs: &mut ComponentStore) -> Result<Vec<Entity>, SceneError> {
+        self.spawn_with(|input| input.apply(cs))
+    }
+
+    /// Spawns the entities of the scene as a series of `Input`s, each of which is passed to
+    /// `apply` (which returns the entity created by an `Input::NewEntity`). This lets an `Engine`
+    /// record the spawning, as `Engine::spawn_scene` does. Fails in the same cases as `spawn`.
+    pub fn spawn_with<F>(mut self, mut apply: F) -> Result<Vec<Entity>, SceneError>
+    where
+        F: FnMut(Input) -> Option<Entity>,
+    {
+        self.check_entities()?;
+        let spawned = self
+            .entities
+            .iter()
+            .map(|_| apply(Input::NewEntity).expect("creating an entity didn't return it"))
+            .collect::<Vec<_>>();
+        let mut map = |entity: Entity| spawned[entity.0.get() - 1];
+
+        for (scene_entity, &entity) in self.entities.into_iter().zip(&spawned) {
+            if !scene_entity.assets.is_empty() {
+                let assets = Box::new(AssetRefs(scene_entity.assets));
+                let _ = apply(Input::SetComponent(entity, assets));
+            }
+            for mut component in scene_entity.components {
+                component.remap_entities(&mut map);
+                let _ = apply(Input::SetComponent(entity, component));
+            }
+        }
+
+        Ok(spawned)
+    }
+}
+
+/// A single entity in a `Scene`.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct SceneEntity {
+    /// The names of the assets used by the entity. When spawned, these are stored in an
+    /// `AssetRefs` component.
+    #[serde(default)]
+    pub assets: Vec<String>,
+
+    /// The components of the entity.
+    #[serde(default)]
+    pub components: Vec<Box<dyn Component>>,
+}
+
+/// Returns the entity that refers to the `n`th (1-based) entity in a scene, for use when building
+/// scenes in code.
+pub fn scene_entity(n: usize) -> Option<Entity> {
+    NonZeroUsize::new(n).map(Entity)
+}
+
+/// An error loading a scene.
+#[derive(Debug)]
+pub enum SceneError {
+    /// A component referred to an entity that was not in the scene.
+    DanglingEntity(usize),
+
+    /// An asset (either the scene itself, or one it refers to) was not present.
+    MissingAsset(String),
+
+    /// The asset with the given name was not a scene.
+    NotAScene(String),
+
+    /// The scene could not be parsed or serialized.
+    Parse(String),
+}
+
+impl Display for SceneError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            SceneError::DanglingEntity(n) => {
+                write!(fmt, "Entity {} is referred to, but is not in the scene", n)
+            }
+            SceneError::MissingAsset(name) => write!(fmt, "Missing asset: {}", name),
+            SceneError::NotAScene(name) => write!(fmt, "The asset {} is not a scene", name),
+            SceneError::Parse(msg) => write!(fmt, "Invalid scene: {}", msg),
+        }
+    }
+}
+
+impl Error for SceneError {}
@@ -0,0 +1,86 @@
+//! In-memory snapshots of a `ComponentStore`, for rolling the world back.
+//!
+//! Unlike serializing the store, taking a snapshot only clones the storage of each cloneable
+//! component type, so it is cheap enough to do every tick. This is meant for client-side
+//! prediction and rollback netcode, where the client keeps the last few ticks in a
+//! `SnapshotRing` and restores one when the server disagrees with its prediction.
+
+use crate::{unsafe_option_vec::UnsafeOptionVec, Entity};
+use hashbrown::HashMap;
+use std::{any::TypeId, collections::VecDeque};
+
+/// A copy of the components in a `ComponentStore`, taken by `ComponentStore::snapshot`.
+///
+/// Only components that opt into cloning (see `Component::clone_component`) are saved.
+#[derive(Debug)]
+pub struct Snapshot {
+    pub(crate) storages: HashMap<TypeId, UnsafeOptionVec>,
+    pub(crate) next_entity: usize,
+    pub(crate) deleted: HashMap<Entity, u64>,
+}
+
+/// A ring buffer of the snapshots taken on the last few ticks.
+#[derive(Debug)]
+pub struct SnapshotRing {
+    capacity: usize,
+    snapshots: VecDeque<(u64, Snapshot)>,
+}
+
+impl SnapshotRing {
+    /// Creates an empty ring buffer that holds up to `capacity` snapshots.
+    pub fn new(capacity: usize) -> SnapshotRing {
+        assert!(capacity > 0, "capacity must be positive");
+        SnapshotRing {
+            capacity,
+            snapshots: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Returns the number of snapshots in the buffer.
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    /// Returns whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    /// Adds the snapshot taken on the given tick, dropping the oldest snapshot if the buffer is
+    /// full. Ticks should be pushed in increasing order; any snapshots from the same or later
+    /// ticks (which would have been made obsolete by a rollback) are dropped first.
+    pub fn push(&mut self, tick: u64, snapshot: Snapshot) {
+        while self.snapshots.back().map(|&(t, _)| t >= tick) == Some(true) {
+            let _ = self.snapshots.pop_back();
+        }
+        if self.snapshots.len() == self.capacity {
+            let _ = self.snapshots.pop_front();
+        }
+        self.snapshots.push_back((tick, snapshot));
+    }
+
+    /// Returns the snapshot taken on the given tick, if it is still in the buffer.
+    pub fn get(&self, tick: u64) -> Option<&Snapshot> {
+        self.snapshots
+            .iter()
+            .find(|&&(t, _)| t == tick)
+            .map(|(_, snapshot)| snapshot)
+    }
+
+    /// Returns the oldest tick whose snapshot is still in the buffer.
+    pub fn oldest_tick(&self) -> Option<u64> {
+        self.snapshots.front().map(|&(tick, _)| tick)
+    }
+
+    /// Returns the newest tick whose snapshot is in the buffer.
+    pub fn newest_tick(&self) -> Option<u64> {
+        self.snapshots.back().map(|&(tick, _)| tick)
+    }
+
+    /// Drops the snapshots taken after the given tick.
+    pub fn truncate_after(&mut self, tick: u64) {
+        while self.snapshots.back().map(|&(t, _)| t > tick) == Some(true) {
+            let _ = self.snapshots.pop_back();
+        }
+    }
+}
@@ -0,0 +1,351 @@
+//! A spatial index over `Position` components.
+
+use crate::{components::Position, ComponentStore, Entity};
+use cgmath::{InnerSpace, Point3, Vector3};
+use hashbrown::{HashMap, HashSet};
+use std::cmp::Ordering;
+
+/// The coordinates of a cell in the grid.
+type Cell = [i32; 3];
+
+/// A uniform grid over the `Position`s of entities, which answers proximity queries without
+/// scanning every entity.
+///
+/// The index is kept up to date from the changes to `Position` recorded by the `ComponentStore`;
+/// see `ComponentStore::set_spatial_index`. Entities are treated as points.
+#[derive(Debug)]
+pub struct SpatialIndex {
+    cell_size: f32,
+    cells: HashMap<Cell, Vec<Entity>>,
+    positions: HashMap<Entity, Point3<f32>>,
+    last_tick: u64,
+}
+
+impl SpatialIndex {
+    /// Creates a new, empty index with the given size of grid cells. The cell size should be on
+    /// the order of the most common query radius.
+    pub fn new(cell_size: f32) -> SpatialIndex {
+        assert!(cell_size > 0.0, "cell size must be positive");
+        SpatialIndex {
+            cell_size,
+            cells: HashMap::new(),
+            positions: HashMap::new(),
+            last_tick: 0,
+        }
+    }
+
+    /// Returns the size of the grid cells.
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Returns the number of entities in the index.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Returns whether the index is empty.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Returns the position of the entity, as of the last update.
+    pub fn position(&self, entity: Entity) -> Option<Point3<f32>> {
+        self.positions.get(&entity).cloned()
+    }
+
+    /// Updates the index from the `Position`s that have changed since the last update.
+    pub fn update(&mut self, cs: &ComponentStore) {
+        for entity in cs.changed_since::<Position>(self.last_tick) {
+            let new = cs.get_component::<Position>(entity).map(|p| p.0);
+            let old = self.positions.get(&entity).cloned();
+            if old == new {
+                continue;
+            }
+
+            if let Some(old) = old {
+                let cell = self.cell_of(old);
+                let now_empty = match self.cells.get_mut(&cell) {
+                    Some(entities) => {
+                        entities.retain(|&e| e != entity);
+                        entities.is_empty()
+                    }
+                    None => false,
+                };
+                if now_empty {
+                    let _ = self.cells.remove(&cell);
+                }
+            }
+
+            match new {
+                Some(new) => {
+                    let cell = self.cell_of(new);
+                    self.cells.entry(cell).or_insert_with(Vec::new).push(entity);
+                    let _ = self.positions.insert(entity, new);
+                }
+                None => {
+                    let _ = self.positions.remove(&entity);
+                }
+            }
+        }
+        self.last_tick = cs.change_tick();
+    }
+
+    /// Returns the entities within `radius` of `center`.
+    pub fn within_radius(&self, center: Point3<f32>, radius: f32) -> Vec<Entity> {
+        let r = Vector3::new(radius, radius, radius);
+        let radius2 = radius * radius;
+        self.candidates(center - r, center + r)
+            .filter(|&(_, p)| (p - center).magnitude2() <= radius2)
+            .map(|(e, _)| e)
+            .collect()
+    }
+
+    /// Returns the entities within the axis-aligned bounding box with the given corners.
+    pub fn within_aabb(&self, min: Point3<f32>, max: Point3<f32>) -> Vec<Entity> {
+        self.candidates(min, max)
+            .filter(|&(_, p)| {
+                min.x <= p.x
+                    && p.x <= max.x
+                    && min.y <= p.y
+                    && p.y <= max.y
+                    && min.z <= p.z
+                    && p.z <= max.z
+            })
+            .map(|(e, _)| e)
+            .collect()
+    }
+
+    /// Returns the (up to) `k` entities nearest to `point`, nearest first.
+    pub fn nearest(&self, point: Point3<f32>, k: usize) -> Vec<Entity> {
+        if k == 0 || self.is_empty() {
+            return Vec::new();
+        }
+
+        let center = self.cell_of(point);
+        let max_ring = self
+            .cells
+            .keys()
+            .map(|cell| {
+                (0..3)
+                    .map(|i| (i64::from(cell[i]) - i64::from(center[i])).abs())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut found = Vec::new();
+        for ring in 0..=max_ring {
+            let ring_cells = (2 * ring + 1).pow(3) - (2 * ring - 1).max(0).pow(3);
+            if ring_cells > self.cells.len() as i64 {
+                // The rings have gotten bigger than the number of occupied cells, so it's cheaper
+                // to check everything that's left.
+                for (cell, entities) in &self.cells {
+                    let dist = (0..3)
+                        .map(|i| (i64::from(cell[i]) - i64::from(center[i])).abs())
+                        .max()
+                        .unwrap_or(0);
+                    if dist >= ring {
+                        for &entity in entities {
+                            let p = self.positions[&entity];
+                            found.push(((p - point).magnitude2(), entity));
+                        }
+                    }
+                }
+                found.sort_by(|l, r| l.0.partial_cmp(&r.0).unwrap_or(Ordering::Equal));
+                found.truncate(k);
+                break;
+            }
+
+            self.for_each_in_ring(center, ring as i32, |entity, p| {
+                found.push(((p - point).magnitude2(), entity))
+            });
+            found.sort_by(|l, r| l.0.partial_cmp(&r.0).unwrap_or(Ordering::Equal));
+            found.truncate(k);
+
+            // Every entity in a ring further out is at least this far away.
+            let searched = ring as f32 * self.cell_size;
+            if found.len() == k && found[k - 1].0 <= searched * searched {
+                break;
+            }
+        }
+        found.into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// Returns the entities within `radius` of the ray starting at `origin` going in `direction`,
+    /// up to `max_distance` along the ray. Each is returned with its distance along the ray, and
+    /// they are sorted by that distance.
+    pub fn raycast(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+        radius: f32,
+    ) -> Vec<(Entity, f32)> {
+        if direction.magnitude2() == 0.0 || max_distance.is_nan() {
+            return Vec::new();
+        }
+        let direction = direction.normalize();
+        let reach = (radius / self.cell_size).ceil() as i32;
+        let radius2 = radius * radius;
+        let hit = |entity: Entity| {
+            let offset = self.positions[&entity] - origin;
+            let t = offset.dot(direction);
+            if t >= 0.0 && t <= max_distance && (offset - direction * t).magnitude2() <= radius2 {
+                Some((entity, t))
+            } else {
+                None
+            }
+        };
+
+        let start = self.cell_of(origin);
+        let end = self.cell_of(origin + direction * max_distance);
+        let walked = (0..3)
+            .map(|i| (i64::from(end[i]) - i64::from(start[i])).unsigned_abs() + 1)
+            .fold(0u64, u64::saturating_add);
+        let per_cell = (2 * u64::from(reach.max(0) as u32) + 1).saturating_pow(3);
+        let mut hits = if walked.saturating_mul(per_cell) > self.cells.len() as u64 {
+            // The ray passes by more cells than are occupied (it may even be infinitely long), so
+            // just check every entity.
+            self.positions
+                .keys()
+                .filter_map(|&entity| hit(entity))
+                .collect()
+        } else {
+            let mut seen = HashSet::new();
+            let mut hits = Vec::new();
+            for cell in self.cells_along(origin, direction, max_distance) {
+                for dx in -reach..=reach {
+                    for dy in -reach..=reach {
+                        for dz in -reach..=reach {
+                            let cell = [cell[0] + dx, cell[1] + dy, cell[2] + dz];
+                            if seen.insert(cell) {
+                                let entities = self.cells.get(&cell).into_iter().flatten();
+                                hits.extend(entities.filter_map(|&entity| hit(entity)));
+                            }
+                        }
+                    }
+                }
+            }
+            hits
+        };
+        hits.sort_by(|l, r| l.1.partial_cmp(&r.1).unwrap_or(Ordering::Equal));
+        hits
+    }
+
+    /// Returns the cell containing the point.
+    fn cell_of(&self, p: Point3<f32>) -> Cell {
+        let f = |x: f32| (x / self.cell_size).floor() as i32;
+        [f(p.x), f(p.y), f(p.z)]
+    }
+
+    /// Returns the entities (and their positions) in all the cells overlapping the given box.
+    fn candidates<'a>(
+        &'a self,
+        min: Point3<f32>,
+        max: Point3<f32>,
+    ) -> Box<dyn Iterator<Item = (Entity, Point3<f32>)> + 'a> {
+        let lo = self.cell_of(min);
+        let hi = self.cell_of(max);
+        let count = (0..3)
+            .map(|i| (i64::from(hi[i]) - i64::from(lo[i]) + 1).max(0) as u64)
+            .fold(1u64, u64::saturating_mul);
+
+        let positions = &self.positions;
+        let with_position = move |&entity: &Entity| (entity, positions[&entity]);
+        if count > self.cells.len() as u64 {
+            // The box covers more cells than are occupied, so just check all the occupied ones.
+            Box::new(
+                self.cells
+                    .iter()
+                    .filter(move |(cell, _)| (0..3).all(|i| lo[i] <= cell[i] && cell[i] <= hi[i]))
+                    .flat_map(|(_, entities)| entities.iter())
+                    .map(with_position),
+            )
+        } else {
+            Box::new(
+                (lo[0]..=hi[0])
+                    .flat_map(move |x| (lo[1]..=hi[1]).map(move |y| (x, y)))
+                    .flat_map(move |(x, y)| (lo[2]..=hi[2]).map(move |z| [x, y, z]))
+                    .filter_map(move |cell| self.cells.get(&cell))
+                    .flat_map(|entities| entities.iter())
+                    .map(with_position),
+            )
+        }
+    }
+
+    /// Calls `f` with each entity in the cells that are exactly `ring` cells away (in the
+    /// Chebyshev distance) from `center`.
+    fn for_each_in_ring<F: FnMut(Entity, Point3<f32>)>(&self, center: Cell, ring: i32, mut f: F) {
+        let mut visit = |dx: i32, dy: i32, dz: i32| {
+            let cell = [center[0] + dx, center[1] + dy, center[2] + dz];
+            for &entity in self.cells.get(&cell).into_iter().flatten() {
+                f(entity, self.positions[&entity]);
+            }
+        };
+
+        for dx in -ring..=ring {
+            for dy in -ring..=ring {
+                if dx.abs() == ring || dy.abs() == ring {
+                    for dz in -ring..=ring {
+                        visit(dx, dy, dz);
+                    }
+                } else {
+                    visit(dx, dy, -ring);
+                    visit(dx, dy, ring);
+                }
+            }
+        }
+    }
+
+    /// Returns the cells a ray passes through, in order, using the algorithm from Amanatides and
+    /// Woo's "A Fast Voxel Traversal Algorithm for Ray Tracing." This takes time proportional to
+    /// the number of cells, so callers should check that the ray is short enough first.
+    fn cells_along(
+        &self,
+        origin: Point3<f32>,
+        direction: Vector3<f32>,
+        max_distance: f32,
+    ) -> Vec<Cell> {
+        let mut cell = self.cell_of(origin);
+        let end = self.cell_of(origin + direction * max_distance);
+        let origin = [origin.x, origin.y, origin.z];
+        let direction = [direction.x, direction.y, direction.z];
+
+        let mut step = [0; 3];
+        let mut t_max = [std::f32::INFINITY; 3];
+        let mut t_delta = [std::f32::INFINITY; 3];
+        for i in 0..3 {
+            if direction[i] > 0.0 {
+                step[i] = 1;
+                let boundary = (cell[i] + 1) as f32 * self.cell_size;
+                t_max[i] = (boundary - origin[i]) / direction[i];
+                t_delta[i] = self.cell_size / direction[i];
+            } else if direction[i] < 0.0 {
+                step[i] = -1;
+                let boundary = cell[i] as f32 * self.cell_size;
+                t_max[i] = (boundary - origin[i]) / direction[i];
+                t_delta[i] = -self.cell_size / direction[i];
+            }
+        }
+
+        let mut cells = vec![cell];
+        while cell != end {
+            let i = if t_max[0] <= t_max[1] && t_max[0] <= t_max[2] {
+                0
+            } else if t_max[1] <= t_max[2] {
+                1
+            } else {
+                2
+            };
+            if t_max[i] > max_distance {
+                break;
+            }
+            cell[i] += step[i];
+            t_max[i] += t_delta[i];
+            cells.push(cell);
+        }
+        cells
+    }
+}
@@ -1,10 +1,17 @@
 #![allow(clippy::blacklisted_name)]
 
 use crate::{
-    components::{Name, Position},
-    Component, ComponentStore,
+    components::{AssetRefs, DebugFlag, Name, Position},
+    delta::{Delta, DeltaError, MAX_ENTITIES},
+    quantize::{BitReader, BitWriter, Codec, Quantize, WorldBounds},
+    replay::{Input, Recording, ReplayError, WorldHash},
+    scene::{scene_entity, Scene, SceneEntity, SceneError},
+    snapshot::SnapshotRing,
+    spatial::SpatialIndex,
+    system_mut, CloneComponent, Component, ComponentStore, Engine, Entity, MapEntities, SystemMut,
 };
-use cgmath::Point3;
+use assets::{Asset, Assets};
+use cgmath::{InnerSpace, Point3, Quaternion, Rotation3, Vector3};
 use serde::{Deserialize, Serialize};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
@@ -119,3 +126,991 @@ fn no_double_drop() {
     store.set_component(foo, P::default());
     store.set_component(foo, P::default());
 }
+
+#[test]
+fn clone_entity() {
+    #[derive(Debug, Deserialize, Serialize)]
+    struct NotClone;
+    #[typetag::serde]
+    impl Component for NotClone {}
+
+    let mut store = ComponentStore::new();
+    let foo = store.new_entity();
+    store.set_component(foo, Name("Foo".to_string()));
+    store.set_component(foo, Position::new(1.0, 2.0, 3.0));
+    store.set_component(foo, NotClone);
+
+    let bar = store.clone_entity(foo);
+    assert_ne!(foo, bar);
+    assert_eq!(
+        store.get_component::<Name>(bar),
+        Some(&Name("Foo".to_string()))
+    );
+    assert_eq!(
+        store.get_component::<Position>(bar),
+        Some(&Position::new(1.0, 2.0, 3.0))
+    );
+    assert!(store.get_component::<NotClone>(bar).is_none());
+    assert!(store.get_component::<NotClone>(foo).is_some());
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Parent(Entity);
+
+#[typetag::serde]
+impl Component for Parent {
+    fn clone_component() -> Option<CloneComponent> {
+        Some(CloneComponent::of::<Parent>())
+    }
+
+    fn remap_entities(&mut self, map: &mut dyn FnMut(Entity) -> Entity) {
+        self.0.map_entities(map)
+    }
+}
+
+#[test]
+fn instantiate_remaps_entities() {
+    let mut store = ComponentStore::new();
+    let world = store.new_entity();
+    let root = store.new_entity();
+    let child = store.new_entity();
+    store.set_component(root, Parent(world));
+    store.set_component(root, Name("Root".to_string()));
+    store.set_component(child, Parent(root));
+
+    let copies = store.instantiate(&[root, child]);
+    assert_eq!(copies.len(), 2);
+    assert_eq!(
+        store.get_component::<Parent>(copies[0]).map(|p| p.0),
+        Some(world)
+    );
+    assert_eq!(
+        store.get_component::<Parent>(copies[1]).map(|p| p.0),
+        Some(copies[0])
+    );
+    assert_eq!(
+        store.get_component::<Name>(copies[0]),
+        Some(&Name("Root".to_string()))
+    );
+    assert_eq!(store.get_component::<Name>(copies[1]), None);
+
+    // The templates are left untouched.
+    assert_eq!(
+        store.get_component::<Parent>(child).map(|p| p.0),
+        Some(root)
+    );
+
+    // Entities listed twice are copied once.
+    let entities = store.iter_entities().count();
+    let copies = store.instantiate(&[child, root, child]);
+    assert_eq!(store.iter_entities().count(), entities + 2);
+    assert_eq!(copies[0], copies[2]);
+    assert_eq!(
+        store.get_component::<Parent>(copies[0]).map(|p| p.0),
+        Some(copies[1])
+    );
+}
+
+fn example_scene() -> Scene {
+    Scene {
+        entities: vec![
+            SceneEntity {
+                assets: vec!["player.iqm".to_string()],
+                components: vec![
+                    Box::new(Name("player".to_string())),
+                    Box::new(Position::new(1.0, 2.0, 3.0)),
+                ],
+            },
+            SceneEntity {
+                assets: vec![],
+                components: vec![Box::new(Parent(scene_entity(1).unwrap()))],
+            },
+        ],
+    }
+}
+
+#[test]
+fn scene_round_trip_and_spawn() {
+    let src = example_scene().to_sexpr().unwrap();
+    let scene = Scene::parse(&src).unwrap();
+
+    let mut store = ComponentStore::new();
+    let _ = store.new_entity();
+    let spawned = scene.spawn(&mut store).unwrap();
+    assert_eq!(spawned.len(), 2);
+
+    assert_eq!(
+        store.get_component::<Name>(spawned[0]),
+        Some(&Name("player".to_string()))
+    );
+    assert_eq!(
+        store.get_component::<Position>(spawned[0]),
+        Some(&Position::new(1.0, 2.0, 3.0))
+    );
+    assert_eq!(
+        store.get_component::<AssetRefs>(spawned[0]),
+        Some(&AssetRefs(vec!["player.iqm".to_string()]))
+    );
+    assert_eq!(
+        store.get_component::<Parent>(spawned[1]).map(|p| p.0),
+        Some(spawned[0])
+    );
+    assert_eq!(store.get_component::<AssetRefs>(spawned[1]), None);
+}
+
+#[test]
+fn scene_from_entities() {
+    let mut store = ComponentStore::new();
+    let other = store.new_entity();
+    let child = store.new_entity();
+    let root = store.new_entity();
+    store.set_component(root, Name("root".to_string()));
+    store.set_component(root, AssetRefs(vec!["root.iqm".to_string()]));
+    store.set_component(child, Parent(root));
+    store.set_component(child, Velocity(2.0));
+
+    // Entities are numbered by their place in the list, and references between them follow.
+    let scene = Scene::from_entities(&store, &[root, child]).unwrap();
+    assert_eq!(scene.entities[0].assets, vec!["root.iqm".to_string()]);
+    assert_eq!(scene.entities[0].components.len(), 1);
+    assert_eq!(scene.entities[1].components.len(), 2);
+
+    let mut copy = ComponentStore::new();
+    let spawned = Scene::parse(&scene.to_sexpr().unwrap())
+        .unwrap()
+        .spawn(&mut copy)
+        .unwrap();
+    assert_eq!(copy.find_by_name("root"), Some(spawned[0]));
+    assert_eq!(
+        copy.get_component::<Parent>(spawned[1]).map(|p| p.0),
+        Some(spawned[0])
+    );
+    assert_eq!(
+        copy.get_component::<Velocity>(spawned[1]),
+        Some(&Velocity(2.0))
+    );
+
+    // References to entities outside the list can't be saved.
+    store.set_component(child, Parent(other));
+    match Scene::from_entities(&store, &[root, child]) {
+        Err(SceneError::DanglingEntity(n)) => assert_eq!(scene_entity(n), Some(other)),
+        result => panic!("expected a dangling entity, got {:?}", result),
+    }
+
+    // ...unless the components holding them are dropped.
+    let (scene, dropped) = Scene::from_entities_dropping_dangling(&store, &[root, child]).unwrap();
+    assert_eq!(dropped, vec!["Parent"]);
+    assert_eq!(scene.entities[1].components.len(), 1);
+}
+
+#[test]
+fn scene_validation() {
+    let mut assets = Assets::new();
+    let src = example_scene().to_sexpr().unwrap();
+    assets.insert("town".to_string(), Asset::Scene(src));
+
+    match Scene::load(&assets, "town") {
+        Err(SceneError::MissingAsset(name)) => assert_eq!(name, "player.iqm"),
+        r => panic!("expected missing asset, got {:?}", r),
+    }
+    match Scene::load(&assets, "nowhere") {
+        Err(SceneError::MissingAsset(name)) => assert_eq!(name, "nowhere"),
+        r => panic!("expected missing asset, got {:?}", r),
+    }
+
+    let mut scene = example_scene();
+    assert!(scene.check_entities().is_ok());
+    scene.entities.truncate(1);
+    scene.entities[0]
+        .components
+        .push(Box::new(Parent(scene_entity(5).unwrap())));
+    match scene.check_entities() {
+        Err(SceneError::DanglingEntity(5)) => {}
+        r => panic!("expected dangling entity, got {:?}", r),
+    }
+
+    // Scenes with dangling entities can't be loaded or spawned.
+    scene.entities[0].assets.clear();
+    assets.insert(
+        "dangling".to_string(),
+        Asset::Scene(scene.to_sexpr().unwrap()),
+    );
+    match Scene::load(&assets, "dangling") {
+        Err(SceneError::DanglingEntity(5)) => {}
+        r => panic!("expected dangling entity, got {:?}", r),
+    }
+    let mut store = ComponentStore::new();
+    match scene.spawn(&mut store) {
+        Err(SceneError::DanglingEntity(5)) => {}
+        r => panic!("expected dangling entity, got {:?}", r),
+    }
+    assert_eq!(store.iter_entities().count(), 0);
+}
+
+#[test]
+fn change_ticks() {
+    let mut store = ComponentStore::new();
+    let foo = store.new_entity();
+    let bar = store.new_entity();
+    store.set_component(foo, Position::new(0.0, 0.0, 0.0));
+    store.set_component(bar, Position::new(0.0, 0.0, 0.0));
+
+    let tick = store.change_tick();
+    assert_eq!(store.changed_since::<Position>(tick), vec![foo, bar]);
+    store.maintain();
+    assert_eq!(store.changed_since::<Position>(tick + 1), vec![]);
+
+    let _ = store.get_component::<Position>(foo);
+    let _ = store.get_mut_component::<Position>(foo);
+    store.remove_component::<Position>(bar);
+    store.remove_component::<Name>(bar);
+    assert_eq!(store.changed_since::<Position>(tick + 1), vec![bar]);
+    assert_eq!(store.changed_since::<Name>(tick), vec![]);
+}
+
+#[system_mut(simple)]
+fn Push(_entity: Entity, dt: f32, position: &mut Position, velocity: &Velocity) {
+    position.0.x += velocity.0 * dt;
+}
+
+#[test]
+fn idle_system_mut_changes_nothing() {
+    let mut store = ComponentStore::new();
+    let foo = store.new_entity();
+    let bar = store.new_entity();
+    let _ = store.new_entity();
+    store.set_component(foo, Position::new(0.0, 0.0, 0.0));
+    store.set_component(bar, Velocity(1.0));
+    store.maintain();
+
+    // No entity has both a position and a velocity, so the system doesn't run on any of them.
+    let mut push = Push;
+    let tick = store.change_tick();
+    push.run(&mut store, 1.0);
+    assert_eq!(store.changed_since::<Position>(tick), vec![]);
+    assert_eq!(store.changed_since::<Velocity>(tick), vec![]);
+
+    store.set_component(foo, Velocity(2.0));
+    store.maintain();
+    let tick = store.change_tick();
+    push.run(&mut store, 1.0);
+    assert_eq!(store.changed_since::<Position>(tick), vec![foo]);
+    assert_eq!(store.changed_since::<Velocity>(tick), vec![]);
+}
+
+fn sorted(mut entities: Vec<Entity>) -> Vec<Entity> {
+    entities.sort_by_key(|e| format!("{:?}", e));
+    entities
+}
+
+#[test]
+fn spatial_index() {
+    let mut store = ComponentStore::new();
+    let a = store.new_entity();
+    let b = store.new_entity();
+    let c = store.new_entity();
+    let d = store.new_entity();
+    store.set_component(a, Position::new(0.0, 0.0, 0.0));
+    store.set_component(b, Position::new(3.0, 0.0, 0.0));
+    store.set_component(c, Position::new(0.0, 25.0, 0.0));
+    store.set_component(d, Position::new(-1000.0, 0.0, 0.0));
+    store.set_spatial_index(SpatialIndex::new(10.0));
+
+    let index = store.spatial_index().unwrap();
+    assert_eq!(index.len(), 4);
+    assert_eq!(
+        sorted(index.within_radius(Point3::new(1.0, 0.0, 0.0), 5.0)),
+        sorted(vec![a, b])
+    );
+    assert_eq!(
+        index.within_aabb(Point3::new(-1.0, 20.0, -1.0), Point3::new(1.0, 30.0, 1.0)),
+        vec![c]
+    );
+    assert_eq!(index.nearest(Point3::new(2.0, 0.0, 0.0), 2), vec![b, a]);
+    assert_eq!(index.nearest(Point3::new(-900.0, 0.0, 0.0), 1), vec![d]);
+    assert_eq!(index.nearest(Point3::new(0.0, 0.0, 0.0), 10).len(), 4);
+
+    let hits = index.raycast(
+        Point3::new(-10.0, 0.5, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        100.0,
+        1.0,
+    );
+    assert_eq!(hits.iter().map(|&(e, _)| e).collect::<Vec<_>>(), vec![a, b]);
+    assert!((hits[0].1 - 10.0).abs() < 1e-4);
+
+    // Rays can go on forever without visiting every cell along the way.
+    let hits = index.raycast(
+        Point3::new(-10.0, 0.5, 0.0),
+        Vector3::new(1.0, 0.0, 0.0),
+        std::f32::INFINITY,
+        1.0,
+    );
+    assert_eq!(hits.iter().map(|&(e, _)| e).collect::<Vec<_>>(), vec![a, b]);
+
+    // Moves and removals are picked up by maintain.
+    *store.get_mut_component(a) = Some(Position::new(0.0, 24.0, 0.0));
+    store.remove_component::<Position>(b);
+    store.maintain();
+    let index = store.spatial_index().unwrap();
+    assert_eq!(index.len(), 3);
+    assert_eq!(index.within_radius(Point3::new(1.0, 0.0, 0.0), 5.0), vec![]);
+    assert_eq!(
+        sorted(index.within_radius(Point3::new(0.0, 24.5, 0.0), 1.0)),
+        sorted(vec![a, c])
+    );
+}
+
+#[test]
+fn name_index() {
+    let mut store = ComponentStore::new();
+    let foo = store.new_entity();
+    let bar = store.new_entity();
+    let baz = store.new_entity();
+    store.set_component(foo, Name("foo".to_string()));
+    store.set_component(bar, Name("bar".to_string()));
+
+    assert_eq!(store.find_by_name("foo"), Some(foo));
+    assert_eq!(store.find_by_name("bar"), Some(bar));
+    assert_eq!(store.find_by_name("baz"), None);
+    assert_eq!(store.duplicate_names().count(), 0);
+
+    store.set_component(baz, Name("foo".to_string()));
+    assert_eq!(store.find_by_name("foo"), Some(foo));
+    assert_eq!(store.find_all_by_name("foo"), &[foo, baz]);
+    assert_eq!(
+        store.duplicate_names().collect::<Vec<_>>(),
+        vec![("foo", &[foo, baz][..])]
+    );
+
+    store.remove_component::<Name>(foo);
+    assert_eq!(store.find_by_name("foo"), Some(baz));
+    assert_eq!(store.duplicate_names().count(), 0);
+
+    assert_eq!(
+        store.take_component::<Name>(bar),
+        Some(Name("bar".to_string()))
+    );
+    assert_eq!(store.find_by_name("bar"), None);
+
+    // Renames through a mutable reference are picked up by maintain.
+    if let Some(name) = store.get_mut_component::<Name>(baz).as_mut() {
+        name.0 = "quux".to_string();
+    }
+    store.maintain();
+    assert_eq!(store.find_by_name("foo"), None);
+    assert_eq!(store.find_by_name("quux"), Some(baz));
+
+    // Cloned entities are indexed too.
+    let quux2 = store.clone_entity(baz);
+    assert_eq!(store.find_all_by_name("quux"), &[baz, quux2]);
+}
+
+#[test]
+fn store_round_trip() {
+    let mut store = ComponentStore::new();
+    let foo = store.new_entity();
+    let empty = store.new_entity();
+    let bar = store.new_entity();
+    store.set_component(foo, Name("foo".to_string()));
+    store.set_component(foo, Position::new(1.0, 2.0, 3.0));
+    store.set_component(bar, Parent(foo));
+
+    let src = serde_sexpr::to_string(&store).unwrap();
+    let mut copy: ComponentStore = serde_sexpr::from_str(&src).unwrap();
+    assert_eq!(copy.get_component::<Name>(foo), store.get_component(foo));
+    assert_eq!(
+        copy.get_component::<Position>(foo),
+        store.get_component(foo)
+    );
+    assert_eq!(copy.get_component::<Parent>(bar).map(|p| p.0), Some(foo));
+    assert_eq!(copy.get_component::<Name>(empty), None);
+    assert_eq!(copy.find_by_name("foo"), Some(foo));
+    assert!(copy.new_entity() != bar);
+}
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+struct Velocity(f32);
+
+#[typetag::serde]
+impl Component for Velocity {}
+
+/// Moves entities along the x axis by their velocity, scaled by `self.0`.
+struct Move(f32);
+
+impl SystemMut for Move {
+    fn run(&mut self, cs: &mut ComponentStore, dt: f32) {
+        for entity in cs.iter_entities() {
+            let v = match cs.get_component::<Velocity>(entity) {
+                Some(v) => v.0,
+                None => continue,
+            };
+            if let Some(p) = cs.get_mut_component::<Position>(entity).as_mut() {
+                p.0.x += v * dt * self.0;
+            }
+        }
+    }
+}
+
+#[test]
+fn record_and_replay() {
+    let mut engine = Engine::new(Assets::new()).add_mut_pass(Move(1.0));
+    let foo = engine.store.new_entity();
+    engine
+        .store
+        .set_component(foo, Position::new(0.0, 0.0, 0.0));
+    engine.store.set_component(foo, Velocity(1.0));
+
+    engine.start_recording(2).unwrap();
+    engine.run_tick(0.5);
+    let bar = engine.apply_input(Input::NewEntity).unwrap();
+    let _ = engine.apply_input(Input::SetComponent(
+        bar,
+        Box::new(Position::new(10.0, 0.0, 0.0)),
+    ));
+    let _ = engine.apply_input(Input::SetComponent(bar, Box::new(Velocity(-2.0))));
+    engine.run_tick(0.25);
+    engine.run_tick(0.25);
+    engine.run_tick(1.0);
+    let recording = engine.stop_recording().unwrap().unwrap();
+    assert_eq!(recording.ticks.len(), 4);
+    assert!(recording.ticks[1].hash.is_some());
+    assert!(recording.ticks[2].hash.is_none());
+
+    let recording = Recording::parse(&recording.to_sexpr().unwrap()).unwrap();
+    let mut replayed = Engine::new(Assets::new()).add_mut_pass(Move(1.0));
+    replayed.replay(recording).unwrap();
+    assert_eq!(
+        replayed.store.get_component::<Position>(foo),
+        engine.store.get_component(foo)
+    );
+    assert_eq!(
+        replayed.store.get_component::<Position>(bar),
+        Some(&Position::new(7.0, 0.0, 0.0))
+    );
+
+    // A system that behaves differently is caught at the first checkpoint after it diverges.
+    let src = {
+        let mut engine = Engine::new(Assets::new()).add_mut_pass(Move(1.0));
+        let foo = engine.store.new_entity();
+        engine
+            .store
+            .set_component(foo, Position::new(0.0, 0.0, 0.0));
+        engine.store.set_component(foo, Velocity(0.0));
+        engine.start_recording(1).unwrap();
+        engine.run_tick(1.0);
+        let _ = engine.apply_input(Input::SetComponent(foo, Box::new(Velocity(1.0))));
+        engine.run_tick(1.0);
+        engine
+            .stop_recording()
+            .unwrap()
+            .unwrap()
+            .to_sexpr()
+            .unwrap()
+    };
+    let mut diverging = Engine::new(Assets::new()).add_mut_pass(Move(2.0));
+    match diverging.replay(Recording::parse(&src).unwrap()) {
+        Err(ReplayError::Diverged { tick, components }) => {
+            assert_eq!(tick, 1);
+            assert_eq!(components, vec!["Position"]);
+        }
+        result => panic!("expected divergence, got {:?}", result),
+    }
+
+    // An engine without the systems the recording was made with can't replay it at all.
+    let mut without_systems = Engine::new(Assets::new());
+    match without_systems.replay(Recording::parse(&src).unwrap()) {
+        Err(ReplayError::WrongSystems { expected, found }) => {
+            assert_eq!(expected, vec![Move(2.0).name().to_string()]);
+            assert!(found.is_empty());
+        }
+        result => panic!("expected the wrong systems, got {:?}", result),
+    }
+}
+
+#[test]
+fn spawned_scenes_are_recorded() {
+    let mut engine = Engine::new(Assets::new()).add_mut_pass(Move(1.0));
+    engine.start_recording(1).unwrap();
+    let spawned = engine.spawn_scene(example_scene()).unwrap();
+    engine.run_tick(1.0);
+    let recording = engine.stop_recording().unwrap().unwrap();
+    assert_eq!(recording.ticks[0].inputs.len(), 6);
+
+    let mut replayed = Engine::new(Assets::new()).add_mut_pass(Move(1.0));
+    replayed.replay(recording).unwrap();
+    assert_eq!(
+        replayed.store.get_component::<Name>(spawned[0]),
+        Some(&Name("player".to_string()))
+    );
+    assert_eq!(
+        replayed
+            .store
+            .get_component::<Parent>(spawned[1])
+            .map(|p| p.0),
+        Some(spawned[0])
+    );
+}
+
+#[test]
+fn snapshot_and_restore() {
+    let mut store = ComponentStore::new();
+    store.set_spatial_index(SpatialIndex::new(1.0));
+    let foo = store.new_entity();
+    store.set_component(foo, Name("foo".to_string()));
+    store.set_component(foo, Position::new(0.0, 0.0, 0.0));
+    store.set_component(foo, Velocity(1.0));
+    store.maintain();
+
+    let mut ring = SnapshotRing::new(2);
+    ring.push(0, store.snapshot());
+
+    store.set_component(foo, Position::new(5.0, 0.0, 0.0));
+    store.set_component(foo, Velocity(2.0));
+    let bar = store.new_entity();
+    store.set_component(bar, Name("bar".to_string()));
+    store.set_component(bar, DebugFlag);
+    store.set_component(bar, Velocity(3.0));
+    store.maintain();
+    ring.push(1, store.snapshot());
+
+    store.restore(ring.get(0).unwrap());
+    store.maintain();
+    assert_eq!(
+        store.get_component::<Position>(foo),
+        Some(&Position::new(0.0, 0.0, 0.0))
+    );
+    assert_eq!(store.get_component::<Name>(bar), None);
+    assert_eq!(store.get_component::<DebugFlag>(bar), None);
+    assert_eq!(store.find_by_name("bar"), None);
+    assert_eq!(
+        store.spatial_index().unwrap().position(foo),
+        Some(Point3::new(0.0, 0.0, 0.0))
+    );
+
+    // Components that can't be cloned aren't rolled back, but they're removed from entities that
+    // are forgotten, which are handed out again.
+    assert_eq!(store.get_component::<Velocity>(foo), Some(&Velocity(2.0)));
+    assert_eq!(
+        store.changed_since::<Velocity>(store.change_tick() - 1),
+        vec![bar]
+    );
+    assert_eq!(store.new_entity(), bar);
+    assert_eq!(store.get_component::<Velocity>(bar), None);
+
+    // Snapshots can be restored more than once.
+    store.restore(ring.get(1).unwrap());
+    store.restore(ring.get(1).unwrap());
+    assert_eq!(store.find_by_name("bar"), Some(bar));
+    assert_eq!(store.get_component::<DebugFlag>(bar), Some(&DebugFlag));
+
+    // Old snapshots fall out of the ring, and pushing an earlier tick drops the later ones.
+    ring.push(2, store.snapshot());
+    assert_eq!(ring.len(), 2);
+    assert!(ring.get(0).is_none());
+    ring.push(1, store.snapshot());
+    assert_eq!(ring.oldest_tick(), Some(1));
+    assert_eq!(ring.newest_tick(), Some(1));
+}
+
+#[test]
+fn delete_entity() {
+    let mut store = ComponentStore::new();
+    let foo = store.new_entity();
+    let bar = store.new_entity();
+    store.set_component(foo, Name("foo".to_string()));
+    store.set_component(foo, Position::new(1.0, 2.0, 3.0));
+    store.set_component(bar, Position::new(1.0, 2.0, 3.0));
+
+    store.delete_entity(foo);
+    assert!(!store.is_alive(foo));
+    assert!(store.is_alive(bar));
+    assert_eq!(store.get_component::<Name>(foo), None);
+    assert_eq!(store.get_component::<Position>(foo), None);
+    assert_eq!(store.find_by_name("foo"), None);
+    assert_eq!(
+        store.get_component::<Position>(bar),
+        Some(&Position::new(1.0, 2.0, 3.0))
+    );
+
+    let copy: ComponentStore =
+        serde_sexpr::from_str(&serde_sexpr::to_string(&store).unwrap()).unwrap();
+    assert!(!copy.is_alive(foo));
+    assert!(copy.is_alive(bar));
+}
+
+/// Sends a delta through S-expressions, like a server would send it to a client.
+fn send_delta(delta: Delta<&dyn Component>) -> Delta {
+    serde_sexpr::from_str(&serde_sexpr::to_string(&delta).unwrap()).unwrap()
+}
+
+#[test]
+fn delta_since() {
+    let mut server = ComponentStore::new();
+    let foo = server.new_entity();
+    let bar = server.new_entity();
+    server.set_component(foo, Name("foo".to_string()));
+    server.set_component(foo, Position::new(0.0, 0.0, 0.0));
+    server.set_component(bar, Position::new(1.0, 0.0, 0.0));
+    server.set_component(bar, DebugFlag);
+
+    let mut client = ComponentStore::new();
+    send_delta(Delta::since(&server, 0))
+        .apply(&mut client)
+        .unwrap();
+    assert_eq!(WorldHash::of(&client), WorldHash::of(&server));
+
+    server.maintain();
+    let tick = server.change_tick();
+    server.set_component(foo, Position::new(0.0, 5.0, 0.0));
+    server.remove_component::<DebugFlag>(bar);
+    let baz = server.new_entity();
+    server.set_component(baz, Name("baz".to_string()));
+    let quux = server.new_entity();
+    server.set_component(quux, DebugFlag);
+    server.delete_entity(quux);
+    server.delete_entity(foo);
+
+    let delta = Delta::since(&server, tick);
+    assert_eq!(delta.created, vec![baz]);
+    assert_eq!(delta.destroyed, vec![foo, quux]);
+    assert_eq!(delta.removed, vec![(bar, "DebugFlag".to_string())]);
+    assert_eq!(delta.added.len(), 1);
+    assert!(delta.changed.is_empty());
+
+    send_delta(delta).apply(&mut client).unwrap();
+    assert_eq!(WorldHash::of(&client), WorldHash::of(&server));
+    assert!(!client.is_alive(foo));
+    assert!(!client.is_alive(quux));
+    assert!(client.is_alive(baz));
+    assert_eq!(client.find_by_name("baz"), Some(baz));
+    assert_eq!(client.new_entity(), server.new_entity());
+}
+
+/// A generic component with a name of its own, so its `typetag` name is nothing like its type's.
+#[derive(Debug, Deserialize, Serialize)]
+struct Tagged<T>(T);
+
+#[typetag::serde(name = "U8Tag")]
+impl Component for Tagged<u8> {}
+
+#[test]
+fn delta_removes_renamed_components() {
+    let mut server = ComponentStore::new();
+    let foo = server.new_entity();
+    server.set_component(foo, Tagged(1u8));
+    let mut client = ComponentStore::new();
+    send_delta(Delta::since(&server, 0))
+        .apply(&mut client)
+        .unwrap();
+    assert!(client.get_component::<Tagged<u8>>(foo).is_some());
+
+    let old: ComponentStore =
+        serde_sexpr::from_str(&serde_sexpr::to_string(&server).unwrap()).unwrap();
+    server.maintain();
+    let tick = server.change_tick();
+    server.remove_component::<Tagged<u8>>(foo);
+    let removed = vec![(foo, "U8Tag".to_string())];
+    assert_eq!(Delta::between(&old, &server).removed, removed);
+    let delta = Delta::since(&server, tick);
+    assert_eq!(delta.removed, removed);
+    send_delta(delta).apply(&mut client).unwrap();
+    assert!(client.get_component::<Tagged<u8>>(foo).is_none());
+}
+
+#[test]
+fn delta_entities_are_bounded() {
+    let mut client = ComponentStore::new();
+    let _ = client.new_entity();
+    let far = scene_entity(1 << 40).unwrap();
+    let delta = |next_entity, created| Delta {
+        next_entity,
+        created,
+        destroyed: Vec::new(),
+        added: Vec::new(),
+        changed: Vec::new(),
+        removed: vec![(far, "Name".to_string())],
+    };
+
+    match delta(usize::max_value(), Vec::new()).apply(&mut client) {
+        Err(DeltaError::TooManyEntities(n)) => assert_eq!(n, usize::max_value()),
+        r => panic!("expected too many entities, got {:?}", r),
+    }
+    match delta(MAX_ENTITIES, vec![scene_entity(3).unwrap()]).apply(&mut client) {
+        Err(DeltaError::EntityOutOfRange(entity)) => assert_eq!(entity, far),
+        r => panic!("expected an entity out of range, got {:?}", r),
+    }
+    assert_eq!(client.next_entity(), 2);
+}
+
+#[test]
+fn steady_state_delta_is_empty() {
+    let mut engine = Engine::new(Assets::new()).add_mut_pass(Push);
+    let foo = engine.store.new_entity();
+    let bar = engine.store.new_entity();
+    let _ = engine.store.new_entity();
+    engine.store.set_component(foo, Name("foo".to_string()));
+    engine
+        .store
+        .set_component(foo, Position::new(0.0, 0.0, 0.0));
+    engine.store.set_component(bar, Velocity(1.0));
+    engine.run_once();
+
+    // Nothing changes from one tick to the next, so there's nothing to send.
+    let tick = engine.store.change_tick();
+    engine.run_once();
+    let delta = Delta::since(&engine.store, tick);
+    assert!(delta.is_empty(), "{:?}", delta);
+    let delta = Delta::since_in_scope(&engine.store, tick, |_| true, |_| true);
+    assert!(delta.is_empty(), "{:?}", delta);
+}
+
+#[test]
+fn delta_in_scope() {
+    let mut server = ComponentStore::new();
+    let foo = server.new_entity();
+    let bar = server.new_entity();
+    server.set_component(foo, Name("foo".to_string()));
+    server.set_component(foo, Position::new(0.0, 0.0, 0.0));
+    server.set_component(bar, Name("bar".to_string()));
+    server.set_component(bar, Position::new(1.0, 0.0, 0.0));
+
+    // The client starts out only seeing foo.
+    let mut client = ComponentStore::new();
+    let delta = Delta::since_in_scope(&server, 0, |_| false, |entity| entity == foo);
+    assert_eq!(delta.created, vec![foo]);
+    assert_eq!(delta.added.len(), 2);
+    send_delta(delta).apply(&mut client).unwrap();
+    assert_eq!(client.find_by_name("foo"), Some(foo));
+    assert_eq!(client.find_by_name("bar"), None);
+
+    // Then foo moves out of scope while bar moves into it, bringing all its components.
+    server.maintain();
+    let tick = server.change_tick();
+    server.set_component(foo, Position::new(0.0, 5.0, 0.0));
+    let delta = Delta::since_in_scope(
+        &server,
+        tick,
+        |entity| entity == foo,
+        |entity| entity == bar,
+    );
+    assert_eq!(delta.created, vec![bar]);
+    assert_eq!(delta.destroyed, vec![foo]);
+    assert_eq!(delta.added.len(), 2);
+    assert!(delta.changed.is_empty());
+    send_delta(delta).apply(&mut client).unwrap();
+    client.maintain();
+    assert!(!client.is_alive(foo));
+    assert_eq!(client.find_by_name("bar"), Some(bar));
+
+    // Entities that come back into scope come back to life.
+    server.maintain();
+    let tick = server.change_tick();
+    server.set_component(bar, Position::new(2.0, 0.0, 0.0));
+    let delta = Delta::since_in_scope(&server, tick, |entity| entity == bar, |_| true);
+    assert_eq!(delta.created, vec![foo]);
+    assert_eq!(delta.changed.len(), 1);
+    send_delta(delta).apply(&mut client).unwrap();
+    client.maintain();
+    assert_eq!(WorldHash::of(&client), WorldHash::of(&server));
+    assert!(client.is_alive(foo));
+}
+
+#[test]
+fn delta_recreates_entities_the_client_has() {
+    let mut server = ComponentStore::new();
+    let foo = server.new_entity();
+    server.set_component(foo, Name("foo".to_string()));
+    server.set_component(foo, DebugFlag);
+    let mut client = ComponentStore::new();
+    send_delta(Delta::since(&server, 0))
+        .apply(&mut client)
+        .unwrap();
+
+    // If the server can't tell whether the client got the removal, it sends foo afresh.
+    server.maintain();
+    server.remove_component::<DebugFlag>(foo);
+    server.set_component(foo, Position::new(1.0, 0.0, 0.0));
+    let delta = Delta::since_in_scope(&server, 0, |_| false, |_| true);
+    assert_eq!(delta.created, vec![foo]);
+    send_delta(delta).apply(&mut client).unwrap();
+    client.maintain();
+    assert_eq!(WorldHash::of(&client), WorldHash::of(&server));
+    assert!(client.get_component::<DebugFlag>(foo).is_none());
+    assert_eq!(client.find_by_name("foo"), Some(foo));
+}
+
+#[test]
+fn delta_between() {
+    let mut server = ComponentStore::new();
+    let foo = server.new_entity();
+    let bar = server.new_entity();
+    server.set_component(foo, Name("foo".to_string()));
+    server.set_component(foo, Position::new(0.0, 0.0, 0.0));
+    server.set_component(bar, Position::new(1.0, 0.0, 0.0));
+    server.set_component(bar, DebugFlag);
+    let old: ComponentStore =
+        serde_sexpr::from_str(&serde_sexpr::to_string(&server).unwrap()).unwrap();
+    let mut client: ComponentStore =
+        serde_sexpr::from_str(&serde_sexpr::to_string(&server).unwrap()).unwrap();
+
+    // Mutable accesses that don't change anything aren't sent.
+    let _ = server.get_mut_component::<Position>(bar);
+    server.set_component(foo, Position::new(0.0, 5.0, 0.0));
+    server.remove_component::<DebugFlag>(bar);
+    let baz = server.new_entity();
+    server.set_component(baz, Name("baz".to_string()));
+
+    let delta = Delta::between(&old, &server);
+    assert_eq!(delta.created, vec![baz]);
+    assert!(delta.destroyed.is_empty());
+    assert_eq!(delta.added.len(), 1);
+    assert_eq!(delta.changed.len(), 1);
+    assert_eq!(delta.changed[0].0, foo);
+    assert_eq!(delta.removed, vec![(bar, "DebugFlag".to_string())]);
+
+    send_delta(delta).apply(&mut client).unwrap();
+    assert_eq!(WorldHash::of(&client), WorldHash::of(&server));
+    assert!(Delta::between(&client, &server).is_empty());
+}
+
+#[test]
+fn bit_packing() {
+    let mut w = BitWriter::new(WorldBounds::default());
+    w.write_bool(true);
+    w.write_bits(0b101, 3);
+    w.write_varint(300);
+    w.write_bytes(b"hi");
+    (-5i64).quantize(&mut w);
+    Some(Name("foo".to_string())).quantize(&mut w);
+    f32::NAN.quantize(&mut w);
+    assert_eq!(w.len_bits(), 1 + 3 + 16 + (8 + 16) + 8 + (1 + 8 + 24) + 32);
+    let buf = w.into_bytes();
+
+    let mut r = BitReader::new(&buf, WorldBounds::default());
+    assert_eq!(r.read_bool(), Some(true));
+    assert_eq!(r.read_bits(3), Some(0b101));
+    assert_eq!(r.read_varint(), Some(300));
+    assert_eq!(r.read_bytes(), Some(b"hi".to_vec()));
+    assert_eq!(i64::dequantize(&mut r), Some(-5));
+    assert_eq!(
+        Option::<Name>::dequantize(&mut r),
+        Some(Some(Name("foo".to_string())))
+    );
+    assert!(f32::dequantize(&mut r).unwrap().is_nan());
+    assert!(r.remaining_bits() < 8);
+    assert_eq!(r.read_bits(8), None);
+}
+
+#[test]
+fn quantize_positions() {
+    let bounds = WorldBounds {
+        min: Point3::new(-100.0, 0.0, -100.0),
+        max: Point3::new(100.0, 50.0, 100.0),
+        precision: 0.01,
+    };
+    assert_eq!(bounds.position_bits(), [15, 13, 15]);
+
+    for i in 0..1000 {
+        let t = i as f32 / 1000.0;
+        let position = Position::new(-100.0 + 200.0 * t, 50.0 * (t * 7.0).fract(), 99.99 - t);
+        let mut w = BitWriter::new(bounds);
+        position.quantize(&mut w);
+        assert_eq!(w.len_bits(), 43);
+        let buf = w.into_bytes();
+        let quantized = Position::dequantize(&mut BitReader::new(&buf, bounds)).unwrap();
+        let error = quantized.0 - position.0;
+        for &axis in &[error.x, error.y, error.z] {
+            assert!(axis.abs() <= bounds.precision / 2.0, "{:?}", error);
+        }
+    }
+
+    // Positions outside the bounds are clamped to them.
+    let mut w = BitWriter::new(bounds);
+    Position::new(-1000.0, 60.0, 0.0).quantize(&mut w);
+    let buf = w.into_bytes();
+    let quantized = Position::dequantize(&mut BitReader::new(&buf, bounds)).unwrap();
+    assert!((quantized.0 - Point3::new(-100.0, 50.0, 0.0)).magnitude() < 0.01);
+}
+
+#[test]
+fn quantize_rotations() {
+    let mut rotations = vec![
+        Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        Quaternion::new(-1.0, 0.0, 0.0, 0.0),
+        Quaternion::new(0.0, 0.0, -1.0, 0.0),
+        Quaternion::new(0.5, -0.5, 0.5, -0.5),
+        // Not normalized.
+        Quaternion::new(2.0, 1.0, 0.0, 0.0),
+    ];
+    for i in 0..100 {
+        let angle = cgmath::Rad(i as f32 * 0.37);
+        let axis = Vector3::new((i as f32).sin(), (i as f32).cos(), 0.5).normalize();
+        rotations.push(Quaternion::from_axis_angle(axis, angle));
+    }
+
+    for rotation in rotations {
+        let mut w = BitWriter::new(WorldBounds::default());
+        rotation.quantize(&mut w);
+        assert_eq!(w.len_bits(), 32);
+        let buf = w.into_bytes();
+        let quantized =
+            Quaternion::dequantize(&mut BitReader::new(&buf, WorldBounds::default())).unwrap();
+
+        // q and -q are the same rotation, so only the magnitude of the dot product matters.
+        let similarity = rotation.normalize().dot(quantized).abs();
+        assert!(1.0 - similarity < 1e-5, "{:?} -> {:?}", rotation, quantized);
+        assert!((quantized.magnitude() - 1.0).abs() < 1e-3);
+    }
+}
+
+#[test]
+fn codec_round_trip() {
+    let mut server = ComponentStore::new();
+    let foo = server.new_entity();
+    let bar = server.new_entity();
+    server.set_component(foo, Name("foo".to_string()));
+    server.set_component(foo, Position::new(1.25, -2.5, 1000.0));
+    server.set_component(foo, Velocity(3.5));
+    server.set_component(bar, Position::new(0.1, 0.2, 0.3));
+    server.set_component(bar, Parent(foo));
+    server.maintain();
+
+    let codec = Codec::new(WorldBounds::default());
+    let delta = Delta::since(&server, 0);
+    let buf = codec.encode_delta(&delta);
+    assert!(buf.len() < serde_sexpr::to_string(&delta).unwrap().len());
+
+    // Unregistered components are sent as S-expressions, so they arrive unchanged.
+    let mut client = ComponentStore::new();
+    codec
+        .decode_delta(&buf)
+        .unwrap()
+        .apply(&mut client)
+        .unwrap();
+    client.maintain();
+    assert_eq!(client.find_by_name("foo"), Some(foo));
+    assert_eq!(client.get_component::<Velocity>(foo), Some(&Velocity(3.5)));
+    assert_eq!(client.get_component::<Parent>(bar).map(|p| p.0), Some(foo));
+    for &entity in &[foo, bar] {
+        let sent = server.get_component::<Position>(entity).unwrap().0;
+        let received = client.get_component::<Position>(entity).unwrap().0;
+        assert!((sent - received).magnitude() < 0.01);
+    }
+
+    // Removals are sent too.
+    let tick = server.change_tick();
+    server.remove_component::<Name>(foo);
+    server.remove_component::<Velocity>(foo);
+    let buf = codec.encode_delta(&Delta::since(&server, tick));
+    codec
+        .decode_delta(&buf)
+        .unwrap()
+        .apply(&mut client)
+        .unwrap();
+    client.maintain();
+    assert_eq!(client.find_by_name("foo"), None);
+    assert_eq!(client.get_component::<Velocity>(foo), None);
+
+    assert!(codec.decode_delta(&buf[..buf.len() - 1]).is_err());
+}
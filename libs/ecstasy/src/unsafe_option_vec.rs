@@ -33,6 +33,11 @@ impl UnsafeOptionVec {
         }
     }
 
+    /// Returns the number of allocated `Option<T>`'s.
+    pub fn allocated_len(&self) -> usize {
+        self.len
+    }
+
     /// Grows the vector to (at least) the given size.
     fn grow_to<T: 'static + Send + Sync>(&mut self, mut n: usize) {
         let old_len = self.len;
@@ -112,6 +117,19 @@ impl UnsafeOptionVec {
         NonNull::new_unchecked(self.ptr.as_ptr().add(size))
     }
 
+    /// Reads the `n`th value from the `UnsafeOptionVec`, or returns `None` if `n` is out of
+    /// bounds.
+    #[safety(eq(self.layout, Layout::new::<Option<T>>()),
+        "T must have the same layout as the type that was given to `UnsafeOptionVec::new`")]
+    #[safety("T must be the same type as was given to `UnsafeOptionVec::new`")]
+    pub unsafe fn get<T: 'static + Send + Sync>(&self, n: usize) -> Option<&Option<T>> {
+        if n < self.len {
+            Some(&*self.ptr(n).cast::<Option<T>>().as_ptr())
+        } else {
+            None
+        }
+    }
+
     /// Reads the `n`th value from the `UnsafeOptionVec`. This will extend the underlying
     /// allocation if `n` is out of bounds.
     #[safety(eq(self.layout, Layout::new::<Option<T>>()),
@@ -125,6 +143,25 @@ impl UnsafeOptionVec {
         let ptr = self.ptr(n).cast::<Option<T>>().as_ptr();
         &mut *ptr
     }
+
+    /// Clones the vector, including all its elements.
+    #[safety(eq(self.layout, Layout::new::<Option<T>>()),
+        "T must have the same layout as the type that was given to `UnsafeOptionVec::new`")]
+    #[safety("T must be the same type as was given to `UnsafeOptionVec::new`")]
+    pub unsafe fn clone_as<T: 'static + Clone + Send + Sync>(&self) -> UnsafeOptionVec {
+        let mut vec = UnsafeOptionVec::new::<T>();
+        if self.len != 0 {
+            // Every element is initialized to `None` first, so a panicking `clone` can't leave
+            // uninitialized elements to be dropped.
+            vec.grow_to::<T>(self.len);
+            for i in 0..self.len {
+                if let Some(value) = &*self.ptr(i).cast::<Option<T>>().as_ptr() {
+                    *vec.get_mut::<T>(i) = Some(value.clone());
+                }
+            }
+        }
+        vec
+    }
 }
 
 impl Debug for UnsafeOptionVec {
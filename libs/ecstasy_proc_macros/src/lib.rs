@@ -3,22 +3,161 @@ extern crate proc_macro;
 use proc_macro::TokenStream;
 use quote::quote;
 use syn::{
-    parse_macro_input, spanned::Spanned, Block, DeriveInput, Error, FnArg, Ident, ItemFn, Pat,
-    ReturnType, Type, Visibility,
+    parse_macro_input, spanned::Spanned, Attribute, Block, Data, DeriveInput, Error, FnArg, Ident,
+    Index, ItemFn, Member, Meta, NestedMeta, Pat, ReturnType, Type, Visibility,
 };
 use uuid::Uuid;
 
-#[proc_macro_derive(Component)]
+/// Derives `ecstasy::Component`. See the `ecstasy::Component` docs for the supported attributes.
+#[proc_macro_derive(Component, attributes(component))]
 pub fn derive_component(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let name = input.ident;
+    derive_component_inner(input).unwrap_or_else(|err| err.to_compile_error().into())
+}
+
+fn derive_component_inner(input: DeriveInput) -> Result<TokenStream, Error> {
+    let mut clone = false;
+    let mut quantize = false;
+    for word in component_attrs(&input.attrs)? {
+        if word == "clone" {
+            clone = true;
+        } else if word == "quantize" {
+            quantize = true;
+        } else {
+            return Err(Error::new(
+                word.span(),
+                format!("unknown component attribute `{}`", word),
+            ));
+        }
+    }
+
+    let mut entity_fields = Vec::new();
+    if let Data::Struct(data) = &input.data {
+        for (i, field) in data.fields.iter().enumerate() {
+            for word in component_attrs(&field.attrs)? {
+                if word == "entity" {
+                    entity_fields.push(match &field.ident {
+                        Some(ident) => Member::Named(ident.clone()),
+                        None => Member::Unnamed(Index::from(i)),
+                    });
+                } else {
+                    return Err(Error::new(
+                        word.span(),
+                        format!("unknown component field attribute `{}`", word),
+                    ));
+                }
+            }
+        }
+    }
+
+    let clone_component = if clone {
+        quote! {
+            fn clone_component() -> std::option::Option<::ecstasy::CloneComponent> {
+                std::option::Option::Some(::ecstasy::CloneComponent::of::<Self>())
+            }
+        }
+    } else {
+        quote! {}
+    };
+
+    let remap_entities = if entity_fields.is_empty() {
+        quote! {}
+    } else {
+        quote! {
+            fn remap_entities(
+                &mut self,
+                map: &mut dyn FnMut(::ecstasy::Entity) -> ::ecstasy::Entity,
+            ) {
+                #(::ecstasy::MapEntities::map_entities(&mut self.#entity_fields, map);)*
+            }
+        }
+    };
+
+    let name = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
-    TokenStream::from(quote! {
+    let quantize_impl = if quantize {
+        derive_quantize(&input)?
+    } else {
+        quote! {}
+    };
+    Ok(TokenStream::from(quote! {
         #[typetag::serde]
-        impl #impl_generics ::ecstasy::Component for #name #ty_generics #where_clause {}
+        impl #impl_generics ::ecstasy::Component for #name #ty_generics #where_clause {
+            #clone_component
+            #remap_entities
+        }
+
+        #quantize_impl
+    }))
+}
+
+/// Implements `ecstasy::quantize::Quantize` for a struct, by quantizing each of its fields in
+/// order.
+fn derive_quantize(input: &DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "`#[component(quantize)]` can only be used on structs",
+            ))
+        }
+    };
+
+    let members = fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        })
+        .collect::<Vec<_>>();
+    let dequantize_fields = members
+        .iter()
+        .map(|member| quote! { #member: ::ecstasy::quantize::Quantize::dequantize(r)? })
+        .collect::<Vec<_>>();
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::ecstasy::quantize::Quantize for #name #ty_generics #where_clause {
+            fn quantize(&self, w: &mut ::ecstasy::quantize::BitWriter) {
+                #(::ecstasy::quantize::Quantize::quantize(&self.#members, w);)*
+            }
+
+            fn dequantize(
+                r: &mut ::ecstasy::quantize::BitReader,
+            ) -> std::option::Option<Self> {
+                std::option::Option::Some(#name { #(#dequantize_fields,)* })
+            }
+        }
     })
 }
 
+/// Returns the words inside all the `#[component(...)]` attributes in `attrs`.
+fn component_attrs(attrs: &[Attribute]) -> Result<Vec<Ident>, Error> {
+    let mut words = Vec::new();
+    for attr in attrs {
+        if attr.path.segments.len() != 1 || attr.path.segments[0].ident != "component" {
+            continue;
+        }
+        match attr.parse_meta()? {
+            Meta::List(list) => {
+                for nested in list.nested {
+                    match nested {
+                        NestedMeta::Meta(Meta::Word(word)) => words.push(word),
+                        nested => {
+                            return Err(Error::new(nested.span(), "invalid component attribute"))
+                        }
+                    }
+                }
+            }
+            meta => return Err(Error::new(meta.span(), "invalid component attribute")),
+        }
+    }
+    Ok(words)
+}
+
 /// Creates an `ecstasy::System` from a function. See the `ecstasy` crate for an example.
 #[proc_macro_attribute]
 pub fn system(_attr: TokenStream, item: TokenStream) -> TokenStream {
@@ -29,6 +168,9 @@ pub fn system(_attr: TokenStream, item: TokenStream) -> TokenStream {
 }
 
 /// Creates an `ecstasy::SystemMut` from a function. See the `ecstasy` crate for an example.
+///
+/// Components taken by `&mut` are counted as changed on every entity the system runs on, so take
+/// the ones it only reads by `&`.
 #[proc_macro_attribute]
 pub fn system_mut(_attr: TokenStream, item: TokenStream) -> TokenStream {
     let func = parse_macro_input!(item as ItemFn);
@@ -57,7 +199,7 @@ fn system_inner(system_like: SystemLike) -> Result<TokenStream, Error> {
 
     let body = inputs
         .into_iter()
-        .fold(quote! { #block }, |block, (pat, ty)| {
+        .fold(quote! { #block }, |block, (pat, ty, _)| {
             quote! {
                 if let Some(#pat) = cs.get_component::<#ty>(#entity_pat) {
                     #block
@@ -80,6 +222,10 @@ fn system_inner(system_like: SystemLike) -> Result<TokenStream, Error> {
             fn run(&mut self, cs: &ecstasy::ComponentStore, #dt_pat: #dt_ty) {
                 cs.iter_entities().for_each(|#entity_pat: #entity_ty| #body)
             }
+
+            fn name(&self) -> &str {
+                #name_str
+            }
         }
 
         #attrs
@@ -106,7 +252,7 @@ fn system_mut_inner(system_like: SystemLike) -> Result<TokenStream, Error> {
         proc_macro2::Span::call_site(),
     );
 
-    let tys_must_be_distinct = triangle_perms(inputs.iter().map(|(_, t)| t))
+    let tys_must_be_distinct = triangle_perms(inputs.iter().map(|(_, t, _)| t))
         .map(|(l, r)| {
             quote! {
                 // TODO: Should this have std::stringify!() or something?
@@ -116,15 +262,36 @@ fn system_mut_inner(system_like: SystemLike) -> Result<TokenStream, Error> {
         })
         .collect::<proc_macro2::TokenStream>();
 
+    // Only entities that have every input are borrowed mutably, so that the others aren't counted
+    // as changed. Inputs taken by `&` are never borrowed mutably at all.
+    let all_present = inputs
+        .iter()
+        .map(|(_, ty, _)| quote! { && cs.get_component::<#ty>(#entity_pat).is_some() })
+        .collect::<proc_macro2::TokenStream>();
     let body = inputs
         .into_iter()
-        .fold(quote! { #block }, |block, (pat, ty)| {
-            quote! {
-                if let Some(#pat) = unsafe { cs.unsafe_get_mut_component::<#ty>(#entity_pat) } {
-                    #block
+        .fold(quote! { #block }, |block, (pat, ty, mutable)| {
+            if mutable {
+                quote! {
+                    if let Some(#pat) =
+                        unsafe { cs.unsafe_get_mut_component::<#ty>(#entity_pat) }.as_mut()
+                    {
+                        #block
+                    }
+                }
+            } else {
+                quote! {
+                    if let Some(#pat) = cs.get_component::<#ty>(#entity_pat) {
+                        #block
+                    }
                 }
             }
         });
+    let body = quote! {
+        if true #all_present {
+            #body
+        }
+    };
 
     let name_str = name.to_string();
     Ok(TokenStream::from(quote! {
@@ -142,6 +309,10 @@ fn system_mut_inner(system_like: SystemLike) -> Result<TokenStream, Error> {
                 #tys_must_be_distinct
                 cs.iter_entities().for_each(|#entity_pat: #entity_ty| #body)
             }
+
+            fn name(&self) -> &str {
+                #name_str
+            }
         }
 
         #attrs
@@ -182,10 +353,13 @@ struct SystemLike {
     entity_ty: Type,
     dt_pat: Pat,
     dt_ty: Type,
-    inputs: Vec<(Pat, Type)>,
+    /// Each component argument, and whether it's taken mutably.
+    inputs: Vec<(Pat, Type, bool)>,
 }
 
-fn system_like(func: ItemFn, name: &str, args_mut: bool) -> Result<SystemLike, Error> {
+/// Checks that `func` can be made into a system. Component arguments may only be `&mut` if
+/// `allow_mut` is set.
+fn system_like(func: ItemFn, name: &str, allow_mut: bool) -> Result<SystemLike, Error> {
     if let Some(constness) = func.constness {
         Err(Error::new(
             constness.span(),
@@ -249,17 +423,13 @@ fn system_like(func: ItemFn, name: &str, args_mut: bool) -> Result<SystemLike, E
                                         name
                                     ),
                                 ))
-                            } else if r.mutability.is_some() == args_mut {
-                                Ok((pat, *r.elem))
-                            } else {
+                            } else if r.mutability.is_some() && !allow_mut {
                                 Err(Error::new(
                                     r.span(),
-                                    format!(
-                                        "invalid {} argument: should{} be mutable",
-                                        name,
-                                        if args_mut { "" } else { " not" }
-                                    ),
+                                    format!("invalid {} argument: should not be mutable", name),
                                 ))
+                            } else {
+                                Ok((pat, *r.elem, r.mutability.is_some()))
                             }
                         }
                         _ => Err(Error::new(
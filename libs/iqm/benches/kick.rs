@@ -1,5 +1,5 @@
 use criterion::{criterion_group, criterion_main, Criterion};
-use iqm::IQM;
+use iqm::{IQMRef, IQM};
 use std::fs::read;
 
 fn kick(c: &mut Criterion) {
@@ -8,6 +8,11 @@ fn kick(c: &mut Criterion) {
         b.iter(|| IQM::parse_from(&kick_iqm).unwrap())
     });
 
+    c.bench_function("loading actors/player/kick.iqm (borrowed)", |b| {
+        let kick_iqm = read("../../assets/actors/player/kick.iqm").unwrap();
+        b.iter(|| IQMRef::parse_from(&kick_iqm).unwrap().num_vertexes())
+    });
+
     c.bench_function("loading actors/player/kick.iqm (incl. IO)", |b| {
         b.iter(|| {
             let kick_iqm = read("../../assets/actors/player/kick.iqm").unwrap();
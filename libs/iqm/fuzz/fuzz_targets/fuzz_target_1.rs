@@ -4,12 +4,119 @@
 extern crate libfuzzer_sys;
 
 use byteorder::{ByteOrder, LittleEndian};
+use iqm::{ExtensionRegistry, ExtensionType, IQMRef, IQM};
+
+/// A type for unnamed extensions, so the registry has something to decode.
+struct Any(Vec<u8>);
+
+impl ExtensionType for Any {
+    const NAME: &'static str = "";
+
+    fn decode(_: &IQM, data: &[u8]) -> Option<Any> {
+        Some(Any(data.to_vec()))
+    }
+}
 
 fuzz_target!(|data: &[u8]| {
+    // The magic, version, and filesize are fixed up, so the fuzzer spends its time on the rest of
+    // the file.
     let mut data = data.to_vec();
     let l = data.len() as u32;
     if l >= 24 {
+        data[..16].copy_from_slice(b"INTERQUAKEMODEL\0");
+        LittleEndian::write_u32(&mut data[16..20], 2);
         LittleEndian::write_u32(&mut data[20..24], l);
-        let _ = iqm::IQM::parse_from(&data);
+        match (IQM::parse_from(&data), IQMRef::parse_from(&data)) {
+            (Ok(iqm), Ok(view)) => {
+                exercise(&iqm);
+                exercise_ref(&view);
+
+                // The view should hold the same data as the parsed file.
+                let written = write(&iqm);
+                assert_eq!(write(&IQM::from(&view)), written);
+                round_trip(&written);
+            }
+            (Err(_), Err(_)) => {}
+            (iqm, view) => panic!(
+                "IQM::parse_from gave {:?}, but IQMRef::parse_from gave {:?}",
+                iqm.err(),
+                view.err()
+            ),
+        }
     }
 });
+
+/// Writes out a parsed file, which should always succeed.
+fn write(iqm: &IQM) -> Vec<u8> {
+    let mut bs = Vec::new();
+    iqm.write_to(&mut bs).expect("a parsed file couldn't be written");
+    bs
+}
+
+/// Checks that a written file parses, and is written the same way again. The files are compared
+/// rather than the parsed data, since the data may contain NaNs.
+fn round_trip(written: &[u8]) {
+    let reparsed = IQM::parse_from(written).expect("a written file couldn't be parsed");
+    assert_eq!(write(&reparsed), written);
+}
+
+/// Calls everything that reads a parsed file, none of which should panic.
+fn exercise(iqm: &IQM) {
+    for mesh in &iqm.meshes {
+        let _ = iqm.text(mesh.name);
+        let _ = iqm.mesh_triangles(mesh);
+        let _ = iqm.mesh_adjacency(mesh);
+    }
+    for array in &iqm.vertex_arrays {
+        let _ = array.to_f32();
+        let _ = array.to_vec2();
+        let _ = array.to_vec3();
+        let _ = array.to_vec4_normalized();
+        let _ = array.to_u32x4();
+    }
+
+    let _ = iqm.bind_matrices();
+    for frame in 0..iqm.num_frames.min(16) {
+        let _ = iqm.frame(frame);
+    }
+    for animation in &iqm.animations {
+        for &t in &[-1.0, 0.0, 0.3, 1.0, 1e9, std::f32::NAN, std::f32::INFINITY] {
+            if let Some(pose) = iqm.sample(animation, t) {
+                let _ = iqm.skinning_matrices(&pose);
+            }
+            let _ = iqm.bounds_at(animation, t);
+        }
+    }
+
+    let mut registry = ExtensionRegistry::new();
+    registry.register::<Any>();
+    let _ = registry.decode_all(iqm);
+}
+
+/// Iterates over everything in a view, none of which should panic.
+fn exercise_ref(view: &IQMRef) {
+    for mesh in view.meshes() {
+        let _ = view.text(mesh.name);
+    }
+    for array in view.vertex_arrays() {
+        let _ = array.len();
+        let _ = array.to_data();
+        let _ = array.iter_f32().count();
+    }
+    let _ = view.num_vertexes();
+    let _ = view.triangles().count();
+    let _ = view.adjacency().map(Iterator::count);
+    let _ = view.joints().count();
+    let _ = view.poses().count();
+    let _ = view.animations().count();
+    let _ = view.num_frames();
+    let _ = view.num_framechannels();
+    let _ = view.frames().count();
+    let _ = view.bounds().map(Iterator::count);
+    let _ = view.comments().len();
+    for extension in view.extensions() {
+        if let Some(name) = view.text(extension.name) {
+            let _ = view.extension(name);
+        }
+    }
+}
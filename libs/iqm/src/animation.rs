@@ -0,0 +1,240 @@
+//! Poses, frames, and the animations made of them.
+
+use crate::{
+    check_index, entries,
+    joints::{read_parent, read_transform},
+    section, IqmError, Reason, Section, Texts, Transform,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use cgmath::Vector3;
+use std::ops::Range;
+
+/// The flag set on animations that loop.
+pub const IQM_LOOP: u32 = 1;
+
+/// The number of channels in a pose: 3 for the translation, 4 for the rotation, and 3 for the
+/// scale.
+const NUM_CHANNELS: usize = 10;
+
+/// How a joint's transform is encoded in each frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    /// The index of the pose's parent, which always comes before it, or `None` if the pose is a
+    /// root.
+    pub parent: Option<usize>,
+
+    /// Which channels are stored in the frames. The channels are the translation (x, y, z), the
+    /// rotation (x, y, z, w), and the scale (x, y, z), and channel `n` is present if bit `n` is
+    /// set. Each frame has a value for each present channel of each pose, in order.
+    pub channel_mask: u32,
+
+    /// The value of each channel, before the value from the frame (scaled by the channel's
+    /// `channel_scale`) is added to it.
+    pub channel_offset: [f32; 10],
+
+    /// The amount the value of each channel in the frames is scaled by.
+    pub channel_scale: [f32; 10],
+}
+
+impl Pose {
+    /// Returns the number of channels stored in the frames for this pose.
+    pub fn num_channels(&self) -> usize {
+        self.channel_mask.count_ones() as usize
+    }
+
+    /// Decodes the transform for this pose, taking its values in a frame from `values`. Returns
+    /// `None` if there aren't enough values.
+    fn decode<I: Iterator<Item = u16>>(&self, values: &mut I) -> Option<Transform> {
+        let mut channels = self.channel_offset;
+        for (i, channel) in channels.iter_mut().enumerate() {
+            if self.channel_mask & (1 << i) != 0 {
+                *channel += f32::from(values.next()?) * self.channel_scale[i];
+            }
+        }
+        Some(read_transform(&channels))
+    }
+}
+
+/// A single animation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    /// The name of the animation. This is an index into the text entries, or `None` if the
+    /// animation has no name.
+    pub name: Option<usize>,
+
+    /// The range of frames that make up this animation.
+    pub frames: Range<usize>,
+
+    /// The number of frames per second.
+    pub framerate: f32,
+
+    /// The flags. Only `IQM_LOOP` is defined.
+    pub flags: u32,
+}
+
+impl Animation {
+    /// Returns whether the animation loops.
+    pub fn is_looping(&self) -> bool {
+        self.flags & IQM_LOOP != 0
+    }
+
+    /// Returns the frames to interpolate between at the given time (in seconds since the
+    /// animation started), and how far between them to interpolate. Looping animations wrap
+    /// around from their last frame to their first; others stop at their last frame.
+    pub(crate) fn frames_at(&self, t: f32) -> Option<(usize, usize, f32)> {
+        let len = self.frames.len();
+        if len == 0 {
+            return None;
+        }
+
+        let mut position = t * self.framerate;
+        if !position.is_finite() {
+            position = 0.0;
+        }
+        let position = if self.is_looping() {
+            position.rem_euclid(len as f32)
+        } else {
+            position.max(0.0).min((len - 1) as f32)
+        };
+
+        // Rounding can make a wrapped position equal to `len`.
+        let frame = (position.floor() as usize).min(len - 1);
+        let next = if self.is_looping() {
+            (frame + 1) % len
+        } else {
+            (frame + 1).min(len - 1)
+        };
+        let start = self.frames.start;
+        Some((start + frame, start + next, position - frame as f32))
+    }
+}
+
+/// The bounds of the model in a single frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    /// The minimum coordinates of the bounding box.
+    pub mins: Vector3<f32>,
+
+    /// The maximum coordinates of the bounding box.
+    pub maxs: Vector3<f32>,
+
+    /// The radius of the bounding circle in the X-Y plane, around the origin.
+    pub xy_radius: f32,
+
+    /// The radius of the bounding sphere, around the origin.
+    pub radius: f32,
+}
+
+impl Bounds {
+    /// Returns the smallest bounds that contain both these bounds and another.
+    pub fn union(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            mins: Vector3::new(
+                self.mins.x.min(other.mins.x),
+                self.mins.y.min(other.mins.y),
+                self.mins.z.min(other.mins.z),
+            ),
+            maxs: Vector3::new(
+                self.maxs.x.max(other.maxs.x),
+                self.maxs.y.max(other.maxs.y),
+                self.maxs.z.max(other.maxs.z),
+            ),
+            xy_radius: self.xy_radius.max(other.xy_radius),
+            radius: self.radius.max(other.radius),
+        }
+    }
+}
+
+/// Decodes the transforms of each pose, from their values in a frame. Returns `None` if there
+/// aren't enough values.
+pub(crate) fn decode_frame(poses: &[Pose], values: &[u16]) -> Option<Vec<Transform>> {
+    let mut values = values.iter().cloned();
+    poses.iter().map(|pose| pose.decode(&mut values)).collect()
+}
+
+/// Parses `n` poses, checking that each one's parent comes before it.
+pub(crate) fn parse_poses(
+    bs: &[u8],
+    offset: u32,
+    n: usize,
+) -> Result<impl Iterator<Item = Result<Pose, IqmError>> + '_, IqmError> {
+    Ok(entries(bs, Section::Poses, offset, n, 88)?
+        .enumerate()
+        .map(|(i, (offset, bs))| {
+            let channel_mask = LittleEndian::read_u32(&bs[4..8]);
+            if channel_mask >> NUM_CHANNELS != 0 {
+                let reason = Reason::InvalidChannelMask(channel_mask);
+                return Err(IqmError::invalid(Section::Poses, offset, reason));
+            }
+            let mut pose = Pose {
+                parent: read_parent(Section::Poses, offset, i, LittleEndian::read_i32(&bs[0..4]))?,
+                channel_mask,
+                channel_offset: [0.0; 10],
+                channel_scale: [0.0; 10],
+            };
+            LittleEndian::read_f32_into(&bs[8..48], &mut pose.channel_offset);
+            LittleEndian::read_f32_into(&bs[48..88], &mut pose.channel_scale);
+            Ok(pose)
+        }))
+}
+
+/// Parses `n` animations, checking that their frames are less than `num_frames`.
+pub(crate) fn parse_animations<'a>(
+    bs: &'a [u8],
+    offset: u32,
+    n: usize,
+    num_frames: usize,
+    texts: &'a Texts<'a>,
+) -> Result<impl Iterator<Item = Result<Animation, IqmError>> + 'a, IqmError> {
+    Ok(
+        entries(bs, Section::Animations, offset, n, 20)?.map(move |(offset, bs)| {
+            let first = LittleEndian::read_u32(&bs[4..8]) as usize;
+            let len = LittleEndian::read_u32(&bs[8..12]) as usize;
+            let end = first.saturating_add(len);
+            if end > num_frames {
+                check_index(Section::Animations, offset, end - 1, num_frames)?;
+            }
+            Ok(Animation {
+                name: texts.name(
+                    Section::Animations,
+                    offset,
+                    LittleEndian::read_u32(&bs[0..4]),
+                )?,
+                frames: first..end,
+                framerate: LittleEndian::read_f32(&bs[12..16]),
+                flags: LittleEndian::read_u32(&bs[16..20]),
+            })
+        }),
+    )
+}
+
+/// Parses the frames, which hold `num_frames * num_channels` values.
+pub(crate) fn parse_frames(
+    bs: &[u8],
+    offset: u32,
+    num_frames: usize,
+    num_channels: usize,
+) -> Result<impl ExactSizeIterator<Item = u16> + '_, IqmError> {
+    let n = num_frames.saturating_mul(num_channels);
+    let bs = section(bs, Section::Frames, offset, n, 2)?;
+    Ok(bs.chunks_exact(2).map(LittleEndian::read_u16))
+}
+
+/// Parses the bounds of each of the `num_frames` frames.
+pub(crate) fn parse_bounds(
+    bs: &[u8],
+    offset: u32,
+    num_frames: usize,
+) -> Result<impl ExactSizeIterator<Item = Bounds> + '_, IqmError> {
+    let bs = section(bs, Section::Bounds, offset, num_frames, 32)?;
+    Ok(bs.chunks_exact(32).map(|bs| {
+        let mut fs = [0.0; 8];
+        LittleEndian::read_f32_into(bs, &mut fs);
+        Bounds {
+            mins: Vector3::new(fs[0], fs[1], fs[2]),
+            maxs: Vector3::new(fs[3], fs[4], fs[5]),
+            xy_radius: fs[6],
+            radius: fs[7],
+        }
+    }))
+}
@@ -0,0 +1,251 @@
+//! Errors from parsing IQM files.
+
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// An error parsing an IQM file.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IqmError {
+    /// The file doesn't start with the magic identifier "INTERQUAKEMODEL\0".
+    BadMagic,
+
+    /// The file is of a version other than 2, the only one supported.
+    WrongVersion(u32),
+
+    /// The size of the file given in the header isn't its actual size.
+    FilesizeMismatch {
+        /// The size given in the header.
+        header: u32,
+
+        /// The actual size of the file.
+        actual: usize,
+    },
+
+    /// Part of a section of the file is invalid.
+    Invalid {
+        /// The section the invalid data is in.
+        section: Section,
+
+        /// The byte offset of the invalid data within the file. This is the start of the
+        /// invalid entry, for sections made of entries.
+        offset: usize,
+
+        /// What's wrong with the data.
+        reason: Reason,
+    },
+}
+
+impl IqmError {
+    /// Creates an `IqmError::Invalid`.
+    pub(crate) fn invalid(section: Section, offset: usize, reason: Reason) -> IqmError {
+        IqmError::Invalid {
+            section,
+            offset,
+            reason,
+        }
+    }
+}
+
+impl Display for IqmError {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            IqmError::BadMagic => write!(fmt, "Not an IQM file (bad magic number)"),
+            IqmError::WrongVersion(version) => write!(
+                fmt,
+                "Unsupported IQM version {} (only version 2 is supported)",
+                version
+            ),
+            IqmError::FilesizeMismatch { header, actual } => write!(
+                fmt,
+                "The header says the file is {} bytes, but it's {} bytes",
+                header, actual
+            ),
+            IqmError::Invalid {
+                section,
+                offset,
+                reason,
+            } => write!(fmt, "In the {} (at byte {}): {}", section, offset, reason),
+        }
+    }
+}
+
+impl Error for IqmError {}
+
+/// A section of an IQM file.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Section {
+    /// The header.
+    Header,
+
+    /// The text entries.
+    Text,
+
+    /// The meshes.
+    Meshes,
+
+    /// The vertex arrays, and their data.
+    VertexArrays,
+
+    /// The triangles.
+    Triangles,
+
+    /// The adjacency table of the triangles.
+    Adjacency,
+
+    /// The joints.
+    Joints,
+
+    /// The poses.
+    Poses,
+
+    /// The animations.
+    Animations,
+
+    /// The frames.
+    Frames,
+
+    /// The bounds of each frame.
+    Bounds,
+
+    /// The comments.
+    Comments,
+
+    /// The extensions, and their data.
+    Extensions,
+}
+
+impl Display for Section {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        let name = match self {
+            Section::Header => "header",
+            Section::Text => "text",
+            Section::Meshes => "meshes",
+            Section::VertexArrays => "vertex arrays",
+            Section::Triangles => "triangles",
+            Section::Adjacency => "adjacency table",
+            Section::Joints => "joints",
+            Section::Poses => "poses",
+            Section::Animations => "animations",
+            Section::Frames => "frames",
+            Section::Bounds => "bounds",
+            Section::Comments => "comments",
+            Section::Extensions => "extensions",
+        };
+        fmt.write_str(name)
+    }
+}
+
+/// Why part of an IQM file is invalid.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Reason {
+    /// The data runs past the end of the file.
+    OutOfBounds,
+
+    /// The data isn't aligned to the size of its values (or 4 bytes, for structures).
+    Misaligned,
+
+    /// A string isn't valid UTF-8.
+    InvalidUtf8,
+
+    /// A string isn't terminated by a NUL byte.
+    UnterminatedString,
+
+    /// The first string isn't the empty string.
+    MissingEmptyString,
+
+    /// A name is given by an offset into the text that isn't the start of a string.
+    DanglingString(u32),
+
+    /// A vertex array has a type that isn't defined.
+    UnknownVertexArrayType(u32),
+
+    /// A vertex array has a format that isn't defined.
+    UnknownVertexFormat(u32),
+
+    /// A vertex array has no components.
+    EmptyVertexArray,
+
+    /// A vertex array comes after one of a later type, or of the same (non-custom) type.
+    UnorderedVertexArray,
+
+    /// An index (of a vertex, triangle, or frame) is too large.
+    IndexOutOfRange {
+        /// The index.
+        index: usize,
+
+        /// The number of things that could be indexed.
+        len: usize,
+    },
+
+    /// A joint or pose comes before its parent (or is its own parent).
+    ParentAfterChild(usize),
+
+    /// A pose has channels set in its mask beyond the 10 that are defined.
+    InvalidChannelMask(u32),
+
+    /// The number of values in each frame isn't the total number of channels the poses have.
+    ChannelCountMismatch {
+        /// The total number of channels the poses have.
+        poses: usize,
+
+        /// The number of values in each frame, according to the header.
+        frames: usize,
+    },
+
+    /// There are both joints and poses, but not the same number of each.
+    PoseCountMismatch {
+        /// The number of joints.
+        joints: usize,
+
+        /// The number of poses.
+        poses: usize,
+    },
+
+    /// The list of extensions loops back on itself.
+    ExtensionCycle,
+
+    /// The list of extensions ends before the number of extensions in the header.
+    ExtensionListTooShort,
+}
+
+impl Display for Reason {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        match self {
+            Reason::OutOfBounds => write!(fmt, "runs past the end of the file"),
+            Reason::Misaligned => write!(fmt, "misaligned"),
+            Reason::InvalidUtf8 => write!(fmt, "invalid UTF-8"),
+            Reason::UnterminatedString => write!(fmt, "unterminated string"),
+            Reason::MissingEmptyString => write!(fmt, "doesn't start with the empty string"),
+            Reason::DanglingString(offset) => {
+                write!(fmt, "{} isn't the offset of a string", offset)
+            }
+            Reason::UnknownVertexArrayType(kind) => {
+                write!(fmt, "unknown vertex array type {}", kind)
+            }
+            Reason::UnknownVertexFormat(format) => write!(fmt, "unknown vertex format {}", format),
+            Reason::EmptyVertexArray => write!(fmt, "vertex array with no components"),
+            Reason::UnorderedVertexArray => write!(fmt, "vertex arrays out of order"),
+            Reason::IndexOutOfRange { index, len } => {
+                write!(fmt, "index {} out of range (there are {})", index, len)
+            }
+            Reason::ParentAfterChild(parent) => {
+                write!(fmt, "the parent ({}) doesn't come before its child", parent)
+            }
+            Reason::InvalidChannelMask(mask) => write!(fmt, "invalid channel mask {:#x}", mask),
+            Reason::ChannelCountMismatch { poses, frames } => write!(
+                fmt,
+                "the poses have {} channels, but frames have {} values",
+                poses, frames
+            ),
+            Reason::PoseCountMismatch { joints, poses } => {
+                write!(fmt, "there are {} joints, but {} poses", joints, poses)
+            }
+            Reason::ExtensionCycle => write!(fmt, "the list of extensions loops"),
+            Reason::ExtensionListTooShort => {
+                write!(fmt, "the list of extensions ends early")
+            }
+        }
+    }
+}
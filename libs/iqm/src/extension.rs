@@ -0,0 +1,148 @@
+//! Extensions, which hold data the format doesn't otherwise define.
+
+use crate::{section, IqmError, Reason, Section, Texts, IQM};
+use byteorder::{ByteOrder, LittleEndian};
+use std::{
+    any::Any,
+    collections::{HashMap, HashSet},
+    fmt::{Debug, Formatter, Result as FmtResult},
+};
+
+/// A single extension, as raw data.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extension {
+    /// The name of the extension. This is an index into the text entries, or `None` if the
+    /// extension has no name.
+    pub name: Option<usize>,
+
+    /// The data of the extension.
+    pub data: Vec<u8>,
+}
+
+impl From<&ExtensionRef<'_>> for Extension {
+    fn from(extension: &ExtensionRef) -> Extension {
+        Extension {
+            name: extension.name,
+            data: extension.data.to_vec(),
+        }
+    }
+}
+
+/// A single extension, with its data borrowed from the file (see `IQMRef`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExtensionRef<'a> {
+    /// The name of the extension. This is an index into the text entries, or `None` if the
+    /// extension has no name.
+    pub name: Option<usize>,
+
+    /// The data of the extension.
+    pub data: &'a [u8],
+}
+
+/// A type of extension that can be decoded from its data.
+pub trait ExtensionType: Any + Sized {
+    /// The name extensions of this type have.
+    const NAME: &'static str;
+
+    /// Decodes an extension from its data, returning `None` if it's invalid. The rest of the file
+    /// is available, e.g. to look up text entries.
+    fn decode(iqm: &IQM, data: &[u8]) -> Option<Self>;
+}
+
+/// A function that decodes a type of extension.
+type Decoder = fn(&IQM, &[u8]) -> Option<Box<dyn Any>>;
+
+/// The types of extensions a program understands, which can be decoded by name.
+pub struct ExtensionRegistry {
+    decoders: HashMap<&'static str, Decoder>,
+}
+
+impl ExtensionRegistry {
+    /// Creates a registry with no types registered.
+    pub fn new() -> ExtensionRegistry {
+        ExtensionRegistry {
+            decoders: HashMap::new(),
+        }
+    }
+
+    /// Registers a type of extension, replacing any other type registered with the same name.
+    pub fn register<T: ExtensionType>(&mut self) {
+        fn decode<T: ExtensionType>(iqm: &IQM, data: &[u8]) -> Option<Box<dyn Any>> {
+            let extension: Box<dyn Any> = Box::new(T::decode(iqm, data)?);
+            Some(extension)
+        }
+
+        let _ = self.decoders.insert(T::NAME, decode::<T>);
+    }
+
+    /// Decodes an extension from the given file with the type registered for its name. Returns
+    /// `None` if no type is registered for the name, or if the extension is invalid.
+    pub fn decode(&self, iqm: &IQM, extension: &Extension) -> Option<Box<dyn Any>> {
+        let decode = self.decoders.get(iqm.text(extension.name)?)?;
+        decode(iqm, &extension.data)
+    }
+
+    /// Decodes each extension in the given file that has a type registered for its name, giving
+    /// the index of each along with it.
+    pub fn decode_all(&self, iqm: &IQM) -> Vec<(usize, Option<Box<dyn Any>>)> {
+        iqm.extensions
+            .iter()
+            .enumerate()
+            .filter(|(_, extension)| {
+                iqm.text(extension.name)
+                    .map(|name| self.decoders.contains_key(name))
+                    == Some(true)
+            })
+            .map(|(i, extension)| (i, self.decode(iqm, extension)))
+            .collect()
+    }
+}
+
+impl Debug for ExtensionRegistry {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        fmt.debug_set().entries(self.decoders.keys()).finish()
+    }
+}
+
+impl Default for ExtensionRegistry {
+    fn default() -> ExtensionRegistry {
+        ExtensionRegistry::new()
+    }
+}
+
+/// The offset of the header field holding the offset of the first extension.
+const OFS_EXTENSIONS: usize = 120;
+
+/// Parses the `n` extensions in the list starting at `offset`, checking that the list doesn't
+/// loop back on itself.
+pub(crate) fn parse_extensions<'a>(
+    bs: &'a [u8],
+    mut offset: u32,
+    n: usize,
+    texts: &Texts,
+) -> Result<Vec<ExtensionRef<'a>>, IqmError> {
+    let mut seen = HashSet::new();
+    let mut extensions = Vec::new();
+    // Errors in the list itself are reported at the offset that links to the bad entry.
+    let mut link = OFS_EXTENSIONS;
+    for _ in 0..n {
+        if offset == 0 {
+            let reason = Reason::ExtensionListTooShort;
+            return Err(IqmError::invalid(Section::Extensions, link, reason));
+        } else if !seen.insert(offset) {
+            let reason = Reason::ExtensionCycle;
+            return Err(IqmError::invalid(Section::Extensions, link, reason));
+        }
+        let entry = section(bs, Section::Extensions, offset, 1, 16)?;
+        let name = LittleEndian::read_u32(&entry[0..4]);
+        let len = LittleEndian::read_u32(&entry[4..8]) as usize;
+        let start = LittleEndian::read_u32(&entry[8..12]);
+        extensions.push(ExtensionRef {
+            name: texts.name(Section::Extensions, offset as usize, name)?,
+            data: section(bs, Section::Extensions, start, len, 1)?,
+        });
+        link = offset as usize + 12;
+        offset = LittleEndian::read_u32(&entry[12..16]);
+    }
+    Ok(extensions)
+}
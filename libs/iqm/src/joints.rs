@@ -0,0 +1,171 @@
+//! Joints, which make up the skeleton meshes are bound to.
+
+use crate::{entries, IqmError, Reason, Section, Texts};
+use byteorder::{ByteOrder, LittleEndian};
+use cgmath::{InnerSpace, Matrix4, Quaternion, Vector3, VectorSpace};
+use std::convert::TryFrom;
+
+/// A translation, rotation, and scale, relative to a joint's parent (or to the model, for joints
+/// with no parent). A point is scaled first, then rotated, then translated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    /// The translation.
+    pub translate: Vector3<f32>,
+
+    /// The rotation, which should be normalized.
+    pub rotate: Quaternion<f32>,
+
+    /// The scale along each axis.
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    /// Returns the matrix that applies this transform.
+    pub fn to_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.translate)
+            * Matrix4::from(self.rotate)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// Returns the matrix that undoes this transform. This is computed from the parts of the
+    /// transform rather than by inverting `to_matrix`, so it's cheaper and more precise, but the
+    /// result has infinite entries if any component of the scale is zero.
+    pub fn to_inverse_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_nonuniform_scale(1.0 / self.scale.x, 1.0 / self.scale.y, 1.0 / self.scale.z)
+            * Matrix4::from(self.rotate.conjugate())
+            * Matrix4::from_translation(-self.translate)
+    }
+
+    /// Interpolates between this transform (when `amount` is 0) and another (when it's 1). The
+    /// rotation takes the shortest path.
+    pub fn interpolate(&self, other: &Transform, amount: f32) -> Transform {
+        let rotate = if self.rotate.dot(other.rotate) < 0.0 {
+            -other.rotate
+        } else {
+            other.rotate
+        };
+        Transform {
+            translate: self.translate.lerp(other.translate, amount),
+            rotate: self.rotate.nlerp(rotate, amount),
+            scale: self.scale.lerp(other.scale, amount),
+        }
+    }
+}
+
+/// A single joint.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Joint {
+    /// The name of the joint. This is an index into the text entries, or `None` if the joint has
+    /// no name.
+    pub name: Option<usize>,
+
+    /// The index of the joint's parent, which always comes before it, or `None` if the joint is a
+    /// root.
+    pub parent: Option<usize>,
+
+    /// The joint's transform in the bind pose, relative to its parent.
+    pub transform: Transform,
+}
+
+/// Computes the model-space matrix of each joint, given the matrix of each relative to its parent.
+/// Parents must come before their children; joints whose parents don't are treated as roots.
+pub(crate) fn to_model_space(joints: &[Joint], local: &[Matrix4<f32>]) -> Vec<Matrix4<f32>> {
+    let mut matrices: Vec<Matrix4<f32>> = Vec::with_capacity(joints.len());
+    for (joint, &matrix) in joints.iter().zip(local) {
+        let matrix = match joint.parent.and_then(|parent| matrices.get(parent)) {
+            Some(parent) => parent * matrix,
+            None => matrix,
+        };
+        matrices.push(matrix);
+    }
+    matrices
+}
+
+/// Reads a transform from 10 floats: the translation, the rotation (as x, y, z, w), and the scale.
+/// The rotation is normalized, unless it already is to within rounding errors, since normalizing
+/// it again could change it slightly, and a written file wouldn't read back the same. Rotations
+/// that can't be normalized (those of length zero, or with infinite or NaN components) are read as
+/// no rotation.
+pub(crate) fn read_transform(fs: &[f32]) -> Transform {
+    Transform {
+        translate: Vector3::new(fs[0], fs[1], fs[2]),
+        rotate: read_rotation([fs[3], fs[4], fs[5], fs[6]]),
+        scale: Vector3::new(fs[7], fs[8], fs[9]),
+    }
+}
+
+/// Reads and normalizes a rotation stored as x, y, z, w, as described for `read_transform`. The
+/// length is found as an `f64`, so large and small components don't overflow or underflow.
+fn read_rotation(xyzw: [f32; 4]) -> Quaternion<f32> {
+    let len2 = xyzw
+        .iter()
+        .map(|&c| f64::from(c) * f64::from(c))
+        .sum::<f64>();
+    let [x, y, z, w] = xyzw;
+    if (len2 - 1.0).abs() <= 1e-6 {
+        Quaternion::new(w, x, y, z)
+    } else if len2.is_normal() {
+        let len = len2.sqrt();
+        let c = |c: f32| (f64::from(c) / len) as f32;
+        Quaternion::new(c(w), c(x), c(y), c(z))
+    } else {
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    }
+}
+
+/// Encodes a transform as `read_transform` decodes it.
+pub(crate) fn write_transform(transform: &Transform) -> [f32; 10] {
+    let Transform {
+        translate: t,
+        rotate: r,
+        scale: s,
+    } = transform;
+    [t.x, t.y, t.z, r.v.x, r.v.y, r.v.z, r.s, s.x, s.y, s.z]
+}
+
+/// Encodes the parent of a joint or pose, with -1 for none.
+pub(crate) fn write_parent(parent: Option<usize>) -> Option<i32> {
+    match parent {
+        Some(parent) => i32::try_from(parent).ok(),
+        None => Some(-1),
+    }
+}
+
+/// Decodes the parent of the `i`th joint or pose (which starts at `offset` in the file), checking
+/// that it comes before its child.
+pub(crate) fn read_parent(
+    which: Section,
+    offset: usize,
+    i: usize,
+    parent: i32,
+) -> Result<Option<usize>, IqmError> {
+    if parent < 0 {
+        Ok(None)
+    } else if (parent as usize) < i {
+        Ok(Some(parent as usize))
+    } else {
+        let reason = Reason::ParentAfterChild(parent as usize);
+        Err(IqmError::invalid(which, offset, reason))
+    }
+}
+
+/// Parses `n` joints, checking that each one's parent comes before it.
+pub(crate) fn parse_joints<'a>(
+    bs: &'a [u8],
+    offset: u32,
+    n: usize,
+    texts: &'a Texts<'a>,
+) -> Result<impl Iterator<Item = Result<Joint, IqmError>> + 'a, IqmError> {
+    Ok(entries(bs, Section::Joints, offset, n, 48)?
+        .enumerate()
+        .map(move |(i, (offset, bs))| {
+            let parent = LittleEndian::read_i32(&bs[4..8]);
+            let mut transform = [0.0; 10];
+            LittleEndian::read_f32_into(&bs[8..48], &mut transform);
+            Ok(Joint {
+                name: texts.name(Section::Joints, offset, LittleEndian::read_u32(&bs[0..4]))?,
+                parent: read_parent(Section::Joints, offset, i, parent)?,
+                transform: read_transform(&transform),
+            })
+        }))
+}
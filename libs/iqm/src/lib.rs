@@ -1,4 +1,4 @@
-//! A parser for the IQM format used by Cube 2 games.
+//! A parser and writer for the IQM format used by Cube 2 games.
 //!
 //! See [here](http://sauerbraten.org/iqm/) for more information about the format, or the `iqm.txt`
 //! file stored adjacently.
@@ -34,53 +34,229 @@
     while_true
 )]
 
+mod animation;
+mod error;
+mod extension;
+mod joints;
 #[cfg(test)]
 mod tests;
-
+mod triangles;
+mod vertex;
+mod view;
+mod write;
+
+pub use crate::{
+    animation::{Animation, Bounds, Pose, IQM_LOOP},
+    error::{IqmError, Reason, Section},
+    extension::{Extension, ExtensionRef, ExtensionRegistry, ExtensionType},
+    joints::{Joint, Transform},
+    vertex::{VertexArray, VertexArrayRef, VertexArrayType, VertexData, VertexFormat},
+    view::IQMRef,
+};
 use byteorder::{ByteOrder, LittleEndian};
-use log::warn;
-use std::{ops::Range, str::from_utf8};
+use cgmath::Matrix4;
+use std::{
+    io::{self, ErrorKind, Write},
+    ops::Range,
+    str::from_utf8,
+};
 
 /// The data stored within an IQM file.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct IQM {
     /// Text entries.
     pub text: Vec<String>,
 
     /// Mesh entries.
     pub meshes: Vec<Mesh>,
-    // TODO: vertex_arrays: (u32, u32, u32),
-    // TODO: triangles: (u32, u32, u32),
-    // TODO: joints: (u32, u32),
-    // TODO: poses: (u32, u32),
-    // TODO: animations: (u32, u32),
-    // TODO: frames: (u32, u32, u32, u32),
+
+    /// The number of vertices. Each vertex array has this many entries.
+    pub num_vertexes: usize,
+
+    /// Vertex arrays, ordered by their types.
+    pub vertex_arrays: Vec<VertexArray>,
+
+    /// Triangles, each given as the indices of its vertices.
+    pub triangles: Vec<[u32; 3]>,
+
+    /// For each triangle, the index of the triangle adjacent to each of its edges (the edge from
+    /// its first vertex to its second, and so on), or `None` if no triangle is adjacent to that
+    /// edge. This is only present if the file includes it.
+    pub adjacency: Option<Vec<[Option<u32>; 3]>>,
+
+    /// The joints of the skeleton, in their bind pose. Each joint's parent comes before it.
+    pub joints: Vec<Joint>,
+
+    /// How the transform of each joint is encoded in the frames. If there are both joints and
+    /// poses, there are the same number of each.
+    pub poses: Vec<Pose>,
+
+    /// Animations, each of which is a range of frames.
+    pub animations: Vec<Animation>,
+
+    /// The number of frames.
+    pub num_frames: usize,
+
+    /// The number of values in each frame. This is the total number of channels the poses have.
+    pub num_framechannels: usize,
+
+    /// The values of the channels in each frame, frame by frame.
+    pub frames: Vec<u16>,
+
+    /// The bounds of the model in each frame. This is only present if the file includes it.
+    pub bounds: Option<Vec<Bounds>>,
+
     /// Comment entries.
     pub comments: Vec<String>,
+
+    /// Extensions, in the order they're listed in the file. `ExtensionRegistry` can decode them.
+    pub extensions: Vec<Extension>,
 }
 
 impl IQM {
-    /// Attempts to read the data from the contents of an IQM file.
-    pub fn parse_from(bs: &[u8]) -> Option<IQM> {
-        let header = Header::parse_from(bs)?;
-        if header.extensions.0 != 0 || header.extensions.1 != 0 {
-            warn!("Extensions are not yet supported");
+    /// Attempts to read the data from the contents of an IQM file. This copies all of it; see
+    /// `IQMRef` to borrow it instead.
+    pub fn parse_from(bs: &[u8]) -> Result<IQM, IqmError> {
+        IQMRef::parse_from(bs).map(|iqm| IQM::from(&iqm))
+    }
+
+    /// Writes the data as an IQM file, which `parse_from` reads back as the same data. Names are
+    /// written as offsets into a rebuilt text section, so text entries that nothing names are
+    /// kept. Data that wouldn't parse back (such as a triangle with a vertex that doesn't exist)
+    /// is rejected with an error of kind `InvalidInput`, and nothing is written.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let bs = write::write(self)?;
+        let _ = IQM::parse_from(&bs).map_err(|err| io::Error::new(ErrorKind::InvalidInput, err))?;
+        w.write_all(&bs)
+    }
+
+    /// Returns the text entry with the given index, such as the name of a mesh. An index of
+    /// `None` (an absent name) gives the empty string.
+    pub fn text(&self, index: Option<usize>) -> Option<&str> {
+        match index {
+            Some(index) => self.text.get(index).map(|s| s.as_str()),
+            None => Some(""),
+        }
+    }
+
+    /// Returns the triangles of a mesh.
+    pub fn mesh_triangles(&self, mesh: &Mesh) -> Option<&[[u32; 3]]> {
+        self.triangles.get(mesh.triangles.clone())
+    }
+
+    /// Returns the adjacency of the triangles of a mesh (see `IQM::adjacency`), if the file
+    /// included it.
+    pub fn mesh_adjacency(&self, mesh: &Mesh) -> Option<&[[Option<u32>; 3]]> {
+        self.adjacency.as_ref()?.get(mesh.triangles.clone())
+    }
+
+    /// Returns the matrix of each joint in the bind pose, relative to its parent.
+    pub fn local_matrices(&self) -> Vec<Matrix4<f32>> {
+        self.joints
+            .iter()
+            .map(|joint| joint.transform.to_matrix())
+            .collect()
+    }
+
+    /// Returns the matrix of each joint in the bind pose, relative to the model.
+    pub fn bind_matrices(&self) -> Vec<Matrix4<f32>> {
+        joints::to_model_space(&self.joints, &self.local_matrices())
+    }
+
+    /// Returns the inverse of each of the `bind_matrices`, which take vertices from the model's
+    /// space to the space of each joint. Skinning multiplies these by the matrices of the joints
+    /// in an animated pose.
+    pub fn inverse_bind_matrices(&self) -> Vec<Matrix4<f32>> {
+        let mut matrices: Vec<Matrix4<f32>> = Vec::with_capacity(self.joints.len());
+        for joint in &self.joints {
+            let matrix = joint.transform.to_inverse_matrix();
+            let matrix = match joint.parent.and_then(|parent| matrices.get(parent)) {
+                Some(parent) => matrix * parent,
+                None => matrix,
+            };
+            matrices.push(matrix);
+        }
+        matrices
+    }
+
+    /// Returns the matrices that take each vertex from the bind pose to the given pose, such as
+    /// one returned by `sample`, for skinning. The pose has the transform of each joint relative
+    /// to its parent.
+    pub fn skinning_matrices(&self, pose: &[Transform]) -> Vec<Matrix4<f32>> {
+        let local = pose.iter().map(Transform::to_matrix).collect::<Vec<_>>();
+        joints::to_model_space(&self.joints, &local)
+            .into_iter()
+            .zip(self.inverse_bind_matrices())
+            .map(|(matrix, inverse)| matrix * inverse)
+            .collect()
+    }
+
+    /// Returns the first animation with the given name.
+    pub fn animation(&self, name: &str) -> Option<&Animation> {
+        self.animations
+            .iter()
+            .find(|animation| self.text(animation.name) == Some(name))
+    }
+
+    /// Decodes the transform of each pose in a frame, relative to its parent.
+    pub fn frame(&self, frame: usize) -> Option<Vec<Transform>> {
+        let start = frame.checked_mul(self.num_framechannels)?;
+        let end = start.checked_add(self.num_framechannels)?;
+        animation::decode_frame(&self.poses, self.frames.get(start..end)?)
+    }
+
+    /// Samples an animation at the given time (in seconds since it started), interpolating
+    /// between the frames on either side. Returns the transform of each pose relative to its
+    /// parent, or `None` if the animation has no frames (or isn't from this file).
+    pub fn sample(&self, animation: &Animation, t: f32) -> Option<Vec<Transform>> {
+        let (from, to, amount) = animation.frames_at(t)?;
+        let from = self.frame(from)?;
+        let to = self.frame(to)?;
+        Some(
+            from.iter()
+                .zip(&to)
+                .map(|(from, to)| from.interpolate(to, amount))
+                .collect(),
+        )
+    }
+
+    /// Returns bounds that contain the model at the given time in an animation, or `None` if the
+    /// file doesn't include bounds or the animation has no frames. Between frames, where `sample`
+    /// interpolates, these are the union of the bounds of the frames on either side rather than
+    /// an interpolation of them, so the model never pokes out of them.
+    pub fn bounds_at(&self, animation: &Animation, t: f32) -> Option<Bounds> {
+        let bounds = self.bounds.as_ref()?;
+        let (from, to, amount) = animation.frames_at(t)?;
+        if amount == 0.0 {
+            bounds.get(from).cloned()
+        } else {
+            Some(bounds.get(from)?.union(bounds.get(to)?))
         }
+    }
+
+    /// Returns the first extension with the given name.
+    pub fn extension(&self, name: &str) -> Option<&Extension> {
+        self.extensions
+            .iter()
+            .find(|extension| self.text(extension.name) == Some(name))
+    }
 
-        let index = |n, (l, o)| &bs[o as usize..][..n * l as usize];
+    /// Decodes the first extension of the given type, if there is one and it's valid.
+    pub fn decode_extension<T: ExtensionType>(&self) -> Option<T> {
+        T::decode(self, &self.extension(T::NAME)?.data)
+    }
 
-        Some(IQM {
-            text: parse_texts(index(1, header.text))?,
-            meshes: parse_meshes(index(24, header.meshes))?,
-            comments: parse_texts(index(1, header.comments))?,
-        })
+    /// Returns the first vertex array of the given type.
+    pub fn vertex_array(&self, kind: VertexArrayType) -> Option<&VertexArray> {
+        self.vertex_arrays.iter().find(|array| array.kind == kind)
     }
 }
 
 /// A single mesh.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Mesh {
-    /// The name of the mesh. This is an index into the text entries.
+    /// The name of the mesh. This is an index into the text entries, or `None` if the mesh has no
+    /// name.
     pub name: Option<usize>,
 
     /// The material to be used. TODO: Where do these end up?
@@ -93,6 +269,9 @@ pub struct Mesh {
     pub triangles: Range<usize>,
 }
 
+/// The size of the header, in bytes.
+const HEADER_SIZE: usize = 124;
+
 /// The header of the IQM file.
 #[derive(Clone, Copy, Debug)]
 struct Header {
@@ -114,10 +293,11 @@ struct Header {
     /// The number and offset of the meshes.
     meshes: (u32, u32),
 
-    /// TODO: uint num_vertexarrays, num_vertexes, ofs_vertexarrays;
+    /// The number of vertex arrays, the number of vertices, and the offset of the vertex arrays.
     vertex_arrays: (u32, u32, u32),
 
-    /// TODO: uint num_triangles, ofs_triangles, ofs_adjacency;
+    /// The number and offset of the triangles, and the offset of the adjacency table (or 0 if
+    /// there isn't one), which has the same number of entries.
     triangles: (u32, u32, u32),
 
     /// The number and offset of the joints.
@@ -129,7 +309,8 @@ struct Header {
     /// The number and offset of the animations.
     animations: (u32, u32),
 
-    /// TODO: uint num_frames, num_framechannels, ofs_frames, ofs_bounds;
+    /// The number of frames, the number of values in each, the offset of the frames, and the
+    /// offset of the bounds of each frame (or 0 if there aren't any).
     frames: (u32, u32, u32, u32),
 
     /// The number and offset of the comments.
@@ -142,11 +323,9 @@ struct Header {
 
 impl Header {
     /// Attempts to read the header from the contents of an IQM file.
-    pub fn parse_from(bs: &[u8]) -> Option<Header> {
-        const HEADER_SIZE: usize = std::mem::size_of::<Header>();
-        assert_eq!(HEADER_SIZE, 124);
+    pub fn parse_from(bs: &[u8]) -> Result<Header, IqmError> {
         if bs.len() < HEADER_SIZE {
-            return None;
+            return Err(IqmError::invalid(Section::Header, 0, Reason::OutOfBounds));
         }
 
         macro_rules! u32_at {
@@ -188,91 +367,165 @@ impl Header {
             extensions: u32_at!(2 x 116),
         };
 
-        if header.filesize as usize == bs.len() && header.validate() {
-            Some(header)
+        if &header.magic != b"INTERQUAKEMODEL\0" {
+            Err(IqmError::BadMagic)
+        } else if header.version != 2 {
+            Err(IqmError::WrongVersion(header.version))
+        } else if header.filesize as usize != bs.len() {
+            Err(IqmError::FilesizeMismatch {
+                header: header.filesize,
+                actual: bs.len(),
+            })
         } else {
-            None
+            Ok(header)
         }
     }
+}
 
-    /// Validates that a header is reasonable. Also check that `self.filesize == file.len()`!
-    fn validate(&self) -> bool {
-        if &self.magic != b"INTERQUAKEMODEL\0" || self.version != 2 {
-            return false;
-        }
-
-        let check_filesize = |n: u32, (l, o): (u32, u32)| {
-            let l = l.checked_mul(n).and_then(|l| l.checked_add(o));
-            match l {
-                Some(l) => l <= self.filesize,
-                None => false,
-            }
-        };
+/// Returns the `n` entries of `size` bytes each starting at `offset` in the file, checking that
+/// they're in bounds and that the offset is aligned to 4 bytes, as the format requires of every
+/// `ofs_*` field. Empty sections may have any offset (the format says they should have an offset
+/// of 0).
+fn section(
+    bs: &[u8],
+    which: Section,
+    offset: u32,
+    n: usize,
+    size: usize,
+) -> Result<&[u8], IqmError> {
+    let start = offset as usize;
+    let out_of_bounds = IqmError::invalid(which, start, Reason::OutOfBounds);
+    let len = n.checked_mul(size).ok_or(out_of_bounds)?;
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if start % 4 != 0 {
+        return Err(IqmError::invalid(which, start, Reason::Misaligned));
+    }
+    start
+        .checked_add(len)
+        .and_then(|end| bs.get(start..end))
+        .ok_or(out_of_bounds)
+}
 
-        check_filesize(1, self.text) &&
-        check_filesize(24, self.meshes) &&
+/// Returns the `n` entries of `size` bytes each starting at `offset` in the file, as `section`
+/// does, along with the offset of each within the file.
+fn entries(
+    bs: &[u8],
+    which: Section,
+    offset: u32,
+    n: usize,
+    size: usize,
+) -> Result<impl Iterator<Item = (usize, &[u8])>, IqmError> {
+    let start = offset as usize;
+    let bs = section(bs, which, offset, n, size)?;
+    Ok(bs
+        .chunks(size)
+        .enumerate()
+        .map(move |(i, entry)| (start + i * size, entry)))
+}
 
-        // TODO: vertex_arrays: (u32, u32, u32),
-        // TODO: triangles: (u32, u32, u32),
+/// Checks that an index is less than `len`.
+fn check_index(which: Section, offset: usize, index: usize, len: usize) -> Result<(), IqmError> {
+    if index < len {
+        Ok(())
+    } else {
+        let reason = Reason::IndexOutOfRange { index, len };
+        Err(IqmError::invalid(which, offset, reason))
+    }
+}
 
-        check_filesize(1, self.joints) && // TODO: Fixme
-        check_filesize(1, self.poses) && // TODO: Fixme
-        check_filesize(1, self.animations) && // TODO: Fixme
+/// The text entries, along with the offset of each one within the text section.
+#[derive(Clone, Debug)]
+struct Texts<'a> {
+    strings: Vec<&'a str>,
+    offsets: Vec<u32>,
+}
 
-        // TODO: frames: (u32, u32, u32, u32),
+impl Texts<'_> {
+    /// Returns the index of the text entry starting at the given offset, or `None` if the offset
+    /// is 0 (which is the empty string, used for absent names). Offsets that aren't the start of
+    /// an entry are invalid, and give `None` overall.
+    fn index_of(&self, offset: u32) -> Option<Option<usize>> {
+        if offset == 0 {
+            Some(None)
+        } else {
+            self.offsets.binary_search(&offset).ok().map(Some)
+        }
+    }
 
-        check_filesize(1, self.comments) &&
-        check_filesize(1, self.extensions)
+    /// Returns the index of the text entry naming the entry of the given section that starts at
+    /// `entry` in the file, as `index_of` does.
+    fn name(&self, which: Section, entry: usize, offset: u32) -> Result<Option<usize>, IqmError> {
+        self.index_of(offset)
+            .ok_or_else(|| IqmError::invalid(which, entry, Reason::DanglingString(offset)))
     }
 }
 
-fn parse_meshes(bs: &[u8]) -> Option<Vec<Mesh>> {
-    fn range(n: u32, l: u32) -> Range<usize> {
-        let n = n as usize;
-        let l = l as usize;
-        n..(n + l)
+fn parse_meshes<'a>(
+    bs: &'a [u8],
+    (n, offset): (u32, u32),
+    num_vertexes: usize,
+    num_triangles: usize,
+    texts: &'a Texts<'a>,
+) -> Result<impl Iterator<Item = Result<Mesh, IqmError>> + 'a, IqmError> {
+    fn range(offset: usize, first: u32, len: u32, max: usize) -> Result<Range<usize>, IqmError> {
+        let first = first as usize;
+        let end = first.saturating_add(len as usize);
+        if end > max {
+            check_index(Section::Meshes, offset, end - 1, max)?;
+        }
+        Ok(first..end)
     }
 
-    if bs.len() % 24 != 0 {
-        return None;
-    }
-    let meshes = (0..bs.len() / 24)
-        .into_iter()
-        .map(|n| n * 24)
-        .map(|n| &bs[n..][..24])
-        .map(|bs| Mesh {
-            name: match LittleEndian::read_u32(&bs[0..4]) {
-                0 => None,
-                n => Some(n as usize - 1),
-            },
-            material: LittleEndian::read_u32(&bs[4..8]),
-            vertices: range(
-                LittleEndian::read_u32(&bs[8..12]),
-                LittleEndian::read_u32(&bs[12..16]),
-            ),
-            triangles: range(
-                LittleEndian::read_u32(&bs[16..20]),
-                LittleEndian::read_u32(&bs[20..24]),
-            ),
-        })
-        .collect();
-    Some(meshes)
+    Ok(
+        entries(bs, Section::Meshes, offset, n as usize, 24)?.map(move |(offset, bs)| {
+            Ok(Mesh {
+                name: texts.name(Section::Meshes, offset, LittleEndian::read_u32(&bs[0..4]))?,
+                material: LittleEndian::read_u32(&bs[4..8]),
+                vertices: range(
+                    offset,
+                    LittleEndian::read_u32(&bs[8..12]),
+                    LittleEndian::read_u32(&bs[12..16]),
+                    num_vertexes,
+                )?,
+                triangles: range(
+                    offset,
+                    LittleEndian::read_u32(&bs[16..20]),
+                    LittleEndian::read_u32(&bs[20..24]),
+                    num_triangles,
+                )?,
+            })
+        }),
+    )
 }
 
-fn parse_texts(mut bs: &[u8]) -> Option<Vec<String>> {
-    let mut texts = Vec::new();
+fn parse_texts(bs: &[u8], which: Section, (n, offset): (u32, u32)) -> Result<Texts<'_>, IqmError> {
+    let mut bs = section(bs, which, offset, n as usize, 1)?;
+    let mut texts = Texts {
+        strings: Vec::new(),
+        offsets: Vec::new(),
+    };
+    let mut start = 0;
     while !bs.is_empty() {
-        let len = bs.iter().cloned().position(|b| b == 0)?;
-        let s = from_utf8(&bs[..len]).ok()?;
-        debug_assert_eq!(bs[len], 0);
+        let error = |reason| IqmError::invalid(which, offset as usize + start as usize, reason);
+        let len = bs
+            .iter()
+            .position(|&b| b == 0)
+            .ok_or_else(|| error(Reason::UnterminatedString))?;
+        let s = from_utf8(&bs[..len]).map_err(|_| error(Reason::InvalidUtf8))?;
         bs = &bs[len + 1..];
-        texts.push(s.to_string());
+        texts.strings.push(s);
+        texts.offsets.push(start);
+        start += len as u32 + 1;
     }
-    if !texts.is_empty() {
-        if !texts[0].is_empty() {
-            return None;
+    if !texts.strings.is_empty() {
+        if !texts.strings[0].is_empty() {
+            let reason = Reason::MissingEmptyString;
+            return Err(IqmError::invalid(which, offset as usize, reason));
         }
-        let _ = texts.remove(0);
+        let _ = texts.strings.remove(0);
+        let _ = texts.offsets.remove(0);
     }
-    Some(texts)
+    Ok(texts)
 }
@@ -1,12 +1,1119 @@
-use crate::IQM;
+use crate::{
+    ExtensionRegistry, ExtensionType, IQMRef, IqmError, Reason, Section, VertexArrayType,
+    VertexData, VertexFormat, IQM, IQM_LOOP,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use cgmath::{InnerSpace, Matrix4, Point3, Quaternion, Transform as _, Vector3};
 use std::fs::read;
 
+// The byte offsets of the header fields set by tests.
+const NUM_TEXT: usize = 28;
+const NUM_MESHES: usize = 36;
+const NUM_VERTEXARRAYS: usize = 44;
+const NUM_TRIANGLES: usize = 56;
+const NUM_JOINTS: usize = 68;
+const NUM_POSES: usize = 76;
+const NUM_ANIMS: usize = 84;
+const NUM_FRAMES: usize = 92;
+const NUM_COMMENT: usize = 108;
+const NUM_EXT: usize = 116;
+
+/// Assembles an IQM file byte by byte, so tests can cover every section and malformed files.
+struct FileBuilder {
+    data: Vec<u8>,
+}
+
+impl FileBuilder {
+    fn new() -> FileBuilder {
+        let mut data = vec![0; 124];
+        data[..16].copy_from_slice(b"INTERQUAKEMODEL\0");
+        LittleEndian::write_u32(&mut data[16..20], 2);
+        FileBuilder { data }
+    }
+
+    /// Sets the header field at the given byte offset.
+    fn set(&mut self, field: usize, value: u32) -> &mut FileBuilder {
+        LittleEndian::write_u32(&mut self.data[field..field + 4], value);
+        self
+    }
+
+    /// Appends data to the file, aligned to `align` bytes, returning its offset.
+    fn append(&mut self, bs: &[u8], align: usize) -> u32 {
+        while self.data.len() % align != 0 {
+            self.data.push(0);
+        }
+        let offset = self.data.len() as u32;
+        self.data.extend_from_slice(bs);
+        offset
+    }
+
+    /// Appends a section, setting the header fields for its count (at `field`) and offset (the
+    /// field after).
+    fn section(&mut self, field: usize, count: u32, bs: &[u8]) -> &mut FileBuilder {
+        let offset = self.append(bs, 4);
+        self.set(field, count).set(field + 4, offset)
+    }
+
+    /// Sets the text section to the given strings, returning the offset of each.
+    fn texts(&mut self, texts: &[&str]) -> Vec<u32> {
+        let mut bs = vec![0];
+        let mut offsets = Vec::new();
+        for text in texts {
+            offsets.push(bs.len() as u32);
+            bs.extend_from_slice(text.as_bytes());
+            bs.push(0);
+        }
+        let _ = self.section(NUM_TEXT, bs.len() as u32, &bs);
+        offsets
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        let len = self.data.len() as u32;
+        self.set(20, len).data.clone()
+    }
+}
+
+/// Asserts that parsing a file fails because of the given problem in the given section.
+fn assert_invalid(data: &[u8], section: Section, reason: Reason) {
+    match IQM::parse_from(data) {
+        Err(IqmError::Invalid {
+            section: s,
+            reason: r,
+            ..
+        }) if (s, r) == (section, reason) => {}
+        Err(err) => panic!("expected {:?} in the {}, got {:?}", reason, section, err),
+        Ok(_) => panic!("expected {:?} in the {}, but parsed", reason, section),
+    }
+}
+
+/// Encodes a list of `u32`s.
+fn u32s(ns: &[u32]) -> Vec<u8> {
+    let mut bs = vec![0; ns.len() * 4];
+    LittleEndian::write_u32_into(ns, &mut bs);
+    bs
+}
+
 #[test]
 fn parse_all_assets() {
     for entry in glob::glob("../../assets/**/*.iqm").unwrap() {
         let path = entry.unwrap();
         println!("Parsing {}...", path.display());
         let data = read(path).unwrap();
-        assert!(IQM::parse_from(&data).is_some());
+        assert!(IQM::parse_from(&data).is_ok());
+    }
+}
+
+#[test]
+fn round_trip_all_assets() {
+    for entry in glob::glob("../../assets/**/*.iqm").unwrap() {
+        let path = entry.unwrap();
+        println!("Round-tripping {}...", path.display());
+        assert_round_trips(&read(path).unwrap());
+    }
+}
+
+/// Builds a file with two vertices, and the given vertex arrays, each given as its type, format,
+/// size, and data.
+fn with_vertex_arrays(arrays: &[(u32, u32, u32, &[u8])]) -> Vec<u8> {
+    let mut file = FileBuilder::new();
+    let names = file.texts(&["body", "glow"]);
+    let _ = file.section(NUM_MESHES, 1, &u32s(&[names[0], 0, 0, 2, 0, 0]));
+    let mut entries = Vec::new();
+    for &(kind, format, size, data) in arrays {
+        let offset = file.append(data, 8);
+        entries.extend_from_slice(&[kind, 0, format, size, offset]);
+    }
+    let offset = file.append(&u32s(&entries), 4);
+    file.set(NUM_VERTEXARRAYS, arrays.len() as u32)
+        .set(NUM_VERTEXARRAYS + 4, 2)
+        .set(NUM_VERTEXARRAYS + 8, offset)
+        .finish()
+}
+
+#[test]
+fn vertex_arrays() {
+    let mut positions = vec![0; 24];
+    LittleEndian::write_f32_into(&[1.0, 2.0, 3.0, -1.0, -2.0, -3.0], &mut positions);
+    let mut texcoords = vec![0; 32];
+    LittleEndian::write_f64_into(&[0.0, 0.5, 1.0, 0.25], &mut texcoords);
+    let mut normals = vec![0; 12];
+    // 1.0, -2.0, 0.5; then 0, 65504 (the largest half), and a tiny subnormal.
+    LittleEndian::write_u16_into(
+        &[0x3c00, 0xc000, 0x3800, 0x0000, 0x7bff, 0x0001],
+        &mut normals,
+    );
+    let colors = [255, 0, 51, 255, 0, 0, 0, 0];
+    let glow = [0x80, 0x7f];
+
+    let custom = 0x10 + 6; // The offset of "glow".
+    let data = with_vertex_arrays(&[
+        (0, 7, 3, &positions[..]),
+        (1, 8, 2, &texcoords[..]),
+        (2, 6, 3, &normals[..]),
+        (6, 1, 4, &colors[..]),
+        (custom, 0, 1, &glow[..]),
+    ]);
+    let iqm = IQM::parse_from(&data).unwrap();
+    assert_eq!(iqm.text(iqm.meshes[0].name), Some("body"));
+    assert_eq!(iqm.num_vertexes, 2);
+    assert_eq!(iqm.vertex_arrays.len(), 5);
+
+    let positions = iqm.vertex_array(VertexArrayType::Position).unwrap();
+    assert_eq!(positions.format(), VertexFormat::Float);
+    assert_eq!(positions.len(), 2);
+    assert_eq!(
+        positions.to_vec3(),
+        Some(vec![[1.0, 2.0, 3.0], [-1.0, -2.0, -3.0]])
+    );
+    assert_eq!(positions.to_vec2(), None);
+
+    let texcoords = iqm.vertex_array(VertexArrayType::TexCoord).unwrap();
+    assert_eq!(texcoords.to_vec2(), Some(vec![[0.0, 0.5], [1.0, 0.25]]));
+
+    let normals = iqm.vertex_array(VertexArrayType::Normal).unwrap().to_f32();
+    assert_eq!(&normals[0], &[1.0, -2.0, 0.5]);
+    assert_eq!(&normals[1][..2], &[0.0, 65504.0]);
+    assert!((normals[1][2] - 5.96e-8).abs() < 1e-9);
+
+    let colors = iqm.vertex_array(VertexArrayType::Color).unwrap();
+    assert_eq!(
+        colors.to_vec4_normalized(),
+        Some(vec![[1.0, 0.0, 0.2, 1.0], [0.0, 0.0, 0.0, 0.0]])
+    );
+    assert_eq!(colors.to_u32x4(), Some(vec![[255, 0, 51, 255], [0; 4]]));
+
+    let glow = &iqm.vertex_arrays[4];
+    assert_eq!(glow.kind, VertexArrayType::Custom(1));
+    assert_eq!(iqm.text(Some(1)), Some("glow"));
+    assert_eq!(glow.data, VertexData::Byte(vec![-128, 127]));
+    assert_eq!(glow.data.to_f32_normalized(), vec![-1.0, 1.0]);
+    assert_eq!(glow.data.to_u32(), None);
+}
+
+#[test]
+fn invalid_vertex_arrays() {
+    let floats = [0; 24];
+    let doubles = [0; 48];
+
+    // Arrays must be ordered by type.
+    let unordered = Reason::UnorderedVertexArray;
+    let data = with_vertex_arrays(&[(2, 7, 3, &floats[..]), (0, 7, 3, &floats[..])]);
+    assert_invalid(&data, Section::VertexArrays, unordered);
+    let data = with_vertex_arrays(&[(0, 7, 3, &floats[..]), (0, 7, 3, &floats[..])]);
+    assert_invalid(&data, Section::VertexArrays, unordered);
+
+    // Types between colors and custom types are reserved, and custom types must be named by the
+    // start of a string.
+    for &(kind, reason) in &[
+        (7, Reason::UnknownVertexArrayType(7)),
+        (0x10, Reason::DanglingString(0)),
+        (0x10 + 2, Reason::DanglingString(2)),
+        (0x10 + 100, Reason::DanglingString(100)),
+    ] {
+        let data = with_vertex_arrays(&[(kind, 7, 3, &floats[..])]);
+        assert_invalid(&data, Section::VertexArrays, reason);
+    }
+
+    // Formats must be known, and arrays can't be empty or run off the end of the file.
+    for &(format, size, reason) in &[
+        (9, 3, Reason::UnknownVertexFormat(9)),
+        (7, 0, Reason::EmptyVertexArray),
+        (7, 1000, Reason::OutOfBounds),
+    ] {
+        let data = with_vertex_arrays(&[(0, format, size, &doubles[..])]);
+        assert_invalid(&data, Section::VertexArrays, reason);
+    }
+
+    // Arrays must be aligned to the size of their format, or 4 bytes if that's smaller.
+    let mut data = with_vertex_arrays(&[(0, 8, 3, &doubles[..])]);
+    let entry = LittleEndian::read_u32(&data[NUM_VERTEXARRAYS + 8..]) as usize;
+    let offset = LittleEndian::read_u32(&data[entry + 16..]);
+    assert!(IQM::parse_from(&data).is_ok());
+    LittleEndian::write_u32(&mut data[entry + 16..], offset + 4);
+    assert_eq!(
+        IQM::parse_from(&data).unwrap_err(),
+        IqmError::Invalid {
+            section: Section::VertexArrays,
+            offset: offset as usize + 4,
+            reason: Reason::Misaligned,
+        }
+    );
+}
+
+/// Builds a file with four vertices, and the given triangles, adjacency table, and meshes (as
+/// their first vertex, vertex count, first triangle, and triangle count).
+fn with_triangles(triangles: &[u32], adjacency: Option<&[u32]>, meshes: &[[u32; 4]]) -> Vec<u8> {
+    let mut file = FileBuilder::new();
+    let meshes = meshes
+        .iter()
+        .flat_map(|mesh| vec![0, 0, mesh[0], mesh[1], mesh[2], mesh[3]])
+        .collect::<Vec<_>>();
+    let _ = file.section(NUM_MESHES, meshes.len() as u32 / 6, &u32s(&meshes));
+    let _ = file.section(NUM_TRIANGLES, triangles.len() as u32 / 3, &u32s(triangles));
+    if let Some(adjacency) = adjacency {
+        let offset = file.append(&u32s(adjacency), 4);
+        let _ = file.set(NUM_TRIANGLES + 8, offset);
+    }
+    file.set(NUM_VERTEXARRAYS + 4, 4).finish()
+}
+
+#[test]
+fn triangles() {
+    // A quad, split along its diagonal, and a separate triangle.
+    let triangles = [0, 1, 2, 0, 2, 3, 1, 2, 3];
+    let adjacency = [!0, !0, 1, 0, !0, !0, !0, !0, !0];
+    let meshes = [[0, 4, 0, 2], [1, 3, 2, 1]];
+
+    let iqm = IQM::parse_from(&with_triangles(&triangles, None, &meshes)).unwrap();
+    assert_eq!(iqm.triangles, vec![[0, 1, 2], [0, 2, 3], [1, 2, 3]]);
+    assert_eq!(iqm.adjacency, None);
+    assert_eq!(iqm.mesh_adjacency(&iqm.meshes[0]), None);
+
+    let iqm = IQM::parse_from(&with_triangles(&triangles, Some(&adjacency), &meshes)).unwrap();
+    assert_eq!(iqm.mesh_triangles(&iqm.meshes[1]), Some(&[[1, 2, 3]][..]));
+    assert_eq!(
+        iqm.mesh_adjacency(&iqm.meshes[0]),
+        Some(&[[None, None, Some(1)], [Some(0), None, None]][..])
+    );
+}
+
+#[test]
+fn invalid_triangles() {
+    let triangles = [0, 1, 2, 0, 2, 3];
+    let meshes = [[0, 4, 0, 2]];
+    assert!(IQM::parse_from(&with_triangles(&triangles, None, &meshes)).is_ok());
+
+    // Triangles must refer to vertices that exist...
+    let data = with_triangles(&[0, 1, 2, 0, 2, 4], None, &meshes);
+    let reason = Reason::IndexOutOfRange { index: 4, len: 4 };
+    assert_invalid(&data, Section::Triangles, reason);
+
+    // ...and neighbours to triangles that exist.
+    let data = with_triangles(&triangles, Some(&[!0, !0, 1, 2, !0, !0]), &meshes);
+    let reason = Reason::IndexOutOfRange { index: 2, len: 2 };
+    assert_invalid(&data, Section::Adjacency, reason);
+
+    // Meshes can't include more vertices or triangles than there are.
+    for &(mesh, len) in &[([1, 4, 0, 2], 4), ([0, 4, 1, 2], 2)] {
+        let data = with_triangles(&triangles, None, &[mesh]);
+        let reason = Reason::IndexOutOfRange { index: len, len };
+        assert_invalid(&data, Section::Meshes, reason);
+    }
+}
+
+/// Builds a file with the given joints, each given as its parent and its translation, rotation,
+/// and scale.
+fn with_joints(joints: &[(i32, [f32; 10])]) -> Vec<u8> {
+    let mut file = FileBuilder::new();
+    let names = file.texts(&["root", "arm", "hand"]);
+    let mut bs = Vec::new();
+    for (&(parent, transform), &name) in joints.iter().zip(names.iter().cycle()) {
+        let mut joint = [0; 48];
+        LittleEndian::write_u32(&mut joint[0..4], name);
+        LittleEndian::write_i32(&mut joint[4..8], parent);
+        LittleEndian::write_f32_into(&transform, &mut joint[8..48]);
+        bs.extend_from_slice(&joint);
+    }
+    file.section(NUM_JOINTS, joints.len() as u32, &bs).finish()
+}
+
+/// Asserts that two matrices are equal, up to rounding errors.
+fn assert_matrix_eq(a: Matrix4<f32>, b: Matrix4<f32>) {
+    let a: &[f32; 16] = a.as_ref();
+    let b: &[f32; 16] = b.as_ref();
+    assert!(
+        a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5),
+        "{:?} != {:?}",
+        a,
+        b
+    );
+}
+
+#[test]
+fn joints() {
+    // A quarter turn about the Z axis; the quaternion isn't normalized in the file.
+    let q = 2.0 * 0.5f32.sqrt();
+    let data = with_joints(&[
+        (-1, [1.0, 0.0, 0.0, 0.0, 0.0, q, q, 1.0, 1.0, 1.0]),
+        (0, [2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 2.0, 2.0, 2.0]),
+        (1, [1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0]),
+    ]);
+    let iqm = IQM::parse_from(&data).unwrap();
+    assert_eq!(iqm.joints.len(), 3);
+    assert_eq!(iqm.text(iqm.joints[1].name), Some("arm"));
+    assert_eq!(iqm.joints[0].parent, None);
+    assert_eq!(iqm.joints[2].parent, Some(1));
+    let rotate = iqm.joints[0].transform.rotate;
+    assert!((rotate.s - 0.5f32.sqrt()).abs() < 1e-6);
+    assert_eq!(
+        iqm.joints[1].transform.rotate,
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    );
+
+    let local = iqm.local_matrices();
+    assert_matrix_eq(
+        local[1],
+        Matrix4::from_translation(Vector3::new(2.0, 0.0, 0.0)) * Matrix4::from_scale(2.0),
+    );
+
+    // The hand is 2 units along the (turned) arm, then 1 more unit, scaled by the arm's scale.
+    let bind = iqm.bind_matrices();
+    let origin = Point3::new(0.0, 0.0, 0.0);
+    let hand = bind[2].transform_point(origin);
+    assert!(
+        (hand - Point3::new(1.0, 4.0, 0.0)).magnitude() < 1e-5,
+        "{:?}",
+        hand
+    );
+
+    for (bind, inverse) in bind.iter().zip(iqm.inverse_bind_matrices()) {
+        assert_matrix_eq(bind * inverse, Matrix4::from_scale(1.0));
+    }
+}
+
+#[test]
+fn degenerate_rotations() {
+    // Components this large overflow if the length is found as an `f32`, and a rotation of length
+    // zero can't be normalized at all.
+    let data = with_joints(&[
+        (-1, [0.0, 0.0, 0.0, 1e30, 1e30, 1e30, 1e30, 1.0, 1.0, 1.0]),
+        (-1, [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0]),
+    ]);
+    let iqm = IQM::parse_from(&data).unwrap();
+    assert_eq!(
+        iqm.joints[0].transform.rotate,
+        Quaternion::new(0.5, 0.5, 0.5, 0.5)
+    );
+    assert_eq!(
+        iqm.joints[1].transform.rotate,
+        Quaternion::new(1.0, 0.0, 0.0, 0.0)
+    );
+    assert_round_trips(&data);
+}
+
+#[test]
+fn invalid_joints() {
+    let identity = [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0];
+    assert!(IQM::parse_from(&with_joints(&[(-1, identity), (0, identity)])).is_ok());
+
+    // Parents must come before their children.
+    for &parent in &[1, 2] {
+        let data = with_joints(&[(-1, identity), (parent, identity)]);
+        let reason = Reason::ParentAfterChild(parent as usize);
+        assert_invalid(&data, Section::Joints, reason);
+    }
+}
+
+/// Builds a file with a chain of joints, each one unit along the X axis from its parent, and a
+/// pose for each with the given channel mask. Every channel is scaled by a half. The animations
+/// are each given as their first frame, number of frames, framerate, and flags.
+fn with_animations(masks: &[u32], frames: &[u16], anims: &[(u32, u32, f32, u32)]) -> Vec<u8> {
+    let mut file = FileBuilder::new();
+    let names = file.texts(&["walk", "stop"]);
+    let mut joints = Vec::new();
+    let mut poses = Vec::new();
+    for (i, &mask) in masks.iter().enumerate() {
+        let x = if i == 0 { 0.0 } else { 1.0 };
+        let mut transform = [0; 40];
+        LittleEndian::write_f32_into(
+            &[x, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0],
+            &mut transform,
+        );
+        let parent = i as u32 as i32 - 1;
+
+        joints.extend_from_slice(&u32s(&[0, parent as u32]));
+        joints.extend_from_slice(&transform);
+        poses.extend_from_slice(&u32s(&[parent as u32, mask]));
+        poses.extend_from_slice(&transform);
+        poses.extend_from_slice(&u32s(&[0.5f32.to_bits(); 10]));
+    }
+    let _ = file.section(NUM_JOINTS, masks.len() as u32, &joints);
+    let _ = file.section(NUM_POSES, masks.len() as u32, &poses);
+
+    let mut bs = Vec::new();
+    for (&(first, len, framerate, flags), &name) in anims.iter().zip(names.iter().cycle()) {
+        bs.extend_from_slice(&u32s(&[name, first, len, framerate.to_bits(), flags]));
+    }
+    let _ = file.section(NUM_ANIMS, anims.len() as u32, &bs);
+
+    let num_framechannels = masks.iter().map(|mask| mask.count_ones()).sum::<u32>();
+    let mut bs = vec![0; frames.len() * 2];
+    LittleEndian::write_u16_into(frames, &mut bs);
+    let offset = file.append(&bs, 4);
+    let num_frames = frames.len() as u32 / num_framechannels.max(1);
+    file.set(NUM_FRAMES, num_frames)
+        .set(NUM_FRAMES + 4, num_framechannels)
+        .set(NUM_FRAMES + 8, offset)
+        .finish()
+}
+
+#[test]
+fn animations() {
+    // The root moves along the X axis, and the other joint is fixed relative to it.
+    let data = with_animations(
+        &[1, 0],
+        &[0, 2, 4],
+        &[(0, 3, 2.0, IQM_LOOP), (1, 2, 1.0, 0)],
+    );
+    let iqm = IQM::parse_from(&data).unwrap();
+    assert_eq!(iqm.poses.len(), 2);
+    assert_eq!(iqm.poses[1].parent, Some(0));
+    assert_eq!(iqm.num_frames, 3);
+    assert_eq!(iqm.num_framechannels, 1);
+
+    let frame = iqm.frame(1).unwrap();
+    assert_eq!(frame[0].translate, Vector3::new(1.0, 0.0, 0.0));
+    assert_eq!(frame[1].translate, Vector3::new(1.0, 0.0, 0.0));
+    assert_eq!(frame[1].scale, Vector3::new(1.0, 1.0, 1.0));
+    assert!(iqm.frame(3).is_none());
+
+    let walk = iqm.animation("walk").unwrap();
+    let stop = iqm.animation("stop").unwrap();
+    assert!(walk.is_looping());
+    assert!(!stop.is_looping());
+    assert_eq!(stop.frames, 1..3);
+    assert!(iqm.animation("run").is_none());
+
+    // Looping animations wrap around from the last frame to the first, in either direction;
+    // others stop at either end.
+    let x = |animation, t| iqm.sample(animation, t).unwrap()[0].translate.x;
+    assert_eq!(x(walk, 0.25), 0.5);
+    assert_eq!(x(walk, 1.25), 1.0);
+    assert_eq!(x(walk, -0.25), 1.0);
+    assert_eq!(x(walk, 3.0), 0.0);
+    assert_eq!(x(stop, 0.5), 1.5);
+    assert_eq!(x(stop, 10.0), 2.0);
+    assert_eq!(x(stop, -1.0), 1.0);
+
+    // Both joints are moved along with the root.
+    let pose = iqm.sample(walk, 0.5).unwrap();
+    for matrix in iqm.skinning_matrices(&pose) {
+        assert_matrix_eq(
+            matrix,
+            Matrix4::from_translation(Vector3::new(1.0, 0.0, 0.0)),
+        );
+    }
+}
+
+#[test]
+fn invalid_animations() {
+    assert!(IQM::parse_from(&with_animations(&[1, 0x3ff], &[0; 22], &[(0, 2, 1.0, 0)])).is_ok());
+
+    // Animations can't run past the last frame.
+    let data = with_animations(&[1], &[0, 1], &[(1, 2, 1.0, 0)]);
+    let reason = Reason::IndexOutOfRange { index: 2, len: 2 };
+    assert_invalid(&data, Section::Animations, reason);
+
+    // Poses can only have 10 channels.
+    let data = with_animations(&[0x400], &[0], &[]);
+    assert_invalid(&data, Section::Poses, Reason::InvalidChannelMask(0x400));
+
+    // Frames must have a value for each channel of each pose.
+    let mut data = with_animations(&[1, 1], &[0, 1], &[]);
+    assert!(IQM::parse_from(&data).is_ok());
+    LittleEndian::write_u32(&mut data[NUM_FRAMES..], 2);
+    LittleEndian::write_u32(&mut data[NUM_FRAMES + 4..], 1);
+    let reason = Reason::ChannelCountMismatch {
+        poses: 2,
+        frames: 1,
+    };
+    assert_invalid(&data, Section::Frames, reason);
+
+    // Frames are aligned to 4 bytes, even though each value is a `u16`.
+    let mut data = with_animations(&[1], &[0, 1, 2], &[]);
+    let offset = LittleEndian::read_u32(&data[NUM_FRAMES + 8..]);
+    LittleEndian::write_u32(&mut data[NUM_FRAMES..], 2);
+    LittleEndian::write_u32(&mut data[NUM_FRAMES + 8..], offset + 2);
+    assert_invalid(&data, Section::Frames, Reason::Misaligned);
+}
+
+/// Appends the given bounds for each frame to a file.
+fn with_bounds(mut data: Vec<u8>, bounds: &[[f32; 8]]) -> Vec<u8> {
+    while data.len() % 4 != 0 {
+        data.push(0);
+    }
+    let offset = data.len() as u32;
+    for bounds in bounds {
+        let mut bs = [0; 32];
+        LittleEndian::write_f32_into(bounds, &mut bs);
+        data.extend_from_slice(&bs);
+    }
+    let len = data.len() as u32;
+    LittleEndian::write_u32(&mut data[NUM_FRAMES + 12..], offset);
+    LittleEndian::write_u32(&mut data[20..], len);
+    data
+}
+
+#[test]
+fn bounds() {
+    let data = with_animations(&[1], &[0, 2, 4], &[(0, 3, 2.0, IQM_LOOP)]);
+    let iqm = IQM::parse_from(&data).unwrap();
+    assert_eq!(iqm.bounds, None);
+    assert_eq!(iqm.bounds_at(&iqm.animations[0], 0.0), None);
+
+    // The bounds must have an entry for every frame.
+    let short = with_bounds(data.clone(), &[[0.0; 8]; 2]);
+    assert_invalid(&short, Section::Bounds, Reason::OutOfBounds);
+
+    let data = with_bounds(
+        data,
+        &[
+            [-1.0, -1.0, 0.0, 1.0, 1.0, 2.0, 1.5, 2.5],
+            [-1.0, -2.0, 0.0, 1.0, 2.0, 2.0, 2.5, 3.0],
+            [-3.0, -1.0, 0.0, 3.0, 1.0, 1.0, 3.0, 3.5],
+        ],
+    );
+    let iqm = IQM::parse_from(&data).unwrap();
+    let bounds = iqm.bounds.as_ref().unwrap();
+    assert_eq!(bounds.len(), 3);
+    assert_eq!(bounds[1].mins, Vector3::new(-1.0, -2.0, 0.0));
+    assert_eq!(bounds[1].xy_radius, 2.5);
+
+    // On a frame, the bounds are that frame's; between frames, they contain both.
+    let walk = &iqm.animations[0];
+    assert_eq!(iqm.bounds_at(walk, 0.5), Some(bounds[1]));
+    let between = iqm.bounds_at(walk, 1.25).unwrap();
+    assert_eq!(between.mins, Vector3::new(-3.0, -1.0, 0.0));
+    assert_eq!(between.maxs, Vector3::new(3.0, 1.0, 2.0));
+    assert_eq!((between.xy_radius, between.radius), (3.0, 3.5));
+}
+
+/// Builds a file with the given extensions, each given as its name and data. The entries are
+/// stored in reverse order, so the list runs backwards through the file. The offset of each
+/// entry is returned too.
+fn with_extensions(extensions: &[(&str, &[u8])]) -> (Vec<u8>, Vec<u32>) {
+    let mut file = FileBuilder::new();
+    let names = extensions.iter().map(|&(name, _)| name).collect::<Vec<_>>();
+    let names = file.texts(&names);
+    let mut next = 0;
+    let mut offsets = Vec::new();
+    for (&(_, data), &name) in extensions.iter().zip(&names).rev() {
+        let data_offset = file.append(data, 4);
+        next = file.append(&u32s(&[name, data.len() as u32, data_offset, next]), 4);
+        offsets.insert(0, next);
+    }
+    let data = file
+        .set(NUM_EXT, extensions.len() as u32)
+        .set(NUM_EXT + 4, next)
+        .finish();
+    (data, offsets)
+}
+
+/// An extension holding the framerate a model was exported at.
+#[derive(Debug, PartialEq)]
+struct Fps(f32);
+
+impl ExtensionType for Fps {
+    const NAME: &'static str = "FPS";
+
+    fn decode(_: &IQM, data: &[u8]) -> Option<Fps> {
+        if data.len() == 4 {
+            Some(Fps(LittleEndian::read_f32(data)))
+        } else {
+            None
+        }
+    }
+}
+
+#[test]
+fn extensions() {
+    let fps = 30.0f32.to_bits().to_le_bytes();
+    let (data, _) = with_extensions(&[("tags", b"a b c"), ("FPS", &fps), ("FPS", b"faster")]);
+    let iqm = IQM::parse_from(&data).unwrap();
+    assert_eq!(iqm.extensions.len(), 3);
+    assert_eq!(iqm.text(iqm.extensions[0].name), Some("tags"));
+    assert_eq!(iqm.extensions[0].data, b"a b c");
+    assert_eq!(iqm.extension("FPS").unwrap().data, &fps);
+    assert!(iqm.extension("LODs").is_none());
+    assert_eq!(iqm.decode_extension::<Fps>(), Some(Fps(30.0)));
+
+    let mut registry = ExtensionRegistry::new();
+    registry.register::<Fps>();
+    assert!(registry.decode(&iqm, &iqm.extensions[0]).is_none());
+    let decoded = registry.decode_all(&iqm);
+    assert_eq!(decoded.len(), 2);
+    assert_eq!(decoded[0].0, 1);
+    let fps = decoded[0].1.as_ref().unwrap().downcast_ref::<Fps>();
+    assert_eq!(fps, Some(&Fps(30.0)));
+    assert_eq!(decoded[1].0, 2);
+    assert!(decoded[1].1.is_none());
+}
+
+#[test]
+fn invalid_extensions() {
+    let (data, offsets) = with_extensions(&[("a", b"1"), ("b", b"22"), ("c", b"333")]);
+    assert!(IQM::parse_from(&data).is_ok());
+
+    // The list can't loop, or end early.
+    let mut looped = data.clone();
+    LittleEndian::write_u32(&mut looped[offsets[1] as usize + 12..], offsets[0]);
+    assert_eq!(
+        IQM::parse_from(&looped).unwrap_err(),
+        IqmError::Invalid {
+            section: Section::Extensions,
+            offset: offsets[1] as usize + 12,
+            reason: Reason::ExtensionCycle,
+        }
+    );
+    let mut short = data.clone();
+    LittleEndian::write_u32(&mut short[NUM_EXT..], 4);
+    assert_invalid(&short, Section::Extensions, Reason::ExtensionListTooShort);
+
+    // Neither entries nor their data can run off the end of the file.
+    let mut past_end = data.clone();
+    LittleEndian::write_u32(&mut past_end[offsets[2] as usize + 4..], 1000);
+    assert_invalid(&past_end, Section::Extensions, Reason::OutOfBounds);
+    let mut past_end = data;
+    let len = past_end.len() as u32;
+    LittleEndian::write_u32(&mut past_end[offsets[1] as usize + 12..], len);
+    assert_invalid(&past_end, Section::Extensions, Reason::OutOfBounds);
+}
+
+#[test]
+fn invalid_headers() {
+    let data = FileBuilder::new().finish();
+    assert!(IQM::parse_from(&data).is_ok());
+
+    let mut bad_magic = data.clone();
+    bad_magic[0] = b'i';
+    assert_eq!(IQM::parse_from(&bad_magic).unwrap_err(), IqmError::BadMagic);
+    let version = FileBuilder::new().set(16, 1).finish();
+    assert_eq!(
+        IQM::parse_from(&version).unwrap_err(),
+        IqmError::WrongVersion(1)
+    );
+    let filesize = FileBuilder::new().finish()[..100].to_vec();
+    assert_invalid(&filesize, Section::Header, Reason::OutOfBounds);
+    let filesize = FileBuilder::new().set(20, 1000).data.clone();
+    assert_eq!(
+        IQM::parse_from(&filesize).unwrap_err(),
+        IqmError::FilesizeMismatch {
+            header: 1000,
+            actual: 124,
+        }
+    );
+
+    // Sections must be in bounds, and aligned.
+    let past_end = FileBuilder::new()
+        .set(NUM_MESHES, 1)
+        .set(NUM_MESHES + 4, 124)
+        .finish();
+    assert_invalid(&past_end, Section::Meshes, Reason::OutOfBounds);
+    let mut file = FileBuilder::new();
+    let offset = file.append(&[0; 26], 4) + 2;
+    let misaligned = file.set(NUM_MESHES, 1).set(NUM_MESHES + 4, offset).finish();
+    let err = IQM::parse_from(&misaligned).unwrap_err();
+    assert_eq!(err.to_string(), "In the meshes (at byte 126): misaligned");
+
+    // That includes sections of bytes, which are aligned to 4 bytes like the rest.
+    for &(field, section) in &[(NUM_TEXT, Section::Text), (NUM_COMMENT, Section::Comments)] {
+        let mut file = FileBuilder::new();
+        let offset = file.append(b"\0\0\0\0", 4) + 2;
+        let misaligned = file.set(field, 2).set(field + 4, offset).finish();
+        assert_invalid(&misaligned, section, Reason::Misaligned);
+    }
+}
+
+#[test]
+fn invalid_texts() {
+    for &(text, reason) in &[
+        (&b"\0abc"[..], Reason::UnterminatedString),
+        (b"\0\xff\0", Reason::InvalidUtf8),
+        (b"abc\0", Reason::MissingEmptyString),
+    ] {
+        let data = FileBuilder::new()
+            .section(NUM_TEXT, text.len() as u32, text)
+            .finish();
+        assert_invalid(&data, Section::Text, reason);
+    }
+
+    // Names must be the offset of the start of a string.
+    let mut file = FileBuilder::new();
+    let names = file.texts(&["body"]);
+    let _ = file.section(NUM_MESHES, 1, &u32s(&[names[0] + 1, 0, 0, 0, 0, 0]));
+    assert_invalid(&file.finish(), Section::Meshes, Reason::DanglingString(2));
+}
+
+/// Builds a file with something in every section.
+fn full_file() -> Vec<u8> {
+    let mut file = FileBuilder::new();
+    let names = file.texts(&["body", "walk", "FPS"]);
+    let _ = file.section(NUM_MESHES, 1, &u32s(&[names[0], 0, 0, 4, 0, 2]));
+
+    let mut positions = vec![0; 48];
+    LittleEndian::write_f32_into(
+        &[0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 11.0],
+        &mut positions,
+    );
+    let offset = file.append(&positions, 4);
+    let entry = file.append(&u32s(&[0, 0, 7, 3, offset]), 4);
+    let _ = file
+        .set(NUM_VERTEXARRAYS, 1)
+        .set(NUM_VERTEXARRAYS + 4, 4)
+        .set(NUM_VERTEXARRAYS + 8, entry);
+
+    let _ = file.section(NUM_TRIANGLES, 2, &u32s(&[0, 1, 2, 0, 2, 3]));
+    let adjacency = file.append(&u32s(&[!0, !0, 1, 0, !0, !0]), 4);
+    let _ = file.set(NUM_TRIANGLES + 8, adjacency);
+
+    let mut joints = Vec::new();
+    let mut poses = Vec::new();
+    for (parent, mask) in &[(!0, 1), (0, 0)] {
+        let mut transform = [0; 40];
+        LittleEndian::write_f32_into(
+            &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0],
+            &mut transform,
+        );
+        joints.extend_from_slice(&u32s(&[0, *parent]));
+        joints.extend_from_slice(&transform);
+        poses.extend_from_slice(&u32s(&[*parent, *mask]));
+        poses.extend_from_slice(&transform);
+        poses.extend_from_slice(&u32s(&[0.5f32.to_bits(); 10]));
+    }
+    let _ = file.section(NUM_JOINTS, 2, &joints);
+    let _ = file.section(NUM_POSES, 2, &poses);
+    let anim = u32s(&[names[1], 0, 3, 10.0f32.to_bits(), IQM_LOOP]);
+    let _ = file.section(NUM_ANIMS, 1, &anim);
+    let frames = file.append(&[0, 0, 2, 0, 4, 0], 4);
+    let bounds = file.append(&u32s(&[1.0f32.to_bits(); 24]), 4);
+    let _ = file
+        .set(NUM_FRAMES, 3)
+        .set(NUM_FRAMES + 4, 1)
+        .set(NUM_FRAMES + 8, frames)
+        .set(NUM_FRAMES + 12, bounds);
+
+    let comments = b"\0made by hand\0";
+    let _ = file.section(NUM_COMMENT, comments.len() as u32, comments);
+    let data = file.append(&30.0f32.to_bits().to_le_bytes(), 4);
+    let extension = file.append(&u32s(&[names[2], 4, data, 0]), 4);
+    file.set(NUM_EXT, 1).set(NUM_EXT + 4, extension).finish()
+}
+
+/// Calls everything that reads a parsed file, none of which should panic.
+fn exercise(iqm: &IQM) {
+    for mesh in &iqm.meshes {
+        let _ = iqm.text(mesh.name);
+        let _ = iqm.mesh_triangles(mesh);
+        let _ = iqm.mesh_adjacency(mesh);
+    }
+    for array in &iqm.vertex_arrays {
+        let _ = array.to_f32();
+        let _ = array.to_vec3();
+        let _ = array.to_vec4_normalized();
+        let _ = array.to_u32x4();
+    }
+    let _ = iqm.bind_matrices();
+    for animation in &iqm.animations {
+        for &t in &[-1.0, 0.0, 0.15, 1e9, std::f32::NAN, std::f32::INFINITY] {
+            if let Some(pose) = iqm.sample(animation, t) {
+                let _ = iqm.skinning_matrices(&pose);
+            }
+            let _ = iqm.bounds_at(animation, t);
+        }
+    }
+    let _ = iqm.decode_extension::<Fps>();
+}
+
+#[test]
+fn full_file_parses() {
+    let iqm = IQM::parse_from(&full_file()).unwrap();
+    assert_eq!(iqm.comments, vec!["made by hand"]);
+    assert_eq!(iqm.decode_extension::<Fps>(), Some(Fps(30.0)));
+    let walk = iqm.animation("walk").unwrap();
+    assert_eq!(iqm.sample(walk, 0.15).unwrap()[0].translate.x, 2.5);
+    exercise(&iqm);
+}
+
+/// Files in `fixtures/crashers` that panicked the parser before every index into the file was
+/// checked. Each is `fixtures/animated.iqm` with the first vertex or triangle of its mesh set to
+/// `!0`, so finding the end of the mesh's range overflowed on 32-bit targets. Those were the only
+/// files that could: the sections it sliced into were checked against the file's size by the
+/// header's validation first, and its assert on the size of the header didn't depend on the file.
+#[test]
+fn crashers() {
+    let mut crashers = 0;
+    for entry in glob::glob("fixtures/crashers/*.iqm").unwrap() {
+        let path = entry.unwrap();
+        println!("Parsing {}...", path.display());
+        let data = read(path).unwrap();
+        assert!(IQM::parse_from(&data).is_err());
+        assert!(IQMRef::parse_from(&data).is_err());
+        crashers += 1;
+    }
+    assert_eq!(crashers, 2);
+}
+
+/// Parses the file the API robustness tests below start from, which has something in every
+/// section. It's the output of `full_file`, checked in so that changes to that don't change what
+/// they test. Each method they call panicked on some data; `frame` did on the file as it is, but
+/// the parser rejects files with the inconsistencies the others need, so they change the fields
+/// of the parsed file to the same effect.
+fn robustness_fixture() -> IQM {
+    IQM::parse_from(include_bytes!("../fixtures/animated.iqm")).unwrap()
+}
+
+/// `frame` overflowed finding the end of a frame far past the end of the file.
+#[test]
+fn robust_frame_index_overflow() {
+    let mut iqm = robustness_fixture();
+    assert!(iqm.frame(!0).is_none());
+    iqm.num_framechannels = !0;
+    assert!(iqm.frame(1).is_none());
+}
+
+/// Decoding a frame indexed past its values when the poses had more channels than it (which the
+/// parser rejects with `ChannelCountMismatch`).
+#[test]
+fn robust_pose_with_too_many_channels() {
+    let mut iqm = robustness_fixture();
+    iqm.poses[1].channel_mask = 0x3ff;
+    assert!(iqm.frame(0).is_none());
+    assert!(iqm.sample(&iqm.animations[0], 0.0).is_none());
+}
+
+/// Computing model-space matrices indexed past the matrices computed so far when a joint's parent
+/// came after it (which the parser rejects with `ParentAfterChild`). Such joints are now treated
+/// as roots.
+#[test]
+fn robust_joint_before_its_parent() {
+    let mut iqm = robustness_fixture();
+    iqm.joints[0].parent = Some(1);
+    assert_eq!(iqm.bind_matrices()[0], iqm.local_matrices()[0]);
+}
+
+/// Inverting the bind pose had the same problem as `robust_joint_before_its_parent`.
+#[test]
+fn robust_inverse_joint_before_its_parent() {
+    let mut iqm = robustness_fixture();
+    iqm.joints[0].parent = Some(7);
+    let inverse = iqm.inverse_bind_matrices();
+    assert_matrix_eq(
+        iqm.bind_matrices()[0] * inverse[0],
+        Matrix4::from_scale(1.0),
+    );
+}
+
+/// Grouping the components of a vertex array with no components per vertex (which the parser
+/// rejects with `EmptyVertexArray`) split them into chunks of size zero.
+#[test]
+fn robust_empty_vertex_array() {
+    let mut iqm = robustness_fixture();
+    iqm.vertex_arrays[0].size = 0;
+    assert!(iqm.vertex_arrays[0].to_f32().is_empty());
+    assert!(iqm.vertex_arrays[0].to_f32_normalized().is_empty());
+}
+
+/// Converting the components of a vertex array to vectors copied a partial vertex at the end into
+/// a whole one. Parsed arrays always hold whole vertices.
+#[test]
+fn robust_partial_vertex() {
+    let mut iqm = robustness_fixture();
+    iqm.vertex_arrays[0].data = VertexData::Float(vec![0.0; 4]);
+    assert_eq!(iqm.vertex_arrays[0].to_vec3(), Some(vec![[0.0; 3]]));
+}
+
+/// Converting the components of a vertex array to indices indexed past a partial vertex at the
+/// end.
+#[test]
+fn robust_partial_indices() {
+    let mut iqm = robustness_fixture();
+    iqm.vertex_arrays[0].size = 4;
+    iqm.vertex_arrays[0].data = VertexData::UInt(vec![1, 2, 3, 4, 5]);
+    assert_eq!(iqm.vertex_arrays[0].to_u32x4(), Some(vec![[1, 2, 3, 4]]));
+}
+
+/// Parsed files can be changed, so nothing should panic on other inconsistent data either.
+#[test]
+fn inconsistent_files() {
+    let mut iqm = robustness_fixture();
+    iqm.vertex_arrays[0].size = 5;
+    iqm.animations[0].frames = 2..7;
+    exercise(&iqm);
+    assert_eq!(iqm.vertex_arrays[0].to_f32().len(), 2);
+}
+
+/// Asserts that a file parses to the same data after being written, and that writing that data
+/// again gives the same file.
+fn assert_round_trips(data: &[u8]) {
+    let iqm = IQM::parse_from(data).unwrap();
+    let mut written = Vec::new();
+    iqm.write_to(&mut written).unwrap();
+    let reparsed = IQM::parse_from(&written).unwrap();
+    assert_eq!(reparsed, iqm);
+
+    let mut rewritten = Vec::new();
+    reparsed.write_to(&mut rewritten).unwrap();
+    assert_eq!(rewritten, written);
+}
+
+#[test]
+fn round_trip() {
+    assert_round_trips(&full_file());
+    assert_round_trips(&FileBuilder::new().finish());
+
+    let mut doubles = vec![0; 48];
+    LittleEndian::write_f64_into(&[0.0, 0.5, 1.0, 0.25, -2.0, 1e100], &mut doubles);
+    let bytes = [1, 2, 3, 4, 5, 6];
+    assert_round_trips(&with_vertex_arrays(&[
+        (1, 0, 3, &bytes[..]),
+        (2, 8, 3, &doubles[..]),
+        (4, 3, 3, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11][..]),
+        (0x10 + 6, 1, 1, &bytes[..2]),
+        (0x10 + 1, 6, 1, &[0x00, 0x3c, 0x00, 0xc0][..]),
+    ]));
+
+    let triangles = [0, 1, 2, 0, 2, 3, 1, 2, 3];
+    let adjacency = [!0, 1, !0, 0, !0, !0, !0, !0, 0];
+    assert_round_trips(&with_triangles(&triangles, None, &[[0, 4, 0, 3]]));
+    assert_round_trips(&with_triangles(
+        &triangles,
+        Some(&adjacency),
+        &[[0, 3, 0, 1], [0, 4, 1, 2]],
+    ));
+
+    let frames = [0, 1, 2, 3, 4, 5, 6, 7, 8];
+    let data = with_animations(
+        &[0b11, 0b1, 0],
+        &frames,
+        &[(0, 2, 10.0, IQM_LOOP), (2, 1, 0.0, 0)],
+    );
+    assert_round_trips(&data);
+    let bounds = [[0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.5, 2.0]; 3];
+    assert_round_trips(&with_bounds(data, &bounds));
+
+    let (data, _) = with_extensions(&[("FPS", &[0, 0, 0xf0, 0x41]), ("odd", b"abc"), ("", b"")]);
+    assert_round_trips(&data);
+}
+
+#[test]
+fn write_layout() {
+    let iqm = IQM::parse_from(&full_file()).unwrap();
+    let mut data = Vec::new();
+    iqm.write_to(&mut data).unwrap();
+
+    // The text section is rebuilt, starting with the empty string.
+    assert_eq!(LittleEndian::read_u32(&data[NUM_TEXT + 4..]), 124);
+    assert_eq!(&data[124..139], b"\0body\0walk\0FPS\0");
+    assert_eq!(LittleEndian::read_u32(&data[20..]), data.len() as u32);
+
+    // Every other section is aligned, and its entries follow the text.
+    for &field in &[NUM_MESHES, NUM_VERTEXARRAYS + 4, NUM_TRIANGLES, NUM_JOINTS] {
+        let offset = LittleEndian::read_u32(&data[field + 4..]);
+        assert_eq!(offset % 4, 0);
+        assert!(offset >= 139);
+    }
+    assert_eq!(LittleEndian::read_u32(&data[NUM_FRAMES + 8..]) % 4, 0);
+    assert_eq!(LittleEndian::read_u32(&data[NUM_FRAMES + 12..]) % 4, 0);
+
+    // Without bounds, the comments come straight after the frames, which are an odd number of
+    // `u16`s long.
+    let mut unbounded = iqm.clone();
+    unbounded.bounds = None;
+    assert_eq!(unbounded.frames.len() % 2, 1);
+    let mut data = Vec::new();
+    unbounded.write_to(&mut data).unwrap();
+    assert_eq!(LittleEndian::read_u32(&data[NUM_COMMENT + 4..]) % 4, 0);
+    assert_round_trips(&data);
+
+    // Empty sections have an offset of 0.
+    let mut iqm = iqm;
+    iqm.comments.clear();
+    iqm.extensions.clear();
+    let mut data = Vec::new();
+    iqm.write_to(&mut data).unwrap();
+    assert_eq!(&data[NUM_COMMENT..NUM_COMMENT + 8], &[0; 8]);
+    assert_eq!(&data[NUM_EXT..NUM_EXT + 8], &[0; 8]);
+}
+
+#[test]
+fn write_inconsistent() {
+    let iqm = IQM::parse_from(&full_file()).unwrap();
+    let assert_rejected = |change: &dyn Fn(&mut IQM)| {
+        let mut iqm = iqm.clone();
+        change(&mut iqm);
+        let mut data = Vec::new();
+        let err = iqm.write_to(&mut data).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(data.is_empty());
+    };
+
+    assert_rejected(&|iqm| iqm.meshes[0].name = Some(3));
+    assert_rejected(&|iqm| iqm.vertex_arrays[0].kind = VertexArrayType::Custom(5));
+    assert_rejected(&|iqm| iqm.text[0] = "bo\0dy".to_string());
+    assert_rejected(&|iqm| iqm.vertex_arrays[0].data = VertexData::Float(vec![1.0; 9]));
+    assert_rejected(&|iqm| iqm.adjacency.as_mut().unwrap().truncate(1));
+    assert_rejected(&|iqm| iqm.frames.truncate(2));
+    assert_rejected(&|iqm| iqm.bounds = Some(Vec::new()));
+    assert_rejected(&|iqm| iqm.animations[0].frames.start = 4);
+
+    // These are only caught by parsing the written file.
+    assert_rejected(&|iqm| iqm.triangles[0][0] = 4);
+    assert_rejected(&|iqm| iqm.joints[0].parent = Some(1));
+    assert_rejected(&|iqm| iqm.poses[0].channel_mask = 3);
+}
+
+#[test]
+fn borrowed() {
+    let data = full_file();
+    let iqm = IQMRef::parse_from(&data).unwrap();
+    assert_eq!(IQM::from(&iqm), IQM::parse_from(&data).unwrap());
+    assert_eq!(iqm.texts(), &["body", "walk", "FPS"]);
+    assert_eq!(iqm.comments(), &["made by hand"]);
+    assert_eq!(iqm.meshes().count(), 1);
+    assert_eq!(
+        iqm.triangles().collect::<Vec<_>>(),
+        vec![[0, 1, 2], [0, 2, 3]]
+    );
+    assert_eq!(iqm.frames().collect::<Vec<_>>(), vec![0, 2, 4]);
+    assert_eq!(iqm.bounds().unwrap().count(), 3);
+    let walk = iqm.animations().next().unwrap();
+    assert_eq!(iqm.text(walk.name), Some("walk"));
+
+    // Strings and data point into the file, rather than being copied.
+    let within = |bs: &[u8]| {
+        let file = data.as_ptr() as usize..data.as_ptr() as usize + data.len();
+        file.contains(&(bs.as_ptr() as usize))
+    };
+    assert!(within(iqm.texts()[0].as_bytes()));
+    assert!(within(iqm.comments()[0].as_bytes()));
+    let positions = iqm.vertex_array(VertexArrayType::Position).unwrap();
+    assert!(within(positions.data));
+    assert_eq!(positions.len(), 4);
+    let fps = iqm.extension("FPS").unwrap();
+    assert!(within(fps.data));
+    assert_eq!(fps.data, &30.0f32.to_bits().to_le_bytes());
+
+    // Invalid files fail the same way.
+    let mut data = data.clone();
+    LittleEndian::write_u32(&mut data[NUM_TRIANGLES..], 3);
+    assert_eq!(
+        IQMRef::parse_from(&data).unwrap_err(),
+        IQM::parse_from(&data).unwrap_err()
+    );
+}
+
+#[test]
+fn borrowed_vertex_arrays() {
+    let mut doubles = vec![0; 16];
+    LittleEndian::write_f64_into(&[0.5, -1e100], &mut doubles);
+    let data = with_vertex_arrays(&[
+        (0, 0, 1, &[0x80, 0x7f][..]),
+        (1, 3, 1, &[0xff, 0xff, 0x01, 0x00][..]),
+        (
+            2,
+            4,
+            1,
+            &[0xff, 0xff, 0xff, 0xff, 0x02, 0x00, 0x00, 0x00][..],
+        ),
+        (3, 6, 1, &[0x00, 0x3c, 0x00, 0xc0][..]),
+        (4, 8, 1, &doubles[..]),
+    ]);
+    let iqm = IQMRef::parse_from(&data).unwrap();
+    for array in iqm.vertex_arrays() {
+        assert_eq!(
+            array.iter_f32().collect::<Vec<_>>(),
+            array.to_data().to_f32()
+        );
+        assert_eq!(array.to_data().format(), array.format);
     }
 }
@@ -0,0 +1,50 @@
+//! Triangles, and the adjacency between them.
+
+use crate::{check_index, entries, IqmError, Section};
+use byteorder::{ByteOrder, LittleEndian};
+
+/// The value in the adjacency table for an edge with no neighbouring triangle.
+pub(crate) const NO_NEIGHBOUR: u32 = !0;
+
+/// Parses `n` triangles, checking that their vertices are less than `num_vertexes`.
+pub(crate) fn parse_triangles(
+    bs: &[u8],
+    offset: u32,
+    n: usize,
+    num_vertexes: usize,
+) -> Result<impl Iterator<Item = Result<[u32; 3], IqmError>> + '_, IqmError> {
+    Ok(
+        entries(bs, Section::Triangles, offset, n, 12)?.map(move |(offset, bs)| {
+            let mut triangle = [0; 3];
+            LittleEndian::read_u32_into(bs, &mut triangle);
+            for &vertex in &triangle {
+                check_index(Section::Triangles, offset, vertex as usize, num_vertexes)?;
+            }
+            Ok(triangle)
+        }),
+    )
+}
+
+/// Parses the adjacency table for `n` triangles, checking that each neighbour is one of the
+/// triangles.
+pub(crate) fn parse_adjacency(
+    bs: &[u8],
+    offset: u32,
+    n: usize,
+) -> Result<impl Iterator<Item = Result<[Option<u32>; 3], IqmError>> + '_, IqmError> {
+    Ok(
+        entries(bs, Section::Adjacency, offset, n, 12)?.map(move |(offset, bs)| {
+            let mut adjacent = [None; 3];
+            for (i, neighbour) in adjacent.iter_mut().enumerate() {
+                *neighbour = match LittleEndian::read_u32(&bs[i * 4..]) {
+                    NO_NEIGHBOUR => None,
+                    t => {
+                        check_index(Section::Adjacency, offset, t as usize, n)?;
+                        Some(t)
+                    }
+                };
+            }
+            Ok(adjacent)
+        }),
+    )
+}
@@ -0,0 +1,594 @@
+//! Vertex arrays.
+
+use crate::{entries, section, IqmError, Reason, Section, Texts};
+use byteorder::{ByteOrder, LittleEndian};
+use std::convert::TryFrom;
+
+/// The value of the first custom vertex array type. Types from this one up name custom arrays;
+/// those between `Color` and this one are reserved.
+const IQM_CUSTOM: u32 = 0x10;
+
+/// What the data in a vertex array means.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum VertexArrayType {
+    /// The position of each vertex. Usually 3 floats.
+    Position,
+
+    /// The texture coordinates of each vertex. Usually 2 floats.
+    TexCoord,
+
+    /// The normal of each vertex. Usually 3 floats.
+    Normal,
+
+    /// The tangent of each vertex, with the sign of the bitangent as the fourth component. Usually
+    /// 4 floats.
+    Tangent,
+
+    /// The indices of the joints each vertex is bound to. Usually 4 unsigned bytes.
+    BlendIndexes,
+
+    /// The weights of the joints each vertex is bound to. Usually 4 unsigned bytes.
+    BlendWeights,
+
+    /// The color of each vertex. Usually 4 unsigned bytes.
+    Color,
+
+    /// Some other data, whose name is an index into the text entries.
+    Custom(usize),
+}
+
+impl VertexArrayType {
+    /// Decodes a vertex array type, resolving custom types' names against the text entries.
+    fn from_u32(n: u32, texts: &Texts) -> Result<VertexArrayType, Reason> {
+        match n {
+            0 => Ok(VertexArrayType::Position),
+            1 => Ok(VertexArrayType::TexCoord),
+            2 => Ok(VertexArrayType::Normal),
+            3 => Ok(VertexArrayType::Tangent),
+            4 => Ok(VertexArrayType::BlendIndexes),
+            5 => Ok(VertexArrayType::BlendWeights),
+            6 => Ok(VertexArrayType::Color),
+            n if n >= IQM_CUSTOM => match texts.index_of(n - IQM_CUSTOM) {
+                Some(Some(index)) => Ok(VertexArrayType::Custom(index)),
+                _ => Err(Reason::DanglingString(n - IQM_CUSTOM)),
+            },
+            _ => Err(Reason::UnknownVertexArrayType(n)),
+        }
+    }
+
+    /// Encodes a vertex array type, given the offset of each text entry. Returns `None` if a
+    /// custom type's name isn't one of the text entries.
+    pub(crate) fn to_u32(self, text_offsets: &[u32]) -> Option<u32> {
+        match self {
+            VertexArrayType::Position => Some(0),
+            VertexArrayType::TexCoord => Some(1),
+            VertexArrayType::Normal => Some(2),
+            VertexArrayType::Tangent => Some(3),
+            VertexArrayType::BlendIndexes => Some(4),
+            VertexArrayType::BlendWeights => Some(5),
+            VertexArrayType::Color => Some(6),
+            VertexArrayType::Custom(index) => text_offsets
+                .get(index)
+                .and_then(|&offset| offset.checked_add(IQM_CUSTOM)),
+        }
+    }
+}
+
+/// The format of each component in a vertex array.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum VertexFormat {
+    /// `i8`.
+    Byte,
+
+    /// `u8`.
+    UByte,
+
+    /// `i16`.
+    Short,
+
+    /// `u16`.
+    UShort,
+
+    /// `i32`.
+    Int,
+
+    /// `u32`.
+    UInt,
+
+    /// A 16-bit (IEEE 754 half precision) float.
+    Half,
+
+    /// `f32`.
+    Float,
+
+    /// `f64`.
+    Double,
+}
+
+impl VertexFormat {
+    /// Decodes a vertex format.
+    fn from_u32(n: u32) -> Option<VertexFormat> {
+        match n {
+            0 => Some(VertexFormat::Byte),
+            1 => Some(VertexFormat::UByte),
+            2 => Some(VertexFormat::Short),
+            3 => Some(VertexFormat::UShort),
+            4 => Some(VertexFormat::Int),
+            5 => Some(VertexFormat::UInt),
+            6 => Some(VertexFormat::Half),
+            7 => Some(VertexFormat::Float),
+            8 => Some(VertexFormat::Double),
+            _ => None,
+        }
+    }
+
+    /// Encodes a vertex format.
+    pub(crate) fn to_u32(self) -> u32 {
+        match self {
+            VertexFormat::Byte => 0,
+            VertexFormat::UByte => 1,
+            VertexFormat::Short => 2,
+            VertexFormat::UShort => 3,
+            VertexFormat::Int => 4,
+            VertexFormat::UInt => 5,
+            VertexFormat::Half => 6,
+            VertexFormat::Float => 7,
+            VertexFormat::Double => 8,
+        }
+    }
+
+    /// Returns the size of a component in bytes.
+    pub fn size(self) -> usize {
+        match self {
+            VertexFormat::Byte | VertexFormat::UByte => 1,
+            VertexFormat::Short | VertexFormat::UShort | VertexFormat::Half => 2,
+            VertexFormat::Int | VertexFormat::UInt | VertexFormat::Float => 4,
+            VertexFormat::Double => 8,
+        }
+    }
+}
+
+/// The components of a vertex array, in the array's format.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VertexData {
+    /// `VertexFormat::Byte` components.
+    Byte(Vec<i8>),
+
+    /// `VertexFormat::UByte` components.
+    UByte(Vec<u8>),
+
+    /// `VertexFormat::Short` components.
+    Short(Vec<i16>),
+
+    /// `VertexFormat::UShort` components.
+    UShort(Vec<u16>),
+
+    /// `VertexFormat::Int` components.
+    Int(Vec<i32>),
+
+    /// `VertexFormat::UInt` components.
+    UInt(Vec<u32>),
+
+    /// `VertexFormat::Half` components, as their raw bits.
+    Half(Vec<u16>),
+
+    /// `VertexFormat::Float` components.
+    Float(Vec<f32>),
+
+    /// `VertexFormat::Double` components.
+    Double(Vec<f64>),
+}
+
+impl VertexData {
+    /// Decodes `n` components of the given format from the start of `bs`, which must be long
+    /// enough.
+    fn parse(format: VertexFormat, n: usize, bs: &[u8]) -> VertexData {
+        let bs = &bs[..n * format.size()];
+        match format {
+            VertexFormat::Byte => VertexData::Byte(bs.iter().map(|&b| b as i8).collect()),
+            VertexFormat::UByte => VertexData::UByte(bs.to_vec()),
+            VertexFormat::Short => {
+                let mut data = vec![0; n];
+                LittleEndian::read_i16_into(bs, &mut data);
+                VertexData::Short(data)
+            }
+            VertexFormat::UShort => {
+                let mut data = vec![0; n];
+                LittleEndian::read_u16_into(bs, &mut data);
+                VertexData::UShort(data)
+            }
+            VertexFormat::Int => {
+                let mut data = vec![0; n];
+                LittleEndian::read_i32_into(bs, &mut data);
+                VertexData::Int(data)
+            }
+            VertexFormat::UInt => {
+                let mut data = vec![0; n];
+                LittleEndian::read_u32_into(bs, &mut data);
+                VertexData::UInt(data)
+            }
+            VertexFormat::Half => {
+                let mut data = vec![0; n];
+                LittleEndian::read_u16_into(bs, &mut data);
+                VertexData::Half(data)
+            }
+            VertexFormat::Float => {
+                let mut data = vec![0.0; n];
+                LittleEndian::read_f32_into(bs, &mut data);
+                VertexData::Float(data)
+            }
+            VertexFormat::Double => {
+                let mut data = vec![0.0; n];
+                LittleEndian::read_f64_into(bs, &mut data);
+                VertexData::Double(data)
+            }
+        }
+    }
+
+    /// Encodes the components, as `parse` decodes them.
+    pub(crate) fn write(&self) -> Vec<u8> {
+        let mut bs = vec![0; self.len() * self.format().size()];
+        match self {
+            VertexData::Byte(data) => {
+                for (b, &x) in bs.iter_mut().zip(data) {
+                    *b = x as u8;
+                }
+            }
+            VertexData::UByte(data) => bs.copy_from_slice(data),
+            VertexData::Short(data) => LittleEndian::write_i16_into(data, &mut bs),
+            VertexData::UShort(data) | VertexData::Half(data) => {
+                LittleEndian::write_u16_into(data, &mut bs)
+            }
+            VertexData::Int(data) => LittleEndian::write_i32_into(data, &mut bs),
+            VertexData::UInt(data) => LittleEndian::write_u32_into(data, &mut bs),
+            VertexData::Float(data) => LittleEndian::write_f32_into(data, &mut bs),
+            VertexData::Double(data) => LittleEndian::write_f64_into(data, &mut bs),
+        }
+        bs
+    }
+
+    /// Returns the format of the components.
+    pub fn format(&self) -> VertexFormat {
+        match self {
+            VertexData::Byte(_) => VertexFormat::Byte,
+            VertexData::UByte(_) => VertexFormat::UByte,
+            VertexData::Short(_) => VertexFormat::Short,
+            VertexData::UShort(_) => VertexFormat::UShort,
+            VertexData::Int(_) => VertexFormat::Int,
+            VertexData::UInt(_) => VertexFormat::UInt,
+            VertexData::Half(_) => VertexFormat::Half,
+            VertexData::Float(_) => VertexFormat::Float,
+            VertexData::Double(_) => VertexFormat::Double,
+        }
+    }
+
+    /// Returns the number of components.
+    pub fn len(&self) -> usize {
+        match self {
+            VertexData::Byte(data) => data.len(),
+            VertexData::UByte(data) => data.len(),
+            VertexData::Short(data) => data.len(),
+            VertexData::UShort(data) | VertexData::Half(data) => data.len(),
+            VertexData::Int(data) => data.len(),
+            VertexData::UInt(data) => data.len(),
+            VertexData::Float(data) => data.len(),
+            VertexData::Double(data) => data.len(),
+        }
+    }
+
+    /// Returns whether there are no components.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Converts the components to `f32`s, as numbers; e.g. a `UByte` of 255 becomes 255.0.
+    pub fn to_f32(&self) -> Vec<f32> {
+        match self {
+            VertexData::Byte(data) => data.iter().map(|&x| f32::from(x)).collect(),
+            VertexData::UByte(data) => data.iter().map(|&x| f32::from(x)).collect(),
+            VertexData::Short(data) => data.iter().map(|&x| f32::from(x)).collect(),
+            VertexData::UShort(data) => data.iter().map(|&x| f32::from(x)).collect(),
+            VertexData::Int(data) => data.iter().map(|&x| x as f32).collect(),
+            VertexData::UInt(data) => data.iter().map(|&x| x as f32).collect(),
+            VertexData::Half(data) => data.iter().map(|&x| half_to_f32(x)).collect(),
+            VertexData::Float(data) => data.clone(),
+            VertexData::Double(data) => data.iter().map(|&x| x as f32).collect(),
+        }
+    }
+
+    /// Converts the components to `f32`s, mapping the range of integer formats onto `[0, 1]`
+    /// (for unsigned formats) or `[-1, 1]` (for signed ones), as GPUs do for normalized vertex
+    /// attributes; e.g. a `UByte` of 255 becomes 1.0. Floating-point formats are converted as
+    /// by `to_f32`.
+    pub fn to_f32_normalized(&self) -> Vec<f32> {
+        fn signed(x: f32, max: f32) -> f32 {
+            (x / max).max(-1.0)
+        }
+
+        match self {
+            VertexData::Byte(data) => data.iter().map(|&x| signed(x.into(), 127.0)).collect(),
+            VertexData::UByte(data) => data.iter().map(|&x| f32::from(x) / 255.0).collect(),
+            VertexData::Short(data) => data.iter().map(|&x| signed(x.into(), 32767.0)).collect(),
+            VertexData::UShort(data) => data.iter().map(|&x| f32::from(x) / 65535.0).collect(),
+            VertexData::Int(data) => data
+                .iter()
+                .map(|&x| signed(x as f32, 2_147_483_647.0))
+                .collect(),
+            VertexData::UInt(data) => data.iter().map(|&x| x as f32 / 4_294_967_295.0).collect(),
+            VertexData::Half(_) | VertexData::Float(_) | VertexData::Double(_) => self.to_f32(),
+        }
+    }
+
+    /// Converts the components to `u32`s, if they're all non-negative integers. This is meant for
+    /// indices, such as `BlendIndexes`.
+    pub fn to_u32(&self) -> Option<Vec<u32>> {
+        fn convert<T: Copy, E>(data: &[T], f: impl Fn(T) -> Result<u32, E>) -> Option<Vec<u32>> {
+            data.iter().map(|&x| f(x).ok()).collect()
+        }
+
+        match self {
+            VertexData::Byte(data) => convert(data, |x| u8::try_from(x).map(u32::from)),
+            VertexData::UByte(data) => Some(data.iter().map(|&x| u32::from(x)).collect()),
+            VertexData::Short(data) => convert(data, |x| u16::try_from(x).map(u32::from)),
+            VertexData::UShort(data) => Some(data.iter().map(|&x| u32::from(x)).collect()),
+            VertexData::Int(data) => convert(data, u32::try_from),
+            VertexData::UInt(data) => Some(data.clone()),
+            VertexData::Half(_) | VertexData::Float(_) | VertexData::Double(_) => None,
+        }
+    }
+}
+
+/// An array with one entry for each vertex, each of which has `size` components.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VertexArray {
+    /// What the data means.
+    pub kind: VertexArrayType,
+
+    /// The flags. These have no meaning defined by the format.
+    pub flags: u32,
+
+    /// The number of components for each vertex.
+    pub size: usize,
+
+    /// The components, `size` for each vertex in turn.
+    pub data: VertexData,
+}
+
+impl VertexArray {
+    /// Returns the format of the components.
+    pub fn format(&self) -> VertexFormat {
+        self.data.format()
+    }
+
+    /// Returns the number of vertices.
+    pub fn len(&self) -> usize {
+        self.data.len().checked_div(self.size).unwrap_or(0)
+    }
+
+    /// Returns whether there are no vertices.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the components as `f32`s (see `VertexData::to_f32`), grouped by vertex.
+    pub fn to_f32(&self) -> Vec<Vec<f32>> {
+        self.group(&self.data.to_f32())
+    }
+
+    /// Returns the components as normalized `f32`s (see `VertexData::to_f32_normalized`), grouped
+    /// by vertex.
+    pub fn to_f32_normalized(&self) -> Vec<Vec<f32>> {
+        self.group(&self.data.to_f32_normalized())
+    }
+
+    /// Groups components by vertex. Components left over after the last whole vertex (which
+    /// there are none of in a parsed array) are dropped.
+    fn group(&self, data: &[f32]) -> Vec<Vec<f32>> {
+        if self.size == 0 {
+            return Vec::new();
+        }
+        data.chunks_exact(self.size)
+            .map(|chunk| chunk.to_vec())
+            .collect()
+    }
+
+    /// Returns the components as exactly `n` `f32`s per vertex, or `None` if the array has a
+    /// different size.
+    fn to_f32_exact<A: Default + AsMut<[f32]>>(
+        &self,
+        n: usize,
+        normalized: bool,
+    ) -> Option<Vec<A>> {
+        if self.size != n {
+            return None;
+        }
+        let data = if normalized {
+            self.data.to_f32_normalized()
+        } else {
+            self.data.to_f32()
+        };
+        Some(
+            data.chunks_exact(n)
+                .map(|chunk| {
+                    let mut a = A::default();
+                    a.as_mut().copy_from_slice(chunk);
+                    a
+                })
+                .collect(),
+        )
+    }
+
+    /// Returns the components as 2 `f32`s per vertex, or `None` if the array has a different size.
+    pub fn to_vec2(&self) -> Option<Vec<[f32; 2]>> {
+        self.to_f32_exact(2, false)
+    }
+
+    /// Returns the components as 3 `f32`s per vertex, or `None` if the array has a different size.
+    pub fn to_vec3(&self) -> Option<Vec<[f32; 3]>> {
+        self.to_f32_exact(3, false)
+    }
+
+    /// Returns the components as 4 `f32`s per vertex, or `None` if the array has a different size.
+    /// Integer formats are normalized (see `VertexData::to_f32_normalized`), since 4-component
+    /// integer arrays are usually weights or colors.
+    pub fn to_vec4_normalized(&self) -> Option<Vec<[f32; 4]>> {
+        self.to_f32_exact(4, true)
+    }
+
+    /// Returns the components as 4 `u32`s per vertex, or `None` if the array has a different size
+    /// or isn't made of non-negative integers.
+    pub fn to_u32x4(&self) -> Option<Vec<[u32; 4]>> {
+        if self.size != 4 {
+            return None;
+        }
+        Some(
+            self.data
+                .to_u32()?
+                .chunks_exact(4)
+                .map(|c| [c[0], c[1], c[2], c[3]])
+                .collect(),
+        )
+    }
+}
+
+impl From<&VertexArrayRef<'_>> for VertexArray {
+    fn from(array: &VertexArrayRef) -> VertexArray {
+        VertexArray {
+            kind: array.kind,
+            flags: array.flags,
+            size: array.size,
+            data: VertexData::parse(array.format, array.num_components(), array.data),
+        }
+    }
+}
+
+/// A vertex array whose components are borrowed from the file (see `IQMRef`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VertexArrayRef<'a> {
+    /// What the data means.
+    pub kind: VertexArrayType,
+
+    /// The flags. These have no meaning defined by the format.
+    pub flags: u32,
+
+    /// The format of the components.
+    pub format: VertexFormat,
+
+    /// The number of components for each vertex.
+    pub size: usize,
+
+    /// The components, `size` for each vertex in turn, as they're stored in the file (in
+    /// little-endian order). On little-endian machines, this can be uploaded to the GPU as-is.
+    pub data: &'a [u8],
+}
+
+impl<'a> VertexArrayRef<'a> {
+    /// Returns the number of components.
+    fn num_components(&self) -> usize {
+        self.data.len() / self.format.size()
+    }
+
+    /// Returns the number of vertices.
+    pub fn len(&self) -> usize {
+        self.num_components().checked_div(self.size).unwrap_or(0)
+    }
+
+    /// Returns whether there are no vertices.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Decodes the components, as `VertexArray` holds them.
+    pub fn to_data(&self) -> VertexData {
+        VertexData::parse(self.format, self.num_components(), self.data)
+    }
+
+    /// Returns an iterator over the components converted to `f32`s, as by `VertexData::to_f32`,
+    /// which decodes them as it goes rather than all at once.
+    pub fn iter_f32(&self) -> impl ExactSizeIterator<Item = f32> + 'a {
+        let format = self.format;
+        self.data
+            .chunks_exact(format.size())
+            .map(move |bs| match format {
+                VertexFormat::Byte => f32::from(bs[0] as i8),
+                VertexFormat::UByte => f32::from(bs[0]),
+                VertexFormat::Short => f32::from(LittleEndian::read_i16(bs)),
+                VertexFormat::UShort => f32::from(LittleEndian::read_u16(bs)),
+                VertexFormat::Int => LittleEndian::read_i32(bs) as f32,
+                VertexFormat::UInt => LittleEndian::read_u32(bs) as f32,
+                VertexFormat::Half => half_to_f32(LittleEndian::read_u16(bs)),
+                VertexFormat::Float => LittleEndian::read_f32(bs),
+                VertexFormat::Double => LittleEndian::read_f64(bs) as f32,
+            })
+    }
+}
+
+/// Parses the `n` vertex arrays described by the entries at `offset`, each of which has
+/// `num_vertexes` entries.
+pub(crate) fn parse_vertex_arrays<'a>(
+    bs: &'a [u8],
+    offset: u32,
+    n: usize,
+    num_vertexes: usize,
+    texts: &Texts,
+) -> Result<Vec<VertexArrayRef<'a>>, IqmError> {
+    let mut arrays: Vec<VertexArrayRef> = Vec::new();
+    for (entry_offset, entry) in entries(bs, Section::VertexArrays, offset, n, 20)? {
+        let error = |reason| IqmError::invalid(Section::VertexArrays, entry_offset, reason);
+        let kind = VertexArrayType::from_u32(LittleEndian::read_u32(&entry[0..4]), texts)
+            .map_err(error)?;
+        let flags = LittleEndian::read_u32(&entry[4..8]);
+        let format = LittleEndian::read_u32(&entry[8..12]);
+        let format = VertexFormat::from_u32(format)
+            .ok_or_else(|| error(Reason::UnknownVertexFormat(format)))?;
+        let size = LittleEndian::read_u32(&entry[12..16]) as usize;
+        let offset = LittleEndian::read_u32(&entry[16..20]);
+
+        // Arrays must come in the order of their types, and only custom ones may be repeated.
+        if let Some(last) = arrays.last() {
+            let in_order = match (last.kind, kind) {
+                (VertexArrayType::Custom(_), VertexArrayType::Custom(_)) => true,
+                (last, kind) => last < kind,
+            };
+            if !in_order {
+                return Err(error(Reason::UnorderedVertexArray));
+            }
+        }
+        if size == 0 {
+            return Err(error(Reason::EmptyVertexArray));
+        }
+
+        if offset as usize % format.size().max(4) != 0 {
+            let reason = Reason::Misaligned;
+            return Err(IqmError::invalid(
+                Section::VertexArrays,
+                offset as usize,
+                reason,
+            ));
+        }
+
+        let n = num_vertexes.saturating_mul(size);
+        let data = section(bs, Section::VertexArrays, offset, n, format.size())?;
+        arrays.push(VertexArrayRef {
+            kind,
+            flags,
+            format,
+            size,
+            data,
+        });
+    }
+    Ok(arrays)
+}
+
+/// Converts a 16-bit float to an `f32`.
+pub(crate) fn half_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+    match exponent {
+        0 => sign * mantissa * 2.0f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2.0f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,287 @@
+//! A view of an IQM file that borrows from its contents.
+
+use crate::{
+    animation, extension, joints, parse_meshes, parse_texts, triangles, vertex, Animation, Bounds,
+    Extension, ExtensionRef, Header, IqmError, Joint, Mesh, Pose, Reason, Section, Texts,
+    VertexArray, VertexArrayRef, VertexArrayType, IQM,
+};
+
+/// The data stored within an IQM file, borrowed from its contents (such as a memory-mapped file)
+/// rather than copied. The whole file is checked when it's parsed, as by `IQM::parse_from`, and
+/// entries are then decoded as they're iterated over.
+#[derive(Clone, Debug)]
+pub struct IQMRef<'a> {
+    bs: &'a [u8],
+    header: Header,
+    texts: Texts<'a>,
+    comments: Vec<&'a str>,
+    vertex_arrays: Vec<VertexArrayRef<'a>>,
+    extensions: Vec<ExtensionRef<'a>>,
+}
+
+/// Checks each entry of a section.
+fn check<T>(
+    entries: Result<impl Iterator<Item = Result<T, IqmError>>, IqmError>,
+) -> Result<(), IqmError> {
+    for entry in entries? {
+        let _ = entry?;
+    }
+    Ok(())
+}
+
+/// Returns the entries of a section that has already been checked.
+fn checked<T>(
+    entries: Result<impl Iterator<Item = Result<T, IqmError>>, IqmError>,
+) -> impl Iterator<Item = T> {
+    entries.into_iter().flatten().filter_map(Result::ok)
+}
+
+impl<'a> IQMRef<'a> {
+    /// Attempts to read the data from the contents of an IQM file, failing in the same cases as
+    /// `IQM::parse_from`.
+    pub fn parse_from(bs: &'a [u8]) -> Result<IQMRef<'a>, IqmError> {
+        let header = Header::parse_from(bs)?;
+        let mut iqm = IQMRef {
+            bs,
+            header,
+            texts: parse_texts(bs, Section::Text, header.text)?,
+            comments: Vec::new(),
+            vertex_arrays: Vec::new(),
+            extensions: Vec::new(),
+        };
+
+        if header.triangles.2 != 0 {
+            check(iqm.try_adjacency(header.triangles.2))?;
+        }
+        check(iqm.try_meshes())?;
+        check(iqm.try_joints())?;
+        check(iqm.try_poses())?;
+        let (num_joints, num_poses) = (header.joints.0 as usize, header.poses.0 as usize);
+        if num_joints != 0 && num_poses != 0 && num_joints != num_poses {
+            let reason = Reason::PoseCountMismatch {
+                joints: num_joints,
+                poses: num_poses,
+            };
+            return Err(IqmError::invalid(
+                Section::Poses,
+                header.poses.1 as usize,
+                reason,
+            ));
+        }
+        let channels = iqm.poses().map(|pose| pose.num_channels()).sum::<usize>();
+        if channels != iqm.num_framechannels() {
+            let reason = Reason::ChannelCountMismatch {
+                poses: channels,
+                frames: iqm.num_framechannels(),
+            };
+            return Err(IqmError::invalid(
+                Section::Frames,
+                header.frames.2 as usize,
+                reason,
+            ));
+        }
+
+        let (num_vertex_arrays, _, ofs_vertex_arrays) = header.vertex_arrays;
+        iqm.vertex_arrays = vertex::parse_vertex_arrays(
+            bs,
+            ofs_vertex_arrays,
+            num_vertex_arrays as usize,
+            iqm.num_vertexes(),
+            &iqm.texts,
+        )?;
+        check(iqm.try_triangles())?;
+        check(iqm.try_animations())?;
+        let _ = iqm.try_frames()?;
+        if let Some(bounds) = iqm.try_bounds() {
+            let _ = bounds?;
+        }
+        iqm.comments = parse_texts(bs, Section::Comments, header.comments)?.strings;
+        iqm.extensions = extension::parse_extensions(
+            bs,
+            header.extensions.1,
+            header.extensions.0 as usize,
+            &iqm.texts,
+        )?;
+        Ok(iqm)
+    }
+
+    /// Returns the text entries.
+    pub fn texts(&self) -> &[&'a str] {
+        &self.texts.strings
+    }
+
+    /// Returns the text entry with the given index, as `IQM::text` does.
+    pub fn text(&self, index: Option<usize>) -> Option<&'a str> {
+        match index {
+            Some(index) => self.texts.strings.get(index).cloned(),
+            None => Some(""),
+        }
+    }
+
+    /// Returns the meshes.
+    pub fn meshes(&self) -> impl Iterator<Item = Mesh> + '_ {
+        checked(self.try_meshes())
+    }
+
+    /// Returns the number of vertices. Each vertex array has this many entries.
+    pub fn num_vertexes(&self) -> usize {
+        self.header.vertex_arrays.1 as usize
+    }
+
+    /// Returns the vertex arrays, ordered by their types.
+    pub fn vertex_arrays(&self) -> &[VertexArrayRef<'a>] {
+        &self.vertex_arrays
+    }
+
+    /// Returns the first vertex array of the given type.
+    pub fn vertex_array(&self, kind: VertexArrayType) -> Option<&VertexArrayRef<'a>> {
+        self.vertex_arrays.iter().find(|array| array.kind == kind)
+    }
+
+    /// Returns the triangles, each given as the indices of its vertices.
+    pub fn triangles(&self) -> impl Iterator<Item = [u32; 3]> + 'a {
+        checked(self.try_triangles())
+    }
+
+    /// Returns the adjacency of the triangles (see `IQM::adjacency`), if the file includes it.
+    pub fn adjacency(&self) -> Option<impl Iterator<Item = [Option<u32>; 3]> + 'a> {
+        match self.header.triangles.2 {
+            0 => None,
+            offset => Some(checked(self.try_adjacency(offset))),
+        }
+    }
+
+    /// Returns the joints of the skeleton, in their bind pose.
+    pub fn joints(&self) -> impl Iterator<Item = Joint> + '_ {
+        checked(self.try_joints())
+    }
+
+    /// Returns the poses, which say how the transform of each joint is encoded in the frames.
+    pub fn poses(&self) -> impl Iterator<Item = Pose> + 'a {
+        checked(self.try_poses())
+    }
+
+    /// Returns the animations.
+    pub fn animations(&self) -> impl Iterator<Item = Animation> + '_ {
+        checked(self.try_animations())
+    }
+
+    /// Returns the number of frames.
+    pub fn num_frames(&self) -> usize {
+        self.header.frames.0 as usize
+    }
+
+    /// Returns the number of values in each frame.
+    pub fn num_framechannels(&self) -> usize {
+        self.header.frames.1 as usize
+    }
+
+    /// Returns the values of the channels in each frame, frame by frame.
+    pub fn frames(&self) -> impl Iterator<Item = u16> + 'a {
+        self.try_frames().into_iter().flatten()
+    }
+
+    /// Returns the bounds of the model in each frame, if the file includes them.
+    pub fn bounds(&self) -> Option<impl Iterator<Item = Bounds> + 'a> {
+        self.try_bounds().map(|bounds| bounds.into_iter().flatten())
+    }
+
+    /// Returns the comment entries.
+    pub fn comments(&self) -> &[&'a str] {
+        &self.comments
+    }
+
+    /// Returns the extensions, in the order they're listed in the file.
+    pub fn extensions(&self) -> &[ExtensionRef<'a>] {
+        &self.extensions
+    }
+
+    /// Returns the first extension with the given name.
+    pub fn extension(&self, name: &str) -> Option<&ExtensionRef<'a>> {
+        self.extensions
+            .iter()
+            .find(|extension| self.text(extension.name) == Some(name))
+    }
+
+    /// Parses the meshes. The other `try_` methods parse the other sections likewise, for
+    /// `parse_from` to check and the public methods to iterate over.
+    fn try_meshes(&self) -> Result<impl Iterator<Item = Result<Mesh, IqmError>> + '_, IqmError> {
+        let num_triangles = self.header.triangles.0 as usize;
+        let (bs, texts) = (self.bs, &self.texts);
+        parse_meshes(
+            bs,
+            self.header.meshes,
+            self.num_vertexes(),
+            num_triangles,
+            texts,
+        )
+    }
+
+    fn try_triangles(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<[u32; 3], IqmError>> + 'a, IqmError> {
+        let (n, offset, _) = self.header.triangles;
+        triangles::parse_triangles(self.bs, offset, n as usize, self.num_vertexes())
+    }
+
+    /// Parses the adjacency table, which is at `offset`.
+    fn try_adjacency(
+        &self,
+        offset: u32,
+    ) -> Result<impl Iterator<Item = Result<[Option<u32>; 3], IqmError>> + 'a, IqmError> {
+        let n = self.header.triangles.0 as usize;
+        triangles::parse_adjacency(self.bs, offset, n)
+    }
+
+    fn try_joints(&self) -> Result<impl Iterator<Item = Result<Joint, IqmError>> + '_, IqmError> {
+        let (n, offset) = self.header.joints;
+        joints::parse_joints(self.bs, offset, n as usize, &self.texts)
+    }
+
+    fn try_poses(&self) -> Result<impl Iterator<Item = Result<Pose, IqmError>> + 'a, IqmError> {
+        let (n, offset) = self.header.poses;
+        animation::parse_poses(self.bs, offset, n as usize)
+    }
+
+    fn try_animations(
+        &self,
+    ) -> Result<impl Iterator<Item = Result<Animation, IqmError>> + '_, IqmError> {
+        let (n, offset) = self.header.animations;
+        let num_frames = self.num_frames();
+        animation::parse_animations(self.bs, offset, n as usize, num_frames, &self.texts)
+    }
+
+    fn try_frames(&self) -> Result<impl Iterator<Item = u16> + 'a, IqmError> {
+        let offset = self.header.frames.2;
+        animation::parse_frames(self.bs, offset, self.num_frames(), self.num_framechannels())
+    }
+
+    fn try_bounds(&self) -> Option<Result<impl Iterator<Item = Bounds> + 'a, IqmError>> {
+        match self.header.frames.3 {
+            0 => None,
+            offset => Some(animation::parse_bounds(self.bs, offset, self.num_frames())),
+        }
+    }
+}
+
+impl From<&IQMRef<'_>> for IQM {
+    fn from(iqm: &IQMRef) -> IQM {
+        IQM {
+            text: iqm.texts().iter().map(|s| s.to_string()).collect(),
+            meshes: iqm.meshes().collect(),
+            num_vertexes: iqm.num_vertexes(),
+            vertex_arrays: iqm.vertex_arrays().iter().map(VertexArray::from).collect(),
+            triangles: iqm.triangles().collect(),
+            adjacency: iqm.adjacency().map(Iterator::collect),
+            joints: iqm.joints().collect(),
+            poses: iqm.poses().collect(),
+            animations: iqm.animations().collect(),
+            num_frames: iqm.num_frames(),
+            num_framechannels: iqm.num_framechannels(),
+            frames: iqm.frames().collect(),
+            bounds: iqm.bounds().map(Iterator::collect),
+            comments: iqm.comments().iter().map(|s| s.to_string()).collect(),
+            extensions: iqm.extensions().iter().map(Extension::from).collect(),
+        }
+    }
+}
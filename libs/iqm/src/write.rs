@@ -0,0 +1,277 @@
+//! Writing IQM files.
+
+use crate::{
+    joints::{write_parent, write_transform},
+    triangles::NO_NEIGHBOUR,
+    HEADER_SIZE, IQM,
+};
+use byteorder::{ByteOrder, LittleEndian};
+use std::{
+    convert::TryFrom,
+    io::{Error, ErrorKind, Result},
+    ops::Range,
+};
+
+/// An IQM file being assembled, along with the offset of each text entry in it.
+struct Writer {
+    bs: Vec<u8>,
+    text_offsets: Vec<u32>,
+}
+
+impl Writer {
+    /// Sets the header field at the given byte offset.
+    fn set(&mut self, field: usize, value: u32) {
+        LittleEndian::write_u32(&mut self.bs[field..field + 4], value);
+    }
+
+    /// Appends data to the file, aligned to `align` bytes, returning its offset.
+    fn append(&mut self, bs: &[u8], align: usize) -> Result<u32> {
+        while self.bs.len() % align != 0 {
+            self.bs.push(0);
+        }
+        let offset = to_u32(self.bs.len(), "the file")?;
+        self.bs.extend_from_slice(bs);
+        Ok(offset)
+    }
+
+    /// Appends a section of `count` entries, setting the header fields for its count (at `field`)
+    /// and offset (the field after). Empty sections are left with an offset of 0.
+    fn section(&mut self, field: usize, count: usize, bs: &[u8], align: usize) -> Result<()> {
+        if count != 0 {
+            let offset = self.append(bs, align)?;
+            self.set(field, to_u32(count, "a section")?);
+            self.set(field + 4, offset);
+        }
+        Ok(())
+    }
+
+    /// Returns the offset of the text entry with the given index, or 0 (the empty string) for
+    /// `None`.
+    fn name(&self, index: Option<usize>, what: &str) -> Result<u32> {
+        match index {
+            Some(index) => self
+                .text_offsets
+                .get(index)
+                .cloned()
+                .ok_or_else(|| inconsistent(format!("{} names a missing text entry", what))),
+            None => Ok(0),
+        }
+    }
+}
+
+/// Returns an error for an `IQM` that can't be written.
+fn inconsistent(message: String) -> Error {
+    Error::new(ErrorKind::InvalidInput, message)
+}
+
+/// Converts a count or offset to a `u32`, failing if it's too large.
+fn to_u32(n: usize, what: &str) -> Result<u32> {
+    u32::try_from(n).map_err(|_| inconsistent(format!("{} is too large", what)))
+}
+
+/// Encodes a range as its start and length.
+fn range(range: &Range<usize>, what: &str) -> Result<[u32; 2]> {
+    let len = range
+        .end
+        .checked_sub(range.start)
+        .ok_or_else(|| inconsistent(format!("{} has a backwards range", what)))?;
+    Ok([to_u32(range.start, what)?, to_u32(len, what)?])
+}
+
+/// Checks that a section has the number of entries the header will say it does.
+fn check_len(len: usize, expected: usize, what: &str) -> Result<()> {
+    if len == expected {
+        Ok(())
+    } else {
+        let message = format!("there are {} {}, not {}", len, what, expected);
+        Err(inconsistent(message))
+    }
+}
+
+/// Encodes strings as a string table, which starts with the empty string, returning it along with
+/// the offset of each string.
+fn string_table(strings: &[String]) -> Result<(Vec<u8>, Vec<u32>)> {
+    let mut bs = vec![0];
+    let mut offsets = Vec::with_capacity(strings.len());
+    for s in strings {
+        if s.contains('\0') {
+            return Err(inconsistent(format!("{:?} contains a NUL byte", s)));
+        }
+        offsets.push(to_u32(bs.len(), "the text")?);
+        bs.extend_from_slice(s.as_bytes());
+        bs.push(0);
+    }
+    Ok((bs, offsets))
+}
+
+/// Encodes a list of `u32`s.
+fn u32s(ns: &[u32]) -> Vec<u8> {
+    let mut bs = vec![0; ns.len() * 4];
+    LittleEndian::write_u32_into(ns, &mut bs);
+    bs
+}
+
+/// Encodes a list of `f32`s.
+fn f32s(fs: &[f32]) -> Vec<u8> {
+    let mut bs = vec![0; fs.len() * 4];
+    LittleEndian::write_f32_into(fs, &mut bs);
+    bs
+}
+
+/// Encodes an IQM file. The sections are laid out in the order of the header, each aligned as
+/// `IQM::parse_from` requires.
+pub(crate) fn write(iqm: &IQM) -> Result<Vec<u8>> {
+    let (text, text_offsets) = string_table(&iqm.text)?;
+    let mut w = Writer {
+        bs: vec![0; HEADER_SIZE],
+        text_offsets,
+    };
+    w.bs[..16].copy_from_slice(b"INTERQUAKEMODEL\0");
+    w.set(16, 2);
+    w.section(28, text.len(), &text, 4)?;
+
+    let mut meshes = Vec::with_capacity(iqm.meshes.len() * 24);
+    for mesh in &iqm.meshes {
+        let [first_vertex, num_vertexes] = range(&mesh.vertices, "a mesh")?;
+        let [first_triangle, num_triangles] = range(&mesh.triangles, "a mesh")?;
+        meshes.extend(u32s(&[
+            w.name(mesh.name, "a mesh")?,
+            mesh.material,
+            first_vertex,
+            num_vertexes,
+            first_triangle,
+            num_triangles,
+        ]));
+    }
+    w.section(36, iqm.meshes.len(), &meshes, 4)?;
+
+    let mut vertex_arrays = Vec::with_capacity(iqm.vertex_arrays.len() * 20);
+    for array in &iqm.vertex_arrays {
+        let kind = array
+            .kind
+            .to_u32(&w.text_offsets)
+            .ok_or_else(|| inconsistent("a vertex array names a missing text entry".into()))?;
+        let expected = iqm.num_vertexes.saturating_mul(array.size);
+        check_len(array.data.len(), expected, "components in a vertex array")?;
+        let format = array.format();
+        let offset = w.append(&array.data.write(), format.size().max(4))?;
+        vertex_arrays.extend(u32s(&[
+            kind,
+            array.flags,
+            format.to_u32(),
+            to_u32(array.size, "a vertex array")?,
+            offset,
+        ]));
+    }
+    // The number of vertices sits between the number of vertex arrays and their offset.
+    w.set(48, to_u32(iqm.num_vertexes, "the number of vertices")?);
+    if !iqm.vertex_arrays.is_empty() {
+        let offset = w.append(&vertex_arrays, 4)?;
+        w.set(44, to_u32(iqm.vertex_arrays.len(), "a section")?);
+        w.set(52, offset);
+    }
+
+    let triangles = iqm.triangles.iter().flatten().cloned().collect::<Vec<_>>();
+    w.section(56, iqm.triangles.len(), &u32s(&triangles), 4)?;
+    if let Some(adjacency) = &iqm.adjacency {
+        check_len(adjacency.len(), iqm.triangles.len(), "adjacency entries")?;
+        let adjacency = adjacency
+            .iter()
+            .flatten()
+            .map(|neighbour| neighbour.unwrap_or(NO_NEIGHBOUR))
+            .collect::<Vec<_>>();
+        if !adjacency.is_empty() {
+            let offset = w.append(&u32s(&adjacency), 4)?;
+            w.set(64, offset);
+        }
+    }
+
+    let mut joints = Vec::with_capacity(iqm.joints.len() * 48);
+    for joint in &iqm.joints {
+        let parent = write_parent(joint.parent)
+            .ok_or_else(|| inconsistent("a joint's parent is too large".into()))?;
+        joints.extend(u32s(&[w.name(joint.name, "a joint")?, parent as u32]));
+        joints.extend(f32s(&write_transform(&joint.transform)));
+    }
+    w.section(68, iqm.joints.len(), &joints, 4)?;
+
+    let mut poses = Vec::with_capacity(iqm.poses.len() * 88);
+    for pose in &iqm.poses {
+        let parent = write_parent(pose.parent)
+            .ok_or_else(|| inconsistent("a pose's parent is too large".into()))?;
+        poses.extend(u32s(&[parent as u32, pose.channel_mask]));
+        poses.extend(f32s(&pose.channel_offset));
+        poses.extend(f32s(&pose.channel_scale));
+    }
+    w.section(76, iqm.poses.len(), &poses, 4)?;
+
+    let mut animations = Vec::with_capacity(iqm.animations.len() * 20);
+    for animation in &iqm.animations {
+        let [first_frame, num_frames] = range(&animation.frames, "an animation")?;
+        animations.extend(u32s(&[
+            w.name(animation.name, "an animation")?,
+            first_frame,
+            num_frames,
+            animation.framerate.to_bits(),
+            animation.flags,
+        ]));
+    }
+    w.section(84, iqm.animations.len(), &animations, 4)?;
+
+    let expected = iqm.num_frames.saturating_mul(iqm.num_framechannels);
+    check_len(iqm.frames.len(), expected, "frame values")?;
+    w.set(92, to_u32(iqm.num_frames, "the number of frames")?);
+    w.set(96, to_u32(iqm.num_framechannels, "the number of channels")?);
+    if !iqm.frames.is_empty() {
+        let mut frames = vec![0; iqm.frames.len() * 2];
+        LittleEndian::write_u16_into(&iqm.frames, &mut frames);
+        let offset = w.append(&frames, 4)?;
+        w.set(100, offset);
+    }
+    if let Some(bounds) = &iqm.bounds {
+        check_len(bounds.len(), iqm.num_frames, "bounds")?;
+        let mut fs = Vec::with_capacity(bounds.len() * 8);
+        for b in bounds {
+            fs.extend_from_slice(&[b.mins.x, b.mins.y, b.mins.z]);
+            fs.extend_from_slice(&[b.maxs.x, b.maxs.y, b.maxs.z, b.xy_radius, b.radius]);
+        }
+        if !fs.is_empty() {
+            let offset = w.append(&f32s(&fs), 4)?;
+            w.set(104, offset);
+        }
+    }
+
+    if !iqm.comments.is_empty() {
+        let (comments, _) = string_table(&iqm.comments)?;
+        w.section(108, comments.len(), &comments, 4)?;
+    }
+
+    // The extensions are written as a list in order, each linking to the next, after all their
+    // data.
+    let mut extensions = Vec::with_capacity(iqm.extensions.len() * 16);
+    for extension in &iqm.extensions {
+        let offset = w.append(&extension.data, 4)?;
+        extensions.push([
+            w.name(extension.name, "an extension")?,
+            to_u32(extension.data.len(), "an extension")?,
+            offset,
+        ]);
+    }
+    if !extensions.is_empty() {
+        let start = w.append(&[], 4)?;
+        let mut entries = Vec::with_capacity(extensions.len() * 16);
+        for (i, &[name, len, offset]) in extensions.iter().enumerate() {
+            let next = if i + 1 == extensions.len() {
+                0
+            } else {
+                to_u32(start as usize + (i + 1) * 16, "the file")?
+            };
+            entries.extend(u32s(&[name, len, offset, next]));
+        }
+        w.section(116, extensions.len(), &entries, 4)?;
+    }
+
+    let filesize = to_u32(w.bs.len(), "the file")?;
+    w.set(20, filesize);
+    Ok(w.bs)
+}
@@ -0,0 +1,172 @@
+//! An in-process fake network, for testing the `udp` module.
+//!
+//! Packets sent on a `FakeSocket` are delivered to the `FakeSocket` with the address they were
+//! sent to, after the latency of the network has passed. The network can also drop, duplicate,
+//! and (by giving packets different latencies) reorder packets. Time only passes when
+//! `FakeNetwork::advance` is called, and the random choices are made by a seeded generator, so
+//! tests that use it give the same results every time.
+
+use crate::udp::Socket;
+use std::{
+    cell::RefCell,
+    io::Result,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// The conditions packets are sent under.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conditions {
+    /// How long it takes every packet to arrive.
+    pub latency: Duration,
+
+    /// The most extra time a packet can take to arrive, chosen at random for each packet. Packets
+    /// arrive out of order if this is longer than the time between them.
+    pub jitter: Duration,
+
+    /// The chance (from 0 to 1) that a packet is dropped.
+    pub loss: f64,
+
+    /// The chance (from 0 to 1) that a packet arrives twice.
+    pub duplication: f64,
+}
+
+/// A fake network, which `FakeSocket`s are attached to. Clones of it refer to the same network.
+#[derive(Clone, Debug)]
+pub struct FakeNetwork(Rc<RefCell<Network>>);
+
+/// The state of a `FakeNetwork`.
+#[derive(Debug)]
+struct Network {
+    now: Instant,
+    conditions: Conditions,
+    rng: u64,
+    in_flight: Vec<InFlight>,
+
+    /// The number of packets that have been sent, which is used to deliver packets that arrive
+    /// at the same time in the order they were sent.
+    sent: u64,
+}
+
+/// A packet that is on its way.
+#[derive(Debug)]
+struct InFlight {
+    arrives_at: Instant,
+    order: u64,
+    from: SocketAddr,
+    to: SocketAddr,
+    data: Vec<u8>,
+}
+
+impl FakeNetwork {
+    /// Creates a network with perfect conditions, whose random choices are made from the given
+    /// seed.
+    pub fn new(seed: u64) -> FakeNetwork {
+        FakeNetwork(Rc::new(RefCell::new(Network {
+            now: Instant::now(),
+            conditions: Conditions::default(),
+            // Xorshift gets stuck at zero.
+            rng: seed | 1,
+            in_flight: Vec::new(),
+            sent: 0,
+        })))
+    }
+
+    /// Returns the current time on the network.
+    pub fn now(&self) -> Instant {
+        self.0.borrow().now
+    }
+
+    /// Moves time forwards, so that packets can arrive.
+    pub fn advance(&self, dt: Duration) {
+        self.0.borrow_mut().now += dt;
+    }
+
+    /// Sets the conditions packets sent from now on are sent under.
+    pub fn set_conditions(&self, conditions: Conditions) {
+        self.0.borrow_mut().conditions = conditions;
+    }
+
+    /// Creates a socket with the given address.
+    pub fn socket(&self, addr: SocketAddr) -> FakeSocket {
+        FakeSocket {
+            addr,
+            net: self.clone(),
+        }
+    }
+
+    /// Returns the number of packets that are on their way.
+    pub fn in_flight(&self) -> usize {
+        self.0.borrow().in_flight.len()
+    }
+}
+
+impl Network {
+    /// Returns a random number in `[0, 1)`.
+    fn random(&mut self) -> f64 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        (self.rng >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Puts a copy of a packet on its way.
+    fn send(&mut self, from: SocketAddr, to: SocketAddr, data: &[u8]) {
+        let jitter = self.conditions.jitter.mul_f64(self.random());
+        self.in_flight.push(InFlight {
+            arrives_at: self.now + self.conditions.latency + jitter,
+            order: self.sent,
+            from,
+            to,
+            data: data.to_vec(),
+        });
+        self.sent += 1;
+    }
+}
+
+/// A socket on a `FakeNetwork`.
+#[derive(Debug)]
+pub struct FakeSocket {
+    addr: SocketAddr,
+    net: FakeNetwork,
+}
+
+impl FakeSocket {
+    /// Returns the address of the socket.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Socket for FakeSocket {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> Result<()> {
+        let mut net = self.net.0.borrow_mut();
+        if net.random() < net.conditions.loss {
+            return Ok(());
+        }
+        net.send(self.addr, addr, buf);
+        if net.random() < net.conditions.duplication {
+            net.send(self.addr, addr, buf);
+        }
+        Ok(())
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        let mut net = self.net.0.borrow_mut();
+        let now = net.now;
+        let next = net
+            .in_flight
+            .iter()
+            .enumerate()
+            .filter(|(_, packet)| packet.to == self.addr && packet.arrives_at <= now)
+            .min_by_key(|(_, packet)| (packet.arrives_at, packet.order))
+            .map(|(i, _)| i);
+        Ok(next.map(|i| {
+            let packet = net.in_flight.swap_remove(i);
+            let len = packet.data.len().min(buf.len());
+            buf[..len].copy_from_slice(&packet.data[..len]);
+            (len, packet.from)
+        }))
+    }
+}
@@ -0,0 +1,54 @@
+//! The network protocol spoken between the Ia client and server.
+//!
+//! Messages are serialized as S-expressions (see `to_bytes` and `from_bytes`), so that components
+//! can be sent with their `typetag` names, just as in scenes. The exception is the deltas in
+//! updates, which are encoded compactly with an `ecstasy::quantize::Codec`.
+//!
+//! The `udp` module sends the serialized messages over UDP, on a reliable or an unreliable channel.
+//! Updates are sent on the unreliable channel, so they may be lost; each one is a delta from the
+//! last update the client acknowledged with an `Ack`. Everything else is sent reliably. Endpoints
+//! can be tested against the fake network in the `fake_net` module.
+#![deny(
+    bad_style,
+    bare_trait_objects,
+    const_err,
+    dead_code,
+    improper_ctypes,
+    legacy_directory_ownership,
+    missing_debug_implementations,
+    missing_docs,
+    no_mangle_generic_items,
+    non_shorthand_field_patterns,
+    overflowing_literals,
+    path_statements,
+    patterns_in_fns_without_body,
+    plugin_as_library,
+    private_in_public,
+    safe_extern_statics,
+    trivial_casts,
+    trivial_numeric_casts,
+    unconditional_recursion,
+    unions_with_drop_fields,
+    unused,
+    unused_allocation,
+    unused_comparisons,
+    unused_extern_crates,
+    unused_import_braces,
+    unused_parens,
+    unused_qualifications,
+    unused_results,
+    while_true
+)]
+
+pub mod fake_net;
+mod messages;
+mod packet;
+pub mod udp;
+
+pub use crate::messages::{
+    from_bytes, to_bytes, ClientMessage, ClientStats, Credentials, EncodedDelta, ServerMessage,
+    MAX_MESSAGE_LEN, PROTOCOL_VERSION,
+};
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,212 @@
+use ecstasy::{
+    delta::Delta,
+    quantize::{Codec, DecodeError},
+    Component, Entity,
+};
+use serde::{
+    de::{DeserializeOwned, Error as DeError, Unexpected},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{self, Error, ErrorKind},
+};
+
+/// The version of the protocol. Clients and servers only talk to each other if their versions are
+/// the same.
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// The largest message that will be sent or received, in bytes.
+pub const MAX_MESSAGE_LEN: usize = 16 * 1024 * 1024;
+
+/// Serializes a message as an S-expression, for sending on a `udp::Endpoint`.
+pub fn to_bytes<M: Serialize>(msg: &M) -> io::Result<Vec<u8>> {
+    let src = serde_sexpr::to_string(msg)
+        .map_err(|err| Error::new(ErrorKind::InvalidInput, err.to_string()))?;
+    if src.len() > MAX_MESSAGE_LEN {
+        return Err(Error::new(ErrorKind::InvalidInput, "message too long"));
+    }
+    Ok(src.into_bytes())
+}
+
+/// Deserializes a message serialized by `to_bytes`.
+pub fn from_bytes<M: DeserializeOwned>(bytes: &[u8]) -> io::Result<M> {
+    let src = std::str::from_utf8(bytes).map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+    serde_sexpr::from_str(src).map_err(|err| Error::new(ErrorKind::InvalidData, err.to_string()))
+}
+
+/// A message sent from a client to the server.
+#[derive(Debug, Deserialize, Serialize)]
+pub enum ClientMessage {
+    /// The first message a client sends, asking to join the game. (The version of the protocol
+    /// is checked when connecting, by the `udp` module.)
+    Hello {
+        /// The name of the player.
+        name: String,
+
+        /// The credentials to log in with, if the server requires logging in.
+        credentials: Option<Credentials>,
+    },
+
+    /// Inputs from the player, which are set as components on the player's entity. The server
+    /// applies one `Input` per tick, in the order they were sent.
+    Input {
+        /// The client's number for the input. These should count up from zero, one per tick.
+        tick: u64,
+
+        /// The server tick the client was showing other entities at when the input was made. Hit
+        /// tests for the input are done against the world as it was then.
+        view_tick: f32,
+
+        /// The components to set.
+        components: Vec<Box<dyn Component>>,
+    },
+
+    /// Tells the server the update for the given tick has been applied. Updates are sent on the
+    /// unreliable channel, so each is the changes since the last update the client acknowledged.
+    /// This is sent on the unreliable channel too.
+    Ack {
+        /// The tick of the update.
+        tick: u64,
+    },
+
+    /// Asks for a `Stats` message describing the server's clients. This may be sent instead of
+    /// `Hello`, by debugging tools that don't join the game.
+    GetStats,
+}
+
+/// The proof a client gives that it may play as a player.
+#[derive(Clone, Deserialize, PartialEq, Serialize)]
+pub enum Credentials {
+    /// The password of the player's account.
+    Password(String),
+
+    /// A session token from an earlier login.
+    Token(String),
+}
+
+impl Debug for Credentials {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        // Keep secrets out of logs.
+        match self {
+            Credentials::Password(_) => write!(fmt, "Password(..)"),
+            Credentials::Token(_) => write!(fmt, "Token(..)"),
+        }
+    }
+}
+
+/// A message sent from the server to a client.
+#[derive(Debug, Deserialize, Serialize)]
+pub enum ServerMessage {
+    /// The client has joined the game.
+    Welcome {
+        /// The entity controlled by the client.
+        player: Entity,
+
+        /// The number of ticks the server runs per second.
+        tick_rate: u32,
+
+        /// If the client logged in, a token it can log in with again (see `Credentials::Token`)
+        /// until the session expires.
+        session: Option<String>,
+    },
+
+    /// The client was not allowed to join the game. The server closes the connection once this
+    /// has arrived.
+    Rejected(String),
+
+    /// The changes to the world since the last update the client acknowledged, or the whole world
+    /// if it hasn't acknowledged any. Updates are sent on the unreliable channel, unless they're
+    /// too long for it.
+    Update {
+        /// The tick that was just run.
+        tick: u64,
+
+        /// The number of the last input from the client that was applied before the tick was run,
+        /// if any have been.
+        input: Option<u64>,
+
+        /// The changes to the world.
+        delta: EncodedDelta,
+    },
+
+    /// Statistics about each client connected to the server, in reply to `GetStats`.
+    Stats(Vec<ClientStats>),
+}
+
+/// A `Delta` encoded by a `Codec`. The server and client must use codecs with the same types
+/// registered.
+///
+/// This is serialized as a string of hex digits, rather than as a list of numbers.
+#[derive(Clone, PartialEq)]
+pub struct EncodedDelta(pub Vec<u8>);
+
+impl EncodedDelta {
+    /// Encodes a delta.
+    pub fn encode<C: Borrow<dyn Component>>(codec: &Codec, delta: &Delta<C>) -> EncodedDelta {
+        EncodedDelta(codec.encode_delta(delta))
+    }
+
+    /// Decodes the delta.
+    pub fn decode(&self, codec: &Codec) -> Result<Delta, DecodeError> {
+        codec.decode_delta(&self.0)
+    }
+}
+
+impl Debug for EncodedDelta {
+    fn fmt(&self, fmt: &mut Formatter) -> FmtResult {
+        write!(fmt, "EncodedDelta({} bytes)", self.0.len())
+    }
+}
+
+impl Serialize for EncodedDelta {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let hex = self
+            .0
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>();
+        serializer.serialize_str(&hex)
+    }
+}
+
+impl<'de> Deserialize<'de> for EncodedDelta {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<EncodedDelta, D::Error> {
+        let hex = String::deserialize(deserializer)?;
+        let invalid = || DeError::invalid_value(Unexpected::Str(&hex), &"a string of hex digits");
+        if hex.len() % 2 != 0 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid()))
+            .collect::<Result<_, _>>()
+            .map(EncodedDelta)
+    }
+}
+
+/// Statistics about a client connected to the server.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ClientStats {
+    /// The address the client connected from.
+    pub addr: String,
+
+    /// The name of the player, if the client has joined the game.
+    pub name: Option<String>,
+
+    /// The client's entity, if it has joined the game.
+    pub player: Option<Entity>,
+
+    /// The number of entities the client is currently being sent changes to.
+    pub entities_in_scope: usize,
+
+    /// The number of bytes sent to the client.
+    pub bytes_sent: u64,
+
+    /// The number of bytes received from the client.
+    pub bytes_received: u64,
+
+    /// The number of components in the last update sent to the client, counting removed ones.
+    pub last_update_len: usize,
+}
@@ -0,0 +1,194 @@
+//! The format of the packets sent by the `udp` module.
+//!
+//! Every packet starts with `PROTOCOL_ID` and a byte giving its kind. Data packets then carry a
+//! sequence number, the sequence number of the last packet received from the peer, and a bitfield
+//! of which of the 32 packets before that were received, followed by any number of entries. An
+//! entry is either an unreliable message, with its sequence number on the unreliable channel, or a
+//! fragment of a reliable message, with its id on the reliable channel. The high bit of a
+//! fragment's length is set if more fragments of the same message follow it.
+//!
+//! All integers are big-endian.
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Cursor, Read, Result};
+
+/// The first four bytes of every packet, so that stray packets are ignored.
+pub const PROTOCOL_ID: u32 = 0x4961_4e50;
+
+/// The largest packet that will be sent, in bytes. This is small enough to not be fragmented by IP
+/// on most networks.
+pub const MAX_PACKET_LEN: usize = 1200;
+
+/// The length of the header of a data packet, including the kind.
+pub const DATA_HEADER_LEN: usize = 4 + 1 + 2 + 2 + 4;
+
+/// The length of the header of an entry.
+pub const ENTRY_HEADER_LEN: usize = 1 + 2 + 2;
+
+/// The set bit in a fragment's length when more fragments follow it.
+const MORE_FRAGMENTS: u16 = 0x8000;
+
+/// A packet.
+#[derive(Debug, PartialEq)]
+pub enum Packet {
+    /// Asks to connect, with the protocol version the client speaks.
+    Connect(u32),
+
+    /// Accepts a connection.
+    Accept,
+
+    /// Rejects a connection, with the reason why.
+    Reject(String),
+
+    /// Carries messages and acknowledgements.
+    Data(DataPacket),
+
+    /// Closes the connection.
+    Disconnect,
+}
+
+/// The contents of a data packet.
+#[derive(Debug, Default, PartialEq)]
+pub struct DataPacket {
+    /// The sequence number of the packet.
+    pub seq: u16,
+
+    /// The sequence number of the newest packet received from the peer.
+    pub ack: u16,
+
+    /// Bit `n` is set if the packet `ack - n - 1` was received from the peer.
+    pub ack_bits: u32,
+
+    /// The messages and fragments in the packet.
+    pub entries: Vec<Entry>,
+}
+
+/// A message or fragment in a data packet.
+#[derive(Debug, PartialEq)]
+pub enum Entry {
+    /// A message on the unreliable channel.
+    Unreliable {
+        /// The sequence number of the message on the unreliable channel.
+        seq: u16,
+
+        /// The message.
+        data: Vec<u8>,
+    },
+
+    /// A fragment of a message on the reliable channel.
+    Reliable {
+        /// The id of the fragment on the reliable channel.
+        id: u16,
+
+        /// Whether more fragments of the message follow this one.
+        more: bool,
+
+        /// The fragment.
+        data: Vec<u8>,
+    },
+}
+
+impl Entry {
+    /// Returns the number of bytes the entry takes up in a packet.
+    pub fn encoded_len(&self) -> usize {
+        let data = match self {
+            Entry::Unreliable { data, .. } | Entry::Reliable { data, .. } => data,
+        };
+        ENTRY_HEADER_LEN + data.len()
+    }
+}
+
+impl Packet {
+    /// Encodes the packet.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(MAX_PACKET_LEN);
+        buf.write_u32::<BigEndian>(PROTOCOL_ID).unwrap();
+        match self {
+            Packet::Connect(version) => {
+                buf.write_u8(0).unwrap();
+                buf.write_u32::<BigEndian>(*version).unwrap();
+            }
+            Packet::Accept => buf.write_u8(1).unwrap(),
+            Packet::Reject(reason) => {
+                buf.write_u8(2).unwrap();
+                buf.extend_from_slice(reason.as_bytes());
+            }
+            Packet::Data(data) => {
+                buf.write_u8(3).unwrap();
+                buf.write_u16::<BigEndian>(data.seq).unwrap();
+                buf.write_u16::<BigEndian>(data.ack).unwrap();
+                buf.write_u32::<BigEndian>(data.ack_bits).unwrap();
+                for entry in &data.entries {
+                    let (kind, n, len, data) = match entry {
+                        Entry::Unreliable { seq, data } => (0, *seq, data.len() as u16, data),
+                        Entry::Reliable { id, more, data } => {
+                            let more = if *more { MORE_FRAGMENTS } else { 0 };
+                            (1, *id, data.len() as u16 | more, data)
+                        }
+                    };
+                    buf.write_u8(kind).unwrap();
+                    buf.write_u16::<BigEndian>(n).unwrap();
+                    buf.write_u16::<BigEndian>(len).unwrap();
+                    buf.extend_from_slice(data);
+                }
+            }
+            Packet::Disconnect => buf.write_u8(4).unwrap(),
+        }
+        buf
+    }
+
+    /// Decodes a packet, returning `None` if it is malformed or isn't from this protocol.
+    pub fn decode(buf: &[u8]) -> Option<Packet> {
+        decode(&mut Cursor::new(buf)).ok().and_then(|packet| packet)
+    }
+}
+
+/// Decodes a packet from a cursor. Returns `Ok(None)` if it is malformed, and an error if it is
+/// truncated.
+fn decode(cur: &mut Cursor<&[u8]>) -> Result<Option<Packet>> {
+    if cur.read_u32::<BigEndian>()? != PROTOCOL_ID {
+        return Ok(None);
+    }
+    let packet = match cur.read_u8()? {
+        0 => Packet::Connect(cur.read_u32::<BigEndian>()?),
+        1 => Packet::Accept,
+        2 => {
+            let mut reason = String::new();
+            let _ = cur.read_to_string(&mut reason)?;
+            Packet::Reject(reason)
+        }
+        3 => {
+            let mut data = DataPacket {
+                seq: cur.read_u16::<BigEndian>()?,
+                ack: cur.read_u16::<BigEndian>()?,
+                ack_bits: cur.read_u32::<BigEndian>()?,
+                entries: Vec::new(),
+            };
+            while (cur.position() as usize) < cur.get_ref().len() {
+                let kind = cur.read_u8()?;
+                let n = cur.read_u16::<BigEndian>()?;
+                let len = cur.read_u16::<BigEndian>()?;
+                let mut buf = vec![0; (len & !MORE_FRAGMENTS) as usize];
+                cur.read_exact(&mut buf)?;
+                data.entries.push(match kind {
+                    0 => Entry::Unreliable { seq: n, data: buf },
+                    1 => Entry::Reliable {
+                        id: n,
+                        more: len & MORE_FRAGMENTS != 0,
+                        data: buf,
+                    },
+                    _ => return Ok(None),
+                });
+            }
+            Packet::Data(data)
+        }
+        4 => Packet::Disconnect,
+        _ => return Ok(None),
+    };
+    Ok(Some(packet))
+}
+
+/// Returns whether sequence number `a` is newer than `b`, allowing for wrapping.
+pub fn seq_newer(a: u16, b: u16) -> bool {
+    a != b && a.wrapping_sub(b) < 0x8000
+}
@@ -0,0 +1,319 @@
+use crate::{
+    fake_net::{Conditions, FakeNetwork, FakeSocket},
+    packet::{DataPacket, Entry, Packet},
+    udp::{Channel, Config, DisconnectReason, Endpoint, Event},
+};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+fn server_addr() -> SocketAddr {
+    "10.0.0.1:7878".parse().unwrap()
+}
+
+fn client_addr() -> SocketAddr {
+    "10.0.0.2:50000".parse().unwrap()
+}
+
+/// Creates a server and a client on the network, without connecting them.
+fn endpoints(net: &FakeNetwork, config: Config) -> (Endpoint<FakeSocket>, Endpoint<FakeSocket>) {
+    let server = Endpoint::server(net.socket(server_addr()), Config::default());
+    let client = Endpoint::client(net.socket(client_addr()), server_addr(), config, net.now());
+    (server, client)
+}
+
+/// Runs the endpoints for the given number of 10ms steps, returning what happened to each.
+fn run(
+    net: &FakeNetwork,
+    server: &mut Endpoint<FakeSocket>,
+    client: &mut Endpoint<FakeSocket>,
+    steps: usize,
+) -> (Vec<Event>, Vec<Event>) {
+    let mut server_events = Vec::new();
+    let mut client_events = Vec::new();
+    for _ in 0..steps {
+        net.advance(Duration::from_millis(10));
+        server_events.extend(server.update(net.now()).unwrap());
+        client_events.extend(client.update(net.now()).unwrap());
+    }
+    (server_events, client_events)
+}
+
+/// Returns the messages received on the given channel.
+fn messages(events: &[Event], on: Channel) -> Vec<&[u8]> {
+    events
+        .iter()
+        .filter_map(|event| match event {
+            Event::Message { channel, msg, .. } if *channel == on => Some(&msg[..]),
+            _ => None,
+        })
+        .collect()
+}
+
+#[test]
+fn packet_round_trip() {
+    let packets = vec![
+        Packet::Connect(3),
+        Packet::Accept,
+        Packet::Reject("go away".to_string()),
+        Packet::Disconnect,
+        Packet::Data(DataPacket::default()),
+        Packet::Data(DataPacket {
+            seq: 65535,
+            ack: 12,
+            ack_bits: 0xdead_beef,
+            entries: vec![
+                Entry::Unreliable {
+                    seq: 4,
+                    data: b"state".to_vec(),
+                },
+                Entry::Reliable {
+                    id: 9,
+                    more: true,
+                    data: vec![0; 1000],
+                },
+                Entry::Reliable {
+                    id: 10,
+                    more: false,
+                    data: Vec::new(),
+                },
+            ],
+        }),
+    ];
+    for packet in packets {
+        assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    }
+
+    // Truncated and foreign packets are ignored.
+    let data = Packet::Data(DataPacket {
+        entries: vec![Entry::Unreliable {
+            seq: 0,
+            data: b"state".to_vec(),
+        }],
+        ..DataPacket::default()
+    })
+    .encode();
+    assert_eq!(Packet::decode(&data[..data.len() - 1]), None);
+    assert_eq!(Packet::decode(b"GET / HTTP/1.1\r\n"), None);
+}
+
+#[test]
+fn handshake() {
+    let net = FakeNetwork::new(1);
+    net.set_conditions(Conditions {
+        latency: Duration::from_millis(30),
+        loss: 0.5,
+        ..Conditions::default()
+    });
+    let (mut server, mut client) = endpoints(&net, Config::default());
+
+    // Losing handshake packets just makes it take longer.
+    let (server_events, client_events) = run(&net, &mut server, &mut client, 200);
+    assert_eq!(server_events, vec![Event::Connected(client_addr())]);
+    assert_eq!(client_events, vec![Event::Connected(server_addr())]);
+    assert_eq!(
+        server.connections().collect::<Vec<_>>(),
+        vec![client_addr()]
+    );
+    assert!(client.rtt(server_addr()).unwrap() >= Duration::from_millis(60));
+
+    // Closing the connection is seen by the other end.
+    client.disconnect(server_addr()).unwrap();
+    net.set_conditions(Conditions::default());
+    let (server_events, client_events) = run(&net, &mut server, &mut client, 10);
+    assert_eq!(
+        server_events,
+        vec![Event::Disconnected(client_addr(), DisconnectReason::Closed)]
+    );
+    assert_eq!(client_events, vec![]);
+    assert_eq!(server.connections().count(), 0);
+}
+
+#[test]
+fn rejects_other_versions() {
+    let net = FakeNetwork::new(2);
+    let config = Config {
+        version: 0,
+        ..Config::default()
+    };
+    let (mut server, mut client) = endpoints(&net, config);
+
+    let (server_events, client_events) = run(&net, &mut server, &mut client, 10);
+    assert_eq!(server_events, vec![]);
+    match &client_events[..] {
+        [Event::Disconnected(addr, DisconnectReason::Rejected(_))] => {
+            assert_eq!(*addr, server_addr())
+        }
+        events => panic!("expected a rejection, got {:?}", events),
+    }
+}
+
+#[test]
+fn reliable_channel() {
+    let net = FakeNetwork::new(3);
+    net.set_conditions(Conditions {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(40),
+        loss: 0.3,
+        duplication: 0.2,
+    });
+    let (mut server, mut client) = endpoints(&net, Config::default());
+
+    // Messages can be sent before the connection is made, and are sent once it is.
+    let mut sent = (0..50u32)
+        .map(|i| format!("message {}", i).into_bytes())
+        .collect::<Vec<_>>();
+    sent.insert(10, Vec::new());
+    sent.insert(20, (0..100_000u32).map(|i| i as u8).collect());
+    for msg in &sent {
+        client.send(server_addr(), Channel::Reliable, msg).unwrap();
+    }
+
+    let (server_events, _) = run(&net, &mut server, &mut client, 1000);
+    assert_eq!(messages(&server_events, Channel::Reliable), sent);
+}
+
+#[test]
+fn reliable_channel_from_server() {
+    let net = FakeNetwork::new(6);
+    let (mut server, mut client) = endpoints(&net, Config::default());
+    let _ = run(&net, &mut server, &mut client, 5);
+    net.set_conditions(Conditions {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(40),
+        loss: 0.3,
+        duplication: 0.2,
+    });
+
+    let sent = (0..50u32)
+        .map(|i| format!("update {}", i).into_bytes())
+        .chain(Some((0..10_000u32).map(|i| i as u8).collect()))
+        .collect::<Vec<_>>();
+    let mut received = Vec::new();
+    for msg in &sent {
+        server.send(client_addr(), Channel::Reliable, msg).unwrap();
+        let (_, client_events) = run(&net, &mut server, &mut client, 2);
+        received.extend(
+            messages(&client_events, Channel::Reliable)
+                .into_iter()
+                .map(<[u8]>::to_vec),
+        );
+    }
+    assert!(server.unacked_fragments(client_addr()) > 0);
+
+    // Everything arrives in order despite the losses, and the client's acknowledgements get
+    // through, so the server stops resending.
+    let (_, client_events) = run(&net, &mut server, &mut client, 500);
+    received.extend(
+        messages(&client_events, Channel::Reliable)
+            .into_iter()
+            .map(<[u8]>::to_vec),
+    );
+    assert_eq!(received, sent);
+    assert_eq!(server.unacked_fragments(client_addr()), 0);
+}
+
+#[test]
+fn unreliable_channel() {
+    let net = FakeNetwork::new(4);
+    let (mut server, mut client) = endpoints(&net, Config::default());
+    let _ = run(&net, &mut server, &mut client, 5);
+    net.set_conditions(Conditions {
+        latency: Duration::from_millis(40),
+        jitter: Duration::from_millis(40),
+        loss: 0.3,
+        duplication: 0.2,
+    });
+
+    let mut received = Vec::new();
+    for i in 0..200u32 {
+        server
+            .send(client_addr(), Channel::Unreliable, &i.to_be_bytes())
+            .unwrap();
+        let (_, client_events) = run(&net, &mut server, &mut client, 1);
+        received.extend(
+            messages(&client_events, Channel::Unreliable)
+                .into_iter()
+                .map(|msg| u32::from_be_bytes([msg[0], msg[1], msg[2], msg[3]])),
+        );
+    }
+
+    // Some messages are lost, and late ones are dropped, but none arrive twice or out of order.
+    assert!(received.len() > 50 && received.len() < 200);
+    assert!(received.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // Messages that don't fit in a packet aren't sent.
+    assert!(server
+        .send(client_addr(), Channel::Unreliable, &[0; 2000])
+        .is_err());
+}
+
+#[test]
+fn times_out() {
+    let net = FakeNetwork::new(5);
+    let config = Config {
+        timeout: Duration::from_secs(1),
+        ..Config::default()
+    };
+    let (mut server, mut client) = endpoints(&net, config);
+
+    // Keepalives hold the connection open while nothing is being sent.
+    let (_, client_events) = run(&net, &mut server, &mut client, 300);
+    assert_eq!(client_events, vec![Event::Connected(server_addr())]);
+
+    net.set_conditions(Conditions {
+        loss: 1.0,
+        ..Conditions::default()
+    });
+    let (_, client_events) = run(&net, &mut server, &mut client, 80);
+    assert_eq!(client_events, vec![]);
+    let (_, client_events) = run(&net, &mut server, &mut client, 30);
+    assert_eq!(
+        client_events,
+        vec![Event::Disconnected(
+            server_addr(),
+            DisconnectReason::TimedOut
+        )]
+    );
+}
+
+#[test]
+fn over_localhost() {
+    let server_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    server_socket.set_nonblocking(true).unwrap();
+    let server_addr = server_socket.local_addr().unwrap();
+    let client_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client_socket.set_nonblocking(true).unwrap();
+
+    let mut server = Endpoint::server(server_socket, Config::default());
+    let mut client = Endpoint::client(
+        client_socket,
+        server_addr,
+        Config::default(),
+        Instant::now(),
+    );
+    client
+        .send(server_addr, Channel::Reliable, b"hello")
+        .unwrap();
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut received = Vec::new();
+    while received.is_empty() {
+        assert!(Instant::now() < deadline, "timed out");
+        sleep(Duration::from_millis(1));
+        let _ = client.update(Instant::now()).unwrap();
+        received.extend(
+            server
+                .update(Instant::now())
+                .unwrap()
+                .into_iter()
+                .filter_map(|event| match event {
+                    Event::Message { msg, .. } => Some(msg),
+                    _ => None,
+                }),
+        );
+    }
+    assert_eq!(received, vec![b"hello".to_vec()]);
+}
@@ -0,0 +1,676 @@
+//! Sending messages over UDP.
+//!
+//! An `Endpoint` is either a server, which accepts connections from any number of clients, or a
+//! client, which connects to a single server. A client connects by sending its protocol version
+//! until the server accepts or rejects it. Once connected, messages are sent on one of two
+//! channels:
+//!
+//! - The unreliable channel is for state that is sent often, like the positions of entities.
+//!   Messages may be lost, and are never resent; a message that arrives after a newer one is
+//!   dropped. Each message must fit in a single packet.
+//! - The reliable channel is for things that must arrive, like chat and inventory changes.
+//!   Messages arrive exactly once, in the order they were sent. Large messages (like the initial
+//!   snapshot of the world) are split into fragments, which are put back together on arrival.
+//!
+//! Every packet acknowledges the packets recently received from the peer, and fragments that
+//! aren't acknowledged in time are resent. A connection is closed if nothing is heard from the
+//! peer for a while; when there's nothing else to send, packets are sent anyway to keep it open.
+//!
+//! Endpoints don't keep time themselves; the current time is passed to `Endpoint::update`, which
+//! must be called regularly. This (and the `Socket` trait) is what lets the `fake_net` module test
+//! them deterministically.
+
+use crate::{
+    packet::{seq_newer, DataPacket, Entry, Packet, DATA_HEADER_LEN, ENTRY_HEADER_LEN},
+    MAX_MESSAGE_LEN, PROTOCOL_VERSION,
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{Error, ErrorKind, Result},
+    mem::take,
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+pub use crate::packet::MAX_PACKET_LEN;
+
+/// The largest message that can be sent on the unreliable channel, in bytes.
+pub const MAX_UNRELIABLE_LEN: usize = MAX_PACKET_LEN - DATA_HEADER_LEN - ENTRY_HEADER_LEN;
+
+/// The largest fragment of a reliable message, in bytes.
+const FRAGMENT_LEN: usize = 1024;
+
+/// The most fragments that can be waiting for acknowledgement at once. The receiver ignores
+/// fragments further ahead than this.
+const RELIABLE_WINDOW: usize = 256;
+
+/// How many sent packets are remembered, waiting for acknowledgement.
+const SENT_PACKETS: u16 = 1024;
+
+/// A socket that packets can be sent and received on.
+pub trait Socket {
+    /// Sends a packet. Packets may be silently dropped.
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> Result<()>;
+
+    /// Receives a packet without blocking, returning its length and where it came from. Returns
+    /// `None` if no packets are waiting.
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>>;
+}
+
+/// `UdpSocket`s must be set to be non-blocking.
+impl Socket for UdpSocket {
+    fn send_to(&mut self, buf: &[u8], addr: SocketAddr) -> Result<()> {
+        match UdpSocket::send_to(self, buf, addr) {
+            Ok(_) => Ok(()),
+            Err(ref err) if is_transient(err) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> Result<Option<(usize, SocketAddr)>> {
+        loop {
+            match UdpSocket::recv_from(self, buf) {
+                Ok(received) => return Ok(Some(received)),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(None),
+                Err(ref err) if is_transient(err) => {}
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+/// Returns whether an error from a `UdpSocket` just means a packet was lost (or that some earlier
+/// packet wasn't delivered), which doesn't matter to the protocol.
+fn is_transient(err: &Error) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionRefused
+            | ErrorKind::ConnectionReset
+            | ErrorKind::Interrupted
+            | ErrorKind::WouldBlock
+    )
+}
+
+/// The channel a message is sent on. See the module documentation.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Channel {
+    /// Messages may be lost, and older messages are dropped.
+    Unreliable,
+
+    /// Messages arrive exactly once, in order.
+    Reliable,
+}
+
+/// Something that happened to an `Endpoint`.
+#[derive(Debug, PartialEq)]
+pub enum Event {
+    /// A connection was made to the given address.
+    Connected(SocketAddr),
+
+    /// A message was received.
+    Message {
+        /// The address the message came from.
+        from: SocketAddr,
+
+        /// The channel the message was sent on.
+        channel: Channel,
+
+        /// The message.
+        msg: Vec<u8>,
+    },
+
+    /// The connection to the given address was closed.
+    Disconnected(SocketAddr, DisconnectReason),
+}
+
+/// The reason a connection was closed.
+#[derive(Debug, PartialEq)]
+pub enum DisconnectReason {
+    /// The peer closed the connection.
+    Closed,
+
+    /// Nothing was heard from the peer for too long.
+    TimedOut,
+
+    /// The server rejected the connection, for the given reason.
+    Rejected(String),
+}
+
+/// The settings for an `Endpoint`.
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The version of the protocol. Clients are rejected unless their version is the same as the
+    /// server's.
+    pub version: u32,
+
+    /// How long to wait to hear from the peer before closing a connection.
+    pub timeout: Duration,
+
+    /// How often to send a packet when there's nothing else to send.
+    pub keepalive_interval: Duration,
+
+    /// How often a client asks to connect until the server replies.
+    pub handshake_interval: Duration,
+
+    /// The least time to wait before resending an unacknowledged fragment. The actual time is
+    /// twice the round-trip time, if that's longer.
+    pub min_resend_interval: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            version: PROTOCOL_VERSION,
+            timeout: Duration::from_secs(10),
+            keepalive_interval: Duration::from_millis(100),
+            handshake_interval: Duration::from_millis(100),
+            min_resend_interval: Duration::from_millis(50),
+        }
+    }
+}
+
+/// One end of any number of connections.
+#[derive(Debug)]
+pub struct Endpoint<S: Socket> {
+    socket: S,
+    config: Config,
+    accepting: bool,
+    connections: HashMap<SocketAddr, Connection>,
+    events: Vec<Event>,
+}
+
+impl<S: Socket> Endpoint<S> {
+    /// Creates a server, which accepts connections from clients with the same version.
+    pub fn server(socket: S, config: Config) -> Endpoint<S> {
+        Endpoint {
+            socket,
+            config,
+            accepting: true,
+            connections: HashMap::new(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Creates a client, which starts connecting to the server at the given address on the first
+    /// call to `update`.
+    pub fn client(socket: S, server: SocketAddr, config: Config, now: Instant) -> Endpoint<S> {
+        let mut connections = HashMap::new();
+        let _ = connections.insert(server, Connection::new(false, now));
+        Endpoint {
+            socket,
+            config,
+            accepting: false,
+            connections,
+            events: Vec::new(),
+        }
+    }
+
+    /// Returns the socket the endpoint sends and receives on.
+    pub fn socket(&self) -> &S {
+        &self.socket
+    }
+
+    /// Returns the addresses of the peers that are connected.
+    pub fn connections(&self) -> impl Iterator<Item = SocketAddr> + '_ {
+        self.connections
+            .iter()
+            .filter(|(_, conn)| conn.connected)
+            .map(|(&addr, _)| addr)
+    }
+
+    /// Returns the number of fragments of reliable messages sent to a peer that it hasn't
+    /// acknowledged yet, including those that haven't been sent at all.
+    pub fn unacked_fragments(&self, addr: SocketAddr) -> usize {
+        self.connections
+            .get(&addr)
+            .map_or(0, |conn| conn.reliable_out.len())
+    }
+
+    /// Returns the number of bytes that have been sent to a peer, in packets of any kind.
+    pub fn bytes_sent(&self, addr: SocketAddr) -> u64 {
+        self.connections
+            .get(&addr)
+            .map_or(0, |conn| conn.bytes_sent)
+    }
+
+    /// Returns the number of bytes that have been received from a peer, in packets of any kind.
+    pub fn bytes_received(&self, addr: SocketAddr) -> u64 {
+        self.connections
+            .get(&addr)
+            .map_or(0, |conn| conn.bytes_received)
+    }
+
+    /// Returns the smoothed round-trip time to a peer, if any packets to it have been
+    /// acknowledged.
+    pub fn rtt(&self, addr: SocketAddr) -> Option<Duration> {
+        self.connections.get(&addr).and_then(|conn| conn.rtt)
+    }
+
+    /// Queues a message to be sent to a peer on the next `update`. A client may queue messages
+    /// before it has connected.
+    pub fn send(&mut self, to: SocketAddr, channel: Channel, msg: &[u8]) -> Result<()> {
+        let conn = self
+            .connections
+            .get_mut(&to)
+            .ok_or_else(|| Error::from(ErrorKind::NotConnected))?;
+        match channel {
+            Channel::Unreliable => {
+                if msg.len() > MAX_UNRELIABLE_LEN {
+                    return Err(Error::new(ErrorKind::InvalidInput, "message too long"));
+                }
+                conn.unreliable_out.push(msg.to_vec());
+            }
+            Channel::Reliable => {
+                if msg.len() > MAX_MESSAGE_LEN {
+                    return Err(Error::new(ErrorKind::InvalidInput, "message too long"));
+                }
+                let fragments = msg.len().max(1) + FRAGMENT_LEN - 1;
+                let fragments = fragments / FRAGMENT_LEN;
+                for i in 0..fragments {
+                    let end = ((i + 1) * FRAGMENT_LEN).min(msg.len());
+                    conn.reliable_out.push_back(Fragment {
+                        id: conn.next_reliable_out,
+                        more: i + 1 < fragments,
+                        data: msg[i * FRAGMENT_LEN..end].to_vec(),
+                        acked: false,
+                        sent_at: None,
+                    });
+                    conn.next_reliable_out = conn.next_reliable_out.wrapping_add(1);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Closes the connection to a peer, telling it so. Any messages that haven't been sent yet
+    /// are dropped.
+    pub fn disconnect(&mut self, addr: SocketAddr) -> Result<()> {
+        if self.connections.remove(&addr).is_some() {
+            self.socket.send_to(&Packet::Disconnect.encode(), addr)?;
+        }
+        Ok(())
+    }
+
+    /// Receives packets, closes connections that have timed out, and sends packets, returning
+    /// what happened since the last update.
+    pub fn update(&mut self, now: Instant) -> Result<Vec<Event>> {
+        let mut buf = [0; 2 * MAX_PACKET_LEN];
+        while let Some((len, from)) = self.socket.recv_from(&mut buf)? {
+            if let Some(packet) = Packet::decode(&buf[..len]) {
+                self.handle(now, from, packet)?;
+            }
+            if let Some(conn) = self.connections.get_mut(&from) {
+                conn.bytes_received += len as u64;
+            }
+        }
+
+        let timeout = self.config.timeout;
+        let events = &mut self.events;
+        self.connections.retain(|&addr, conn| {
+            let alive = now.duration_since(conn.last_received) <= timeout;
+            if !alive {
+                events.push(Event::Disconnected(addr, DisconnectReason::TimedOut));
+            }
+            alive
+        });
+
+        for (&addr, conn) in &mut self.connections {
+            conn.flush(now, &self.config, &mut self.socket, addr)?;
+        }
+        Ok(take(&mut self.events))
+    }
+
+    /// Handles a packet.
+    fn handle(&mut self, now: Instant, from: SocketAddr, packet: Packet) -> Result<()> {
+        match packet {
+            Packet::Connect(version) => {
+                if !self.accepting {
+                    return Ok(());
+                } else if version != self.config.version {
+                    let reason = format!(
+                        "The server speaks protocol version {}, but the client speaks {}",
+                        self.config.version, version
+                    );
+                    return self.socket.send_to(&Packet::Reject(reason).encode(), from);
+                }
+
+                let events = &mut self.events;
+                let conn = self.connections.entry(from).or_insert_with(|| {
+                    events.push(Event::Connected(from));
+                    Connection::new(true, now)
+                });
+                conn.last_received = now;
+                let packet = Packet::Accept.encode();
+                conn.bytes_sent += packet.len() as u64;
+                self.socket.send_to(&packet, from)?;
+            }
+            Packet::Accept => {
+                if let Some(conn) = self.connections.get_mut(&from) {
+                    conn.last_received = now;
+                    if !conn.connected {
+                        conn.connected = true;
+                        self.events.push(Event::Connected(from));
+                    }
+                }
+            }
+            Packet::Reject(reason) => {
+                let connecting = self.connections.get(&from).map(|conn| !conn.connected);
+                if connecting == Some(true) {
+                    let _ = self.connections.remove(&from);
+                    let reason = DisconnectReason::Rejected(reason);
+                    self.events.push(Event::Disconnected(from, reason));
+                }
+            }
+            Packet::Data(data) => {
+                if let Some(conn) = self.connections.get_mut(&from) {
+                    // If the server's acceptance was lost, its data shows the client was accepted.
+                    if !conn.connected {
+                        conn.connected = true;
+                        self.events.push(Event::Connected(from));
+                    }
+                    conn.last_received = now;
+                    conn.receive(now, from, data, &mut self.events);
+                }
+            }
+            Packet::Disconnect => {
+                if self.connections.remove(&from).is_some() {
+                    let reason = DisconnectReason::Closed;
+                    self.events.push(Event::Disconnected(from, reason));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of a connection to a peer.
+#[derive(Debug)]
+struct Connection {
+    /// Whether the connection has been accepted.
+    connected: bool,
+    last_received: Instant,
+    last_sent: Option<Instant>,
+
+    /// Whether a data packet has been received since a packet was last sent.
+    needs_ack: bool,
+
+    /// The sequence number of the next packet to send.
+    next_seq: u16,
+
+    /// The packets that were sent but haven't been acknowledged, by sequence number.
+    sent: HashMap<u16, SentPacket>,
+
+    /// The sequence number of the newest packet received from the peer.
+    remote_seq: Option<u16>,
+
+    /// Bit `n` is set if the packet `remote_seq - n - 1` was received from the peer.
+    remote_bits: u32,
+
+    rtt: Option<Duration>,
+    bytes_sent: u64,
+    bytes_received: u64,
+
+    next_unreliable_out: u16,
+    unreliable_out: Vec<Vec<u8>>,
+    last_unreliable_in: Option<u16>,
+
+    /// The id the next fragment queued on the reliable channel will have.
+    next_reliable_out: u16,
+
+    /// The fragments that haven't been acknowledged, in order. Their ids are consecutive.
+    reliable_out: VecDeque<Fragment>,
+
+    /// The id of the next fragment to be delivered.
+    next_reliable_in: u16,
+
+    /// Fragments that arrived before the ones before them, by id.
+    reliable_in: HashMap<u16, (bool, Vec<u8>)>,
+
+    /// The fragments of the message being put back together.
+    assembling: Vec<u8>,
+}
+
+/// A packet that was sent.
+#[derive(Debug)]
+struct SentPacket {
+    sent_at: Instant,
+
+    /// The ids of the fragments in the packet.
+    fragments: Vec<u16>,
+}
+
+/// A fragment of a reliable message.
+#[derive(Debug)]
+struct Fragment {
+    id: u16,
+    more: bool,
+    data: Vec<u8>,
+    acked: bool,
+    sent_at: Option<Instant>,
+}
+
+impl Connection {
+    /// Creates the state for a new connection.
+    fn new(connected: bool, now: Instant) -> Connection {
+        Connection {
+            connected,
+            last_received: now,
+            last_sent: None,
+            needs_ack: false,
+            next_seq: 0,
+            sent: HashMap::new(),
+            remote_seq: None,
+            remote_bits: 0,
+            rtt: None,
+            bytes_sent: 0,
+            bytes_received: 0,
+            next_unreliable_out: 0,
+            unreliable_out: Vec::new(),
+            last_unreliable_in: None,
+            next_reliable_out: 0,
+            reliable_out: VecDeque::new(),
+            next_reliable_in: 0,
+            reliable_in: HashMap::new(),
+            assembling: Vec::new(),
+        }
+    }
+
+    /// Handles a data packet from the peer.
+    fn receive(
+        &mut self,
+        now: Instant,
+        from: SocketAddr,
+        data: DataPacket,
+        events: &mut Vec<Event>,
+    ) {
+        self.ack(now, data.ack);
+        for n in 0..32 {
+            if data.ack_bits & (1 << n) != 0 {
+                self.ack(now, data.ack.wrapping_sub(n + 1));
+            }
+        }
+
+        match self.remote_seq {
+            Some(remote_seq) if seq_newer(data.seq, remote_seq) => {
+                let shift = u32::from(data.seq.wrapping_sub(remote_seq));
+                self.remote_bits = self.remote_bits.checked_shl(shift).unwrap_or(0)
+                    | 1u32.checked_shl(shift - 1).unwrap_or(0);
+                self.remote_seq = Some(data.seq);
+            }
+            Some(remote_seq) => {
+                let age = remote_seq.wrapping_sub(data.seq);
+                if (1..=32).contains(&age) {
+                    self.remote_bits |= 1 << (age - 1);
+                }
+            }
+            None => self.remote_seq = Some(data.seq),
+        }
+        self.needs_ack = true;
+
+        for entry in data.entries {
+            match entry {
+                Entry::Unreliable { seq, data } => {
+                    let newest = self
+                        .last_unreliable_in
+                        .map(|last| seq_newer(seq, last))
+                        .unwrap_or(true);
+                    if newest {
+                        self.last_unreliable_in = Some(seq);
+                        events.push(Event::Message {
+                            from,
+                            channel: Channel::Unreliable,
+                            msg: data,
+                        });
+                    }
+                }
+                Entry::Reliable { id, more, data } => {
+                    let ahead = id.wrapping_sub(self.next_reliable_in) as usize;
+                    if ahead < RELIABLE_WINDOW {
+                        let _ = self.reliable_in.entry(id).or_insert((more, data));
+                    }
+                }
+            }
+        }
+
+        while let Some((more, data)) = self.reliable_in.remove(&self.next_reliable_in) {
+            self.next_reliable_in = self.next_reliable_in.wrapping_add(1);
+            self.assembling.extend_from_slice(&data);
+            if !more {
+                events.push(Event::Message {
+                    from,
+                    channel: Channel::Reliable,
+                    msg: take(&mut self.assembling),
+                });
+            }
+        }
+    }
+
+    /// Handles the acknowledgement of a sent packet.
+    fn ack(&mut self, now: Instant, seq: u16) {
+        let packet = match self.sent.remove(&seq) {
+            Some(packet) => packet,
+            None => return,
+        };
+
+        let sample = now.duration_since(packet.sent_at);
+        self.rtt = Some(match self.rtt {
+            Some(rtt) => rtt * 7 / 8 + sample / 8,
+            None => sample,
+        });
+
+        if let Some(first) = self.reliable_out.front().map(|fragment| fragment.id) {
+            for id in packet.fragments {
+                let i = id.wrapping_sub(first) as usize;
+                if let Some(fragment) = self.reliable_out.get_mut(i) {
+                    fragment.acked = true;
+                }
+            }
+        }
+        while self.reliable_out.front().map(|f| f.acked) == Some(true) {
+            let _ = self.reliable_out.pop_front();
+        }
+    }
+
+    /// Sends whatever is waiting to be sent, or a handshake if the connection hasn't been
+    /// accepted yet.
+    fn flush<S: Socket>(
+        &mut self,
+        now: Instant,
+        config: &Config,
+        socket: &mut S,
+        addr: SocketAddr,
+    ) -> Result<()> {
+        let due = |last: Option<Instant>, interval: Duration| {
+            last.map(|last| now.duration_since(last) >= interval)
+                .unwrap_or(true)
+        };
+
+        if !self.connected {
+            if due(self.last_sent, config.handshake_interval) {
+                self.last_sent = Some(now);
+                let packet = Packet::Connect(config.version).encode();
+                self.bytes_sent += packet.len() as u64;
+                socket.send_to(&packet, addr)?;
+            }
+            return Ok(());
+        }
+
+        let resend_interval = self
+            .rtt
+            .map(|rtt| rtt * 2)
+            .unwrap_or_default()
+            .max(config.min_resend_interval);
+        let mut entries = Vec::new();
+        for fragment in self.reliable_out.iter_mut().take(RELIABLE_WINDOW) {
+            if !fragment.acked && due(fragment.sent_at, resend_interval) {
+                fragment.sent_at = Some(now);
+                entries.push(Entry::Reliable {
+                    id: fragment.id,
+                    more: fragment.more,
+                    data: fragment.data.clone(),
+                });
+            }
+        }
+        for msg in self.unreliable_out.drain(..) {
+            entries.push(Entry::Unreliable {
+                seq: self.next_unreliable_out,
+                data: msg,
+            });
+            self.next_unreliable_out = self.next_unreliable_out.wrapping_add(1);
+        }
+
+        // Pack the entries into as few packets as possible, in order.
+        let mut packets = Vec::new();
+        let mut packet = Vec::new();
+        let mut len = DATA_HEADER_LEN;
+        for entry in entries {
+            if len + entry.encoded_len() > MAX_PACKET_LEN && !packet.is_empty() {
+                packets.push(take(&mut packet));
+                len = DATA_HEADER_LEN;
+            }
+            len += entry.encoded_len();
+            packet.push(entry);
+        }
+        if !packet.is_empty()
+            || (packets.is_empty()
+                && (self.needs_ack || due(self.last_sent, config.keepalive_interval)))
+        {
+            packets.push(packet);
+        }
+
+        for entries in packets {
+            let seq = self.next_seq;
+            self.next_seq = seq.wrapping_add(1);
+            let fragments = entries
+                .iter()
+                .filter_map(|entry| match entry {
+                    Entry::Reliable { id, .. } => Some(*id),
+                    Entry::Unreliable { .. } => None,
+                })
+                .collect();
+            let _ = self.sent.remove(&seq.wrapping_sub(SENT_PACKETS));
+            let _ = self.sent.insert(
+                seq,
+                SentPacket {
+                    sent_at: now,
+                    fragments,
+                },
+            );
+
+            let packet = Packet::Data(DataPacket {
+                seq,
+                // Before anything has been received, this acknowledges a packet that's unlikely to
+                // have been sent.
+                ack: self.remote_seq.unwrap_or(u16::MAX),
+                ack_bits: self.remote_bits,
+                entries,
+            })
+            .encode();
+            self.bytes_sent += packet.len() as u64;
+            socket.send_to(&packet, addr)?;
+            self.needs_ack = false;
+            self.last_sent = Some(now);
+        }
+        Ok(())
+    }
+}